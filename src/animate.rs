@@ -0,0 +1,188 @@
+// Frame-based animation of numeric style properties.
+//
+// A Timeline holds running animations and is advanced once per frame, by
+// whatever drives rendering. Each tick yields a patch of Change::SetStyle
+// with the interpolated values, so simple animations need no tree rebuilds:
+//
+//     let mut timeline = Timeline::new();
+//     timeline.add(Animation::new(vec![Key::Local(3)], "opacity", 0.0, 1.0, 30));
+//     while let Some(diff) = timeline.tick() {
+//         send(diff);
+//     }
+use {Change, DiffTree, Key};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    // Maps linear progress in [0, 1] to eased progress.
+    pub fn apply(&self, t: f64) -> f64 {
+        match *self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    -1.0 + (4.0 - 2.0 * t) * t
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+    // The key path of the animated node from the root, the root excluded.
+    pub path: Vec<Key>,
+    pub property: String,
+    pub from: f64,
+    pub to: f64,
+    // Appended to the value, such as "px" or "%".
+    pub unit: String,
+    // The number of frames the animation takes.
+    pub frames: u32,
+    pub easing: Easing,
+}
+
+impl Animation {
+    // A linear animation of a unitless property.
+    pub fn new(path: Vec<Key>, property: &str, from: f64, to: f64, frames: u32) -> Animation {
+        Animation {
+            path: path,
+            property: property.to_string(),
+            from: from,
+            to: to,
+            unit: String::new(),
+            frames: frames,
+            easing: Easing::Linear,
+        }
+    }
+
+    pub fn unit(mut self, unit: &str) -> Animation {
+        self.unit = unit.to_string();
+        self
+    }
+
+    pub fn easing(mut self, easing: Easing) -> Animation {
+        self.easing = easing;
+        self
+    }
+
+    // The value after `frame` frames, clamped to the end value.
+    pub fn value_at(&self, frame: u32) -> f64 {
+        let t = if self.frames == 0 {
+            1.0
+        } else {
+            (frame as f64 / self.frames as f64).min(1.0)
+        };
+        self.from + (self.to - self.from) * self.easing.apply(t)
+    }
+}
+
+// Identifies an animation added to a Timeline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AnimationId(usize);
+
+#[derive(Debug, Default)]
+pub struct Timeline {
+    // Running animations and the number of frames each has played.
+    running: Vec<(AnimationId, Animation, u32)>,
+    next_id: usize,
+}
+
+impl Timeline {
+    pub fn new() -> Timeline {
+        Timeline::default()
+    }
+
+    // Starts an animation on the next tick, replacing any animation of the
+    // same property on the same node. Starting from the value the node has
+    // reached is up to the caller.
+    pub fn add(&mut self, animation: Animation) -> AnimationId {
+        self.running.retain(|&(_, ref x, _)| x.path != animation.path || x.property != animation.property);
+        let id = AnimationId(self.next_id);
+        self.next_id += 1;
+        self.running.push((id, animation, 0));
+        id
+    }
+
+    // Stops an animation, leaving the property at its current value.
+    pub fn cancel(&mut self, id: AnimationId) -> bool {
+        let len = self.running.len();
+        self.running.retain(|&(x, _, _)| x != id);
+        self.running.len() != len
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.running.is_empty()
+    }
+
+    // Advances every animation by a frame, returning the style changes for
+    // it, or None once nothing is running. Animations are dropped after the
+    // tick that sets their final value.
+    pub fn tick(&mut self) -> Option<DiffTree> {
+        if self.running.is_empty() {
+            return None;
+        }
+        let mut diff = DiffTree {
+            changes: None,
+            children: None,
+            base_checksum: None,
+        };
+        for &mut (_, ref animation, ref mut frame) in self.running.iter_mut() {
+            *frame += 1;
+            if let Some(key) = animation.path.last().cloned() {
+                let value = format!("{}{}", animation.value_at(*frame), animation.unit);
+                diff.push_at(&animation.path,
+                             Change::SetStyle {
+                                 key: key,
+                                 property: animation.property.clone(),
+                                 value: value,
+                             });
+            }
+        }
+        self.running.retain(|&(_, ref animation, frame)| frame < animation.frames);
+        Some(diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Animation, Easing, Timeline};
+    use {Change, DiffTree, Key};
+
+    fn style(diff: &DiffTree) -> String {
+        let (_, ref child) = diff.children.as_ref().unwrap()[0];
+        match child.changes.as_ref().unwrap()[0] {
+            Change::SetStyle { ref property, ref value, .. } => format!("{}: {}", property, value),
+            ref other => panic!("expected a style change, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_timeline() {
+        let mut timeline = Timeline::new();
+        timeline.add(Animation::new(vec![Key::Local(1)], "width", 0.0, 100.0, 4).unit("px"));
+
+        let frames: Vec<String> = (0..4).map(|_| style(&timeline.tick().unwrap())).collect();
+        assert_eq!(frames, vec!["width: 25px", "width: 50px", "width: 75px", "width: 100px"]);
+        assert!(!timeline.is_running());
+        assert!(timeline.tick().is_none());
+    }
+
+    #[test]
+    fn test_easing() {
+        let animation = Animation::new(vec![Key::Local(1)], "opacity", 0.0, 1.0, 10)
+            .easing(Easing::EaseIn);
+        assert_eq!(animation.value_at(5), 0.25);
+        assert_eq!(animation.value_at(20), 1.0);
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+    }
+}
@@ -0,0 +1,239 @@
+// The render loop: a VirtualDom holding the current tree, and a Renderer
+// kept in step with it.
+//
+// App::render diffs the next tree against the current one, has the renderer
+// carry out the patch and makes the next tree current, so callers only
+// build trees. The VirtualDom is there for history, subscriptions and
+// telemetry, but updating it directly leaves the renderer behind.
+use std::io;
+use std::time::SystemTime;
+
+use audit::{AuditRecord, AuditSink};
+use renderer::{RenderDriver, Renderer};
+use {DiffTree, Element, Event, EventType, Key, PatchError, VirtualDom};
+
+pub struct App<R: Renderer> {
+    dom: VirtualDom,
+    driver: RenderDriver<R>,
+    audit: Option<Audit>,
+}
+
+struct Audit {
+    session: String,
+    sink: Box<dyn AuditSink>,
+    // The first error from the sink not yet taken by the caller.
+    error: Option<io::Error>,
+}
+
+impl<R: Renderer> App<R> {
+    // Mounts `tree` with `renderer`. The root node is left for the caller
+    // to attach, see root.
+    pub fn new(renderer: R, tree: Element) -> App<R> {
+        App::with_dom(renderer, VirtualDom::new(tree))
+    }
+
+    // Mounts the tree of `dom`, for one set up with history, telemetry or an
+    // attribute policy.
+    pub fn with_dom(renderer: R, dom: VirtualDom) -> App<R> {
+        let driver = match dom.attribute_policy() {
+            Some(policy) => {
+                let mut tree = dom.tree().clone();
+                tree.enforce(policy);
+                RenderDriver::mount(renderer, &tree)
+            }
+            None => RenderDriver::mount(renderer, dom.tree()),
+        };
+        App {
+            driver: driver,
+            dom: dom,
+            audit: None,
+        }
+    }
+
+    // Records the current tree and every patch applied from now on to
+    // `sink` under `session`, replacing any sink set before.
+    pub fn set_audit<S>(&mut self, session: &str, sink: S) -> io::Result<()>
+        where S: AuditSink + 'static
+    {
+        let mut sink = Box::new(sink);
+        sink.record(&AuditRecord::mount(SystemTime::now(), session, self.dom.tree()))?;
+        self.audit = Some(Audit {
+            session: session.to_string(),
+            sink: sink,
+            error: None,
+        });
+        Ok(())
+    }
+
+    // The first error the audit sink returned since the last call. Patches
+    // are applied whether or not they could be recorded, so callers that
+    // must not show unrecorded UI check this after every render.
+    pub fn take_audit_error(&mut self) -> Option<io::Error> {
+        self.audit.as_mut().and_then(|audit| audit.error.take())
+    }
+
+    pub fn tree(&self) -> &Element {
+        self.dom.tree()
+    }
+
+    pub fn dom(&self) -> &VirtualDom {
+        &self.dom
+    }
+
+    pub fn root(&self) -> &R::Node {
+        self.driver.root()
+    }
+
+    // The node at `path`, the keys of the children from the root down.
+    pub fn node(&self, path: &[Key]) -> Option<&R::Node> {
+        self.driver.node(path)
+    }
+
+    pub fn renderer(&self) -> &R {
+        self.driver.renderer()
+    }
+
+    pub fn renderer_mut(&mut self) -> &mut R {
+        self.driver.renderer_mut()
+    }
+
+    // Makes `next` the current tree and brings the renderer up to date,
+    // returning the patch it was given, if anything changed.
+    pub fn render(&mut self, next: Element) -> Result<Option<DiffTree>, PatchError> {
+        self.render_with_cause(next, None)
+    }
+
+    // As render, recording `cause` as the event that led to `next` in the
+    // history.
+    //
+    // The patch comes from the current tree, so it only fails if the
+    // renderer's nodes were changed behind the app's back. The tree is
+    // current either way, and the renderer is left partly patched.
+    pub fn render_with_cause(&mut self,
+                             next: Element,
+                             cause: Option<Event>)
+                             -> Result<Option<DiffTree>, PatchError> {
+        let previous = match self.audit {
+            Some(_) => Some(self.dom.tree().clone()),
+            None => None,
+        };
+        let origin = cause.as_ref().map(|event| (event.event_type(), event.target()));
+        let diff = match self.dom.update(next, cause) {
+            Some(diff) => diff,
+            None => return Ok(None),
+        };
+        self.driver.patch(&diff)?;
+        if let Some(previous) = previous {
+            record(&mut self.audit, &previous, &diff, origin)?;
+        }
+        Ok(Some(diff))
+    }
+
+    // Removes the node whose exit transition `event` reports the end of, if
+    // it was marked exiting.
+    pub fn transition_end(&mut self, event: &Event) -> Result<Option<DiffTree>, PatchError> {
+        let previous = match self.audit {
+            Some(_) => Some(self.dom.tree().clone()),
+            None => None,
+        };
+        let diff = match self.dom.transition_end(event) {
+            Some(diff) => diff,
+            None => return Ok(None),
+        };
+        self.driver.patch(&diff)?;
+        if let Some(previous) = previous {
+            record(&mut self.audit, &previous, &diff, Some((event.event_type(), event.target())))?;
+        }
+        Ok(Some(diff))
+    }
+}
+
+// Records `diff`, applied to `previous`, if there is a sink. Fails only if
+// the diff doesn't apply to `previous`; errors from the sink are kept for
+// the caller instead.
+fn record(audit: &mut Option<Audit>,
+          previous: &Element,
+          diff: &DiffTree,
+          cause: Option<(EventType, Key)>)
+          -> Result<(), PatchError> {
+    let audit = match *audit {
+        Some(ref mut audit) => audit,
+        None => return Ok(()),
+    };
+    let record = AuditRecord {
+        time: SystemTime::now(),
+        session: &audit.session,
+        cause: cause,
+        operations: diff.to_json_patch(previous)?,
+    };
+    if let Err(error) = audit.sink.record(&record) {
+        audit.error.get_or_insert(error);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+    use rustc_serialize::json::{Json, ToJson};
+    use super::App;
+    use audit::JsonLines;
+    use renderer::StringRenderer;
+    use {Element, Event, EventKind, Key, MouseEventData};
+
+    fn text(value: &str) -> Element {
+        Element::text(Key::Local(0), value)
+    }
+
+    #[test]
+    fn test_render() {
+        let mut app = App::new(StringRenderer::new(), el!(ul[key=0, el!(li[key=1, text("a")])]));
+        let html = |app: &App<StringRenderer>| app.renderer().to_html(*app.root());
+        assert_eq!(html(&app), "<ul><li>a</li></ul>");
+
+        let next = el!(ul[key=0, el!(li[key=2, text("b")]), el!(li[key=1, text("c")])]);
+        assert!(app.render(next.clone()).unwrap().is_some());
+        assert_eq!(html(&app), "<ul><li>b</li><li>c</li></ul>");
+        assert!(app.tree().deep_eq(&next));
+        assert!(app.node(&[Key::Local(2)]).is_some());
+
+        assert_eq!(app.render(next).unwrap(), None);
+        assert_eq!(html(&app), "<ul><li>b</li><li>c</li></ul>");
+    }
+
+    #[test]
+    fn test_audit() {
+        struct Shared(Rc<RefCell<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+                self.0.borrow_mut().write(bytes)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let log = Rc::new(RefCell::new(vec![]));
+        let mut app = App::new(StringRenderer::new(), el!(p[key=0, text("a")]));
+        app.set_audit("s1", JsonLines::new(Shared(log.clone()))).unwrap();
+        let click = Event {
+            target: Key::Local(0),
+            bubbles: true,
+            cancelable: true,
+            timestamp: 0.0,
+            kind: EventKind::Click(MouseEventData::default()),
+        };
+        app.render_with_cause(el!(p[key=0, text("b")]), Some(click)).unwrap();
+        app.render(el!(p[key=0, text("b")])).unwrap();
+        assert!(app.take_audit_error().is_none());
+
+        let log = String::from_utf8(log.borrow().clone()).unwrap();
+        let records: Vec<Json> = log.lines().map(|x| Json::from_str(x).unwrap()).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["patch"][0]["op"], "replace".to_json());
+        assert_eq!(records[1]["event"]["type"], "click".to_json());
+        assert_eq!(records[1]["patch"][0]["value"], "b".to_json());
+    }
+}
@@ -0,0 +1,88 @@
+// Attribute names with optional namespaces.
+//
+// Most HTML attributes live in no namespace, but SVG and XHTML content needs
+// attributes such as `xlink:href` or `xml:lang` to be set in their proper
+// namespace (setAttributeNS in the DOM), which a bare name can't express.
+use std::collections::BTreeMap;
+
+pub const XLINK_NAMESPACE: &str = "http://www.w3.org/1999/xlink";
+pub const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+pub const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+
+pub type Attributes = BTreeMap<AttrName, String>;
+
+// `name` is the qualified name as written in markup, prefix included, so two
+// attributes are the same if both their namespace and qualified name match.
+// Attributes sort by qualified name first, which is the order every
+// serialized form writes them in, whatever order they were inserted in.
+#[derive(Clone, PartialEq, Eq, Debug, Hash, PartialOrd, Ord)]
+pub struct AttrName {
+    pub name: String,
+    pub namespace: Option<String>,
+}
+
+impl AttrName {
+    // An attribute in no namespace.
+    pub fn new(name: &str) -> AttrName {
+        AttrName {
+            name: name.to_string(),
+            namespace: None,
+        }
+    }
+
+    pub fn with_namespace(namespace: &str, name: &str) -> AttrName {
+        AttrName {
+            name: name.to_string(),
+            namespace: Some(namespace.to_string()),
+        }
+    }
+
+    pub fn prefix(&self) -> Option<&str> {
+        self.name.find(':').map(|index| &self.name[..index])
+    }
+
+    pub fn local_name(&self) -> &str {
+        match self.name.find(':') {
+            Some(index) => &self.name[index + 1..],
+            None => &self.name,
+        }
+    }
+}
+
+// Converts a qualified name, placing the reserved `xml`, `xmlns` and the
+// conventional `xlink` prefixes in their namespaces. Any other name, prefixed
+// or not, is left without a namespace; use AttrName::with_namespace for
+// those.
+impl<'a> From<&'a str> for AttrName {
+    fn from(name: &'a str) -> AttrName {
+        let namespace = match name.find(':').map(|index| &name[..index]) {
+            Some("xlink") => Some(XLINK_NAMESPACE),
+            Some("xml") => Some(XML_NAMESPACE),
+            Some("xmlns") => Some(XMLNS_NAMESPACE),
+            Some(_) => None,
+            None if name == "xmlns" => Some(XMLNS_NAMESPACE),
+            None => None,
+        };
+        AttrName {
+            name: name.to_string(),
+            namespace: namespace.map(|namespace| namespace.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AttrName, XLINK_NAMESPACE, XMLNS_NAMESPACE};
+
+    #[test]
+    fn test_from_qualified_name() {
+        let href = AttrName::from("xlink:href");
+        assert_eq!(href, AttrName::with_namespace(XLINK_NAMESPACE, "xlink:href"));
+        assert_eq!(href.prefix(), Some("xlink"));
+        assert_eq!(href.local_name(), "href");
+
+        assert_eq!(AttrName::from("xmlns").namespace, Some(XMLNS_NAMESPACE.to_string()));
+        assert_eq!(AttrName::from("class"), AttrName::new("class"));
+        assert!(AttrName::from("href") != href);
+    }
+}
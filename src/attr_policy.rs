@@ -0,0 +1,338 @@
+// Central checks on the attributes that can run script or load content:
+// `href`, `src`, `srcdoc` and `style`.
+//
+// Rather than each component vetting the URLs it's handed, an
+// AttributePolicy sees every value one of these attributes is about to be
+// set to, and can let it through, rewrite it, or reject it, in which case
+// the attribute is left unset. Policies are enforced on patches, so that
+// they cover whatever a diff sends and whatever a client is about to apply,
+// and on whole trees, for the first render. SafeAttributes is a default
+// policy blocking script URLs.
+//
+// Names are matched by local name, ignoring case, so `xlink:href` is
+// checked as `href`. SetStyle changes are left alone: they carry animation
+// values rather than anything a caller wrote.
+use {AttrName, Attributes, Change, DiffTree, Element, Key, PatchError};
+
+pub const GUARDED_ATTRIBUTES: [&str; 4] = ["href", "src", "srcdoc", "style"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    // Sets the attribute to this value instead.
+    Rewrite(String),
+    Reject,
+}
+
+pub trait AttributePolicy {
+    // Called with the name of one of GUARDED_ATTRIBUTES and the value it's
+    // about to be set to.
+    fn check(&self, name: &AttrName, value: &str) -> Verdict;
+}
+
+impl<F> AttributePolicy for F
+    where F: Fn(&AttrName, &str) -> Verdict
+{
+    fn check(&self, name: &AttrName, value: &str) -> Verdict {
+        self(name, value)
+    }
+}
+
+// A value a policy rejected, at the key path of its node from the root of
+// the tree or patch being checked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rejection {
+    pub path: Vec<Key>,
+    pub name: String,
+    pub value: String,
+}
+
+// The default policy. Rejects `javascript:` and `vbscript:` URLs in href and
+// src, and `data:` URLs other than non-SVG images in src; any srcdoc, whose
+// document runs with the page's origin; and any style using script URLs,
+// the legacy `expression()`, `behavior` or `-moz-binding` hooks, or CSS
+// escapes, which could spell out any of those.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SafeAttributes;
+
+impl AttributePolicy for SafeAttributes {
+    fn check(&self, name: &AttrName, value: &str) -> Verdict {
+        let safe = match &name.local_name().to_ascii_lowercase()[..] {
+            "href" => !matches!(scheme(value).as_ref().map(|x| &x[..]),
+                                Some("javascript") | Some("vbscript") | Some("data")),
+            "src" => {
+                match scheme(value).as_ref().map(|x| &x[..]) {
+                    Some("javascript") | Some("vbscript") => false,
+                    Some("data") => {
+                        let value = value.trim().to_ascii_lowercase();
+                        value.starts_with("data:image/") && !value.starts_with("data:image/svg")
+                    }
+                    _ => true,
+                }
+            }
+            "srcdoc" => false,
+            "style" => safe_style(value),
+            _ => true,
+        };
+        if safe { Verdict::Allow } else { Verdict::Reject }
+    }
+}
+
+// The lowercased scheme of a URL, None if it's relative. Browsers ignore
+// leading and trailing spaces and control characters and any tab or newline
+// when parsing, so `java\tscript:` is a `javascript:` URL.
+fn scheme(url: &str) -> Option<String> {
+    let url: String = url.trim_matches(|c: char| c <= ' ')
+        .chars()
+        .filter(|&c| c != '\t' && c != '\n' && c != '\r')
+        .collect();
+    let end = url.find(':')?;
+    let scheme = &url[..end];
+    let valid = scheme.starts_with(|c: char| c.is_ascii_alphabetic()) &&
+                scheme.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.');
+    if valid { Some(scheme.to_ascii_lowercase()) } else { None }
+}
+
+fn safe_style(value: &str) -> bool {
+    if value.contains('\\') {
+        return false;
+    }
+    // Comments can split a keyword, as in `expr/**/ession(`.
+    let mut stripped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("/*") {
+        stripped.push_str(&rest[..start]);
+        rest = match rest[start + 2..].find("*/") {
+            Some(end) => &rest[start + 2 + end + 2..],
+            None => "",
+        };
+    }
+    stripped.push_str(rest);
+    let stripped: String = stripped.chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    !["javascript:", "vbscript:", "expression(", "behavior:", "-moz-binding"]
+        .iter()
+        .any(|x| stripped.contains(x))
+}
+
+impl DiffTree {
+    // Has `policy` check every guarded attribute the patch sets, in
+    // SetAttribute and UpdateAttributes changes and on inserted and
+    // replacement nodes. A rejected SetAttribute becomes a RemoveAttribute,
+    // so the node doesn't keep the value it had before.
+    pub fn enforce(&mut self, policy: &dyn AttributePolicy) -> Vec<Rejection> {
+        let mut rejected = vec![];
+        self.enforce_at(policy, &mut vec![], &mut rejected);
+        rejected
+    }
+
+    fn enforce_at(&mut self,
+                  policy: &dyn AttributePolicy,
+                  path: &mut Vec<Key>,
+                  rejected: &mut Vec<Rejection>) {
+        if let Some(changes) = self.changes.take() {
+            let mut checked = Vec::with_capacity(changes.len());
+            for change in changes.into_vec() {
+                checked.push(match change {
+                    Change::SetAttribute(name, value) => {
+                        match check(policy, &AttrName::from(&name[..]), value, path, rejected) {
+                            Some(value) => Change::SetAttribute(name, value),
+                            None => Change::RemoveAttribute(name),
+                        }
+                    }
+                    Change::UpdateAttributes { set, mut remove } => {
+                        let mut allowed = Vec::with_capacity(set.len());
+                        for (name, value) in set {
+                            match check(policy, &AttrName::from(&name[..]), value, path, rejected) {
+                                Some(value) => allowed.push((name, value)),
+                                None => remove.push(name),
+                            }
+                        }
+                        Change::UpdateAttributes {
+                            set: allowed,
+                            remove: remove,
+                        }
+                    }
+                    Change::InsertChild(index, mut child) => {
+                        path.push(child.to_key());
+                        child.enforce_at(policy, path, rejected);
+                        path.pop();
+                        Change::InsertChild(index, child)
+                    }
+                    Change::ReplaceNode(mut node) => {
+                        node.enforce_at(policy, path, rejected);
+                        Change::ReplaceNode(node)
+                    }
+                    change => change,
+                });
+            }
+            self.changes = Some(checked.into_boxed_slice());
+        }
+        if let Some(ref mut children) = self.children {
+            for &mut (ref key, ref mut child) in children.iter_mut() {
+                path.push(key.clone());
+                child.enforce_at(policy, path, rejected);
+                path.pop();
+            }
+        }
+    }
+}
+
+impl Element {
+    // Has `policy` check every guarded attribute in the tree, evaluating
+    // Lazy nodes, and removes the rejected ones. For trees rendered whole,
+    // where there is no patch to enforce it on.
+    pub fn enforce(&mut self, policy: &dyn AttributePolicy) -> Vec<Rejection> {
+        let mut rejected = vec![];
+        self.enforce_at(policy, &mut vec![], &mut rejected);
+        rejected
+    }
+
+    // As apply, with `policy` enforced on the patch first.
+    pub fn apply_with_policy(&mut self,
+                             diff: &DiffTree,
+                             policy: &dyn AttributePolicy)
+                             -> Result<Vec<Rejection>, PatchError> {
+        let mut diff = diff.clone();
+        let rejected = diff.enforce(policy);
+        self.apply(&diff)?;
+        Ok(rejected)
+    }
+
+    fn enforce_at(&mut self,
+                  policy: &dyn AttributePolicy,
+                  path: &mut Vec<Key>,
+                  rejected: &mut Vec<Rejection>) {
+        if let Element::Lazy { .. } = *self {
+            *self = self.force().into_owned();
+        }
+        match *self {
+            Element::Void { ref mut attributes, .. } => {
+                enforce_attributes(attributes, policy, path, rejected);
+            }
+            Element::Parent { ref mut attributes, ref mut children, .. } => {
+                enforce_attributes(attributes, policy, path, rejected);
+                for child in children.iter_mut() {
+                    path.push(child.to_key());
+                    child.enforce_at(policy, path, rejected);
+                    path.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn enforce_attributes(attributes: &mut Option<Attributes>,
+                      policy: &dyn AttributePolicy,
+                      path: &[Key],
+                      rejected: &mut Vec<Rejection>) {
+    let emptied = match *attributes {
+        Some(ref mut attributes) => {
+            let names: Vec<AttrName> = attributes.keys().filter(|x| guarded(x)).cloned().collect();
+            for name in names {
+                let value = attributes.remove(&name).expect("name was just listed");
+                if let Some(value) = check(policy, &name, value, path, rejected) {
+                    attributes.insert(name, value);
+                }
+            }
+            attributes.is_empty()
+        }
+        None => false,
+    };
+    if emptied {
+        *attributes = None;
+    }
+}
+
+fn guarded(name: &AttrName) -> bool {
+    GUARDED_ATTRIBUTES.iter().any(|x| name.local_name().eq_ignore_ascii_case(x))
+}
+
+// The value to set the attribute to, None if rejected.
+fn check(policy: &dyn AttributePolicy,
+         name: &AttrName,
+         value: String,
+         path: &[Key],
+         rejected: &mut Vec<Rejection>)
+         -> Option<String> {
+    if !guarded(name) {
+        return Some(value);
+    }
+    match policy.check(name, &value) {
+        Verdict::Allow => Some(value),
+        Verdict::Rewrite(value) => Some(value),
+        Verdict::Reject => {
+            rejected.push(Rejection {
+                path: path.to_vec(),
+                name: name.name.clone(),
+                value: value,
+            });
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AttributePolicy, SafeAttributes, Verdict};
+    use {AttrName, Element, Key};
+
+    fn link(key: u64, href: &str) -> Element {
+        Element::void("a").key(Key::Local(key)).attr("href", href).attr("title", href).build()
+    }
+
+    fn href(element: &Element) -> Option<&str> {
+        match *element {
+            Element::Void { attributes: Some(ref attributes), .. } => {
+                attributes.get(&AttrName::new("href")).map(|x| &x[..])
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_safe_attributes() {
+        let check = |name: &str, value: &str| SafeAttributes.check(&AttrName::from(name), value);
+        assert_eq!(check("href", "https://example.com/"), Verdict::Allow);
+        assert_eq!(check("href", "/relative:path"), Verdict::Allow);
+        assert_eq!(check("href", " Java\tScript:alert(1)"), Verdict::Reject);
+        assert_eq!(check("xlink:href", "javascript:alert(1)"), Verdict::Reject);
+        assert_eq!(check("src", "data:image/png;base64,AAAA"), Verdict::Allow);
+        assert_eq!(check("src", "data:image/svg+xml,<svg/>"), Verdict::Reject);
+        assert_eq!(check("srcdoc", "<p>hi</p>"), Verdict::Reject);
+        assert_eq!(check("style", "color: red"), Verdict::Allow);
+        assert_eq!(check("style", "width: expr/**/ession(alert(1))"), Verdict::Reject);
+    }
+
+    #[test]
+    fn test_enforce() {
+        let before = el!(div[key=0, link(1, "/a")]);
+        let after = el!(div[key=0, link(1, "javascript:x"), link(2, "javascript:y"), link(3, "/c")]);
+        let mut diff = before.diff(&after).unwrap();
+        let rejected = diff.enforce(&SafeAttributes);
+        assert_eq!(rejected.iter().map(|x| x.path.clone()).collect::<Vec<_>>(),
+                   vec![vec![Key::Local(2)], vec![Key::Local(1)]]);
+
+        let mut patched = before.clone();
+        patched.apply(&diff).unwrap();
+        let mut expected = after.clone();
+        expected.enforce(&SafeAttributes);
+        assert!(patched.deep_eq(&expected));
+        assert_eq!(href(patched.get_child(Key::Local(1)).unwrap()), None);
+        assert_eq!(href(patched.get_child(Key::Local(3)).unwrap()), Some("/c"));
+
+        let rewrite = |_: &AttrName, value: &str| {
+            if value.starts_with("javascript:") {
+                Verdict::Rewrite("#".to_string())
+            } else {
+                Verdict::Allow
+            }
+        };
+        let mut patched = before.clone();
+        assert!(patched.apply_with_policy(&before.diff(&after).unwrap(), &rewrite).unwrap().is_empty());
+        assert_eq!(href(patched.get_child(Key::Local(2)).unwrap()), Some("#"));
+    }
+}
@@ -0,0 +1,132 @@
+// An audit trail of what each session's UI showed, for deployments that
+// must be able to reconstruct it later.
+//
+// An App given an AuditSink records the tree it has when the sink is set
+// and every patch it applies from then on, each with the time, the session
+// and the event that caused it. Patches are recorded as JSON Patch
+// operations on the JSON form of the tree before them, see json_patch, and
+// the first record is one operation replacing the whole document, so
+// replaying a session's records in order gives each tree it showed.
+//
+// JsonLines writes records one JSON object per line:
+//
+//   {"event":{"target":{"local":3},"type":"click"},"patch":[...],
+//    "session":"abc","time":1700000000000}
+//
+// `time` is in milliseconds since the Unix epoch and `event` is null for
+// patches with no known cause.
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use json_patch::Operation;
+use rustc_serialize::json::{Json, ToJson};
+use {Element, EventType, Key};
+
+#[derive(Debug)]
+pub struct AuditRecord<'a> {
+    pub time: SystemTime,
+    pub session: &'a str,
+    // The kind and target of the event that led to the patch.
+    pub cause: Option<(EventType, Key)>,
+    pub operations: Vec<Operation>,
+}
+
+impl<'a> AuditRecord<'a> {
+    // The record of `tree` being shown in full.
+    pub fn mount(time: SystemTime, session: &'a str, tree: &Element) -> AuditRecord<'a> {
+        AuditRecord {
+            time: time,
+            session: session,
+            cause: None,
+            operations: vec![Operation::Replace {
+                                 path: String::new(),
+                                 value: tree.to_json(),
+                             }],
+        }
+    }
+}
+
+impl<'a> ToJson for AuditRecord<'a> {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        // Times before the epoch are written as the epoch.
+        let millis = self.time.duration_since(UNIX_EPOCH).map(|x| x.as_millis() as u64).unwrap_or(0);
+        object.insert("time".to_string(), millis.to_json());
+        object.insert("session".to_string(), self.session.to_json());
+        object.insert("event".to_string(),
+                      self.cause.as_ref().map_or(Json::Null, |&(kind, ref target)| {
+                          let mut cause = BTreeMap::new();
+                          cause.insert("type".to_string(), kind.name().to_json());
+                          cause.insert("target".to_string(), target.to_json());
+                          Json::Object(cause)
+                      }));
+        object.insert("patch".to_string(),
+                      Json::Array(self.operations.iter().map(|x| x.to_json()).collect()));
+        Json::Object(object)
+    }
+}
+
+pub trait AuditSink {
+    // Called with every record, in the order the patches were applied. An
+    // error is kept by the App for its caller, see App::take_audit_error.
+    fn record(&mut self, record: &AuditRecord) -> io::Result<()>;
+}
+
+// Writes records as JSON Lines to a file, socket or log pipe, flushing
+// after each one.
+pub struct JsonLines<W: Write> {
+    out: W,
+}
+
+impl<W: Write> JsonLines<W> {
+    pub fn new(out: W) -> JsonLines<W> {
+        JsonLines { out: out }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> AuditSink for JsonLines<W> {
+    fn record(&mut self, record: &AuditRecord) -> io::Result<()> {
+        writeln!(self.out, "{}", record.to_json())?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use rustc_serialize::json::{Json, ToJson};
+    use super::{AuditRecord, AuditSink, JsonLines};
+    use {Element, EventType, Key};
+
+    #[test]
+    fn test_json_lines() {
+        let time = UNIX_EPOCH + Duration::from_millis(1500);
+        let before = el!(p[key=0, Element::text(Key::Local(0), "a")]);
+        let after = el!(p[key=0, Element::text(Key::Local(0), "b")]);
+
+        let mut sink = JsonLines::new(vec![]);
+        sink.record(&AuditRecord::mount(time, "s1", &before)).unwrap();
+        sink.record(&AuditRecord {
+                time: time,
+                session: "s1",
+                cause: Some((EventType::Click, Key::Local(0))),
+                operations: before.diff(&after).unwrap().to_json_patch(&before).unwrap(),
+            })
+            .unwrap();
+        let log = String::from_utf8(sink.into_inner()).unwrap();
+        let lines: Vec<Json> = log.lines().map(|x| Json::from_str(x).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+
+        assert_eq!(lines[0]["time"], Json::U64(1500));
+        assert_eq!(lines[0]["event"], Json::Null);
+        assert_eq!(lines[0]["patch"][0]["value"], before.to_json());
+        assert_eq!(lines[1]["session"], "s1".to_json());
+        assert_eq!(lines[1]["event"]["type"], "click".to_json());
+        assert_eq!(lines[1]["patch"][0]["path"], "/children/0/value".to_json());
+    }
+}
@@ -0,0 +1,109 @@
+// Standard diff workloads for measuring performance, run with Criterion.
+//
+// Enabled by the `bench` feature, and run by the `bench` binary:
+//
+//     cargo run --release --features bench --bin bench -- --bench
+use criterion::{black_box, Criterion};
+use {Children, Element, Key, TextPatch};
+
+const ROWS: u64 = 1000;
+const DEPTH: u64 = 100;
+
+// Each scenario builds a (before, after) pair of trees to diff.
+pub type Scenario = fn() -> (Element, Element);
+
+pub const SCENARIOS: &[(&str, Scenario)] =
+    &[("append-1000-rows", append_rows),
+      ("shuffle-rows", shuffle_rows),
+      ("update-every-10th-text", update_every_10th_text),
+      ("deep-tree-single-leaf-change", deep_tree_single_leaf_change),
+      ("ticking-clock", ticking_clock)];
+
+pub fn benchmarks(c: &mut Criterion) {
+    for &(name, scenario) in SCENARIOS {
+        let (before, after) = scenario();
+        c.bench_function(name, |b| b.iter(|| black_box(before.diff(&after))));
+    }
+    // The same clock tick through the text-only fast path, reusing the patch.
+    let (before, after) = ticking_clock();
+    let mut patch = TextPatch::new();
+    c.bench_function("ticking-clock-diff-text",
+                     |b| b.iter(|| black_box(before.diff_text(&after, &mut patch))));
+}
+
+pub fn run() {
+    let mut c = Criterion::default().configure_from_args();
+    benchmarks(&mut c);
+    c.final_summary();
+}
+
+// An empty table body gaining 1000 rows.
+pub fn append_rows() -> (Element, Element) {
+    (table(vec![]), table((0..ROWS).map(|i| row(i, &format!("row {}", i))).collect()))
+}
+
+// 1000 rows put into a different order with the same content.
+pub fn shuffle_rows() -> (Element, Element) {
+    let mut order: Vec<u64> = (0..ROWS).collect();
+    // A fixed linear congruential generator keeps runs comparable.
+    let mut state = 0x2545f491u64;
+    for i in (1..order.len()).rev() {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let j = (state >> 33) as usize % (i + 1);
+        order.swap(i, j);
+    }
+    (table((0..ROWS).map(|i| row(i, &format!("row {}", i))).collect()),
+     table(order.into_iter().map(|i| row(i, &format!("row {}", i))).collect()))
+}
+
+// 1000 rows where the text of every 10th row changes.
+pub fn update_every_10th_text() -> (Element, Element) {
+    (table((0..ROWS).map(|i| row(i, &format!("row {}", i))).collect()),
+     table((0..ROWS)
+        .map(|i| if i % 10 == 0 {
+            row(i, &format!("row {} (updated)", i))
+        } else {
+            row(i, &format!("row {}", i))
+        })
+        .collect()))
+}
+
+// A chain of 100 nested divs where only the innermost text changes.
+pub fn deep_tree_single_leaf_change() -> (Element, Element) {
+    let chain = |leaf: &str| {
+        (0..DEPTH).fold(text(0, leaf), |child, i| parent(i, "div", vec![child]))
+    };
+    (chain("before"), chain("after"))
+}
+
+// A clock whose hours, minutes and seconds are separate text nodes, with
+// only the seconds changing.
+pub fn ticking_clock() -> (Element, Element) {
+    let clock = |seconds: &str| {
+        parent(0,
+               "time",
+               vec![parent(0, "span", vec![text(0, "12")]),
+                    parent(1, "span", vec![text(0, "00")]),
+                    parent(2, "span", vec![text(0, seconds)])])
+    };
+    (clock("00"), clock("01"))
+}
+
+fn table(rows: Vec<Element>) -> Element {
+    parent(0, "tbody", rows)
+}
+
+fn row(key: u64, label: &str) -> Element {
+    parent(key, "tr", vec![parent(0, "td", vec![text(0, label)])])
+}
+
+fn text(key: u64, value: &str) -> Element {
+    Element::Text {
+        key: Key::Local(key),
+        value: value.to_string(),
+    }
+}
+
+fn parent(key: u64, name: &str, children: Vec<Element>) -> Element {
+    children.into_iter().collect::<Children>().into_parent(Key::Local(key), name)
+}
@@ -0,0 +1,5 @@
+extern crate rusty_dom;
+
+fn main() {
+    rusty_dom::bench::run();
+}
@@ -0,0 +1,332 @@
+// Building elements without writing out their keymaps:
+//
+//     let list = Element::parent("ul")
+//         .key(Key::Local(0))
+//         .attr("class", "items")
+//         .child(Element::void("li").key(Key::Local(1)))
+//         .child(Element::text(Key::Local(2), "more"))
+//         .build();
+//
+// The keymap of a parent is kept by the builder as children are added, so
+// the built element's keymap always matches its children. Keys default to
+// Key::Local(0), and flags record where the builder was created, as
+// Flags::here does.
+//
+// Static markup needn't key its children at all. Children added as builders
+// without a key, and text added with `text`, are keyed by position when the
+// parent is built: the n-th of them gets the n-th Key::Local not taken by a
+// keyed sibling. As in React, keyed children are then matched across
+// renders by key and unkeyed ones by position among the unkeyed, so keyed
+// children coming and going leave the unkeyed matched as before, unless
+// they take a key one of those had. An unkeyed child inserted or removed
+// shifts the keys of those after it, which are patched into each other's
+// places rather than moved; lists whose items come and go should be keyed.
+use std::collections::BTreeSet;
+
+use {AttrName, Attributes, Children, Element, Flags, HandlerId, IntoKey, Key, Listeners, CAPTURE_SUFFIX};
+
+#[derive(Clone, Debug)]
+pub struct ElementBuilder {
+    key: Option<Key>,
+    name: String,
+    attributes: Option<Attributes>,
+    listeners: Listeners,
+    // None for a void element.
+    children: Option<Vec<Child>>,
+    // The keys of the keyed children.
+    keys: BTreeSet<Key>,
+    flags: Flags,
+}
+
+// A child as given to ElementBuilder::child, see IntoChild.
+#[derive(Clone, Debug)]
+pub enum Child {
+    Keyed(Element),
+    // Keyed by position when the parent is built.
+    Unkeyed(ElementBuilder),
+    UnkeyedText(String),
+}
+
+// What ElementBuilder::child takes: an element, which keeps its key, or a
+// builder, which is keyed by position if it wasn't given a key.
+pub trait IntoChild {
+    fn into_child(self) -> Child;
+}
+
+impl IntoChild for Element {
+    fn into_child(self) -> Child {
+        Child::Keyed(self)
+    }
+}
+
+impl IntoChild for ElementBuilder {
+    fn into_child(self) -> Child {
+        match self.key {
+            Some(_) => Child::Keyed(self.build()),
+            None => Child::Unkeyed(self),
+        }
+    }
+}
+
+impl Element {
+    // A builder for an element with children; one left without any is
+    // still a Parent.
+    #[track_caller]
+    pub fn parent(name: &str) -> ElementBuilder {
+        ElementBuilder::new(name, Some(vec![]))
+    }
+
+    // A builder for an element without children.
+    #[track_caller]
+    pub fn void(name: &str) -> ElementBuilder {
+        ElementBuilder::new(name, None)
+    }
+
+    pub fn text(key: Key, value: &str) -> Element {
+        Element::Text {
+            key: key,
+            value: value.to_string(),
+        }
+    }
+}
+
+impl ElementBuilder {
+    #[track_caller]
+    fn new(name: &str, children: Option<Vec<Child>>) -> ElementBuilder {
+        ElementBuilder {
+            key: None,
+            name: name.to_string(),
+            attributes: None,
+            listeners: Listeners::new(),
+            children: children,
+            keys: BTreeSet::new(),
+            flags: Flags::here(),
+        }
+    }
+
+    // Sets the key, given as a Key or anything else with an IntoKey impl,
+    // such as a string id.
+    pub fn key<K: IntoKey>(mut self, key: K) -> ElementBuilder {
+        self.key = Some(key.into_key());
+        self
+    }
+
+    // Sets an attribute, its namespace implied by the name's prefix as in
+    // AttrName's From impl.
+    pub fn attr(mut self, name: &str, value: &str) -> ElementBuilder {
+        self.attributes
+            .get_or_insert_with(Attributes::new)
+            .insert(AttrName::from(name), value.to_string());
+        self
+    }
+
+    // Has `handler` listen for the event named `event`, such as "click",
+    // replacing any handler set for it before.
+    pub fn on(mut self, event: &str, handler: HandlerId) -> ElementBuilder {
+        self.listeners.insert(event.to_string(), handler);
+        self
+    }
+
+    // Has `handler` listen for the event named `event` in the capture phase,
+    // before any handler on the nodes below, see HandlerRegistry::dispatch.
+    pub fn on_capture(mut self, event: &str, handler: HandlerId) -> ElementBuilder {
+        self.listeners.insert(format!("{}{}", event, CAPTURE_SUFFIX), handler);
+        self
+    }
+
+    // Replaces the flags, keeping the recorded source.
+    pub fn flags(mut self, flags: Flags) -> ElementBuilder {
+        self.flags = Flags { source: self.flags.source, ..flags };
+        self
+    }
+
+    // Appends a child. Panics if the element is void or already has a child
+    // with the same key, either of which would leave the tree inconsistent.
+    pub fn child<E: IntoChild>(mut self, child: E) -> ElementBuilder {
+        self.push(child.into_child());
+        self
+    }
+
+    // Makes room for `capacity` more children, so that a long list is added
+    // without the children growing as it goes. Attributes are kept in a
+    // BTreeMap, which has nothing to reserve. A hint on a void element is
+    // ignored; adding the children is what panics.
+    pub fn with_children_capacity(mut self, capacity: usize) -> ElementBuilder {
+        if let Some(ref mut children) = self.children {
+            children.reserve(capacity);
+        }
+        self
+    }
+
+    // Appends each child in turn, as `child` does, making room for as many
+    // as the iterator says it has at least.
+    pub fn children<I>(mut self, children: I) -> ElementBuilder
+        where I: IntoIterator,
+              I::Item: IntoChild
+    {
+        self.extend(children);
+        self
+    }
+
+    // Appends a text child keyed by position.
+    pub fn text(self, value: &str) -> ElementBuilder {
+        self.child(Child::UnkeyedText(value.to_string()))
+    }
+
+    pub fn build(self) -> Element {
+        let key = self.key.unwrap_or(Key::Local(0));
+        match self.children {
+            Some(pending) => {
+                let keys = self.keys;
+                let mut free = (0..).map(Key::Local).filter(|x| !keys.contains(x));
+                let mut children = Children::with_capacity(pending.len());
+                for child in pending {
+                    children.push(match child {
+                        Child::Keyed(element) => element,
+                        Child::Unkeyed(builder) => builder.key(free.next().unwrap()).build(),
+                        Child::UnkeyedText(value) => Element::text(free.next().unwrap(), &value),
+                    });
+                }
+                let (children, keymap) = children.into_parts();
+                Element::Parent {
+                    key: key,
+                    name: self.name,
+                    keymap: keymap,
+                    attributes: self.attributes,
+                    listeners: self.listeners,
+                    children: children,
+                    flags: self.flags,
+                }
+            }
+            None => {
+                Element::Void {
+                    key: key,
+                    name: self.name,
+                    attributes: self.attributes,
+                    listeners: self.listeners,
+                    flags: self.flags,
+                }
+            }
+        }
+    }
+}
+
+impl ElementBuilder {
+    fn push(&mut self, child: Child) {
+        if let Child::Keyed(ref element) = child {
+            if !self.keys.insert(element.to_key()) {
+                panic!("duplicate key {:?} among the children of <{}>", element.to_key(), self.name);
+            }
+        }
+        match self.children {
+            Some(ref mut children) => children.push(child),
+            None => panic!("void element <{}> can't have children", self.name),
+        }
+    }
+}
+
+// Appends each child in turn, as ElementBuilder::children does, for a
+// builder held by reference.
+impl<C: IntoChild> Extend<C> for ElementBuilder {
+    fn extend<I>(&mut self, iter: I)
+        where I: IntoIterator<Item = C>
+    {
+        let iter = iter.into_iter();
+        if let Some(ref mut children) = self.children {
+            children.reserve(iter.size_hint().0);
+        }
+        for child in iter {
+            self.push(child.into_child());
+        }
+    }
+}
+
+impl IntoChild for Child {
+    fn into_child(self) -> Child {
+        self
+    }
+}
+
+impl From<ElementBuilder> for Element {
+    fn from(builder: ElementBuilder) -> Element {
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Element, Flags, IntoKey, Key};
+
+    #[test]
+    fn test_build() {
+        let list = Element::parent("ul")
+            .key(Key::Local(0))
+            .attr("class", "items")
+            .child(Element::void("li").key(Key::Local(1)).attr("hidden", ""))
+            .children((2..4).map(|x| Element::text(Key::Local(x), "x")))
+            .flags(Flags { observe_resize: true, ..Flags::default() })
+            .build();
+        assert_eq!(list.to_html(), "<ul class=\"items\"><li hidden=\"\"></li>xx</ul>");
+        assert!(list.view().flags().observe_resize);
+        assert_eq!(list.view().source().is_some(), cfg!(debug_assertions));
+        assert!(list.try_diff(&list.clone()).is_ok());
+
+        match Element::parent("ul").build() {
+            Element::Parent { ref children, .. } => assert!(children.is_empty()),
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unkeyed_children() {
+        let page = |items: &[&str]| {
+            Element::parent("nav")
+                .child(Element::void("hr"))
+                .children(items.iter().map(|&x| Element::void("a").key(x)))
+                .text("end")
+                .child(Element::text(Key::Local(1), "keyed"))
+                .build()
+        };
+        let before = page(&["a"]);
+        let keys: Vec<Key> = before.view().children().map(|x| x.key()).collect();
+        assert_eq!(keys, vec![Key::Local(0), "a".into_key(), Key::Local(2), Key::Local(1)]);
+
+        // The keyed link coming and going leaves the others in place.
+        let diff = before.diff(&page(&["a", "b"])).unwrap();
+        assert_eq!(diff.changes.as_ref().map(|x| x.len()), Some(1));
+        assert!(diff.children.is_none());
+    }
+
+    #[test]
+    fn test_children_capacity() {
+        let builder = Element::parent("ul").with_children_capacity(64);
+        assert!(builder.children.as_ref().unwrap().capacity() >= 64);
+        let builder = builder.children((0..100).map(|x| Element::void("li").key(x)));
+        assert!(builder.children.as_ref().unwrap().capacity() >= 100);
+        match builder.build() {
+            Element::Parent { ref children, .. } => assert_eq!(children.capacity(), 100),
+            ref other => panic!("unexpected {:?}", other),
+        }
+        assert!(Element::void("br").with_children_capacity(8).children.is_none());
+    }
+
+    #[test]
+    fn test_extend() {
+        let view = |x: &u64| Element::void("li").key(*x).build();
+        let mut list = Element::parent("ul").key(Key::Local(0));
+        list.extend([1, 2].iter().map(view));
+        list.extend(vec![Element::void("hr")]);
+        let list = list.build();
+        let keys: Vec<Key> = list.view().children().map(|x| x.key()).collect();
+        assert_eq!(keys, vec![Key::Local(1), Key::Local(2), Key::Local(0)]);
+        assert!(list.try_diff(&list.clone()).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate key Local(1) among the children of <ul>")]
+    fn test_duplicate_key() {
+        Element::parent("ul")
+            .child(Element::text(Key::Local(1), "a"))
+            .child(Element::text(Key::Local(1), "b"));
+    }
+}
@@ -0,0 +1,270 @@
+// Which changes of a patch have to be applied in order, for backends that
+// can apply the others concurrently or batch them, such as one building a
+// DocumentFragment off-screen.
+//
+// A change depends on an earlier change if both write the same part of a
+// node: its child list, its text, one of its attributes, listeners or
+// inline style properties, or one of its observers. A ReplaceNode writes
+// all of the node and everything below it. Commands such as Focus run after
+// the rest of the patch, so they depend on every other change, and on each
+// other in turn. A MoveNode writes the child lists of both the node's old
+// and new parents, and the changes to the node itself follow it. Changes to
+// different nodes are otherwise independent: a node's changes address its
+// children by key, whatever their position.
+use {Change, DiffTree, Key};
+
+pub struct ChangeGraph<'a> {
+    // The changes with the key path of their node, commands last, otherwise
+    // in the order the patch applies them.
+    changes: Vec<(Vec<Key>, &'a Change)>,
+    dependencies: Vec<Vec<usize>>,
+}
+
+impl DiffTree {
+    // The dependencies between the changes of this patch, see ChangeGraph.
+    pub fn dependencies(&self) -> ChangeGraph<'_> {
+        let mut changes = vec![];
+        let mut commands = vec![];
+        collect(self, &mut vec![], &mut changes, &mut commands);
+        let writes = changes.len();
+        changes.extend(commands);
+
+        let mut dependencies = vec![vec![]; changes.len()];
+        for b in 0..changes.len() {
+            if b >= writes {
+                // After every other change, and the command before.
+                dependencies[b] = (0..b).filter(|&a| a + 1 == b || a < writes).collect();
+                continue;
+            }
+            let (ref path_b, change_b) = changes[b];
+            let removal_b = removal(change_b);
+            for (a, &(ref path_a, change_a)) in changes[..b].iter().enumerate() {
+                let removal_a = removal(change_a);
+                let mut ordered = overlap(path_a, change_a, path_b, change_b);
+                if let Some((from, ref remove)) = removal_a {
+                    ordered |= overlap(from, remove, path_b, change_b);
+                }
+                if let Some((from, ref remove)) = removal_b {
+                    ordered |= overlap(path_a, change_a, from, remove);
+                    if let Some((from_a, ref remove_a)) = removal_a {
+                        ordered |= overlap(from_a, remove_a, from, remove);
+                    }
+                }
+                if ordered {
+                    dependencies[b].push(a);
+                }
+            }
+        }
+        ChangeGraph {
+            changes: changes,
+            dependencies: dependencies,
+        }
+    }
+}
+
+impl<'a> ChangeGraph<'a> {
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    // The change numbered `index`, with the key path of its node.
+    pub fn change(&self, index: usize) -> (&[Key], &'a Change) {
+        let (ref path, change) = self.changes[index];
+        (path, change)
+    }
+
+    // The changes that have to be applied before the one numbered `index`,
+    // all of them numbered lower.
+    pub fn depends_on(&self, index: usize) -> &[usize] {
+        &self.dependencies[index]
+    }
+
+    // Whether two changes have to be applied in the order of their numbers,
+    // directly or through others.
+    pub fn ordered(&self, a: usize, b: usize) -> bool {
+        let (first, last) = if a < b { (a, b) } else { (b, a) };
+        let mut stack = vec![last];
+        let mut seen = vec![false; self.len()];
+        while let Some(index) = stack.pop() {
+            for &dependency in &self.dependencies[index] {
+                if dependency == first {
+                    return true;
+                }
+                if dependency > first && !seen[dependency] {
+                    seen[dependency] = true;
+                    stack.push(dependency);
+                }
+            }
+        }
+        false
+    }
+
+    // The changes in stages, each of them applicable in any order or at
+    // once after those of the stages before.
+    pub fn stages(&self) -> Vec<Vec<usize>> {
+        let mut stage_of: Vec<usize> = Vec::with_capacity(self.len());
+        let mut stages: Vec<Vec<usize>> = vec![];
+        for index in 0..self.len() {
+            let stage = self.dependencies[index].iter().map(|&x| stage_of[x] + 1).max().unwrap_or(0);
+            if stage == stages.len() {
+                stages.push(vec![]);
+            }
+            stages[stage].push(index);
+            stage_of.push(stage);
+        }
+        stages
+    }
+}
+
+fn collect<'a>(diff: &'a DiffTree,
+               path: &mut Vec<Key>,
+               changes: &mut Vec<(Vec<Key>, &'a Change)>,
+               commands: &mut Vec<(Vec<Key>, &'a Change)>) {
+    if let Some(ref node_changes) = diff.changes {
+        for change in node_changes.iter() {
+            match *change {
+                Change::Focus(_) |
+                Change::ScrollIntoView { .. } |
+                Change::Measure { .. } => commands.push((path.clone(), change)),
+                _ => changes.push((path.clone(), change)),
+            }
+        }
+    }
+    if let Some(ref children) = diff.children {
+        for &(ref key, ref child) in children.iter() {
+            path.push(key.clone());
+            collect(child, path, changes, commands);
+            path.pop();
+        }
+    }
+}
+
+// Whether two changes, each at the path of its node, write the same part
+// of the tree.
+fn overlap(path_a: &[Key], a: &Change, path_b: &[Key], b: &Change) -> bool {
+    if path_a == path_b {
+        conflicts(a, b)
+    } else if path_b.starts_with(path_a) {
+        writes_below(a, &path_b[path_a.len()])
+    } else if path_a.starts_with(path_b) {
+        writes_below(b, &path_a[path_b.len()])
+    } else {
+        false
+    }
+}
+
+// Whether a change writes anything below its node's child with this key.
+fn writes_below(change: &Change, child: &Key) -> bool {
+    match *change {
+        Change::ReplaceNode(_) => true,
+        Change::MoveNode { ref key, .. } => key == child,
+        _ => false,
+    }
+}
+
+// The removal of the node a MoveNode moves from its old parent, with that
+// parent's path.
+fn removal(change: &Change) -> Option<(&[Key], Change)> {
+    match *change {
+        Change::MoveNode { ref key, ref from, .. } => Some((from, Change::RemoveChild(key.clone()))),
+        _ => None,
+    }
+}
+
+// The part of its node a change writes.
+#[derive(PartialEq)]
+enum Target<'a> {
+    Node,
+    Children,
+    Text,
+    Attributes(Vec<&'a str>),
+    Listener(&'a str),
+    Style(&'a str),
+    Visibility,
+    Resize,
+}
+
+fn target(change: &Change) -> Target<'_> {
+    match *change {
+        Change::ReplaceNode(_) => Target::Node,
+        Change::RemoveChild(_) |
+        Change::InsertChild(..) |
+        Change::MoveChild(..) |
+        Change::MoveNode { .. } |
+        Change::SortChildren(_) |
+        Change::MarkExiting(_) => Target::Children,
+        Change::UpdateText(_) |
+        Change::SpliceText { .. } => Target::Text,
+        Change::SetAttribute(ref name, _) |
+        Change::RemoveAttribute(ref name) => Target::Attributes(vec![name]),
+        Change::UpdateAttributes { ref set, ref remove } => {
+            Target::Attributes(set.iter().map(|x| &x.0[..]).chain(remove.iter().map(|x| &x[..])).collect())
+        }
+        Change::AddListener(_, ref name, _) |
+        Change::RemoveListener(_, ref name) => Target::Listener(name),
+        Change::SetStyle { ref property, .. } => Target::Style(property),
+        Change::ObserveVisibility(_) |
+        Change::UnobserveVisibility(_) => Target::Visibility,
+        Change::ObserveResize(_) |
+        Change::UnobserveResize(_) => Target::Resize,
+        // Commands are ordered separately.
+        Change::Focus(_) |
+        Change::ScrollIntoView { .. } |
+        Change::Measure { .. } => Target::Node,
+    }
+}
+
+// Whether two changes to the same node write the same part of it.
+fn conflicts(a: &Change, b: &Change) -> bool {
+    match (target(a), target(b)) {
+        (Target::Node, _) | (_, Target::Node) => true,
+        (Target::Attributes(a), Target::Attributes(b)) => a.iter().any(|x| b.contains(x)),
+        (a, b) => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Change, Element, Key};
+
+    fn item(key: u64, class: &str, text: &str) -> Element {
+        Element::parent("li")
+            .key(Key::Local(key))
+            .attr("class", class)
+            .child(Element::text(Key::Local(0), text))
+            .build()
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let before = el!(ul[key=0, item(1, "a", "a"), item(2, "b", "b"), item(3, "c", "c")]);
+        let after = el!(ul[key=0, item(3, "c", "c"), item(1, "x", "a"), item(4, "d", "d")]);
+        let mut diff = before.diff(&after).unwrap();
+        diff.push_at(&[Key::Local(1)], Change::Focus(Key::Local(1)));
+        let graph = diff.dependencies();
+        let changes: Vec<(Vec<Key>, Change)> =
+            (0..graph.len()).map(|x| (graph.change(x).0.to_vec(), graph.change(x).1.clone())).collect();
+        let find = |path: &[Key], change: &Change| {
+            changes.iter().position(|x| x.0 == path && x.1 == *change).expect("change not in the graph")
+        };
+
+        let remove = find(&[], &Change::RemoveChild(Key::Local(2)));
+        let insert = find(&[], &Change::InsertChild(2, item(4, "d", "d")));
+        let class = find(&[Key::Local(1)], &Change::SetAttribute("class".to_string(), "x".to_string()));
+        let focus = find(&[Key::Local(1)], &Change::Focus(Key::Local(1)));
+        assert_eq!(focus, graph.len() - 1);
+        assert!(graph.ordered(remove, insert));
+        assert!(!graph.ordered(remove, class));
+        assert!(graph.ordered(class, focus));
+        assert_eq!(graph.depends_on(class), &[] as &[usize]);
+
+        let stages = graph.stages();
+        assert_eq!(stages.iter().map(|x| x.len()).sum::<usize>(), graph.len());
+        assert_eq!(stages.last(), Some(&vec![focus]));
+        assert!(stages[0].contains(&remove) && stages[0].contains(&class));
+    }
+}
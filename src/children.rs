@@ -0,0 +1,118 @@
+// A list of child elements that keeps its keymap in sync as it grows, so
+// iterator pipelines can produce the children of a Parent directly:
+//
+//     let children: Children = items.iter().map(view).collect();
+//     let list = children.into_parent(Key::Local(0), "ul");
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+
+use {Element, Flags, Key, Listeners};
+
+#[derive(Clone, Debug, Default)]
+pub struct Children {
+    elements: Vec<Element>,
+    keymap: BTreeMap<Key, usize>,
+}
+
+impl Children {
+    pub fn new() -> Children {
+        Children::default()
+    }
+
+    // Room for `capacity` children before the list grows. The keymap is a
+    // BTreeMap, which has no capacity to reserve.
+    pub fn with_capacity(capacity: usize) -> Children {
+        Children {
+            elements: Vec::with_capacity(capacity),
+            keymap: BTreeMap::new(),
+        }
+    }
+
+    // Appends a child. A key already in use keeps pointing at the first
+    // child with it, as diff expects of keymaps with duplicate keys.
+    pub fn push(&mut self, child: Element) {
+        self.keymap.entry(child.to_key()).or_insert(self.elements.len());
+        self.elements.push(child);
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn contains_key(&self, key: Key) -> bool {
+        self.keymap.contains_key(&key)
+    }
+
+    // A Parent element without attributes holding these children.
+    #[track_caller]
+    pub fn into_parent(self, key: Key, name: &str) -> Element {
+        Element::Parent {
+            key: key,
+            name: name.to_string(),
+            keymap: self.keymap,
+            attributes: None,
+            listeners: Listeners::new(),
+            children: self.elements,
+            flags: Flags::here(),
+        }
+    }
+
+    // The children and their matching keymap, for building a Parent with
+    // attributes or flags.
+    pub fn into_parts(self) -> (Vec<Element>, BTreeMap<Key, usize>) {
+        (self.elements, self.keymap)
+    }
+}
+
+impl FromIterator<Element> for Children {
+    fn from_iter<I>(iter: I) -> Children
+        where I: IntoIterator<Item = Element>
+    {
+        let mut children = Children::new();
+        children.extend(iter);
+        children
+    }
+}
+
+impl Extend<Element> for Children {
+    fn extend<I>(&mut self, iter: I)
+        where I: IntoIterator<Item = Element>
+    {
+        for child in iter {
+            self.push(child);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::Children;
+    use {Element, Key};
+
+    fn text(key: u64) -> Element {
+        Element::Text {
+            key: Key::Local(key),
+            value: key.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_collect_and_extend() {
+        let mut children: Children = (0..2).map(text).collect();
+        children.extend(vec![text(5)]);
+        assert_eq!(children.len(), 3);
+
+        let (elements, keymap) = children.into_parts();
+        let mut expected = BTreeMap::new();
+        expected.insert(Key::Local(0), 0);
+        expected.insert(Key::Local(1), 1);
+        expected.insert(Key::Local(5), 2);
+        assert_eq!(keymap, expected);
+        assert_eq!(elements[2].to_key(), Key::Local(5));
+    }
+}
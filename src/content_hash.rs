@@ -0,0 +1,185 @@
+// Content hashes for caching server-rendered pages: equal trees hash equal
+// on every run, platform and build, so a hash can be stored alongside a
+// response and compared with one a client already holds.
+//
+// The tree is hashed in a canonical byte form with Lazy nodes evaluated.
+// Every string is length-prefixed so that adjacent fields can't run into
+// each other. Listeners and flags are included, except where a node was
+// created, which is only debugging information. The hash is 128-bit
+// FNV-1a, which is fast and stable but not cryptographic; don't use it
+// where an attacker could benefit from a collision.
+//
+// Equal content doesn't make for equal patches: a clean Lazy node is never
+// evaluated against another of its key, and the text diff policy picks the
+// text changes. The diff hash, which keys VirtualDom's speculations, also
+// covers both.
+use {Element, Flags, Key, TextDiff};
+
+const OFFSET_BASIS: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+
+struct Fnv(u128);
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u128;
+            self.0 = self.0.wrapping_mul(PRIME);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_str(&mut self, value: &str) {
+        self.write_u64(value.len() as u64);
+        self.write(value.as_bytes());
+    }
+
+    fn write_key(&mut self, key: &Key) {
+        match *key {
+            Key::Local(value) => {
+                self.write(&[0]);
+                self.write_u64(value);
+            }
+            Key::Global(value) => {
+                self.write(&[1]);
+                self.write_u64(value);
+            }
+            Key::Uuid(ref bytes) => {
+                self.write(&[2]);
+                self.write(bytes);
+            }
+            Key::Str(ref value) => {
+                self.write(&[3]);
+                self.write_str(value);
+            }
+        }
+    }
+
+    fn write_flags(&mut self, flags: Flags) {
+        self.write(&[flags.observe_visibility as u8,
+                     flags.observe_resize as u8,
+                     flags.keep_until_transition_end as u8,
+                     flags.island as u8]);
+    }
+
+    fn write_text_diff(&mut self, policy: Option<TextDiff>) {
+        self.write(&[match policy {
+                         None => 0,
+                         Some(TextDiff::Replace) => 1,
+                         Some(TextDiff::Lines) => 2,
+                         Some(TextDiff::Chars) => 3,
+                     }]);
+    }
+}
+
+impl Element {
+    pub fn content_hash(&self) -> u128 {
+        let mut hasher = Fnv(OFFSET_BASIS);
+        hash(self, &mut hasher, false);
+        hasher.0
+    }
+
+    // The content hash, telling apart trees that diff differently against
+    // the same tree.
+    pub(crate) fn diff_hash(&self) -> u128 {
+        let mut hasher = Fnv(OFFSET_BASIS);
+        hash(self, &mut hasher, true);
+        hasher.0
+    }
+}
+
+fn hash(element: &Element, hasher: &mut Fnv, diffing: bool) {
+    if let (true, &Element::Lazy { dirty, .. }) = (diffing, element) {
+        hasher.write(&[b'l', dirty as u8]);
+    }
+    let element = element.force();
+    let view = element.view();
+    match view.text() {
+        Some(text) => {
+            hasher.write(b"t");
+            hasher.write_key(&view.key());
+            hasher.write_str(text);
+        }
+        None => {
+            hasher.write(b"e");
+            hasher.write_key(&view.key());
+            hasher.write_str(view.tag().unwrap_or(""));
+            hasher.write_flags(view.flags());
+            if diffing {
+                hasher.write_text_diff(view.flags().text_diff);
+            }
+            let attributes = view.attributes().into_iter().flat_map(|x| x.iter());
+            hasher.write_u64(view.attributes().map_or(0, |x| x.len()) as u64);
+            for (name, value) in attributes {
+                hasher.write_str(&name.name);
+                hasher.write_str(name.namespace.as_ref().map_or("", |x| &x[..]));
+                hasher.write_str(value);
+            }
+            let listeners = view.listeners().into_iter().flat_map(|x| x.iter());
+            hasher.write_u64(view.listeners().map_or(0, |x| x.len()) as u64);
+            for (event, handler) in listeners {
+                hasher.write_str(event);
+                hasher.write_u64(handler.0);
+            }
+            hasher.write_u64(view.children().len() as u64);
+            for child in view.children() {
+                hash(child.element(), hasher, diffing);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fnv;
+    use {AttrName, Element, Flags, HandlerId, Key, Listeners, Thunk};
+
+    fn text(value: &str) -> Element {
+        Element::Text {
+            key: Key::Local(0),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_fnv() {
+        let mut hasher = Fnv(super::OFFSET_BASIS);
+        hasher.write(b"a");
+        assert_eq!(hasher.0, 0xd228_cb69_6f1a_8caf_7891_2b70_4e4a_8964);
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let tree = el!(p[key=0, text("ab")]);
+        assert_eq!(tree.content_hash(), el!(p[key=0, text("ab")]).content_hash());
+        assert!(tree.content_hash() != el!(p[key=0, text("a"), text("b")]).content_hash());
+        assert!(tree.content_hash() != el!(p[key=1, text("ab")]).content_hash());
+
+        let lazy = el!(div[key=0, Element::Lazy {
+            key: Key::Local(0),
+            dirty: false,
+            thunk: Thunk::new(|| el!(p[key=0, text("ab")])),
+        }]);
+        assert_eq!(lazy.content_hash(), el!(div[key=0, el!(p[key=0, text("ab")])]).content_hash());
+
+        let void = |value: &str| {
+            Element::Void {
+                key: Key::Local(0),
+                name: "img".to_string(),
+                attributes: Some(Some((AttrName::new("alt"), value.to_string())).into_iter().collect()),
+                listeners: Listeners::new(),
+                flags: Flags::default(),
+            }
+        };
+        assert!(void("a").content_hash() != void("b").content_hash());
+        let listening = Element::void("img")
+            .key(Key::Local(0))
+            .attr("alt", "a")
+            .on("load", HandlerId(1))
+            .build();
+        assert!(listening.content_hash() != void("a").content_hash());
+    }
+}
@@ -0,0 +1,217 @@
+// Critical CSS extraction: the subset of a stylesheet that applies to a
+// tree, small enough to inline in a `<style>` element for first paint while
+// the full stylesheet loads.
+//
+// A style rule is kept with those of its selectors that match an element in
+// the tree. Pseudo-classes and pseudo-elements are ignored when matching, so
+// `a:hover` is kept whenever there is an `a`, and selectors the Selector
+// engine can't parse are kept to be safe. Rules inside `@media` blocks are
+// filtered the same way, keeping the block if any rule survives. Other
+// at-rules are dropped. Lazy nodes aren't rendered on first paint and are not
+// looked at.
+use selector::Selector;
+use {Children, Element, Key};
+
+pub fn critical_css(stylesheet: &str, tree: &Element) -> String {
+    let mut css = String::new();
+    extract(&strip_comments(stylesheet), tree, &mut css);
+    css
+}
+
+// A `<style>` element holding the critical CSS.
+pub fn critical_style(stylesheet: &str, tree: &Element, key: Key) -> Element {
+    let text = Element::Text {
+        key: Key::Local(0),
+        value: critical_css(stylesheet, tree),
+    };
+    Some(text).into_iter().collect::<Children>().into_parent(key, "style")
+}
+
+fn extract(stylesheet: &str, tree: &Element, css: &mut String) {
+    let mut rest = stylesheet;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return;
+        }
+        let open = match find_outside_strings(rest, |c| c == '{' || c == ';') {
+            Some(index) => index,
+            None => return,
+        };
+        if rest[open..].starts_with(';') {
+            // A statement at-rule such as @import or @charset.
+            rest = &rest[open + 1..];
+            continue;
+        }
+        let close = match matching_brace(rest, open) {
+            Some(index) => index,
+            None => return,
+        };
+        let prelude = rest[..open].trim();
+        let body = &rest[open + 1..close];
+        rest = &rest[close + 1..];
+
+        if prelude.starts_with("@media") {
+            let mut inner = String::new();
+            extract(body, tree, &mut inner);
+            if !inner.is_empty() {
+                css.push_str(&format!("{}{{{}}}", prelude, inner));
+            }
+        } else if !prelude.starts_with('@') {
+            let selectors: Vec<&str> = prelude.split(',')
+                .map(|x| x.trim())
+                .filter(|x| matches_tree(x, tree))
+                .collect();
+            if !selectors.is_empty() {
+                css.push_str(&format!("{}{{{}}}", selectors.join(","), body.trim()));
+            }
+        }
+    }
+}
+
+fn matches_tree(selector: &str, tree: &Element) -> bool {
+    let stripped = strip_pseudo(selector);
+    if stripped.trim().is_empty() {
+        // Such as `:root` or `::selection`.
+        return true;
+    }
+    match Selector::parse(stripped.trim()) {
+        Ok(selector) => !tree.select(&selector).is_empty(),
+        Err(_) => true,
+    }
+}
+
+// Removes pseudo-classes and pseudo-elements, arguments included.
+fn strip_pseudo(selector: &str) -> String {
+    let mut stripped = String::new();
+    let mut chars = selector.chars().peekable();
+    let mut in_brackets = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => in_brackets = true,
+            ']' => in_brackets = false,
+            ':' if !in_brackets => {
+                while chars.peek() == Some(&':') {
+                    chars.next();
+                }
+                while chars.peek().is_some_and(|&x| x.is_alphanumeric() || x == '-' || x == '_') {
+                    chars.next();
+                }
+                if chars.peek() == Some(&'(') {
+                    let mut depth = 0;
+                    for x in chars.by_ref() {
+                        match x {
+                            '(' => depth += 1,
+                            ')' => depth -= 1,
+                            _ => {}
+                        }
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                }
+                continue;
+            }
+            _ => {}
+        }
+        stripped.push(c);
+    }
+    stripped
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut stripped = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        stripped.push_str(&rest[..start]);
+        rest = match rest[start + 2..].find("*/") {
+            Some(end) => &rest[start + 2 + end + 2..],
+            None => "",
+        };
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+fn find_outside_strings<F>(css: &str, f: F) -> Option<usize>
+    where F: Fn(char) -> bool
+{
+    let mut quote = None;
+    for (index, c) in css.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if f(c) => return Some(index),
+            None => {}
+        }
+    }
+    None
+}
+
+// The index of the brace closing the one at `open`.
+fn matching_brace(css: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut offset = open;
+    loop {
+        let index = offset + find_outside_strings(&css[offset..], |c| c == '{' || c == '}')?;
+        if css[index..].starts_with('{') {
+            depth += 1;
+        } else {
+            depth -= 1;
+            if depth == 0 {
+                return Some(index);
+            }
+        }
+        offset = index + 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{critical_css, critical_style};
+    use parse::parse;
+    use ssr::render_stream;
+    use {AttrName, Attributes, Element, Flags, Key, Listeners};
+
+    #[test]
+    fn test_critical_css() {
+        let mut attributes = Attributes::new();
+        attributes.insert(AttrName::new("class"), "hero".to_string());
+        let tree = el!(body[
+            key=0,
+            Element::Void {
+                key: Key::Local(0),
+                name: "div".to_string(),
+                attributes: Some(attributes),
+                listeners: Listeners::new(),
+                flags: Flags::default(),
+            },
+            el!(a[key=1])
+        ]);
+        let stylesheet = "/* base */ @import url(x.css);
+            body { margin: 0 }
+            .hero, .footer { color: red; content: \"}\" }
+            table td { padding: 0 }
+            a:hover, a::after { color: blue }
+            @media (max-width: 600px) { .hero { font-size: 2em } p { margin: 0 } }
+            @media print { p { color: black } }
+            @font-face { font-family: x }";
+        assert_eq!(critical_css(stylesheet, &tree),
+                   "body{margin: 0}.hero{color: red; content: \"}\"}a:hover,a::after{color: blue}\
+                    @media (max-width: 600px){.hero{font-size: 2em}}");
+    }
+
+    #[test]
+    fn test_critical_style_round_trip() {
+        let tree = el!(ul[key=0, el!(li[key=1])]);
+        let style = critical_style("ul > li { color: red } p { margin: 0 }", &tree, Key::Local(0));
+        assert_eq!(style.to_html(), "<style>ul > li{color: red}</style>");
+        assert!(parse(&style.to_html()).unwrap().deep_eq(&style));
+
+        let mut out = vec![];
+        render_stream(&style, "", &mut out).unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains("<style data-rdk=\"0\">ul > li{color: red}</style>"), "{}", html);
+    }
+}
@@ -0,0 +1,346 @@
+// Interning of the strings that recur across a long-lived patch stream.
+//
+// Tag names, attribute names and class values are assigned an id once they
+// have been used `min_uses` times in a session, and later patches refer to
+// them by id. An encoded envelope carries the strings it assigns ids to:
+//
+//   {"version": 1, "define": {"0": "li", "1": "class"}, "patch": [...]}
+//
+// Within the patch, a tag name or class value that has an id is written as
+// the id, a number, in place of the string. An attribute name is an object
+// member name or a JSON Pointer segment, so it's written as "@" followed by
+// the id instead, and a literal name starting with "@" gets a second "@".
+//
+// Each side of the connection keeps a Dictionary for the session: the
+// sender encodes envelopes with its own, and the receiver decodes them with
+// its own in the order they were sent.
+use std::collections::BTreeMap;
+
+use rustc_serialize::json::{Json, ToJson};
+use Envelope;
+
+#[derive(Clone, Debug)]
+pub struct Dictionary {
+    ids: BTreeMap<String, u64>,
+    strings: BTreeMap<u64, String>,
+    // How often each string without an id has been used so far.
+    uses: BTreeMap<String, usize>,
+    min_uses: usize,
+    max_entries: usize,
+}
+
+impl Default for Dictionary {
+    fn default() -> Dictionary {
+        Dictionary::new()
+    }
+}
+
+impl Dictionary {
+    // Strings are assigned an id on their second use, up to 4096 of them.
+    pub fn new() -> Dictionary {
+        Dictionary {
+            ids: BTreeMap::new(),
+            strings: BTreeMap::new(),
+            uses: BTreeMap::new(),
+            min_uses: 2,
+            max_entries: 4096,
+        }
+    }
+
+    pub fn with_min_uses(mut self, min_uses: usize) -> Dictionary {
+        self.min_uses = min_uses;
+        self
+    }
+
+    // Once the dictionary is full, new strings are always sent literally.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Dictionary {
+        self.max_entries = max_entries;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&str> {
+        self.strings.get(&id).map(|x| &x[..])
+    }
+
+    // The encoded form of `envelope`, assigning ids to the strings that have
+    // now been used often enough.
+    pub fn encode(&mut self, envelope: &Envelope) -> Json {
+        let mut encoder = Encoder {
+            dictionary: self,
+            define: BTreeMap::new(),
+        };
+        let patch = envelope.operations
+            .iter()
+            .map(|x| map_operation(&mut encoder, &x.to_json()).expect("operations are objects"))
+            .collect();
+        let mut object = BTreeMap::new();
+        object.insert("version".to_string(), envelope.version.to_json());
+        if !encoder.define.is_empty() {
+            object.insert("define".to_string(), Json::Object(encoder.define));
+        }
+        object.insert("patch".to_string(), Json::Array(patch));
+        Json::Object(object)
+    }
+
+    // The plain form of an encoded envelope, as Envelope::to_json writes it,
+    // or None if it's malformed or refers to an id never defined.
+    pub fn decode(&mut self, json: &Json) -> Option<Json> {
+        if let Some(define) = json.find("define") {
+            for (id, value) in define.as_object()? {
+                let id = id.parse().ok()?;
+                let value = value.as_string()?;
+                self.ids.insert(value.to_string(), id);
+                self.strings.insert(id, value.to_string());
+            }
+        }
+        let mut decoder = Decoder(self);
+        let patch = json.find("patch")?
+            .as_array()?
+            .iter()
+            .map(|x| map_operation(&mut decoder, x))
+            .collect::<Option<Vec<_>>>()?;
+        let mut object = BTreeMap::new();
+        object.insert("version".to_string(), json.find("version")?.clone());
+        object.insert("patch".to_string(), Json::Array(patch));
+        Some(Json::Object(object))
+    }
+
+    fn intern(&mut self, value: &str, define: &mut BTreeMap<String, Json>) -> Option<u64> {
+        if let Some(&id) = self.ids.get(value) {
+            return Some(id);
+        }
+        if self.ids.len() >= self.max_entries {
+            return None;
+        }
+        let uses = {
+            let uses = self.uses.entry(value.to_string()).or_insert(0);
+            *uses += 1;
+            *uses
+        };
+        if uses < self.min_uses {
+            return None;
+        }
+        self.uses.remove(value);
+        let id = self.ids.len() as u64;
+        self.ids.insert(value.to_string(), id);
+        self.strings.insert(id, value.to_string());
+        define.insert(id.to_string(), value.to_json());
+        // Nothing more gets an id, so there's no point counting.
+        if self.ids.len() >= self.max_entries {
+            self.uses = BTreeMap::new();
+        }
+        Some(id)
+    }
+}
+
+// One direction of the mapping between plain and encoded strings.
+trait Strings {
+    // Whether this maps plain strings to encoded ones.
+    fn encodes(&self) -> bool;
+
+    // A tag name or class value.
+    fn value(&mut self, value: &Json) -> Option<Json>;
+
+    // An attribute name.
+    fn name(&mut self, name: &str) -> Option<String>;
+
+    // Whichever of a string before and after mapping is the plain one.
+    fn plain<'a>(&self, before: &'a str, after: &'a str) -> &'a str {
+        if self.encodes() { before } else { after }
+    }
+}
+
+struct Encoder<'a> {
+    dictionary: &'a mut Dictionary,
+    define: BTreeMap<String, Json>,
+}
+
+impl<'a> Strings for Encoder<'a> {
+    fn encodes(&self) -> bool {
+        true
+    }
+
+    fn value(&mut self, value: &Json) -> Option<Json> {
+        let id = match *value {
+            Json::String(ref value) => self.dictionary.intern(value, &mut self.define),
+            _ => None,
+        };
+        Some(id.map_or_else(|| value.clone(), |x| x.to_json()))
+    }
+
+    fn name(&mut self, name: &str) -> Option<String> {
+        Some(match self.dictionary.intern(name, &mut self.define) {
+            Some(id) => format!("@{}", id),
+            None if name.starts_with('@') => format!("@{}", name),
+            None => name.to_string(),
+        })
+    }
+}
+
+struct Decoder<'a>(&'a Dictionary);
+
+impl<'a> Strings for Decoder<'a> {
+    fn encodes(&self) -> bool {
+        false
+    }
+
+    fn value(&mut self, value: &Json) -> Option<Json> {
+        match value.as_u64() {
+            Some(id) => self.0.get(id).map(|x| x.to_json()),
+            None => Some(value.clone()),
+        }
+    }
+
+    fn name(&mut self, name: &str) -> Option<String> {
+        match name.strip_prefix('@') {
+            Some(rest) if rest.starts_with('@') => Some(rest.to_string()),
+            Some(id) => self.0.get(id.parse().ok()?).map(|x| x.to_string()),
+            None => Some(name.to_string()),
+        }
+    }
+}
+
+fn map_operation<S: Strings>(strings: &mut S, operation: &Json) -> Option<Json> {
+    let mut object = operation.as_object()?.clone();
+    if let Some(from) = object.get("from").cloned() {
+        object.insert("from".to_string(), map_path(strings, from.as_string()?)?.0.to_json());
+    }
+    let (path, class) = match object.get("path") {
+        Some(path) => map_path(strings, path.as_string()?)?,
+        None => return Some(Json::Object(object)),
+    };
+    object.insert("path".to_string(), path.to_json());
+    if let Some(value) = object.get("value").cloned() {
+        let value = if class {
+            strings.value(&value)?
+        } else {
+            map_element(strings, &value)?
+        };
+        object.insert("value".to_string(), value);
+    }
+    Some(Json::Object(object))
+}
+
+// Maps the attribute names in a JSON Pointer, and says whether it points at
+// a class attribute.
+fn map_path<S: Strings>(strings: &mut S, path: &str) -> Option<(String, bool)> {
+    let mut mapped = vec![];
+    let mut class = false;
+    let mut previous = None;
+    for segment in path.split('/') {
+        class = false;
+        if previous == Some("attributes") {
+            let name = strings.name(segment)?;
+            class = strings.plain(segment, &name) == "class";
+            mapped.push(name);
+            previous = None;
+        } else {
+            mapped.push(segment.to_string());
+            previous = Some(segment);
+        }
+    }
+    Some((mapped.join("/"), class))
+}
+
+// Maps the strings of the JSON form of an element. Any other value is left
+// as it is.
+fn map_element<S: Strings>(strings: &mut S, element: &Json) -> Option<Json> {
+    let mut object = match element.as_object() {
+        Some(object) if object.contains_key("type") => object.clone(),
+        _ => return Some(element.clone()),
+    };
+    if let Some(name) = object.get("name").cloned() {
+        object.insert("name".to_string(), strings.value(&name)?);
+    }
+    let attributes = match object.get("attributes") {
+        Some(&Json::Object(ref attributes)) => {
+            let mut mapped = BTreeMap::new();
+            for (name, value) in attributes {
+                let mapped_name = strings.name(name)?;
+                let value = if strings.plain(name, &mapped_name) == "class" {
+                    strings.value(value)?
+                } else {
+                    value.clone()
+                };
+                mapped.insert(mapped_name, value);
+            }
+            Some(Json::Object(mapped))
+        }
+        _ => None,
+    };
+    if let Some(attributes) = attributes {
+        object.insert("attributes".to_string(), attributes);
+    }
+    let children = match object.get("children") {
+        Some(&Json::Array(ref children)) => {
+            Some(children.iter().map(|x| map_element(strings, x)).collect::<Option<Vec<_>>>()?)
+        }
+        _ => None,
+    };
+    if let Some(children) = children {
+        object.insert("children".to_string(), Json::Array(children));
+    }
+    Some(Json::Object(object))
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::ToJson;
+    use super::Dictionary;
+    use {Element, Envelope, Key};
+
+    fn list(items: &[(u64, &str)]) -> Element {
+        Element::parent("ul")
+            .children(items.iter().map(|&(key, name)| {
+                Element::void("li").key(Key::Local(key)).attr("class", "item").attr(name, "x").build()
+            }))
+            .build()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut sender = Dictionary::new().with_min_uses(1);
+        let mut receiver = Dictionary::new();
+        let items = [(1, "title"), (2, "@click"), (3, "title")];
+        let mut sizes = vec![];
+        for len in 0..items.len() {
+            let (base, next) = (list(&items[..len]), list(&items[..len + 1]));
+            let envelope = Envelope::new(&base.diff(&next).unwrap(), &base).unwrap();
+            let encoded = sender.encode(&envelope);
+            assert_eq!(receiver.decode(&encoded), Some(envelope.to_json()));
+            sizes.push((encoded.to_string().len(), envelope.to_json().to_string().len()));
+        }
+        assert!(sizes[2].0 < sizes[2].1);
+        assert_eq!(receiver.len(), sender.len());
+        assert_eq!(receiver.get(0), Some("li"));
+
+        // Ids are only known from the envelope that defined them.
+        let encoded = Dictionary::new().with_min_uses(1).encode(&Envelope::new(
+            &list(&[]).diff(&list(&items[..1])).unwrap(),
+            &list(&[])).unwrap());
+        let mut stripped = encoded.as_object().unwrap().clone();
+        assert!(stripped.remove("define").is_some());
+        assert_eq!(Dictionary::new().decode(&stripped.to_json()), None);
+    }
+
+    #[test]
+    fn test_min_uses() {
+        let mut dictionary = Dictionary::new();
+        let envelope = Envelope::new(&list(&[]).diff(&list(&[(1, "id")])).unwrap(), &list(&[])).unwrap();
+        assert!(dictionary.encode(&envelope).find("define").is_none());
+        assert!(dictionary.encode(&envelope).find("define").is_some());
+        assert_eq!(dictionary.len(), 4);
+
+        let mut dictionary = Dictionary::new().with_min_uses(1).with_max_entries(2);
+        dictionary.encode(&envelope);
+        assert_eq!(dictionary.len(), 2);
+    }
+}
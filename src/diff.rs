@@ -0,0 +1,1054 @@
+// Computing the patch between two element trees.
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use diff_ref::{DiffOutput, Shape};
+use text_diff::text_change;
+use Element::*;
+use {AttrName, Attributes, Change, DiffConfig, DiffError, DiffRef, DiffTree, DomError, Element, Flags, Key,
+     Listeners};
+
+impl Element {
+    pub fn diff(&self, other: &Element) -> Option<DiffTree> {
+        self.diff_with(other, &DiffConfig::default())
+    }
+
+    // Like diff, with the reconciliation heuristics taken from `config`.
+    pub fn diff_with(&self, other: &Element, config: &DiffConfig) -> Option<DiffTree> {
+        self.try_diff_with(other, config).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    // Like diff, but returns an error instead of panicking when a Parent's
+    // keymap doesn't match its children, as happens when a caller-built tree
+    // has a stale keymap.
+    pub fn try_diff(&self, other: &Element) -> Result<Option<DiffTree>, DiffError> {
+        self.try_diff_with(other, &DiffConfig::default())
+    }
+
+    // Diffs the subtree at `path`, the keys of the children from the root
+    // down, against `other_subtree`, leaving the rest of the tree alone. The
+    // patch is addressed at `path` so it applies to the whole tree; it is
+    // empty if the subtree is unchanged. Lazy nodes along the path are not
+    // evaluated, so a path through one isn't found.
+    pub fn diff_at(&self, path: &[Key], other_subtree: &Element) -> Result<DiffTree, DomError> {
+        let subtree = self.get_path(path).ok_or_else(|| DomError::NoSuchNode(path.to_vec()))?;
+        let diff = subtree.try_diff(other_subtree)?.unwrap_or(DiffTree {
+            changes: None,
+            children: None,
+            base_checksum: None,
+        });
+        if diff.is_empty() {
+            return Ok(diff);
+        }
+        Ok(path.iter().rev().fold(diff, |diff, key| {
+            DiffTree {
+                changes: None,
+                children: Some(Box::new([(key.clone(), diff)])),
+                base_checksum: None,
+            }
+        }))
+    }
+
+    pub fn try_diff_with(&self,
+                         other: &Element,
+                         config: &DiffConfig)
+                         -> Result<Option<DiffTree>, DiffError> {
+        let mut diff: Option<DiffTree> = self.diff_node(other, config, None)?;
+        if let Some(ref mut diff) = diff {
+            reparent(diff, self, config)?;
+        }
+        Ok(diff)
+    }
+
+    // Like diff, also recording how long the diff of each child of the root
+    // kept on both sides took, in ascending key order, for DiffStats.
+    pub(crate) fn diff_timed(&self, other: &Element, timings: &mut Vec<(Key, Duration)>) -> Option<DiffTree> {
+        let config = DiffConfig::default();
+        let mut diff: Option<DiffTree> =
+            self.diff_node(other, &config, Some(timings)).unwrap_or_else(|error| panic!("{}", error));
+        if let Some(ref mut diff) = diff {
+            reparent(diff, self, &config).unwrap_or_else(|error| panic!("{}", error));
+        }
+        diff
+    }
+
+    // Like try_diff_with, borrowing the nodes the patch inserts from `other`
+    // rather than copying them, see DiffRef.
+    pub fn diff_ref<'a>(&'a self,
+                        other: &'a Element,
+                        config: &DiffConfig)
+                        -> Result<Option<DiffRef<'a>>, DiffError> {
+        let mut diff: Option<DiffRef> = self.diff_node(other, config, None)?;
+        if let Some(ref mut diff) = diff {
+            reparent(diff, self, config)?;
+        }
+        Ok(diff)
+    }
+
+    // The diff as a DiffTree or DiffRef, before nodes moved to another
+    // parent are paired up.
+    pub(crate) fn diff_node<'a, O: DiffOutput<'a>>(&self,
+                                                   other: &'a Element,
+                                                   config: &DiffConfig,
+                                                   mut timings: Option<&mut Vec<(Key, Duration)>>)
+                                                   -> Result<Option<O>, DiffError> {
+        Ok(match (self, other) {
+            (&Lazy { key: ref left, .. }, &Lazy { key: ref right, dirty: false, .. }) if left == right => {
+                None
+            }
+            // The evaluated nodes don't outlive the diff, so it owns them.
+            (&Lazy { .. }, _) | (_, &Lazy { .. }) => {
+                let (left, right) = (self.force(), other.force());
+                let diff: Option<DiffTree> = left.diff_node(&right, config, timings)?;
+                diff.map(O::from_owned)
+            }
+            (&Text { value: ref left, .. }, &Text { value: ref right, .. }) => {
+                if !config.same_text(left, right) {
+                    Some(O::new(vec![O::change(text_change(left, right, config.text_diff))], vec![]))
+                } else {
+                    None
+                }
+            }
+            (&Void { name: ref left,
+                     attributes: ref left_attributes,
+                     listeners: ref left_listeners,
+                     flags: ref left_flags,
+                     .. },
+             &Void { ref key,
+                     name: ref right,
+                     attributes: ref right_attributes,
+                     listeners: ref right_listeners,
+                     flags: ref right_flags }) => {
+                let mut changes = vec![];
+                if left == right &&
+                   diff_attributes(left_attributes, right_attributes, config, &mut changes) {
+                    diff_listeners(key, left_listeners, right_listeners, &mut changes);
+                    diff_flags(key, left_flags, right_flags, &mut changes);
+                    if changes.is_empty() {
+                        None
+                    } else {
+                        Some(O::new(changes.into_iter().map(O::change).collect(), vec![]))
+                    }
+                } else {
+                    Some(replace(other))
+                }
+            }
+            (&Parent { key: ref left_key,
+                       name: ref left,
+                       children: ref left_children,
+                       keymap: ref left_keymap,
+                       attributes: ref left_attributes,
+                       listeners: ref left_listeners,
+                       flags: ref left_flags },
+             &Parent { ref key,
+                       name: ref right,
+                       children: ref right_children,
+                       keymap: ref right_keymap,
+                       attributes: ref right_attributes,
+                       listeners: ref right_listeners,
+                       flags: ref right_flags }) if left == right => {
+                check_keymap(left_key, left_keymap, left_children)?;
+                check_keymap(key, right_keymap, right_children)?;
+                let mut changes = vec![];
+                if !diff_attributes(left_attributes, right_attributes, config, &mut changes) {
+                    return Ok(Some(replace(other)));
+                }
+                diff_listeners(key, left_listeners, right_listeners, &mut changes);
+                diff_flags(key, left_flags, right_flags, &mut changes);
+                let inherited;
+                let config = match right_flags.text_diff {
+                    Some(policy) if policy != config.text_diff => {
+                        inherited = DiffConfig { text_diff: policy, ..*config };
+                        &inherited
+                    }
+                    _ => config,
+                };
+                if left_keymap.len() < left_children.len() || right_keymap.len() < right_children.len() {
+                    return diff_duplicates(left_children, right_children, changes, config, other);
+                }
+                let mut child_changes = vec![];
+                let mut removed = 0;
+                let mut inserted = 0;
+
+                for (key, &value) in left_keymap.iter() {
+                    if let Some(value_) = config.position(right_keymap, right_children, key) {
+                        let start = timings.as_ref().map(|_| Instant::now());
+                        let child_tree =
+                            left_children[value].diff_node(&right_children[value_], config, None)?;
+                        if let (Some(timings), Some(start)) = (timings.as_mut(), start) {
+                            timings.push((key.clone(), start.elapsed()));
+                        }
+                        if let Some(child_tree) = child_tree {
+                            child_changes.push((key.clone(), child_tree));
+                        }
+                    } else {
+                        if left_children[value].view().flags().keep_until_transition_end {
+                            changes.push(Change::MarkExiting(key.clone()));
+                        } else {
+                            changes.push(Change::RemoveChild(key.clone()));
+                        }
+                        removed += 1;
+                    }
+                }
+                // Kept children, in their order on each side.
+                let mut left_kept = vec![];
+                let mut right_kept = vec![];
+                for child in left_children.iter() {
+                    if config.position(right_keymap, right_children, &child.to_key()).is_some() {
+                        left_kept.push(child.to_key());
+                    }
+                }
+                let mut inserts = vec![];
+                for (index, child) in right_children.iter().enumerate() {
+                    if config.position(left_keymap, left_children, &child.to_key()).is_some() {
+                        right_kept.push(child.to_key());
+                    } else {
+                        inserts.push(O::insert_child(index, child));
+                        inserted += 1;
+                    }
+                }
+                let churn = (removed + inserted) as f64;
+                let total = (left_children.len() + right_children.len()) as f64;
+                if total > 0.0 && churn / total > config.replace_threshold {
+                    return Ok(Some(replace(other)));
+                }
+                if left_kept != right_kept {
+                    moves(&left_kept, &right_kept, &mut changes);
+                }
+                let mut changes: Vec<O::Change> = changes.into_iter().map(O::change).collect();
+                changes.extend(inserts);
+
+                if changes.is_empty() && child_changes.is_empty() {
+                    None
+                } else {
+                    Some(O::new(changes, child_changes))
+                }
+            }
+            _ => Some(replace(other)),
+        })
+    }
+}
+
+fn replace<'a, O: DiffOutput<'a>>(other: &'a Element) -> O {
+    O::new(vec![O::replace_node(other)], vec![])
+}
+
+// Checks that every entry of a Parent's keymap points at the child with that
+// key, so that indexing `children` with it is safe and correct. A key shared
+// by several children must point at the first of them.
+pub(crate) fn check_keymap(parent: &Key,
+                           keymap: &BTreeMap<Key, usize>,
+                           children: &[Element])
+                           -> Result<(), DiffError> {
+    if keymap.len() != children.len() {
+        return check_shared_keymap(parent, keymap, children);
+    }
+    for (key, &index) in keymap {
+        if children.get(index).map(|child| child.to_key()).as_ref() != Some(key) {
+            return Err(DiffError::StaleKeymap {
+                parent: parent.clone(),
+                key: key.clone(),
+                index: index,
+            });
+        }
+    }
+    Ok(())
+}
+
+// The slow path of check_keymap, for a keymap with fewer entries than there
+// are children, which is only right if some of them share keys.
+fn check_shared_keymap(parent: &Key,
+                       keymap: &BTreeMap<Key, usize>,
+                       children: &[Element])
+                       -> Result<(), DiffError> {
+    let mut first = BTreeMap::new();
+    for (index, child) in children.iter().enumerate() {
+        first.entry(child.to_key()).or_insert(index);
+    }
+    if keymap.len() != first.len() {
+        return Err(DiffError::KeymapLength {
+            parent: parent.clone(),
+            keymap: keymap.len(),
+            children: children.len(),
+        });
+    }
+    for (key, &index) in keymap {
+        if first.get(key) != Some(&index) {
+            return Err(DiffError::StaleKeymap {
+                parent: parent.clone(),
+                key: key.clone(),
+                index: index,
+            });
+        }
+    }
+    Ok(())
+}
+
+// Diffs the children of a Parent where some siblings share a key, as built
+// from data with repeated ids. Changes addressed to a shared key apply to
+// the first child with it, so the first child with each key is matched by
+// key and the rest by position. Patches have no way to address those, so
+// the parent is replaced, with `changes` dropped, if any of them changed or
+// the children were added, removed or reordered at all. Such trees are a
+// bug in the caller, which Element::validate reports, as does a VirtualDom
+// to its Telemetry in debug builds.
+fn diff_duplicates<'a, O: DiffOutput<'a>>(left: &[Element],
+                                          right: &'a [Element],
+                                          changes: Vec<Change>,
+                                          config: &DiffConfig,
+                                          other: &'a Element)
+                                          -> Result<Option<O>, DiffError> {
+    if !left.iter().map(Element::to_key).eq(right.iter().map(Element::to_key)) {
+        return Ok(Some(replace(other)));
+    }
+    let mut seen = BTreeSet::new();
+    let mut child_changes = vec![];
+    for (left, right) in left.iter().zip(right) {
+        let first = seen.insert(left.to_key());
+        match left.diff_node(right, config, None)? {
+            Some(diff) if first => child_changes.push((left.to_key(), diff)),
+            Some(_) => return Ok(Some(replace(other))),
+            None => {}
+        }
+    }
+    child_changes.sort_by_key(|&(ref key, _)| key.clone());
+    if changes.is_empty() && child_changes.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(O::new(changes.into_iter().map(O::change).collect(), child_changes)))
+}
+
+// Turns each RemoveChild or MarkExiting of a child with a global key, paired
+// with the one InsertChild of a node with that key elsewhere in `diff`, into
+// a MoveNode, with the diff of the node as it was in `base` against the one
+// inserted. Nodes moved out of a moved node are found in that node's diff,
+// so this repeats until nothing more pairs up. Nodes behind Lazy ones in
+// `base`, and ones whose tag changed, are left to be inserted anew.
+fn reparent<'a, O: DiffOutput<'a>>(diff: &mut O,
+                                   base: &Element,
+                                   config: &DiffConfig)
+                                   -> Result<(), DiffError> {
+    // Evaluated only if there is something to pair.
+    let mut forced = None;
+    let mut moved = false;
+    loop {
+        let mut removals = vec![];
+        let mut inserts = vec![];
+        collect_globals(diff, &mut vec![], &[], &mut removals, &mut inserts);
+        if removals.is_empty() || inserts.is_empty() {
+            break;
+        }
+        let base: &Element = forced.get_or_insert_with(|| base.force());
+        let mut paired = false;
+        for (key, at, from) in removals {
+            let mut matching = inserts.iter().filter(|&&(ref x, _, _)| *x == key);
+            let (parent, index) = match (matching.next(), matching.next()) {
+                (Some(&(_, ref parent, index)), None) => (parent.clone(), index),
+                _ => continue,
+            };
+            let old = match base.get_path(&from).and_then(|x| x.get_child(&key)) {
+                Some(old) => old,
+                None => continue,
+            };
+            let child_diff = O::diff_inserted(old, &node_mut(diff, &parent).changes()[index], config)?;
+            let replaced = child_diff.as_ref().is_some_and(|x| {
+                x.changes().iter().any(|x| matches!(O::shape(x), Shape::Replace))
+            });
+            if replaced {
+                continue;
+            }
+
+            let node = node_mut(diff, &at);
+            let changes = node.take_changes();
+            node.set_changes(changes.into_iter()
+                .filter(|x| match O::shape(x) {
+                    Shape::Other(&Change::RemoveChild(ref x)) |
+                    Shape::Other(&Change::MarkExiting(ref x)) => *x != key,
+                    _ => true,
+                })
+                .collect());
+
+            let node = node_mut(diff, &parent);
+            let mut changes = node.take_changes();
+            let position = match O::shape(&changes[index]) {
+                Shape::Insert(position, _) => position,
+                _ => unreachable!(),
+            };
+            changes[index] = O::change(Change::MoveNode {
+                key: key.clone(),
+                from: from.into_boxed_slice(),
+                index: position,
+            });
+            node.set_changes(changes);
+            if let Some(child_diff) = child_diff {
+                let mut children = node.take_children();
+                let position = children.binary_search_by_key(&&key, |&(ref x, _)| x).unwrap_or_else(|x| x);
+                children.insert(position, (key, child_diff));
+                node.set_children(children);
+            }
+            paired = true;
+            moved = true;
+            // The paths collected may have changed.
+            break;
+        }
+        if !paired {
+            break;
+        }
+    }
+    if moved {
+        prune(diff);
+    }
+    Ok(())
+}
+
+// Collects the RemoveChild and MarkExiting changes of children with global
+// keys, with the path of their node in `diff` and in the tree it applies to,
+// and the InsertChild changes of them, with the path of their node and their
+// index among its changes. `from` is the path of `diff` in the tree it
+// applies to, which differs from `path` below moved nodes.
+fn collect_globals<'a, O: DiffOutput<'a>>(diff: &O,
+                                          path: &mut Vec<Key>,
+                                          from: &[Key],
+                                          removals: &mut Vec<(Key, Vec<Key>, Vec<Key>)>,
+                                          inserts: &mut Vec<(Key, Vec<Key>, usize)>) {
+    let changes = diff.changes();
+    for (index, change) in changes.iter().enumerate() {
+        match O::shape(change) {
+            Shape::Other(&Change::RemoveChild(ref key @ Key::Global(_))) |
+            Shape::Other(&Change::MarkExiting(ref key @ Key::Global(_))) => {
+                removals.push((key.clone(), path.clone(), from.to_vec()))
+            }
+            Shape::Insert(_, element) => {
+                if let Key::Global(_) = element.to_key() {
+                    inserts.push((element.to_key(), path.clone(), index));
+                }
+            }
+            _ => {}
+        }
+    }
+    for &(ref key, ref child) in diff.children() {
+        let mut child_from = changes.iter()
+            .filter_map(|x| match O::shape(x) {
+                Shape::Other(&Change::MoveNode { key: ref x, ref from, .. }) if x == key => {
+                    Some(from.to_vec())
+                }
+                _ => None,
+            })
+            .next()
+            .unwrap_or_else(|| from.to_vec());
+        child_from.push(key.clone());
+        path.push(key.clone());
+        collect_globals(child, path, &child_from, removals, inserts);
+        path.pop();
+    }
+}
+
+// The node of the diff at `path`, which exists.
+fn node_mut<'a, 'd, O: DiffOutput<'a>>(diff: &'d mut O, path: &[Key]) -> &'d mut O {
+    path.iter().fold(diff, |node, key| {
+        let children = node.children_mut();
+        let index = children.iter().position(|&(ref x, _)| x == key).expect("no such node in the diff");
+        &mut children[index].1
+    })
+}
+
+// Drops the nodes left without changes.
+fn prune<'a, O: DiffOutput<'a>>(diff: &mut O) {
+    let mut children = diff.take_children();
+    for &mut (_, ref mut child) in &mut children {
+        prune(child);
+    }
+    children.retain(|&(_, ref child)| !child.changes().is_empty() || !child.children().is_empty());
+    diff.set_children(children);
+}
+
+// Pushes the MoveChild changes putting the kept children, in their order
+// `left` once removals are done, in the order `right`. The longest run of
+// children already in order stays put, and every other child is moved, in
+// the order of `right`, to just after the child preceding it there, so each
+// index is one in the list as it is when that move applies.
+fn moves(left: &[Key], right: &[Key], changes: &mut Vec<Change>) {
+    let target: BTreeMap<Key, usize> =
+        right.iter().enumerate().map(|(index, key)| (key.clone(), index)).collect();
+    let positions: Vec<usize> = left.iter().map(|key| target[key]).collect();
+    let stays = longest_increasing(&positions);
+    let staying: BTreeMap<Key, bool> = left.iter().cloned().zip(stays).collect();
+
+    let mut current = left.to_vec();
+    for (index, key) in right.iter().enumerate() {
+        if staying[key] {
+            continue;
+        }
+        let from = current.iter().position(|x| x == key).unwrap();
+        let to = match index {
+            0 => 0,
+            _ => current.iter().position(|x| *x == right[index - 1]).unwrap() + 1,
+        };
+        if from == to {
+            continue;
+        }
+        current.remove(from);
+        let to = if from < to { to - 1 } else { to };
+        current.insert(to, key.clone());
+        changes.push(Change::MoveChild(key.clone(), to));
+    }
+}
+
+// Marks the members of a longest strictly increasing subsequence of
+// `values`, in O(n log n).
+fn longest_increasing(values: &[usize]) -> Vec<bool> {
+    // The index of the last value of the best subsequence of each length,
+    // and the index of the value before each one in its subsequence.
+    let mut tails: Vec<usize> = vec![];
+    let mut previous = vec![None; values.len()];
+    for (index, &value) in values.iter().enumerate() {
+        let length = match tails.binary_search_by(|&x| values[x].cmp(&value)) {
+            Ok(length) | Err(length) => length,
+        };
+        if length > 0 {
+            previous[index] = Some(tails[length - 1]);
+        }
+        if length == tails.len() {
+            tails.push(index);
+        } else {
+            tails[length] = index;
+        }
+    }
+    let mut members = vec![false; values.len()];
+    let mut next = tails.last().cloned();
+    while let Some(index) = next {
+        members[index] = true;
+        next = previous[index];
+    }
+    members
+}
+
+// Pushes the changes turning `left` into `right`, a missing map being the
+// same as an empty one. Returns false if some changed attribute is in a
+// namespace its name doesn't imply, which the changes can't express.
+fn diff_attributes(left: &Option<Attributes>,
+                   right: &Option<Attributes>,
+                   config: &DiffConfig,
+                   changes: &mut Vec<Change>)
+                   -> bool {
+    let empty = Attributes::new();
+    let left = left.as_ref().unwrap_or(&empty);
+    let right = right.as_ref().unwrap_or(&empty);
+    let implied = |name: &AttrName| AttrName::from(&name.name[..]) == *name;
+    let mut remove = vec![];
+    for name in left.keys() {
+        if !right.contains_key(name) {
+            if !implied(name) {
+                return false;
+            }
+            remove.push(name.name.clone());
+        }
+    }
+    let mut set = vec![];
+    for (name, value) in right {
+        if left.get(name) != Some(value) {
+            if !implied(name) {
+                return false;
+            }
+            set.push((name.name.clone(), value.clone()));
+        }
+    }
+    if set.len() + remove.len() > config.bulk_attributes {
+        changes.push(Change::UpdateAttributes {
+            set: set,
+            remove: remove,
+        });
+    } else {
+        changes.extend(remove.into_iter().map(Change::RemoveAttribute));
+        changes.extend(set.into_iter().map(|(name, value)| Change::SetAttribute(name, value)));
+    }
+    true
+}
+
+// Removed listeners come first, then those added or given another handler.
+fn diff_listeners(key: &Key, left: &Listeners, right: &Listeners, changes: &mut Vec<Change>) {
+    for name in left.keys() {
+        if !right.contains_key(name) {
+            changes.push(Change::RemoveListener(key.clone(), name.clone()));
+        }
+    }
+    for (name, &handler) in right {
+        if left.get(name) != Some(&handler) {
+            changes.push(Change::AddListener(key.clone(), name.clone(), handler));
+        }
+    }
+}
+
+fn diff_flags(key: &Key, left: &Flags, right: &Flags, changes: &mut Vec<Change>) {
+    if left.observe_visibility != right.observe_visibility {
+        if right.observe_visibility {
+            changes.push(Change::ObserveVisibility(key.clone()));
+        } else {
+            changes.push(Change::UnobserveVisibility(key.clone()));
+        }
+    }
+    if left.observe_resize != right.observe_resize {
+        if right.observe_resize {
+            changes.push(Change::ObserveResize(key.clone()));
+        } else {
+            changes.push(Change::UnobserveResize(key.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use {AttrName, Attributes, Change, Children, DiffConfig, DiffError, DiffTree, Element, HandlerId, Key,
+         TextDiff, Thunk};
+
+    #[test]
+    fn test_remove_single() {
+        let left = el!(div[
+            key=0,
+            el!(div[key=1]),
+            el!(div[key=2]),
+            el!(div[key=3])
+        ]);
+        let right = el!(div[
+            key=0,
+            el!(div[key=1]),
+            el!(div[key=2])
+        ]);
+        let diff = left.diff(&right);
+
+        assert_eq!(diff, Some(DiffTree{
+            changes: Some(vec![
+                Change::RemoveChild(Key::Local(3)),
+            ].into_boxed_slice()),
+            children: None,
+            base_checksum: None,
+        }));
+    }
+
+    #[test]
+    fn test_remove_many() {
+        let left = el!(div[
+            key=0,
+            el!(div[key=1]),
+            el!(div[key=2]),
+            el!(div[key=3])
+        ]);
+
+        let right = el!(div[
+            key=0,
+            el!(div[key=1])
+        ]);
+        let diff = left.diff(&right);
+
+        assert_eq!(diff, Some(DiffTree{
+            changes: Some(vec![
+                Change::RemoveChild(Key::Local(2)),
+                Change::RemoveChild(Key::Local(3)),
+            ].into_boxed_slice()),
+            children: None,
+            base_checksum: None,
+        }));
+    }
+
+    #[test]
+    fn test_nested_remove() {
+        let left = el!(div[
+            key=0,
+            el!(div[
+                key=0,
+                el!(div[])
+            ])
+        ]);
+
+        let right = el!(div[
+            key=0,
+            el!(div[])
+        ]);
+
+        let diff = left.diff(&right);
+
+        assert_eq!(diff, Some(DiffTree{
+            changes: None,
+            children: Some(vec![
+                (Key::Local(0), DiffTree{
+                    changes: Some(vec![
+                        Change::ReplaceNode(el!(div[]))
+                    ].into_boxed_slice()),
+                    children: None,
+                    base_checksum: None,
+                })
+            ].into_boxed_slice()),
+            base_checksum: None,
+        }));
+    }
+
+    #[test]
+    fn test_insert_single() {
+        let left = el!(div[
+            key=0,
+            el!(div[key=1]),
+            el!(div[key=2])
+        ]);
+
+        let right = el!(div[
+            key=0,
+            el!(div[key=0]),
+            el!(div[key=1]),
+            el!(div[key=2])
+        ]);
+
+        let diff = left.diff(&right);
+
+        assert_eq!(diff, Some(DiffTree{
+            changes: Some(vec![
+                Change::InsertChild(0, el!(div[key=0])),
+            ].into_boxed_slice()),
+            children: None,
+            base_checksum: None,
+        }));
+    }
+
+    #[test]
+    fn test_move_children() {
+        let left = el!(ul[key=0, el!(li[key=1]), el!(li[key=2]), el!(li[key=3])]);
+        let right = el!(ul[key=0, el!(li[key=4]), el!(li[key=2]), el!(li[key=5]), el!(li[key=1])]);
+        let diff = left.diff(&right).unwrap();
+        assert_eq!(diff.changes, Some(vec![
+            Change::RemoveChild(Key::Local(3)),
+            Change::MoveChild(Key::Local(1), 1),
+            Change::InsertChild(0, el!(li[key=4])),
+            Change::InsertChild(2, el!(li[key=5])),
+        ].into_boxed_slice()));
+
+        // Moving one item of a long list moves just that one.
+        let list = |keys: &[u64]| {
+            keys.iter().map(|&key| el!(li[key=key])).collect::<Children>().into_parent(Key::Local(0), "ul")
+        };
+        let mut keys: Vec<u64> = (0..100).collect();
+        let before = list(&keys);
+        let key = keys.remove(10);
+        keys.insert(90, key);
+        assert_eq!(before.diff(&list(&keys)).unwrap().changes,
+                   Some(vec![Change::MoveChild(Key::Local(10), 90)].into_boxed_slice()));
+
+        // Shuffles, with removals and inserts, patch into place.
+        let mut seed = 7u64;
+        for _ in 0..50 {
+            let mut next: Vec<u64> = (0..20).filter(|x| (x ^ seed) & 3 != 0).collect();
+            for index in (1..next.len()).rev() {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                next.swap(index, (seed >> 33) as usize % (index + 1));
+            }
+            next.push(20 + seed % 5);
+            let after = list(&next);
+            let mut patched = before.clone();
+            patched.apply(&before.diff(&after).unwrap()).unwrap();
+            assert!(patched.deep_eq(&after));
+            assert_eq!(patched.view().children().map(|x| x.key()).collect::<Vec<_>>(),
+                       after.view().children().map(|x| x.key()).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_observe_visibility() {
+        let left = el!(img[key=1]);
+        let mut right = el!(img[key=1]);
+        if let Element::Void { ref mut flags, .. } = right {
+            flags.observe_visibility = true;
+        }
+
+        assert_eq!(left.diff(&right), Some(DiffTree{
+            changes: Some(vec![
+                Change::ObserveVisibility(Key::Local(1)),
+            ].into_boxed_slice()),
+            children: None,
+            base_checksum: None,
+        }));
+        assert_eq!(right.diff(&left), Some(DiffTree{
+            changes: Some(vec![
+                Change::UnobserveVisibility(Key::Local(1)),
+            ].into_boxed_slice()),
+            children: None,
+            base_checksum: None,
+        }));
+    }
+
+    #[test]
+    fn test_observe_resize() {
+        let left = el!(div[key=0, el!(div[key=1])]);
+        let mut right = el!(div[key=0, el!(div[key=1])]);
+        if let Element::Parent { ref mut flags, .. } = right {
+            flags.observe_resize = true;
+        }
+
+        assert_eq!(left.diff(&right), Some(DiffTree{
+            changes: Some(vec![
+                Change::ObserveResize(Key::Local(0)),
+            ].into_boxed_slice()),
+            children: None,
+            base_checksum: None,
+        }));
+    }
+
+    #[test]
+    fn test_reparent() {
+        let global = |key: u64, value: &str| Element::text(Key::Global(key), value);
+        let nested = Element::parent("li").key(Key::Global(7)).child(global(8, "a")).build();
+        let left = el!(div[
+            key=0,
+            el!(ul[key=1, el!(li[key=2]), nested]),
+            el!(ol[key=3, el!(li[key=4])])
+        ]);
+        let right = el!(div[
+            key=0,
+            el!(ul[key=1, el!(li[key=2]), global(8, "b")]),
+            el!(ol[key=3, el!(li[key=4]), Element::parent("li").key(Key::Global(7)).build()])
+        ]);
+        let diff = left.diff(&right).unwrap();
+
+        assert_eq!(diff, DiffTree {
+            changes: None,
+            children: Some(vec![
+                (Key::Local(1), DiffTree {
+                    changes: Some(vec![Change::MoveNode {
+                        key: Key::Global(8),
+                        from: vec![Key::Local(1), Key::Global(7)].into_boxed_slice(),
+                        index: 1,
+                    }].into_boxed_slice()),
+                    children: Some(vec![
+                        (Key::Global(8), DiffTree {
+                            changes: Some(vec![Change::UpdateText("b".to_string())].into_boxed_slice()),
+                            children: None,
+                            base_checksum: None,
+                        }),
+                    ].into_boxed_slice()),
+                    base_checksum: None,
+                }),
+                (Key::Local(3), DiffTree {
+                    changes: Some(vec![Change::MoveNode {
+                        key: Key::Global(7),
+                        from: vec![Key::Local(1)].into_boxed_slice(),
+                        index: 1,
+                    }].into_boxed_slice()),
+                    children: None,
+                    base_checksum: None,
+                }),
+            ].into_boxed_slice()),
+            base_checksum: None,
+        });
+
+        let mut patched = left.clone();
+        patched.apply(&diff).unwrap();
+        assert!(patched.deep_eq(&right));
+
+        // A node whose tag changed is inserted anew.
+        let right = el!(div[
+            key=0,
+            el!(ul[key=1, el!(li[key=2])]),
+            el!(ol[key=3, el!(li[key=4]), global(7, "c")])
+        ]);
+        let diff = left.diff(&right).unwrap();
+        match diff.children.as_ref().unwrap()[1].1.changes.as_ref().unwrap()[0] {
+            Change::InsertChild(1, _) => {}
+            ref change => panic!("unexpected change {:?}", change),
+        }
+    }
+
+    #[test]
+    fn test_lazy() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let lazy = |dirty: bool, value: &'static str| {
+            let calls = calls.clone();
+            Element::Lazy {
+                key: Key::Local(1),
+                dirty: dirty,
+                thunk: Thunk::new(move || {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    Element::Text { key: Key::Local(1), value: value.to_string() }
+                }),
+            }
+        };
+
+        assert_eq!(lazy(false, "a").diff(&lazy(false, "b")), None);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        assert_eq!(lazy(false, "a").diff(&lazy(true, "b")), Some(DiffTree{
+            changes: Some(vec![
+                Change::UpdateText("b".to_string()),
+            ].into_boxed_slice()),
+            children: None,
+            base_checksum: None,
+        }));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let left = el!(div[key=0, el!(div[key=2])]);
+        let right = el!(div[key=0, el!(div[key=2]), lazy(false, "c")]);
+        let diff = left.diff(&right).unwrap();
+        match diff.changes.unwrap()[0] {
+            Change::InsertChild(1, Element::Text { ref value, .. }) => assert_eq!(value, "c"),
+            ref change => panic!("unexpected change {:?}", change),
+        }
+    }
+
+    #[test]
+    fn test_attributes() {
+        let with = |element: Element, attributes: &[(AttrName, &str)]| {
+            let mut element = element;
+            if let Element::Parent { attributes: ref mut x, .. } = element {
+                *x = Some(attributes.iter()
+                    .map(|&(ref name, value)| (name.clone(), value.to_string()))
+                    .collect::<Attributes>());
+            }
+            element
+        };
+        let left = with(el!(div[key=0, el!(div[key=1])]),
+                        &[(AttrName::new("class"), "a"), (AttrName::new("id"), "x")]);
+        let right = with(el!(div[key=0, el!(div[key=1])]),
+                         &[(AttrName::new("class"), "b"), (AttrName::new("title"), "t")]);
+
+        assert_eq!(left.diff(&right), Some(DiffTree{
+            changes: Some(vec![
+                Change::RemoveAttribute("id".to_string()),
+                Change::SetAttribute("class".to_string(), "b".to_string()),
+                Change::SetAttribute("title".to_string(), "t".to_string()),
+            ].into_boxed_slice()),
+            children: None,
+            base_checksum: None,
+        }));
+        assert_eq!(left.diff(&left.clone()), None);
+
+        // Changes can't name a namespace the prefix doesn't imply.
+        let custom = with(el!(div[key=0, el!(div[key=1])]),
+                          &[(AttrName::with_namespace("urn:x", "x:y"), "1")]);
+        match left.diff(&custom).unwrap().changes.unwrap()[0] {
+            Change::ReplaceNode(_) => {}
+            ref change => panic!("unexpected change {:?}", change),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let text = |value: &str| Element::text(Key::Local(1), value);
+        let list = |first: &str, second: &str| el!(ul[key=0, text(first), el!(li[key=2]), text(second)]);
+        let left = list("a", "b");
+        assert_eq!(left.diff(&list("a", "b")), None);
+
+        // The first child with the key is addressed by it.
+        let right = list("c", "b");
+        let diff = left.diff(&right).unwrap();
+        assert!(diff.changes.is_none());
+        assert_eq!(diff.children.as_ref().unwrap()[0].0, Key::Local(1));
+        let mut patched = left.clone();
+        patched.apply(&diff).unwrap();
+        assert!(patched.deep_eq(&right));
+
+        // Later ones and reordering can't be addressed.
+        let reordered = el!(ul[key=0, text("a"), text("b"), el!(li[key=2])]);
+        for right in &[list("a", "c"), reordered] {
+            match left.diff(right).unwrap().changes.unwrap()[..] {
+                [Change::ReplaceNode(_)] => {}
+                ref changes => panic!("unexpected changes {:?}", changes),
+            }
+        }
+
+        // Keymaps must point shared keys at their first child.
+        let mut stale = list("a", "b");
+        if let Element::Parent { ref mut keymap, .. } = stale {
+            keymap.insert(Key::Local(1), 2);
+        }
+        assert_eq!(left.try_diff(&stale),
+                   Err(DiffError::StaleKeymap {
+                       parent: Key::Local(0),
+                       key: Key::Local(1),
+                       index: 2,
+                   }));
+    }
+
+    #[test]
+    fn test_void_attributes() {
+        let input = |kind: &str, value: Option<&str>| {
+            let input = Element::void("input").key(Key::Local(1)).attr("type", kind);
+            match value {
+                Some(value) => input.attr("value", value).build(),
+                None => input.build(),
+            }
+        };
+        assert_eq!(input("text", Some("a")).diff(&input("text", Some("a"))), None);
+        assert_eq!(input("text", Some("a")).diff(&input("email", None)).unwrap().changes.unwrap().to_vec(),
+                   vec![Change::RemoveAttribute("value".to_string()),
+                        Change::SetAttribute("type".to_string(), "email".to_string())]);
+
+        let renamed = Element::void("textarea").key(Key::Local(1)).attr("type", "text").build();
+        match input("text", None).diff(&renamed).unwrap().changes.unwrap()[0] {
+            Change::ReplaceNode(_) => {}
+            ref change => panic!("unexpected change {:?}", change),
+        }
+    }
+
+    #[test]
+    fn test_listeners() {
+        let button = |listeners: &[(&str, u64)]| {
+            let button = Element::parent("button").key(Key::Local(1));
+            listeners.iter().fold(button, |button, &(event, id)| button.on(event, HandlerId(id))).build()
+        };
+        let before = button(&[("click", 1), ("keydown", 2), ("focus", 3)]);
+        let after = button(&[("click", 4), ("focus", 3), ("blur", 5)]);
+        assert_eq!(before.diff(&before.clone()), None);
+        let diff = before.diff(&after).unwrap();
+        assert_eq!(diff.changes.as_ref().unwrap().to_vec(),
+                   vec![Change::RemoveListener(Key::Local(1), "keydown".to_string()),
+                        Change::AddListener(Key::Local(1), "blur".to_string(), HandlerId(5)),
+                        Change::AddListener(Key::Local(1), "click".to_string(), HandlerId(4))]);
+
+        let mut patched = before.clone();
+        patched.apply(&diff).unwrap();
+        assert!(patched.deep_eq(&after));
+        assert!(!before.deep_eq(&after));
+    }
+
+    #[test]
+    fn test_text_diff_policy() {
+        let text = |value: &str| {
+            Element::Text {
+                key: Key::Local(0),
+                value: value.to_string(),
+            }
+        };
+        let page = |log: &str, title: &str| {
+            let mut pre = el!(pre[key=1, text(log)]);
+            if let Element::Parent { ref mut flags, .. } = pre {
+                flags.text_diff = Some(TextDiff::Lines);
+            }
+            el!(div[key=0, pre, el!(h1[key=2, text(title)])])
+        };
+        let left = page("a\nb\n", "Log");
+        let right = page("a\nb\nc\n", "Logs");
+        let mut diff = left.diff(&right).unwrap();
+        let text_changes = |diff: &DiffTree, key| {
+            let child = &diff.children.as_ref().unwrap().iter().find(|x| x.0 == key).unwrap().1;
+            child.children.as_ref().unwrap()[0].1.changes.clone().unwrap().into_vec()
+        };
+        assert_eq!(text_changes(&diff, Key::Local(1)),
+                   vec![Change::SpliceText {
+                            start: 4,
+                            delete: 0,
+                            insert: "c\n".to_string(),
+                        }]);
+        assert_eq!(text_changes(&diff, Key::Local(2)), vec![Change::UpdateText("Logs".to_string())]);
+
+        let mut patched = left.clone();
+        patched.apply(&diff).unwrap();
+        assert!(patched.deep_eq(&right));
+
+        let config = DiffConfig { text_diff: TextDiff::Chars, ..DiffConfig::default() };
+        diff = left.diff_with(&right, &config).unwrap();
+        assert_eq!(text_changes(&diff, Key::Local(2)),
+                   vec![Change::SpliceText {
+                            start: 3,
+                            delete: 0,
+                            insert: "s".to_string(),
+                        }]);
+    }
+}
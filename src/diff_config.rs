@@ -0,0 +1,128 @@
+// Tunable reconciliation heuristics for Element::diff_with.
+//
+// The defaults reproduce Element::diff exactly; workloads with very short
+// lists, wholesale list replacement, or renderers where moves are expensive
+// can adjust them.
+use std::collections::BTreeMap;
+
+#[cfg(feature = "unicode-normalization")]
+use normalize;
+use {Element, Key, TextDiff};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiffConfig {
+    // When the fraction of children inserted or removed, out of all children
+    // on both sides, exceeds this ratio, the whole parent is replaced instead
+    // of patched. Anything at or above 1.0 never replaces.
+    pub replace_threshold: f64,
+    // Child lists no longer than this are searched linearly rather than
+    // through the keymap, which is faster for a handful of children.
+    pub linear_scan_cutoff: usize,
+    // When more attributes than this change on one node, the changes are
+    // sent as a single UpdateAttributes rather than one change each, which
+    // serializes smaller.
+    pub bulk_attributes: usize,
+    // How changed text is sent below elements whose flags don't set a
+    // policy of their own.
+    pub text_diff: TextDiff,
+    // Compare text nodes after normalizing both to NFC, so text differing
+    // only in normalization form produces no UpdateText.
+    #[cfg(feature = "unicode-normalization")]
+    pub normalize_text: bool,
+}
+
+impl Default for DiffConfig {
+    fn default() -> DiffConfig {
+        DiffConfig {
+            replace_threshold: 1.0,
+            linear_scan_cutoff: 8,
+            bulk_attributes: 4,
+            text_diff: TextDiff::Replace,
+            #[cfg(feature = "unicode-normalization")]
+            normalize_text: false,
+        }
+    }
+}
+
+impl DiffConfig {
+    // Whether a text node holding `left` needs no update to hold `right`.
+    pub fn same_text(&self, left: &str, right: &str) -> bool {
+        left == right || self.normalized_eq(left, right)
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    fn normalized_eq(&self, left: &str, right: &str) -> bool {
+        self.normalize_text && normalize::nfc_eq(left, right)
+    }
+
+    #[cfg(not(feature = "unicode-normalization"))]
+    fn normalized_eq(&self, _left: &str, _right: &str) -> bool {
+        false
+    }
+
+    // The index of the child with `key`, using whichever lookup the cutoff
+    // selects.
+    pub fn position(&self, keymap: &BTreeMap<Key, usize>, children: &[Element], key: &Key) -> Option<usize> {
+        if children.len() <= self.linear_scan_cutoff {
+            children.iter().position(|child| child.to_key() == *key)
+        } else {
+            keymap.get(key).cloned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiffConfig;
+    use {AttrName, Change, Element, Flags, Key, Listeners};
+
+    #[test]
+    fn test_replace_threshold() {
+        let left = el!(ul[key=0, el!(li[key=1]), el!(li[key=2])]);
+        let right = el!(ul[key=0, el!(li[key=3]), el!(li[key=4])]);
+        let config = DiffConfig { replace_threshold: 0.5, ..DiffConfig::default() };
+
+        let diff = left.diff_with(&right, &config).unwrap();
+        match diff.changes.as_ref().map(|changes| &changes[..]) {
+            Some(&[Change::ReplaceNode(ref node)]) => assert_eq!(node.to_key(), Key::Local(0)),
+            other => panic!("expected a replacement, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_linear_scan_matches_keymap() {
+        let left = el!(ul[key=0, el!(li[key=1]), el!(li[key=2]), el!(li[key=3])]);
+        let right = el!(ul[key=0, el!(li[key=3]), el!(p[key=2])]);
+        let linear = DiffConfig { linear_scan_cutoff: usize::MAX, ..DiffConfig::default() };
+        let keymap = DiffConfig { linear_scan_cutoff: 0, ..DiffConfig::default() };
+        assert_eq!(left.diff_with(&right, &linear), left.diff_with(&right, &keymap));
+    }
+
+    #[test]
+    fn test_bulk_attributes() {
+        let input = |names: &[&str]| {
+            Element::Void {
+                key: Key::Local(0),
+                name: "input".to_string(),
+                attributes: Some(names.iter().map(|&x| (AttrName::new(x), x.to_string())).collect()),
+                listeners: Listeners::new(),
+                flags: Flags::default(),
+            }
+        };
+        let left = input(&["a", "b"]);
+        let right = input(&["c", "d"]);
+        let config = DiffConfig { bulk_attributes: 3, ..DiffConfig::default() };
+
+        let diff = left.diff_with(&right, &config).unwrap();
+        assert_eq!(diff.changes,
+                   Some(vec![Change::UpdateAttributes {
+                                 set: vec![("c".to_string(), "c".to_string()),
+                                           ("d".to_string(), "d".to_string())],
+                                 remove: vec!["a".to_string(), "b".to_string()],
+                             }]
+                       .into_boxed_slice()));
+        let diff = left.diff_with(&input(&["a", "b", "c"]), &config).unwrap();
+        assert_eq!(diff.changes,
+                   Some(vec![Change::SetAttribute("c".to_string(), "c".to_string())].into_boxed_slice()));
+    }
+}
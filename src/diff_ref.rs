@@ -0,0 +1,330 @@
+// A diff that borrows the nodes it inserts from the tree it leads to.
+//
+// InsertChild and ReplaceNode carry whole subtrees, and copying them out of
+// the next tree dominates the cost of diffing large inserts. Element::diff_ref
+// leaves them where they are, so code that only reads the patch, such as a
+// renderer making its calls or a size estimate, allocates little more than
+// the changes themselves. into_owned copies the nodes, with Lazy nodes
+// evaluated, into the DiffTree Element::diff would have made.
+//
+// The diff builds either kind through DiffOutput, so Element::diff makes its
+// DiffTree directly rather than by way of a DiffRef.
+use patch_buffer::boxed;
+use {Change, DiffConfig, DiffError, DiffTree, Element, Key};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiffRef<'a> {
+    pub changes: Vec<ChangeRef<'a>>,
+    // In ascending key order, as in DiffTree.
+    pub children: Vec<(Key, DiffRef<'a>)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChangeRef<'a> {
+    // Change::InsertChild, of a node of the next tree, which may be Lazy.
+    InsertChild(usize, &'a Element),
+    // Change::ReplaceNode, likewise.
+    ReplaceNode(&'a Element),
+    // Any other change, or one that owns its nodes.
+    Change(Change),
+}
+
+impl<'a> DiffRef<'a> {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.children.iter().all(|&(_, ref x)| x.is_empty())
+    }
+
+    pub fn into_owned(self) -> DiffTree {
+        let changes = self.changes.into_iter().map(ChangeRef::into_owned).collect();
+        let children = self.children.into_iter().map(|(key, child)| (key, child.into_owned())).collect();
+        DiffTree {
+            changes: boxed(changes),
+            children: boxed(children),
+            base_checksum: None,
+        }
+    }
+}
+
+impl<'a> ChangeRef<'a> {
+    pub fn into_owned(self) -> Change {
+        match self {
+            ChangeRef::InsertChild(index, element) => Change::InsertChild(index, element.resolve()),
+            ChangeRef::ReplaceNode(element) => Change::ReplaceNode(element.resolve()),
+            ChangeRef::Change(change) => change,
+        }
+    }
+}
+
+// What the diff builds, with the nodes it inserts borrowed from the tree
+// of lifetime 'a or copied out of it. The rest is for pairing up the nodes
+// moved to another parent, see reparent.
+pub(crate) trait DiffOutput<'a>: Sized {
+    type Change;
+
+    fn new(changes: Vec<Self::Change>, children: Vec<(Key, Self)>) -> Self;
+    // An owned diff, as made for nodes only evaluated during the diff.
+    fn from_owned(diff: DiffTree) -> Self;
+    fn change(change: Change) -> Self::Change;
+    fn insert_child(index: usize, node: &'a Element) -> Self::Change;
+    fn replace_node(node: &'a Element) -> Self::Change;
+
+    fn shape(change: &Self::Change) -> Shape<'_>;
+    // The diff of `old` against the node `insert` inserts.
+    fn diff_inserted(old: &Element, insert: &Self::Change, config: &DiffConfig)
+                     -> Result<Option<Self>, DiffError>;
+    fn changes(&self) -> &[Self::Change];
+    fn take_changes(&mut self) -> Vec<Self::Change>;
+    fn set_changes(&mut self, changes: Vec<Self::Change>);
+    fn children(&self) -> &[(Key, Self)];
+    fn children_mut(&mut self) -> &mut [(Key, Self)];
+    fn take_children(&mut self) -> Vec<(Key, Self)>;
+    fn set_children(&mut self, children: Vec<(Key, Self)>);
+}
+
+// A change of either kind of diff, as reparent looks at it.
+pub(crate) enum Shape<'c> {
+    Insert(usize, &'c Element),
+    Replace,
+    Other(&'c Change),
+}
+
+impl<'a> DiffOutput<'a> for DiffTree {
+    type Change = Change;
+
+    fn new(changes: Vec<Change>, children: Vec<(Key, DiffTree)>) -> DiffTree {
+        DiffTree {
+            changes: boxed(changes),
+            children: boxed(children),
+            base_checksum: None,
+        }
+    }
+
+    fn from_owned(diff: DiffTree) -> DiffTree {
+        diff
+    }
+
+    fn change(change: Change) -> Change {
+        change
+    }
+
+    fn insert_child(index: usize, node: &'a Element) -> Change {
+        Change::InsertChild(index, node.resolve())
+    }
+
+    fn replace_node(node: &'a Element) -> Change {
+        Change::ReplaceNode(node.resolve())
+    }
+
+    fn shape(change: &Change) -> Shape<'_> {
+        match *change {
+            Change::InsertChild(index, ref node) => Shape::Insert(index, node),
+            Change::ReplaceNode(_) => Shape::Replace,
+            ref change => Shape::Other(change),
+        }
+    }
+
+    fn diff_inserted(old: &Element, insert: &Change, config: &DiffConfig)
+                     -> Result<Option<DiffTree>, DiffError> {
+        match *insert {
+            Change::InsertChild(_, ref new) => old.diff_node(new, config, None),
+            _ => Ok(None),
+        }
+    }
+
+    fn changes(&self) -> &[Change] {
+        self.changes.as_ref().map_or(&[], |x| &x[..])
+    }
+
+    fn take_changes(&mut self) -> Vec<Change> {
+        self.changes.take().map_or(vec![], |x| x.into_vec())
+    }
+
+    fn set_changes(&mut self, changes: Vec<Change>) {
+        self.changes = boxed(changes);
+    }
+
+    fn children(&self) -> &[(Key, DiffTree)] {
+        self.children.as_ref().map_or(&[], |x| &x[..])
+    }
+
+    fn children_mut(&mut self) -> &mut [(Key, DiffTree)] {
+        self.children.as_mut().map_or(&mut [], |x| &mut x[..])
+    }
+
+    fn take_children(&mut self) -> Vec<(Key, DiffTree)> {
+        self.children.take().map_or(vec![], |x| x.into_vec())
+    }
+
+    fn set_children(&mut self, children: Vec<(Key, DiffTree)>) {
+        self.children = boxed(children);
+    }
+}
+
+impl<'a> DiffOutput<'a> for DiffRef<'a> {
+    type Change = ChangeRef<'a>;
+
+    fn new(changes: Vec<ChangeRef<'a>>, children: Vec<(Key, DiffRef<'a>)>) -> DiffRef<'a> {
+        DiffRef {
+            changes: changes,
+            children: children,
+        }
+    }
+
+    fn from_owned(diff: DiffTree) -> DiffRef<'a> {
+        DiffRef::from(diff)
+    }
+
+    fn change(change: Change) -> ChangeRef<'a> {
+        ChangeRef::Change(change)
+    }
+
+    fn insert_child(index: usize, node: &'a Element) -> ChangeRef<'a> {
+        ChangeRef::InsertChild(index, node)
+    }
+
+    fn replace_node(node: &'a Element) -> ChangeRef<'a> {
+        ChangeRef::ReplaceNode(node)
+    }
+
+    fn shape<'c>(change: &'c ChangeRef<'a>) -> Shape<'c> {
+        match *change {
+            ChangeRef::InsertChild(index, node) => Shape::Insert(index, node),
+            ChangeRef::ReplaceNode(_) |
+            ChangeRef::Change(Change::ReplaceNode(_)) => Shape::Replace,
+            ChangeRef::Change(Change::InsertChild(index, ref node)) => Shape::Insert(index, node),
+            ChangeRef::Change(ref change) => Shape::Other(change),
+        }
+    }
+
+    fn diff_inserted(old: &Element,
+                     insert: &ChangeRef<'a>,
+                     config: &DiffConfig)
+                     -> Result<Option<DiffRef<'a>>, DiffError> {
+        match *insert {
+            ChangeRef::InsertChild(_, new) => old.diff_node(new, config, None),
+            ChangeRef::Change(Change::InsertChild(_, ref new)) => {
+                Ok(old.diff_node::<DiffTree>(new, config, None)?.map(DiffRef::from))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn changes(&self) -> &[ChangeRef<'a>] {
+        &self.changes
+    }
+
+    fn take_changes(&mut self) -> Vec<ChangeRef<'a>> {
+        self.changes.split_off(0)
+    }
+
+    fn set_changes(&mut self, changes: Vec<ChangeRef<'a>>) {
+        self.changes = changes;
+    }
+
+    fn children(&self) -> &[(Key, DiffRef<'a>)] {
+        &self.children
+    }
+
+    fn children_mut(&mut self) -> &mut [(Key, DiffRef<'a>)] {
+        &mut self.children
+    }
+
+    fn take_children(&mut self) -> Vec<(Key, DiffRef<'a>)> {
+        self.children.split_off(0)
+    }
+
+    fn set_children(&mut self, children: Vec<(Key, DiffRef<'a>)>) {
+        self.children = children;
+    }
+}
+
+// Wraps an owned diff, as made for nodes only evaluated during the diff.
+impl<'a> From<DiffTree> for DiffRef<'a> {
+    fn from(diff: DiffTree) -> DiffRef<'a> {
+        DiffRef {
+            changes: diff.changes
+                .map_or(vec![], |x| x.into_vec())
+                .into_iter()
+                .map(ChangeRef::Change)
+                .collect(),
+            children: diff.children
+                .map_or(vec![], |x| x.into_vec())
+                .into_iter()
+                .map(|(key, child)| (key, DiffRef::from(child)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use super::ChangeRef;
+    use {DiffConfig, Element, HandlerId, Key, TextDiff, Thunk};
+
+    #[test]
+    fn test_diff_ref() {
+        let before = el!(ul[key=0, el!(li[key=1, Element::text(Key::Local(0), "a")])]);
+        let after = el!(ul[key=0,
+                           el!(li[key=1, Element::text(Key::Local(0), "a")]),
+                           el!(li[key=2, Element::text(Key::Local(0), "b")])]);
+        let diff = before.diff_ref(&after, &DiffConfig::default()).unwrap().unwrap();
+        match diff.changes[..] {
+            [ChangeRef::InsertChild(1, child)] => {
+                assert!(ptr::eq(child, after.get_path(&[Key::Local(2)]).unwrap()))
+            }
+            ref changes => panic!("unexpected changes {:?}", changes),
+        }
+        assert!(!diff.is_empty());
+        assert_eq!(Some(diff.into_owned()), before.diff(&after));
+
+        let text = Element::text(Key::Local(0), "c");
+        match before.diff_ref(&text, &DiffConfig::default()).unwrap().unwrap().changes[..] {
+            [ChangeRef::ReplaceNode(&Element::Text { .. })] => {}
+            ref changes => panic!("unexpected changes {:?}", changes),
+        }
+        assert_eq!(before.diff_ref(&before, &DiffConfig::default()), Ok(None));
+    }
+
+    #[test]
+    fn test_matches_diff() {
+        let text = |key: Key, value: &str| Element::text(key, value);
+        let item = |key: u64, value: &str| el!(li[key=key, text(Key::Local(0), value)]);
+        let nested = Element::parent("li").key(Key::Global(7)).child(text(Key::Global(8), "a")).build();
+        let lazy = |value: &'static str, dirty: bool| {
+            Element::Lazy {
+                key: Key::Local(5),
+                dirty: dirty,
+                thunk: Thunk::new(move || el!(p[key=5, Element::text(Key::Local(0), value)])),
+            }
+        };
+        let pairs = vec![
+            // Text, inserts, removals and moves among siblings.
+            (el!(ul[key=0, item(1, "a"), item(2, "b"), item(3, "c")]),
+             el!(ul[key=0, item(3, "c"), item(1, "one more"), item(4, "d")])),
+            // Attributes and listeners.
+            (Element::void("input").attr("type", "text").attr("id", "q").build(),
+             Element::void("input").attr("type", "search").on("input", HandlerId(3)).build()),
+            // A node of another tag, and a tree of another root.
+            (el!(div[key=0, el!(p[key=1])]), el!(div[key=0, el!(span[key=1])])),
+            (el!(div[key=0]), text(Key::Local(0), "gone")),
+            // A global key moving to another parent.
+            (el!(div[key=0, el!(ul[key=1, item(2, "x"), nested]), el!(ol[key=3, item(4, "y")])]),
+             el!(div[key=0,
+                     el!(ul[key=1, item(2, "x"), text(Key::Global(8), "b")]),
+                     el!(ol[key=3, item(4, "y"), Element::parent("li").key(Key::Global(7)).build()])])),
+            // Lazy nodes, clean and dirty.
+            (el!(div[key=0, lazy("a", false)]), el!(div[key=0, lazy("b", false)])),
+            (el!(div[key=0, lazy("a", false)]), el!(div[key=0, lazy("b", true)])),
+        ];
+        let configs = [DiffConfig::default(),
+                       DiffConfig { text_diff: TextDiff::Chars, ..DiffConfig::default() },
+                       DiffConfig { replace_threshold: 0.25, ..DiffConfig::default() }];
+        for (left, right) in pairs {
+            for config in &configs {
+                let borrowed = left.diff_ref(&right, config).unwrap().map(|x| x.into_owned());
+                assert_eq!(borrowed, left.diff_with(&right, config), "{:?} -> {:?}", left, right);
+            }
+        }
+    }
+}
@@ -0,0 +1,360 @@
+// Applying patches to a live browser document from Rust compiled to wasm,
+// behind the `wasm` feature.
+//
+// DomRenderer is the Renderer for web_sys nodes, and DomPatcher drives it:
+// it mounts a tree into a container and keeps the node of every key, so
+// patches go straight to the nodes they change without any lookup in the
+// document. The document must be left to the patcher below the container.
+//
+// Observing visibility or size and measuring need observers that report
+// back as events, which is up to the app: those changes are handed to the
+// hook set with DomPatcher::on_effect, along with their node. Listeners are
+// registered on their nodes, and call the hook set with DomPatcher::on_event
+// with their handler and the DOM event. Elements are created in the HTML
+// namespace, so SVG content isn't supported yet.
+//
+// To spare the browser layout work, DOM writes are batched as Batching
+// says: children inserted next to each other into the document go in
+// through one DocumentFragment, and attribute writes are held back to the
+// end of the patch. Either is put through first whenever something needs
+// the document up to date, such as focus.
+//
+// The DOM calls made here only fail if the document was changed behind the
+// patcher's back, which panics.
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::{self, Document, DocumentFragment, HtmlElement, Node, ScrollIntoViewOptions,
+              ScrollLogicalPosition};
+
+use renderer::{RenderDriver, Renderer};
+use {AttrName, Change, DiffTree, Element, HandlerId, Key, PatchError, ScrollBehavior, ScrollBlock,
+     CAPTURE_SUFFIX};
+
+type EventHook = Rc<dyn Fn(HandlerId, web_sys::Event)>;
+type EffectHook = Box<dyn FnMut(&Node, &Change)>;
+type Callback = Closure<dyn FnMut(web_sys::Event)>;
+
+pub struct DomRenderer {
+    document: Document,
+    effects: Option<EffectHook>,
+    // Shared with every registered callback, so the hook can be set after
+    // listeners are. It's an Fn so that events raised while it runs, such
+    // as a focus event, can call it again.
+    events: Rc<RefCell<Option<EventHook>>>,
+    // The callbacks registered for listeners with their node and event
+    // name, kept alive until the listener or its node is removed.
+    listeners: Vec<(Node, String, Callback)>,
+    batching: Batching,
+    staged: Option<Staged>,
+    // Attributes to set, or remove for None, once the patch is done.
+    attribute_writes: Vec<(Node, AttrName, Option<String>)>,
+}
+
+// How DomRenderer batches its writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Batching {
+    // Gathers runs of children inserted into the document at the same place
+    // in a DocumentFragment, attached with a single insertBefore.
+    pub fragments: bool,
+    // Holds attribute writes back to the end of the patch.
+    pub attributes: bool,
+}
+
+impl Default for Batching {
+    fn default() -> Batching {
+        Batching {
+            fragments: true,
+            attributes: true,
+        }
+    }
+}
+
+impl Batching {
+    // Makes every write as it comes.
+    pub fn none() -> Batching {
+        Batching {
+            fragments: false,
+            attributes: false,
+        }
+    }
+}
+
+// Children waiting to be inserted into `parent` before `before`.
+struct Staged {
+    parent: Node,
+    before: Option<Node>,
+    fragment: DocumentFragment,
+}
+
+impl DomRenderer {
+    pub fn new(document: Document) -> DomRenderer {
+        DomRenderer {
+            document: document,
+            effects: None,
+            events: Rc::new(RefCell::new(None)),
+            listeners: vec![],
+            batching: Batching::default(),
+            staged: None,
+            attribute_writes: vec![],
+        }
+    }
+
+    pub fn with_batching(mut self, batching: Batching) -> DomRenderer {
+        self.batching = batching;
+        self
+    }
+
+    // Inserts the staged children, before a change that could depend on them
+    // being in place.
+    fn attach_staged(&mut self) {
+        if let Some(staged) = self.staged.take() {
+            staged.parent
+                .insert_before(&staged.fragment, staged.before.as_ref())
+                .expect("insertBefore failed");
+        }
+    }
+
+    // Brings the document up to date.
+    fn write_all(&mut self) {
+        self.attach_staged();
+        for (node, name, value) in mem::take(&mut self.attribute_writes) {
+            write_attribute(&node, &name, value.as_ref().map(|x| &x[..]));
+        }
+    }
+
+    // Unregisters the listeners of `root` and the nodes below it.
+    fn forget(&mut self, root: &Node) {
+        let (gone, kept): (Vec<_>, Vec<_>) = mem::take(&mut self.listeners)
+            .into_iter()
+            .partition(|&(ref node, ..)| root.contains(Some(node)));
+        self.listeners = kept;
+        for (node, event, callback) in gone {
+            unregister(&node, &event, &callback);
+        }
+    }
+}
+
+fn unregister(node: &Node, event: &str, callback: &Callback) {
+    let (event, capture) = split_capture(event);
+    node.remove_event_listener_with_callback_and_bool(event, callback.as_ref().unchecked_ref(), capture)
+        .expect("removeEventListener failed");
+}
+
+// The DOM event name of a listener's, and whether it's for the capture phase.
+fn split_capture(event: &str) -> (&str, bool) {
+    match event.strip_suffix(CAPTURE_SUFFIX) {
+        Some(event) => (event, true),
+        None => (event, false),
+    }
+}
+
+fn element(node: &Node) -> &web_sys::Element {
+    node.dyn_ref::<web_sys::Element>().expect("attributes are only set on elements")
+}
+
+fn write_attribute(node: &Node, name: &AttrName, value: Option<&str>) {
+    let namespace = name.namespace.as_ref().map(|x| &x[..]);
+    match value {
+        Some(value) => {
+            element(node).set_attribute_ns(namespace, &name.name, value).expect("invalid attribute name")
+        }
+        None => {
+            element(node).remove_attribute_ns(namespace, name.local_name()).expect("invalid attribute name")
+        }
+    }
+}
+
+impl Renderer for DomRenderer {
+    type Node = Node;
+
+    fn create_element(&mut self, name: &str) -> Node {
+        self.document.create_element(name).expect("invalid element name").into()
+    }
+
+    fn create_text(&mut self, value: &str) -> Node {
+        self.document.create_text_node(value).into()
+    }
+
+    fn set_text(&mut self, node: &Node, value: &str) {
+        node.set_text_content(Some(value));
+    }
+
+    fn set_attribute(&mut self, node: &Node, name: &AttrName, value: &str) {
+        if self.batching.attributes {
+            self.attribute_writes.push((node.clone(), name.clone(), Some(value.to_string())));
+        } else {
+            write_attribute(node, name, Some(value));
+        }
+    }
+
+    fn remove_attribute(&mut self, node: &Node, name: &AttrName) {
+        if self.batching.attributes {
+            self.attribute_writes.push((node.clone(), name.clone(), None));
+        } else {
+            write_attribute(node, name, None);
+        }
+    }
+
+    fn add_listener(&mut self, node: &Node, event: &str, handler: HandlerId) {
+        self.remove_listener(node, event);
+        let events = self.events.clone();
+        let callback = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let hook = events.borrow().clone();
+            if let Some(hook) = hook {
+                hook(handler, event);
+            }
+        }) as Box<dyn FnMut(web_sys::Event)>);
+        let (name, capture) = split_capture(event);
+        node.add_event_listener_with_callback_and_bool(name, callback.as_ref().unchecked_ref(), capture)
+            .expect("addEventListener failed");
+        self.listeners.push((node.clone(), event.to_string(), callback));
+    }
+
+    fn remove_listener(&mut self, node: &Node, event: &str) {
+        let index = self.listeners.iter().position(|&(ref x, ref y, _)| x == node && y == event);
+        if let Some(index) = index {
+            let (node, event, callback) = self.listeners.remove(index);
+            unregister(&node, &event, &callback);
+        }
+    }
+
+    // Children of nodes outside the document, such as those of a subtree
+    // being created, are inserted at once: that causes no layout.
+    fn insert_child(&mut self, parent: &Node, child: &Node, before: Option<&Node>) {
+        if !self.batching.fragments || !parent.is_connected() {
+            self.attach_staged();
+            parent.insert_before(child, before).expect("insertBefore failed");
+            return;
+        }
+        let continues = match self.staged {
+            Some(ref staged) => staged.parent == *parent && staged.before.as_ref() == before,
+            None => false,
+        };
+        if !continues {
+            self.attach_staged();
+            self.staged = Some(Staged {
+                parent: parent.clone(),
+                before: before.cloned(),
+                fragment: self.document.create_document_fragment(),
+            });
+        }
+        if let Some(ref staged) = self.staged {
+            staged.fragment.append_child(child).expect("appendChild failed");
+        }
+    }
+
+    fn remove_child(&mut self, parent: &Node, child: &Node) {
+        self.attach_staged();
+        parent.remove_child(child).expect("removeChild failed");
+        self.forget(child);
+    }
+
+    // insertBefore moves a node that is already in the document.
+    fn move_child(&mut self, parent: &Node, child: &Node, before: Option<&Node>) {
+        self.attach_staged();
+        parent.insert_before(child, before).expect("insertBefore failed");
+    }
+
+    // Likewise across parents, keeping the node's listeners.
+    fn move_node(&mut self, _old_parent: &Node, parent: &Node, child: &Node, before: Option<&Node>) {
+        self.attach_staged();
+        parent.insert_before(child, before).expect("insertBefore failed");
+    }
+
+    fn replace_node(&mut self, _parent: Option<&Node>, old: &Node, new: &Node) {
+        self.attach_staged();
+        if let Some(parent) = old.parent_node() {
+            parent.replace_child(new, old).expect("replaceChild failed");
+        }
+        self.forget(old);
+    }
+
+    fn effect(&mut self, node: &Node, change: &Change) {
+        self.write_all();
+        let html = node.dyn_ref::<HtmlElement>();
+        match (change, html) {
+            (&Change::Focus(_), Some(html)) => html.focus().expect("focus failed"),
+            (&Change::SetStyle { ref property, ref value, .. }, Some(html)) => {
+                html.style().set_property(property, value).expect("invalid style property")
+            }
+            (&Change::ScrollIntoView { behavior, block, .. }, Some(html)) => {
+                let options = ScrollIntoViewOptions::new();
+                options.set_behavior(match behavior {
+                    ScrollBehavior::Auto => web_sys::ScrollBehavior::Auto,
+                    ScrollBehavior::Smooth => web_sys::ScrollBehavior::Smooth,
+                    ScrollBehavior::Instant => web_sys::ScrollBehavior::Instant,
+                });
+                options.set_block(match block {
+                    ScrollBlock::Start => ScrollLogicalPosition::Start,
+                    ScrollBlock::Center => ScrollLogicalPosition::Center,
+                    ScrollBlock::End => ScrollLogicalPosition::End,
+                    ScrollBlock::Nearest => ScrollLogicalPosition::Nearest,
+                });
+                html.scroll_into_view_with_scroll_into_view_options(&options);
+            }
+            _ => {
+                if let Some(ref mut effects) = self.effects {
+                    (*effects)(node, change);
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        self.write_all();
+    }
+}
+
+pub struct DomPatcher {
+    driver: RenderDriver<DomRenderer>,
+}
+
+impl DomPatcher {
+    // Creates the nodes of `tree` in `document` and appends them to
+    // `container`.
+    pub fn mount(document: Document, container: &Node, tree: &Element) -> DomPatcher {
+        let driver = RenderDriver::mount(DomRenderer::new(document), tree);
+        container.append_child(driver.root()).expect("appendChild failed");
+        DomPatcher { driver: driver }
+    }
+
+    // Applies `diff`, a patch of the tree currently in the document. See
+    // RenderDriver::patch.
+    pub fn patch(&mut self, diff: &DiffTree) -> Result<(), PatchError> {
+        self.driver.patch(diff)
+    }
+
+    pub fn root(&self) -> &Node {
+        self.driver.root()
+    }
+
+    // The node at `path`, the keys of the children from the root down.
+    pub fn node(&self, path: &[Key]) -> Option<&Node> {
+        self.driver.node(path)
+    }
+
+    // Changes how writes are batched from the next patch on.
+    pub fn set_batching(&mut self, batching: Batching) {
+        self.driver.renderer_mut().batching = batching;
+    }
+
+    // Sets the hook called with the handler and the DOM event whenever a
+    // listener fires.
+    pub fn on_event<F>(&mut self, hook: F)
+        where F: Fn(HandlerId, web_sys::Event) + 'static
+    {
+        *self.driver.renderer().events.borrow_mut() = Some(Rc::new(hook));
+    }
+
+    // Sets the hook receiving the changes the patcher can't carry out by
+    // itself: observing visibility and size, and Measure.
+    pub fn on_effect<F>(&mut self, hook: F)
+        where F: FnMut(&Node, &Change) + 'static
+    {
+        self.driver.renderer_mut().effects = Some(Box::new(hook));
+    }
+}
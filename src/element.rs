@@ -0,0 +1,551 @@
+// The element tree: the Element enum and the types making up its nodes.
+use std::borrow::{Borrow, Cow};
+use std::cmp::{self, Ordering};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Index;
+use std::panic::{Location, RefUnwindSafe};
+use std::sync::Arc;
+
+use self::Element::*;
+use diff::check_keymap;
+use key_index::KeyIndex;
+use {Attributes, DomError, TextDiff};
+
+// Represents an HTML element.
+#[derive(Debug)]
+pub enum Element {
+    Text { key: Key, value: String },
+    Void {
+        key: Key,
+        name: String,
+        attributes: Option<Attributes>,
+        listeners: Listeners,
+        flags: Flags,
+    },
+    Parent {
+        key: Key,
+        name: String,
+        keymap: BTreeMap<Key, usize>,
+        attributes: Option<Attributes>,
+        listeners: Listeners,
+        children: Vec<Element>,
+        flags: Flags,
+    },
+    // A subtree that is only built when the diff needs to look inside it:
+    // two Lazy nodes with the same key are assumed unchanged unless the new
+    // one is marked dirty. The thunk should be pure and return an element
+    // with the same key.
+    Lazy {
+        key: Key,
+        dirty: bool,
+        thunk: Thunk,
+    },
+}
+
+// Shareable across threads and unwind boundaries, as the rest of the tree
+// is.
+#[derive(Clone)]
+pub struct Thunk(Arc<dyn Fn() -> Element + Send + Sync + RefUnwindSafe>);
+
+impl Thunk {
+    pub fn new<F>(f: F) -> Thunk
+        where F: Fn() -> Element + Send + Sync + RefUnwindSafe + 'static
+    {
+        Thunk(Arc::new(f))
+    }
+
+    pub fn call(&self) -> Element {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for Thunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Thunk")
+    }
+}
+
+// Opt-in behaviors the renderer has to set up alongside the node itself.
+#[derive(Copy, Clone, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct Flags {
+    // Report Event::VisibilityChanged as the node enters or leaves the
+    // viewport.
+    pub observe_visibility: bool,
+    // Report Event::ElementResized whenever the node's size changes.
+    pub observe_resize: bool,
+    // When removed, mark the node exiting with Change::MarkExiting so its
+    // exit transition can play, and only remove it once
+    // Event::TransitionEnd arrives; see VirtualDom::transition_end.
+    pub keep_until_transition_end: bool,
+    // Marks the root of an interactive island. Outside islands the page is
+    // static: see Element::diff_islands.
+    pub island: bool,
+    // How changes to the text nodes below this element are sent, overriding
+    // DiffConfig::text_diff and any ancestor's policy; None inherits. Only
+    // affects diffing, so it is ignored when comparing flags.
+    pub text_diff: Option<TextDiff>,
+    // Where the node was created, for diagnostics. Only recorded in debug
+    // builds, by Flags::here and the constructors using it, and ignored when
+    // comparing flags. Not serialized.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub source: Option<&'static Location<'static>>,
+}
+
+impl Flags {
+    // Default flags recording the caller as the node's source in debug
+    // builds.
+    #[track_caller]
+    pub fn here() -> Flags {
+        Flags {
+            source: if cfg!(debug_assertions) {
+                Some(Location::caller())
+            } else {
+                None
+            },
+            ..Flags::default()
+        }
+    }
+}
+
+impl PartialEq for Flags {
+    fn eq(&self, other: &Flags) -> bool {
+        self.observe_visibility == other.observe_visibility &&
+        self.observe_resize == other.observe_resize &&
+        self.keep_until_transition_end == other.keep_until_transition_end &&
+        self.island == other.island
+    }
+}
+
+// Identifies one of the application's event handlers. What it refers to is
+// up to whoever dispatches events; the tree only records which handler
+// listens for which event on a node.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct HandlerId(pub u64);
+
+// The handlers listening on a node, by event name such as "click".
+pub type Listeners = BTreeMap<String, HandlerId>;
+
+// Handlers for the capture phase are listed under the event name with this
+// suffix, as "click:capture", next to the one for the bubble phase.
+pub const CAPTURE_SUFFIX: &str = ":capture";
+
+// Local, Uuid and Str keys tell a node apart from its siblings, and Global
+// ones from every node of the tree. Uuid and Str keys hold the application's
+// own ids as they are, a UUID's 16 bytes or a string, where hashing them
+// into a Local key could make siblings collide. Keys order by variant, in
+// the order here, and then by value.
+#[derive(Clone, PartialEq, Eq, Debug, Hash, PartialOrd, Ord)]
+pub enum Key {
+    Local(u64),
+    Global(u64),
+    Uuid([u8; 16]),
+    Str(Arc<str>),
+}
+
+impl Clone for Element {
+    fn clone(&self) -> Element {
+        match *self {
+            Text { ref key, ref value } => {
+                Text {
+                    key: key.clone(),
+                    value: value.clone(),
+                }
+            }
+            Void { ref key, ref name, ref attributes, ref listeners, ref flags } => {
+                Void {
+                    key: key.clone(),
+                    name: name.clone(),
+                    attributes: attributes.clone(),
+                    listeners: listeners.clone(),
+                    flags: *flags,
+                }
+            }
+            Parent { ref key, ref name, ref attributes, ref listeners, ref children, ref keymap,
+                     ref flags } => {
+                Parent {
+                    key: key.clone(),
+                    name: name.clone(),
+                    keymap: keymap.clone(),
+                    attributes: attributes.clone(),
+                    listeners: listeners.clone(),
+                    children: children.clone(),
+                    flags: *flags,
+                }
+            }
+            Lazy { ref key, ref dirty, ref thunk } => {
+                Lazy {
+                    key: key.clone(),
+                    dirty: *dirty,
+                    thunk: thunk.clone(),
+                }
+            }
+        }
+    }
+}
+
+// Looks up a child by key, panicking if there is none. See get_child.
+impl Index<Key> for Element {
+    type Output = Element;
+
+    fn index(&self, key: Key) -> &Element {
+        match self.get_child(&key) {
+            Some(child) => child,
+            None => panic!("no child with key {:?}", key),
+        }
+    }
+}
+
+// Fast equality checks are implemented by comparing references, not values.
+// Use deep_eq to compare whole trees.
+impl PartialEq for Element {
+    fn eq(&self, other: &Element) -> bool {
+        self.to_key() == other.to_key()
+    }
+}
+
+impl Element {
+    #[inline(always)]
+    pub fn to_key(&self) -> Key {
+        match *self {
+            Text { ref key, .. } |
+            Void { ref key, .. } |
+            Parent { ref key, .. } |
+            Lazy { ref key, .. } => key.clone(),
+        }
+    }
+
+    // The child of a Parent with the given key, looked up through the keymap.
+    // None for other elements or if no child has the key.
+    pub fn get_child<K: Borrow<Key>>(&self, key: K) -> Option<&Element> {
+        match *self {
+            Parent { ref keymap, ref children, .. } => {
+                keymap.get(key.borrow()).map(|&index| &children[index])
+            }
+            _ => None,
+        }
+    }
+
+    // The node at the end of `path`, the keys of the children from this
+    // node down, looked up through each parent's keymap. None if the path
+    // leaves the tree or passes through a Lazy node.
+    pub fn get_path(&self, path: &[Key]) -> Option<&Element> {
+        path.iter().try_fold(self, |node, key| node.get_child(key))
+    }
+
+    // The node with global key `key`, this one included, found by building
+    // a KeyIndex of the tree and following the path it gives. Local keys
+    // aren't unique across a tree and are never found, nor are nodes under
+    // Lazy ones. For repeated lookups, VirtualDom::find keeps its index up
+    // to date instead of building one each time.
+    pub fn find(&self, key: &Key) -> Option<&Element> {
+        self.get_path(KeyIndex::new(self).get(key)?)
+    }
+
+    pub fn get_child_mut<K: Borrow<Key>>(&mut self, key: K) -> Option<&mut Element> {
+        match *self {
+            Parent { ref keymap, ref mut children, .. } => {
+                keymap.get(key.borrow()).map(move |&index| &mut children[index])
+            }
+            _ => None,
+        }
+    }
+
+    // Moves the child with `key` to `new_index`, shifting the children in
+    // between and updating their keymap entries. Returns false if this isn't
+    // a Parent or has no such child. Panics if `new_index` is out of bounds.
+    pub fn move_child(&mut self, key: Key, new_index: usize) -> bool {
+        match *self {
+            Parent { ref mut keymap, ref mut children, .. } => {
+                let index = match keymap.get(&key) {
+                    Some(&index) => index,
+                    None => return false,
+                };
+                assert!(new_index < children.len(),
+                        "new index {} out of bounds for {} children",
+                        new_index,
+                        children.len());
+                let child = children.remove(index);
+                children.insert(new_index, child);
+                if keymap.len() < children.len() {
+                    rebuild_keymap(keymap, children);
+                    return true;
+                }
+                let low = cmp::min(index, new_index);
+                for (i, child) in children[low..cmp::max(index, new_index) + 1].iter().enumerate() {
+                    keymap.insert(child.to_key(), low + i);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Sorts the children of a Parent with `compare` and rebuilds the keymap
+    // to match. Does nothing for other elements.
+    pub fn sort_children_by<F>(&mut self, compare: F)
+        where F: FnMut(&Element, &Element) -> Ordering
+    {
+        if let Parent { ref mut keymap, ref mut children, .. } = *self {
+            children.sort_by(compare);
+            rebuild_keymap(keymap, children);
+        }
+    }
+
+    // This element with a Lazy node evaluated, borrowing it otherwise.
+    pub fn force(&self) -> Cow<'_, Element> {
+        match *self {
+            Lazy { ref thunk, .. } => Cow::Owned(thunk.call().force().into_owned()),
+            _ => Cow::Borrowed(self),
+        }
+    }
+
+    // A copy of this tree with every Lazy node evaluated, as sent to
+    // renderers in InsertChild and ReplaceNode changes.
+    pub fn resolve(&self) -> Element {
+        match *self {
+            Parent { ref key, ref name, ref attributes, ref listeners, ref children, ref keymap,
+                     ref flags } => {
+                Parent {
+                    key: key.clone(),
+                    name: name.clone(),
+                    keymap: keymap.clone(),
+                    attributes: attributes.clone(),
+                    listeners: listeners.clone(),
+                    children: children.iter().map(|child| child.resolve()).collect(),
+                    flags: *flags,
+                }
+            }
+            Lazy { ref thunk, .. } => thunk.call().resolve(),
+            _ => self.clone(),
+        }
+    }
+
+    // Whether the two trees are the same node for node: keys, names,
+    // attributes, listeners, flags, text and children, in order. Lazy nodes are
+    // evaluated, and missing attributes equal an empty set.
+    pub fn deep_eq(&self, other: &Element) -> bool {
+        match (self, other) {
+            (&Lazy { .. }, _) | (_, &Lazy { .. }) => self.force().deep_eq(&other.force()),
+            (&Text { key: ref a_key, value: ref a_value },
+             &Text { key: ref b_key, value: ref b_value }) => a_key == b_key && a_value == b_value,
+            (&Void { key: ref a_key, name: ref a_name, attributes: ref a_attrs, listeners: ref a_listeners,
+                     flags: ref a_flags },
+             &Void { key: ref b_key, name: ref b_name, attributes: ref b_attrs, listeners: ref b_listeners,
+                     flags: ref b_flags }) => {
+                a_key == b_key && a_name == b_name && a_flags == b_flags &&
+                same_attributes(a_attrs, b_attrs) && a_listeners == b_listeners
+            }
+            (&Parent { key: ref a_key, name: ref a_name, attributes: ref a_attrs, listeners: ref a_listeners,
+                       children: ref a_children, flags: ref a_flags, .. },
+             &Parent { key: ref b_key, name: ref b_name, attributes: ref b_attrs, listeners: ref b_listeners,
+                       children: ref b_children, flags: ref b_flags, .. }) => {
+                a_key == b_key && a_name == b_name && a_flags == b_flags &&
+                same_attributes(a_attrs, b_attrs) && a_listeners == b_listeners &&
+                a_children.len() == b_children.len() &&
+                a_children.iter().zip(b_children).all(|(a, b)| a.deep_eq(b))
+            }
+            _ => false,
+        }
+    }
+
+    // Checks that no two siblings anywhere in the tree share a key and that
+    // every keymap matches its children, evaluating Lazy nodes. Patches
+    // can't address all of the children sharing a key, so diffs of their
+    // parent fall back to replacing it whenever they change.
+    pub fn validate(&self) -> Result<(), DomError> {
+        self.validate_at(&mut vec![])
+    }
+
+    fn validate_at(&self, path: &mut Vec<Key>) -> Result<(), DomError> {
+        match *self {
+            Parent { ref key, ref keymap, ref children, .. } => {
+                let mut seen = BTreeMap::new();
+                for (index, child) in children.iter().enumerate() {
+                    if let Some(&first) = seen.get(&child.to_key()) {
+                        return Err(DomError::DuplicateKey {
+                            parent: path.clone(),
+                            key: child.to_key(),
+                            first: (first, children[first].tag_name()),
+                            second: (index, child.tag_name()),
+                        });
+                    }
+                    seen.insert(child.to_key(), index);
+                }
+                check_keymap(key, keymap, children)?;
+                for child in children {
+                    path.push(child.to_key());
+                    child.validate_at(path)?;
+                    path.pop();
+                }
+                Ok(())
+            }
+            Lazy { .. } => self.force().validate_at(path),
+            _ => Ok(()),
+        }
+    }
+
+    fn tag_name(&self) -> String {
+        match *self {
+            Text { .. } => "#text".to_string(),
+            Void { ref name, .. } | Parent { ref name, .. } => name.clone(),
+            Lazy { .. } => self.force().tag_name(),
+        }
+    }
+}
+
+// Points every key at the first child with it, which is where changes
+// addressed to a key shared by several children apply.
+pub(crate) fn rebuild_keymap(keymap: &mut BTreeMap<Key, usize>, children: &[Element]) {
+    keymap.clear();
+    for (index, child) in children.iter().enumerate() {
+        keymap.entry(child.to_key()).or_insert(index);
+    }
+}
+
+fn same_attributes(left: &Option<Attributes>, right: &Option<Attributes>) -> bool {
+    match (left, right) {
+        (&Some(ref left), &Some(ref right)) => left == right,
+        (&Some(ref attrs), &None) | (&None, &Some(ref attrs)) => attrs.is_empty(),
+        (&None, &None) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::UnwindSafe;
+    use {DomError, Element, Key};
+
+    #[test]
+    fn test_thread_safe() {
+        // Trees, Lazy nodes included, can be built on one thread and diffed
+        // on another.
+        fn check<T: Send + Sync + UnwindSafe>() {}
+        check::<Element>();
+    }
+
+    #[test]
+    fn test_get_child() {
+        let mut tree = el!(div[
+            key=0,
+            el!(div[key=2]),
+            el!(span[key=1])
+        ]);
+
+        assert!(tree.get_child(Key::Local(1)).unwrap().deep_eq(&el!(span[key=1])));
+        assert_eq!(tree.get_child(Key::Local(3)), None);
+        assert_eq!(el!(div[key=0]).get_child(Key::Local(0)), None);
+
+        if let Some(&mut Element::Void { ref mut name, .. }) = tree.get_child_mut(Key::Local(2)) {
+            *name = "p".to_string();
+        }
+        match tree[Key::Local(2)] {
+            Element::Void { ref name, .. } => assert_eq!(name, "p"),
+            _ => panic!("expected a void element"),
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let mut item = el!(li[key=3]);
+        if let Element::Void { ref mut key, .. } = item {
+            *key = Key::Global(9);
+        }
+        let tree = el!(div[key=0, el!(p[key=1]), el!(ul[key=2, item.clone()])]);
+
+        assert!(tree.get_path(&[Key::Local(2), Key::Global(9)]).unwrap().deep_eq(&item));
+        assert!(tree.get_path(&[]).unwrap().deep_eq(&tree));
+        assert_eq!(tree.get_path(&[Key::Local(1), Key::Global(9)]), None);
+
+        assert!(tree.find(&Key::Global(9)).unwrap().deep_eq(&item));
+        assert_eq!(tree.find(&Key::Local(1)), None);
+        assert_eq!(tree.find(&Key::Global(1)), None);
+    }
+
+    #[test]
+    fn test_move_child() {
+        let mut tree = el!(div[
+            key=0,
+            el!(div[key=1]),
+            el!(div[key=2]),
+            el!(div[key=3])
+        ]);
+
+        assert!(tree.move_child(Key::Local(3), 0));
+        assert!(!tree.move_child(Key::Local(4), 0));
+        if let Element::Parent { ref keymap, ref children, .. } = tree {
+            let keys: Vec<Key> = children.iter().map(|x| x.to_key()).collect();
+            assert_eq!(keys, vec![Key::Local(3), Key::Local(1), Key::Local(2)]);
+            for (index, key) in keys.iter().enumerate() {
+                assert_eq!(keymap.get(key), Some(&index));
+            }
+        }
+    }
+
+    #[test]
+    fn test_sort_children_by() {
+        let mut tree = el!(div[
+            key=0,
+            el!(div[key=2]),
+            el!(div[key=3]),
+            el!(div[key=1])
+        ]);
+
+        tree.sort_children_by(|a, b| b.to_key().cmp(&a.to_key()));
+        assert!(tree[Key::Local(3)].deep_eq(&el!(div[key=3])));
+        if let Element::Parent { ref keymap, ref children, .. } = tree {
+            let keys: Vec<Key> = children.iter().map(|x| x.to_key()).collect();
+            assert_eq!(keys, vec![Key::Local(3), Key::Local(2), Key::Local(1)]);
+            assert_eq!(keymap.get(&Key::Local(1)), Some(&2));
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let tree = el!(body[key=0, el!(ul[key=1, el!(li[key=2]), el!(li[key=3])]), el!(p[key=4])]);
+        assert_eq!(tree.validate(), Ok(()));
+
+        // el! builds the keymap from the children, so the second child with
+        // key 2 overwrites the first's entry.
+        let list = el!(ul[key=1, el!(li[key=2]), Element::text(Key::Local(5), "x"), el!(p[key=2])]);
+        let duplicate = el!(body[key=0, list]);
+        let error = duplicate.validate().unwrap_err();
+        assert_eq!(error,
+                   DomError::DuplicateKey {
+                       parent: vec![Key::Local(1)],
+                       key: Key::Local(2),
+                       first: (0, "li".to_string()),
+                       second: (2, "p".to_string()),
+                   });
+        assert_eq!(error.to_string(),
+                   "duplicate key Local(2) under the node at [Local(1)]: <li> at index 0 and <p> at index 2");
+
+        let mut stale = el!(ul[key=0, el!(li[key=1])]);
+        if let Element::Parent { ref mut keymap, .. } = stale {
+            keymap.insert(Key::Local(1), 3);
+        }
+        assert!(matches!(stale.validate(), Err(DomError::Diff(_))));
+    }
+
+    #[test]
+    fn test_deep_eq() {
+        let list = |name: &str, class: &str, text: &str| {
+            Element::parent(name)
+                .attr("class", class)
+                .child(el!(li[key=1]))
+                .child(Element::text(Key::Local(2), text))
+                .build()
+        };
+        let tree = list("ul", "items", "a");
+        assert!(tree.deep_eq(&tree.clone()));
+        assert!(el!(li[key=1]).deep_eq(&Element::void("li").key(Key::Local(1)).build()));
+
+        let shorter = Element::parent("ul").attr("class", "items").child(el!(li[key=1])).build();
+        for other in &[list("ol", "items", "a"), list("ul", "list", "a"), list("ul", "items", "b"), shorter] {
+            assert_eq!(tree, *other);
+            assert!(!tree.deep_eq(other));
+        }
+    }
+}
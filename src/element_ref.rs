@@ -0,0 +1,160 @@
+// A read-only view of an element that exposes what every variant has in
+// common, so code that only reads a tree doesn't need to match on all of
+// Element's variants.
+use std::panic::Location;
+use std::slice;
+
+use {AttrName, Attributes, Element, Flags, Key, Listeners};
+
+#[derive(Clone, Copy, Debug)]
+pub struct ElementRef<'a> {
+    element: &'a Element,
+}
+
+// Iterator over the children of an ElementRef.
+#[derive(Clone, Debug)]
+pub struct ChildIter<'a>(slice::Iter<'a, Element>);
+
+impl<'a> ElementRef<'a> {
+    pub fn new(element: &'a Element) -> ElementRef<'a> {
+        ElementRef { element: element }
+    }
+
+    pub fn element(&self) -> &'a Element {
+        self.element
+    }
+
+    pub fn key(&self) -> Key {
+        self.element.to_key()
+    }
+
+    // The tag name, or None for text and Lazy nodes.
+    pub fn tag(&self) -> Option<&'a str> {
+        match *self.element {
+            Element::Void { ref name, .. } |
+            Element::Parent { ref name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    // The content of a text node.
+    pub fn text(&self) -> Option<&'a str> {
+        match *self.element {
+            Element::Text { ref value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn attributes(&self) -> Option<&'a Attributes> {
+        match *self.element {
+            Element::Void { ref attributes, .. } |
+            Element::Parent { ref attributes, .. } => attributes.as_ref(),
+            _ => None,
+        }
+    }
+
+    // Looks up an attribute by qualified name, see AttrName's From impl.
+    pub fn attribute(&self, name: &str) -> Option<&'a str> {
+        self.attributes()
+            .and_then(|attributes| attributes.get(&AttrName::from(name)))
+            .map(|value| &value[..])
+    }
+
+    // The handlers listening on the node, None for a text node.
+    pub fn listeners(&self) -> Option<&'a Listeners> {
+        match *self.element {
+            Element::Void { ref listeners, .. } |
+            Element::Parent { ref listeners, .. } => Some(listeners),
+            _ => None,
+        }
+    }
+
+    pub fn flags(&self) -> Flags {
+        match *self.element {
+            Element::Void { flags, .. } |
+            Element::Parent { flags, .. } => flags,
+            _ => Flags::default(),
+        }
+    }
+
+    // Where the node was created, in debug builds; see Flags::source.
+    pub fn source(&self) -> Option<&'static Location<'static>> {
+        self.flags().source
+    }
+
+    // Whether this is an unevaluated Lazy node, whose children are unknown.
+    pub fn is_lazy(&self) -> bool {
+        matches!(*self.element, Element::Lazy { .. })
+    }
+
+    // The children of a Parent in order; empty for anything else.
+    pub fn children(&self) -> ChildIter<'a> {
+        match *self.element {
+            Element::Parent { ref children, .. } => ChildIter(children.iter()),
+            _ => ChildIter([].iter()),
+        }
+    }
+
+    pub fn child(&self, key: Key) -> Option<ElementRef<'a>> {
+        self.element.get_child(key).map(ElementRef::new)
+    }
+}
+
+impl<'a> Iterator for ChildIter<'a> {
+    type Item = ElementRef<'a>;
+
+    fn next(&mut self) -> Option<ElementRef<'a>> {
+        self.0.next().map(ElementRef::new)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a> DoubleEndedIterator for ChildIter<'a> {
+    fn next_back(&mut self) -> Option<ElementRef<'a>> {
+        self.0.next_back().map(ElementRef::new)
+    }
+}
+
+impl<'a> ExactSizeIterator for ChildIter<'a> {}
+
+impl Element {
+    pub fn view(&self) -> ElementRef<'_> {
+        ElementRef::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {AttrName, Attributes, Element, Flags, Key, Listeners};
+
+    #[test]
+    fn test_view() {
+        let mut attributes = Attributes::new();
+        attributes.insert(AttrName::new("id"), "main".to_string());
+        let tree = Element::Parent {
+            key: Key::Local(0),
+            name: "p".to_string(),
+            keymap: vec![(Key::Local(1), 0)].into_iter().collect(),
+            attributes: Some(attributes),
+            listeners: Listeners::new(),
+            children: vec![Element::Text {
+                               key: Key::Local(1),
+                               value: "hi".to_string(),
+                           }],
+            flags: Flags::default(),
+        };
+        let view = tree.view();
+
+        assert_eq!(view.tag(), Some("p"));
+        assert_eq!(view.attribute("id"), Some("main"));
+        assert_eq!(view.text(), None);
+        assert_eq!(view.children().len(), 1);
+        let text = view.child(Key::Local(1)).unwrap();
+        assert_eq!(text.text(), Some("hi"));
+        assert_eq!(text.tag(), None);
+        assert_eq!(text.children().count(), 0);
+    }
+}
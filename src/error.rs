@@ -0,0 +1,217 @@
+// Errors for trees that don't uphold Element's invariants, and for patches
+// that can't be read back.
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use framing::Encoding;
+use Key;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiffError {
+    // The keymap of `parent` maps `key` to `index`, but the child there is
+    // missing or has another key.
+    StaleKeymap { parent: Key, key: Key, index: usize },
+    // The keymap of `parent` has a different number of entries than it has
+    // children, so some child has no entry or shares a key.
+    KeymapLength {
+        parent: Key,
+        keymap: usize,
+        children: usize,
+    },
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DiffError::StaleKeymap { ref parent, ref key, index } => {
+                write!(f,
+                       "keymap of {:?} maps {:?} to index {}, which holds another child",
+                       parent,
+                       key,
+                       index)
+            }
+            DiffError::KeymapLength { ref parent, keymap, children } => {
+                write!(f,
+                       "keymap of {:?} has {} entries for {} children",
+                       parent,
+                       keymap,
+                       children)
+            }
+        }
+    }
+}
+
+impl Error for DiffError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DomError {
+    // No node is reached by following this key path from the root.
+    NoSuchNode(Vec<Key>),
+    Diff(DiffError),
+    // Two children of the node at `parent` share `key`. Each side gives the
+    // child's index and tag name, `#text` for a text node.
+    DuplicateKey {
+        parent: Vec<Key>,
+        key: Key,
+        first: (usize, String),
+        second: (usize, String),
+    },
+}
+
+impl fmt::Display for DomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DomError::NoSuchNode(ref path) => write!(f, "no node at path {:?}", path),
+            DomError::Diff(ref error) => error.fmt(f),
+            DomError::DuplicateKey { ref parent, ref key, ref first, ref second } => {
+                write!(f,
+                       "duplicate key {:?} under the node at {:?}: <{}> at index {} and <{}> at index {}",
+                       key,
+                       parent,
+                       first.1,
+                       first.0,
+                       second.1,
+                       second.0)
+            }
+        }
+    }
+}
+
+impl Error for DomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            DomError::Diff(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<DiffError> for DomError {
+    fn from(error: DiffError) -> DomError {
+        DomError::Diff(error)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PatchError {
+    // The patch was computed from a tree other than the one it is being
+    // applied to, as happens after a dropped frame.
+    BaseMismatch { expected: u32, found: u32 },
+    // The patch has changes for a node the tree doesn't have, at this key
+    // path from the root.
+    NoSuchNode(Vec<Key>),
+    // A change doesn't fit the node at this path, such as UpdateText for an
+    // element, InsertChild for a text node or a Focus naming another node.
+    // `index` is the position of the change among the node's changes.
+    Mismatch { path: Vec<Key>, index: usize },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PatchError::BaseMismatch { expected, found } => {
+                write!(f,
+                       "patch computed from tree {:08x} applied to tree {:08x}",
+                       expected,
+                       found)
+            }
+            PatchError::NoSuchNode(ref path) => write!(f, "patch addresses missing node {:?}", path),
+            PatchError::Mismatch { ref path, index } => {
+                write!(f, "patch change {} doesn't fit the node at {:?}", index, path)
+            }
+        }
+    }
+}
+
+impl Error for PatchError {}
+
+#[derive(Debug)]
+pub enum FrameError {
+    // Fewer bytes than the header and its payload length call for.
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnknownEncoding(u8),
+    // None of the codecs given handles the frame's encoding.
+    NoCodec(Encoding),
+    Decompress(io::Error),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FrameError::Truncated => write!(f, "truncated patch frame"),
+            FrameError::BadMagic => write!(f, "not a patch frame"),
+            FrameError::UnsupportedVersion(version) => {
+                write!(f, "unsupported patch frame version {}", version)
+            }
+            FrameError::UnknownEncoding(encoding) => {
+                write!(f, "unknown patch frame encoding {}", encoding)
+            }
+            FrameError::NoCodec(encoding) => write!(f, "no codec for {:?} patch frames", encoding),
+            FrameError::Decompress(ref error) => write!(f, "patch frame decompression failed: {}", error),
+        }
+    }
+}
+
+impl Error for FrameError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            FrameError::Decompress(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiffError, DomError};
+    use {DiffTree, Element, Key};
+
+    #[test]
+    fn test_stale_keymap() {
+        let left = el!(ul[key=0, el!(li[key=1]), el!(li[key=2])]);
+        let mut right = el!(ul[key=0, el!(li[key=1]), el!(li[key=2])]);
+        if let Element::Parent { ref mut keymap, .. } = right {
+            keymap.insert(Key::Local(2), 5);
+        }
+
+        assert_eq!(left.try_diff(&right),
+                   Err(DiffError::StaleKeymap {
+                       parent: Key::Local(0),
+                       key: Key::Local(2),
+                       index: 5,
+                   }));
+
+        if let Element::Parent { ref mut keymap, .. } = right {
+            keymap.insert(Key::Local(3), 1);
+        }
+        assert_eq!(left.try_diff(&right),
+                   Err(DiffError::KeymapLength {
+                       parent: Key::Local(0),
+                       keymap: 3,
+                       children: 2,
+                   }));
+        assert!(left.try_diff(&left).is_ok());
+    }
+
+    #[test]
+    fn test_diff_at() {
+        let tree = el!(body[key=0, el!(ul[key=1, el!(li[key=2]), el!(li[key=4])]), el!(p[key=3])]);
+        let list = el!(ul[key=1, el!(li[key=4])]);
+        let diff = tree.diff_at(&[Key::Local(1)], &list).unwrap();
+        let expected = tree.get_child(Key::Local(1)).unwrap().diff(&list).unwrap();
+        assert_eq!(diff,
+                   DiffTree {
+                       changes: None,
+                       children: Some(Box::new([(Key::Local(1), expected)])),
+                       base_checksum: None,
+                   });
+
+        let unchanged = tree.diff_at(&[Key::Local(3)], &el!(p[key=3])).unwrap();
+        assert!(unchanged.changes.is_none() && unchanged.children.is_none());
+        assert_eq!(tree.diff_at(&[Key::Local(1), Key::Local(9)], &el!(li[key=9])),
+                   Err(DomError::NoSuchNode(vec![Key::Local(1), Key::Local(9)])));
+    }
+}
@@ -0,0 +1,472 @@
+// Events reported by the renderer, addressed to nodes by key.
+//
+// Every Event has a target, whether it bubbles and can be canceled, and a
+// timestamp; what else it carries depends on its EventKind.
+//
+// With the `serde` feature, events serialize as JSON objects with their
+// kind in a "type" member, such as `{"type": "click", "target": "3", ...}`.
+// The fields of the kind and of shared payloads such as KeyboardEventData
+// are written inline. Those of shared payloads default when missing, as do
+// `bubbles`, `cancelable` and `timestamp`.
+use {Key, Rect};
+
+// The modifier keys held down during an event.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub meta: bool,
+}
+
+// The payload of the mouse events, Click through MouseUp and ContextMenu,
+// as a MouseEvent of the UI Events spec. Coordinates are in CSS pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct MouseEventData {
+    // Relative to the viewport.
+    pub client_x: f64,
+    pub client_y: f64,
+    // Relative to the screen.
+    pub screen_x: f64,
+    pub screen_y: f64,
+    // The buttons held down, one bit each: 1 for the primary button, 2 for
+    // the secondary and 4 for the middle one.
+    pub buttons: u16,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub modifiers: Modifiers,
+}
+
+// The payload of KeyDown, KeyPress and KeyUp, as a KeyboardEvent of the UI
+// Events spec.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct KeyboardEventData {
+    // The key's value with the modifiers applied, such as "a", "A" or
+    // "Enter".
+    pub key: String,
+    // The physical key whatever the layout, such as "KeyA".
+    pub code: String,
+    // The code point of the key's character, 0 for keys without one. Older
+    // clients send only this.
+    pub char_code: u32,
+    // Whether the key is being held down and the event is an auto-repeat.
+    pub repeat: bool,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub modifiers: Modifiers,
+}
+
+// A point of contact on a touch surface, as a Touch of the Touch Events
+// spec. Coordinates are in CSS pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct TouchPoint {
+    // Stays the same for as long as the point is in contact.
+    pub identifier: i64,
+    // Relative to the viewport.
+    pub client_x: f64,
+    pub client_y: f64,
+    // Relative to the screen.
+    pub screen_x: f64,
+    pub screen_y: f64,
+    // From 0 for no pressure to 1 for the most the device can detect.
+    pub force: f64,
+}
+
+// The payload of TouchStart, TouchMove and TouchEnd.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct TouchEventData {
+    // Every point in contact with the surface after the event, so none
+    // for the TouchEnd lifting the last finger.
+    pub touches: Vec<TouchPoint>,
+    // The points the event is about: the new, moved or lifted ones.
+    pub changed_touches: Vec<TouchPoint>,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub modifiers: Modifiers,
+}
+
+// The payload of the pointer events, as a PointerEvent of the Pointer
+// Events spec, which extends a MouseEvent.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct PointerEventData {
+    // Stays the same from PointerDown until PointerUp or PointerCancel.
+    pub pointer_id: i32,
+    // "mouse", "pen" or "touch".
+    pub pointer_type: String,
+    // From 0 to 1. Hardware without pressure reports 0.5 while a button
+    // is down and 0 otherwise.
+    pub pressure: f64,
+    // Whether this is the main pointer of its type, such as the first
+    // finger down.
+    pub is_primary: bool,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub mouse: MouseEventData,
+}
+
+// The data being dragged or on the clipboard, as a DataTransfer of the HTML
+// spec.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct DataTransfer {
+    // The dragged data as (type, data) pairs, such as ("text/plain",
+    // "hello"), in the order the source added them. Browsers only expose
+    // the data itself during a Drop, so before then it's empty.
+    pub items: Vec<(String, String)>,
+    // The operations the source allows: "none", "copy", "link", "move",
+    // "copyLink", "copyMove", "linkMove" or "all".
+    pub effect_allowed: String,
+    // The operation the current target accepts: "none", "copy", "link" or
+    // "move".
+    pub drop_effect: String,
+}
+
+impl DataTransfer {
+    // The data of the given type, such as "text/plain".
+    pub fn get(&self, kind: &str) -> Option<&str> {
+        self.items.iter().find(|item| item.0 == kind).map(|item| &item.1[..])
+    }
+
+    // The data as plain text, the type every clipboard and drag source
+    // offers.
+    pub fn text(&self) -> Option<&str> {
+        self.get("text/plain")
+    }
+
+    // The types of the data, which are available throughout the drag.
+    pub fn types(&self) -> Vec<&str> {
+        self.items.iter().map(|item| &item.0[..]).collect()
+    }
+}
+
+// The payload of the drag events, as a DragEvent of the HTML spec, which
+// extends a MouseEvent.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct DragEventData {
+    pub data_transfer: DataTransfer,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub mouse: MouseEventData,
+}
+
+// An event along with what every kind of event carries.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Event {
+    pub target: Key,
+    // Whether the event goes on to the target's ancestors. Never set for
+    // observer notifications and measurements.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub bubbles: bool,
+    // Whether handlers can prevent the event's default action. Never set
+    // for observer notifications and measurements, which have none.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cancelable: bool,
+    // When the event happened, in milliseconds since the client's page
+    // loaded, as the DOM's Event.timeStamp.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub timestamp: f64,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub kind: EventKind,
+}
+
+// The kind of an event, with the payload particular to it.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde",
+           derive(Serialize, Deserialize),
+           serde(tag = "type", rename_all = "snake_case"))]
+pub enum EventKind {
+    Click(MouseEventData),
+    DoubleClick(MouseEventData),
+    MouseDown(MouseEventData),
+    MouseEnter(MouseEventData),
+    MouseLeave(MouseEventData),
+    MouseMove(MouseEventData),
+    MouseOut(MouseEventData),
+    MouseUp(MouseEventData),
+    KeyDown(KeyboardEventData),
+    KeyPress(KeyboardEventData),
+    KeyUp(KeyboardEventData),
+    ContextMenu(MouseEventData),
+    Change { value: String },
+    // Sent on every edit of a field, where Change only comes once the edit
+    // is committed.
+    Input { value: String },
+    Focus,
+    Blur,
+    Submit,
+    // The target's new scroll offsets in CSS pixels.
+    Scroll { x: f64, y: f64 },
+    // The scroll amounts of a wheel turn in CSS pixels.
+    Wheel { delta_x: f64, delta_y: f64 },
+    TouchStart(TouchEventData),
+    TouchMove(TouchEventData),
+    TouchEnd(TouchEventData),
+    PointerDown(PointerEventData),
+    PointerMove(PointerEventData),
+    PointerUp(PointerEventData),
+    PointerCancel(PointerEventData),
+    DragStart(DragEventData),
+    Drag(DragEventData),
+    DragEnter(DragEventData),
+    DragOver(DragEventData),
+    DragLeave(DragEventData),
+    Drop(DragEventData),
+    DragEnd(DragEventData),
+    // The clipboard events carry the data as a ClipboardEvent's
+    // clipboardData: what is being pasted, and for Copy and Cut what the
+    // client put on the clipboard by default, which preventing the default
+    // action keeps off it.
+    Copy { clipboard_data: DataTransfer },
+    Cut { clipboard_data: DataTransfer },
+    Paste { clipboard_data: DataTransfer },
+    // Sent for nodes with Flags::observe_visibility whenever the visible
+    // fraction of the node crosses a threshold. Observer notifications are
+    // not DOM events, so they neither bubble nor can be canceled.
+    VisibilityChanged { ratio: f64 },
+    // Sent for nodes with Flags::observe_resize with the new content box
+    // size in CSS pixels.
+    ElementResized { width: f64, height: f64 },
+    TransitionEnd { property_name: String },
+    // The reply to a Change::Measure: the node's border box relative to the
+    // viewport, in CSS pixels.
+    Measured { request_id: u64, rect: Rect },
+}
+
+// The kind of an Event without its payload, for configuring behavior per
+// kind of event.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, PartialOrd, Ord)]
+pub enum EventType {
+    Click,
+    DoubleClick,
+    MouseDown,
+    MouseEnter,
+    MouseLeave,
+    MouseMove,
+    MouseOut,
+    MouseUp,
+    KeyDown,
+    KeyPress,
+    KeyUp,
+    ContextMenu,
+    Change,
+    Input,
+    Focus,
+    Blur,
+    Submit,
+    Scroll,
+    Wheel,
+    TouchStart,
+    TouchMove,
+    TouchEnd,
+    PointerDown,
+    PointerMove,
+    PointerUp,
+    PointerCancel,
+    DragStart,
+    Drag,
+    DragEnter,
+    DragOver,
+    DragLeave,
+    Drop,
+    DragEnd,
+    Copy,
+    Cut,
+    Paste,
+    VisibilityChanged,
+    ElementResized,
+    TransitionEnd,
+    Measured,
+}
+
+impl EventType {
+    // The name listeners for events of this kind are registered under, the
+    // DOM event's name for those that are DOM events.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            EventType::Click => "click",
+            EventType::DoubleClick => "dblclick",
+            EventType::MouseDown => "mousedown",
+            EventType::MouseEnter => "mouseenter",
+            EventType::MouseLeave => "mouseleave",
+            EventType::MouseMove => "mousemove",
+            EventType::MouseOut => "mouseout",
+            EventType::MouseUp => "mouseup",
+            EventType::KeyDown => "keydown",
+            EventType::KeyPress => "keypress",
+            EventType::KeyUp => "keyup",
+            EventType::ContextMenu => "contextmenu",
+            EventType::Change => "change",
+            EventType::Input => "input",
+            EventType::Focus => "focus",
+            EventType::Blur => "blur",
+            EventType::Submit => "submit",
+            EventType::Scroll => "scroll",
+            EventType::Wheel => "wheel",
+            EventType::TouchStart => "touchstart",
+            EventType::TouchMove => "touchmove",
+            EventType::TouchEnd => "touchend",
+            EventType::PointerDown => "pointerdown",
+            EventType::PointerMove => "pointermove",
+            EventType::PointerUp => "pointerup",
+            EventType::PointerCancel => "pointercancel",
+            EventType::DragStart => "dragstart",
+            EventType::Drag => "drag",
+            EventType::DragEnter => "dragenter",
+            EventType::DragOver => "dragover",
+            EventType::DragLeave => "dragleave",
+            EventType::Drop => "drop",
+            EventType::DragEnd => "dragend",
+            EventType::Copy => "copy",
+            EventType::Cut => "cut",
+            EventType::Paste => "paste",
+            EventType::VisibilityChanged => "visibilitychange",
+            EventType::ElementResized => "resize",
+            EventType::TransitionEnd => "transitionend",
+            EventType::Measured => "measure",
+        }
+    }
+}
+
+
+impl EventKind {
+    pub fn event_type(&self) -> EventType {
+        match *self {
+            EventKind::Click(..) => EventType::Click,
+            EventKind::DoubleClick(..) => EventType::DoubleClick,
+            EventKind::MouseDown(..) => EventType::MouseDown,
+            EventKind::MouseEnter(..) => EventType::MouseEnter,
+            EventKind::MouseLeave(..) => EventType::MouseLeave,
+            EventKind::MouseMove(..) => EventType::MouseMove,
+            EventKind::MouseOut(..) => EventType::MouseOut,
+            EventKind::MouseUp(..) => EventType::MouseUp,
+            EventKind::KeyDown(..) => EventType::KeyDown,
+            EventKind::KeyPress(..) => EventType::KeyPress,
+            EventKind::KeyUp(..) => EventType::KeyUp,
+            EventKind::ContextMenu(..) => EventType::ContextMenu,
+            EventKind::Change { .. } => EventType::Change,
+            EventKind::Input { .. } => EventType::Input,
+            EventKind::Focus => EventType::Focus,
+            EventKind::Blur => EventType::Blur,
+            EventKind::Submit => EventType::Submit,
+            EventKind::Scroll { .. } => EventType::Scroll,
+            EventKind::Wheel { .. } => EventType::Wheel,
+            EventKind::TouchStart(..) => EventType::TouchStart,
+            EventKind::TouchMove(..) => EventType::TouchMove,
+            EventKind::TouchEnd(..) => EventType::TouchEnd,
+            EventKind::PointerDown(..) => EventType::PointerDown,
+            EventKind::PointerMove(..) => EventType::PointerMove,
+            EventKind::PointerUp(..) => EventType::PointerUp,
+            EventKind::PointerCancel(..) => EventType::PointerCancel,
+            EventKind::DragStart(..) => EventType::DragStart,
+            EventKind::Drag(..) => EventType::Drag,
+            EventKind::DragEnter(..) => EventType::DragEnter,
+            EventKind::DragOver(..) => EventType::DragOver,
+            EventKind::DragLeave(..) => EventType::DragLeave,
+            EventKind::Drop(..) => EventType::Drop,
+            EventKind::DragEnd(..) => EventType::DragEnd,
+            EventKind::Copy { .. } => EventType::Copy,
+            EventKind::Cut { .. } => EventType::Cut,
+            EventKind::Paste { .. } => EventType::Paste,
+            EventKind::VisibilityChanged { .. } => EventType::VisibilityChanged,
+            EventKind::ElementResized { .. } => EventType::ElementResized,
+            EventKind::TransitionEnd { .. } => EventType::TransitionEnd,
+            EventKind::Measured { .. } => EventType::Measured,
+        }
+    }
+}
+
+impl Event {
+    pub fn event_type(&self) -> EventType {
+        self.kind.event_type()
+    }
+
+    pub fn target(&self) -> Key {
+        self.target.clone()
+    }
+
+    pub fn bubbles(&self) -> bool {
+        self.bubbles
+    }
+
+    pub fn cancelable(&self) -> bool {
+        self.cancelable
+    }
+
+    pub fn timestamp(&self) -> f64 {
+        self.timestamp
+    }
+
+    // The payload of a mouse event.
+    pub fn mouse(&self) -> Option<&MouseEventData> {
+        match self.kind {
+            EventKind::Click(ref data) |
+            EventKind::DoubleClick(ref data) |
+            EventKind::MouseDown(ref data) |
+            EventKind::MouseEnter(ref data) |
+            EventKind::MouseLeave(ref data) |
+            EventKind::MouseMove(ref data) |
+            EventKind::MouseOut(ref data) |
+            EventKind::MouseUp(ref data) |
+            EventKind::ContextMenu(ref data) => Some(data),
+            _ => None,
+        }
+    }
+
+    // The payload of a keyboard event.
+    pub fn keyboard(&self) -> Option<&KeyboardEventData> {
+        match self.kind {
+            EventKind::KeyDown(ref data) |
+            EventKind::KeyPress(ref data) |
+            EventKind::KeyUp(ref data) => Some(data),
+            _ => None,
+        }
+    }
+
+    // The payload of a touch event.
+    pub fn touch(&self) -> Option<&TouchEventData> {
+        match self.kind {
+            EventKind::TouchStart(ref data) |
+            EventKind::TouchMove(ref data) |
+            EventKind::TouchEnd(ref data) => Some(data),
+            _ => None,
+        }
+    }
+
+    // The payload of a pointer event.
+    pub fn pointer(&self) -> Option<&PointerEventData> {
+        match self.kind {
+            EventKind::PointerDown(ref data) |
+            EventKind::PointerMove(ref data) |
+            EventKind::PointerUp(ref data) |
+            EventKind::PointerCancel(ref data) => Some(data),
+            _ => None,
+        }
+    }
+
+    // The payload of a drag event.
+    pub fn drag(&self) -> Option<&DragEventData> {
+        match self.kind {
+            EventKind::DragStart(ref data) |
+            EventKind::Drag(ref data) |
+            EventKind::DragEnter(ref data) |
+            EventKind::DragOver(ref data) |
+            EventKind::DragLeave(ref data) |
+            EventKind::Drop(ref data) |
+            EventKind::DragEnd(ref data) => Some(data),
+            _ => None,
+        }
+    }
+
+    // The data of a clipboard event.
+    pub fn clipboard(&self) -> Option<&DataTransfer> {
+        match self.kind {
+            EventKind::Copy { ref clipboard_data } |
+            EventKind::Cut { ref clipboard_data } |
+            EventKind::Paste { ref clipboard_data } => Some(clipboard_data),
+            _ => None,
+        }
+    }
+}
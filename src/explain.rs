@@ -0,0 +1,218 @@
+// Why a diff came out the way it did.
+//
+// diff_explain returns the patch together with a reason for each of its
+// changes, so that a developer wondering why a subtree was replaced rather
+// than patched can tell a changed tag from a missing key or from the churn
+// heuristic, and fix their keys. Reasons are worked out from the two trees
+// after diffing, so explaining costs nothing when it isn't asked for.
+//
+// Lazy subtrees the diff looked into are evaluated again.
+use std::collections::BTreeMap;
+
+use rustc_serialize::json::{Json, ToJson};
+use {AttrName, Attributes, Change, ChangeKind, DiffConfig, DiffTree, Element, ElementRef, Key};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Explanation {
+    // Key path of the node the change is on, from the root down.
+    pub path: Vec<Key>,
+    // The change's position among that node's changes.
+    pub index: usize,
+    pub kind: ChangeKind,
+    pub reason: Reason,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reason {
+    // The nodes differ in tag, or aren't both text, both childless or both
+    // parents, so one can't be patched into the other.
+    TagMismatch,
+    // An attribute, named here, changed in a namespace its name doesn't
+    // imply, which only a ReplaceNode can express.
+    NamespacedAttribute(String),
+    // More children were inserted and removed than DiffConfig's
+    // replace_threshold allows, so replacing the parent was judged cheaper.
+    HeuristicReplace,
+    // The child with this key is gone from the new tree.
+    KeyMissingOnRight(Key),
+    // The child with this key is new.
+    KeyMissingOnLeft(Key),
+    // The children kept on both sides are in a different order.
+    OrderChanged,
+    // The node's own text, attributes or flags changed, and it was patched
+    // in place.
+    Updated,
+}
+
+impl Reason {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Reason::TagMismatch => "tag-mismatch",
+            Reason::NamespacedAttribute(_) => "namespaced-attribute",
+            Reason::HeuristicReplace => "heuristic-replace",
+            Reason::KeyMissingOnRight(_) => "key-missing-on-right",
+            Reason::KeyMissingOnLeft(_) => "key-missing-on-left",
+            Reason::OrderChanged => "order-changed",
+            Reason::Updated => "updated",
+        }
+    }
+}
+
+// {"path":[{"local":1}],"index":0,"kind":"ReplaceNode","reason":"tag-mismatch"},
+// with a "key" or "attribute" member for the reasons that name one.
+impl ToJson for Explanation {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert("path".to_string(), self.path.to_json());
+        object.insert("index".to_string(), self.index.to_json());
+        object.insert("kind".to_string(), self.kind.name().to_json());
+        object.insert("reason".to_string(), self.reason.name().to_json());
+        match self.reason {
+            Reason::NamespacedAttribute(ref name) => {
+                object.insert("attribute".to_string(), name.to_json());
+            }
+            Reason::KeyMissingOnRight(ref key) |
+            Reason::KeyMissingOnLeft(ref key) => {
+                object.insert("key".to_string(), key.to_json());
+            }
+            _ => {}
+        }
+        Json::Object(object)
+    }
+}
+
+pub fn diff_explain(a: &Element, b: &Element) -> (Option<DiffTree>, Vec<Explanation>) {
+    diff_explain_with(a, b, &DiffConfig::default())
+}
+
+// Like diff_explain, diffing as Element::diff_with does.
+pub fn diff_explain_with(a: &Element,
+                         b: &Element,
+                         config: &DiffConfig)
+                         -> (Option<DiffTree>, Vec<Explanation>) {
+    let diff = a.diff_with(b, config);
+    let mut explanations = vec![];
+    if let Some(ref diff) = diff {
+        explain(diff, a, b, &mut vec![], &mut explanations);
+    }
+    (diff, explanations)
+}
+
+fn explain(diff: &DiffTree,
+           left: &Element,
+           right: &Element,
+           path: &mut Vec<Key>,
+           out: &mut Vec<Explanation>) {
+    let (left, right) = (left.force(), right.force());
+    for (index, change) in diff.changes.iter().flat_map(|x| x.iter()).enumerate() {
+        out.push(Explanation {
+            path: path.clone(),
+            index: index,
+            kind: change.kind(),
+            reason: reason(change, &left, &right),
+        });
+    }
+    for &(ref key, ref child) in diff.children.iter().flat_map(|x| x.iter()) {
+        if let (Some(left), Some(right)) = (left.get_child(key), right.get_child(key)) {
+            path.push(key.clone());
+            explain(child, left, right, path, out);
+            path.pop();
+        }
+    }
+}
+
+fn reason(change: &Change, left: &Element, right: &Element) -> Reason {
+    match *change {
+        Change::ReplaceNode(_) => replace_reason(left.view(), right.view()),
+        Change::RemoveChild(ref key) |
+        Change::MarkExiting(ref key) => Reason::KeyMissingOnRight(key.clone()),
+        Change::InsertChild(_, ref node) => Reason::KeyMissingOnLeft(node.to_key()),
+        Change::MoveChild(..) |
+        Change::SortChildren(_) => Reason::OrderChanged,
+        _ => Reason::Updated,
+    }
+}
+
+fn replace_reason(left: ElementRef, right: ElementRef) -> Reason {
+    let shape = |node: &ElementRef| {
+        match *node.element() {
+            Element::Text { .. } => 0,
+            Element::Parent { .. } => 2,
+            _ => 1,
+        }
+    };
+    if left.tag() != right.tag() || shape(&left) != shape(&right) {
+        return Reason::TagMismatch;
+    }
+    if let Some(name) = namespaced_change(left.attributes(), right.attributes()) {
+        return Reason::NamespacedAttribute(name.name.clone());
+    }
+    Reason::HeuristicReplace
+}
+
+// The first attribute added, removed or changed whose namespace isn't the
+// one its name implies.
+fn namespaced_change<'a>(left: Option<&'a Attributes>,
+                         right: Option<&'a Attributes>)
+                         -> Option<&'a AttrName> {
+    let implied = |name: &AttrName| AttrName::from(&name.name[..]) == *name;
+    let changed = |from: Option<&'a Attributes>, to: Option<&'a Attributes>| {
+        from.into_iter()
+            .flat_map(|x| x.iter())
+            .filter(move |&(name, value)| to.and_then(|x| x.get(name)) != Some(value))
+            .map(|(name, _)| name)
+    };
+    changed(left, right).chain(changed(right, left)).find(|name| !implied(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::ToJson;
+    use super::{diff_explain, diff_explain_with, Reason};
+    use {AttrName, ChangeKind, DiffConfig, Element, Key};
+
+    fn reasons(a: &Element, b: &Element) -> Vec<(Vec<Key>, ChangeKind, Reason)> {
+        diff_explain(a, b).1.into_iter().map(|x| (x.path, x.kind, x.reason)).collect()
+    }
+
+    #[test]
+    fn test_explain() {
+        let left = el!(ul[key=0, el!(li[key=1]), el!(li[key=2]), el!(li[key=3])]);
+        let right = el!(ul[key=0, el!(li[key=3]), el!(p[key=1]), el!(li[key=4])]);
+        assert_eq!(reasons(&left, &right),
+                   vec![(vec![], ChangeKind::RemoveChild, Reason::KeyMissingOnRight(Key::Local(2))),
+                        (vec![], ChangeKind::MoveChild, Reason::OrderChanged),
+                        (vec![], ChangeKind::InsertChild, Reason::KeyMissingOnLeft(Key::Local(4))),
+                        (vec![Key::Local(1)], ChangeKind::ReplaceNode, Reason::TagMismatch)]);
+
+        let text = Element::Text { key: Key::Local(0), value: "a".to_string() };
+        assert_eq!(reasons(&left, &text),
+                   vec![(vec![], ChangeKind::ReplaceNode, Reason::TagMismatch)]);
+        assert_eq!(diff_explain(&left, &left.clone()).1, vec![]);
+
+        // The patch is the one Element::diff_with makes.
+        let config = DiffConfig { replace_threshold: 0.3, ..DiffConfig::default() };
+        let (diff, explanations) = diff_explain_with(&left, &right, &config);
+        assert_eq!(diff, left.diff_with(&right, &config));
+        assert_eq!(explanations[0].reason, Reason::HeuristicReplace);
+        assert_eq!(explanations[0].to_json().to_string(),
+                   r#"{"index":0,"kind":"ReplaceNode","path":[],"reason":"heuristic-replace"}"#);
+    }
+
+    #[test]
+    fn test_explain_attributes() {
+        let with = |name: AttrName| {
+            let mut element = el!(svg[key=0, el!(g[key=1])]);
+            if let Element::Parent { ref mut attributes, .. } = element {
+                *attributes = Some(vec![(name, "1".to_string())].into_iter().collect());
+            }
+            element
+        };
+        let left = with(AttrName::new("width"));
+        assert_eq!(reasons(&left, &with(AttrName::new("height"))),
+                   vec![(vec![], ChangeKind::RemoveAttribute, Reason::Updated),
+                        (vec![], ChangeKind::SetAttribute, Reason::Updated)]);
+        assert_eq!(reasons(&left, &with(AttrName::with_namespace("urn:x", "x:y"))),
+                   vec![(vec![], ChangeKind::ReplaceNode, Reason::NamespacedAttribute("x:y".to_string()))]);
+    }
+}
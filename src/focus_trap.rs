@@ -0,0 +1,182 @@
+// Keeps keyboard focus cycling within a subtree, as modal dialogs must: Tab
+// on the last focusable descendant moves to the first, and Shift+Tab on the
+// first moves to the last.
+//
+// The subtree and its focusable descendants are addressed by key paths from
+// the root of the tree, the root itself excluded, since local keys are only
+// unique among siblings. Focus moves are returned as patches holding a single
+// Change::Focus at the node to focus.
+use {Change, DiffTree, Element, ElementRef, Event, EventKind, Key};
+
+// The char code of the Tab key in KeyDown events.
+const TAB: u32 = 9;
+
+#[derive(Clone, Debug)]
+pub struct FocusTrap {
+    focusable: Vec<Vec<Key>>,
+    current: Option<usize>,
+}
+
+impl FocusTrap {
+    // A trap over the subtree of `tree` at `path`, or None if there is no
+    // such node.
+    pub fn new(tree: &Element, path: &[Key]) -> Option<FocusTrap> {
+        let root = tree.get_path(path)?;
+        let mut found = vec![];
+        let mut stack = path.to_vec();
+        collect(root.view(), &mut stack, &mut found);
+        // Positive tabindex values come first in ascending order, then
+        // everything else in document order; the sort is stable.
+        found.sort_by_key(|&(index, _)| if index > 0 { index } else { i64::MAX });
+        Some(FocusTrap {
+            focusable: found.into_iter().map(|(_, path)| path).collect(),
+            current: None,
+        })
+    }
+
+    // The paths of the focusable descendants in tab order.
+    pub fn focusable(&self) -> &[Vec<Key>] {
+        &self.focusable
+    }
+
+    pub fn current(&self) -> Option<&[Key]> {
+        self.current.map(|index| &self.focusable[index][..])
+    }
+
+    // Records that focus moved to the node at `path`, for example after a
+    // click, so that Tab continues from there.
+    pub fn focused(&mut self, path: &[Key]) {
+        self.current = self.focusable.iter().position(|x| &x[..] == path);
+    }
+
+    // Moves focus to the next focusable descendant, wrapping around.
+    pub fn focus_next(&mut self) -> Option<DiffTree> {
+        let len = self.focusable.len();
+        let index = match self.current {
+            Some(index) => (index + 1) % len.max(1),
+            None => 0,
+        };
+        self.focus(index)
+    }
+
+    // Moves focus to the previous focusable descendant, wrapping around.
+    pub fn focus_previous(&mut self) -> Option<DiffTree> {
+        let len = self.focusable.len();
+        let index = match self.current {
+            Some(0) | None => len.saturating_sub(1),
+            Some(index) => index - 1,
+        };
+        self.focus(index)
+    }
+
+    // Handles Tab and Shift+Tab presses.
+    pub fn handle(&mut self, event: &Event) -> Option<DiffTree> {
+        match event.kind {
+            EventKind::KeyDown(ref keyboard) if keyboard.key == "Tab" || keyboard.char_code == TAB => {
+                if keyboard.modifiers.shift {
+                    self.focus_previous()
+                } else {
+                    self.focus_next()
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn focus(&mut self, index: usize) -> Option<DiffTree> {
+        let path = self.focusable.get(index)?;
+        self.current = Some(index);
+        let mut diff = DiffTree {
+            changes: None,
+            children: None,
+            base_checksum: None,
+        };
+        let key = path.last().unwrap().clone();
+        diff.push_at(path, Change::Focus(key));
+        Some(diff)
+    }
+}
+
+// Collects the tabindex and path of every focusable node under `node`,
+// excluding the node itself.
+fn collect(node: ElementRef, path: &mut Vec<Key>, found: &mut Vec<(i64, Vec<Key>)>) {
+    for child in node.children() {
+        path.push(child.key());
+        if let Some(index) = tab_index(child) {
+            found.push((index, path.clone()));
+        }
+        collect(child, path, found);
+        path.pop();
+    }
+}
+
+// The effective tabindex of a node that can receive focus from the keyboard.
+fn tab_index(node: ElementRef) -> Option<i64> {
+    if node.attribute("disabled").is_some() {
+        return None;
+    }
+    if let Some(index) = node.attribute("tabindex").and_then(|x| x.trim().parse().ok()) {
+        return if index < 0 { None } else { Some(index) };
+    }
+    let focusable = match node.tag() {
+        Some("a") | Some("area") => node.attribute("href").is_some(),
+        Some("button") | Some("select") | Some("textarea") | Some("iframe") => true,
+        Some("input") => node.attribute("type") != Some("hidden"),
+        Some(_) => node.attribute("contenteditable").is_some_and(|x| x != "false"),
+        None => false,
+    };
+    if focusable { Some(0) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FocusTrap;
+    use {AttrName, Attributes, Change, DiffTree, Element, Flags, Key, Listeners};
+
+    fn void(key: u64, name: &str, attributes: &[(&str, &str)]) -> Element {
+        let attributes: Attributes = attributes.iter()
+            .map(|&(name, value)| (AttrName::new(name), value.to_string()))
+            .collect();
+        Element::Void {
+            key: Key::Local(key),
+            name: name.to_string(),
+            attributes: Some(attributes),
+            listeners: Listeners::new(),
+            flags: Flags::default(),
+        }
+    }
+
+    #[test]
+    fn test_cycle() {
+        let tree = el!(body[
+            key=0,
+            void(0, "button", &[]),
+            el!(dialog[
+                key=1,
+                void(0, "input", &[]),
+                void(1, "input", &[("type", "hidden")]),
+                void(2, "button", &[("disabled", "")]),
+                void(3, "div", &[("tabindex", "1")]),
+                void(4, "a", &[("href", "#")])
+            ])
+        ]);
+        let mut trap = FocusTrap::new(&tree, &[Key::Local(1)]).unwrap();
+        let path = |key| vec![Key::Local(1), Key::Local(key)];
+        assert_eq!(trap.focusable(), &[path(3), path(0), path(4)][..]);
+
+        let mut expected = DiffTree {
+            changes: None,
+            children: None,
+            base_checksum: None,
+        };
+        expected.push_at(&path(3), Change::Focus(Key::Local(3)));
+        assert_eq!(trap.focus_next(), Some(expected));
+        trap.focus_next();
+        trap.focus_next();
+        assert_eq!(trap.current(), Some(&path(4)[..]));
+        trap.focus_next();
+        assert_eq!(trap.current(), Some(&path(3)[..]));
+        trap.focus_previous();
+        assert_eq!(trap.current(), Some(&path(4)[..]));
+    }
+}
@@ -0,0 +1,156 @@
+// Rendering the top of a page first and sending the rest as a patch.
+//
+// A page marks its fold with a fold marker element, and everything after
+// the marker in document order is below the fold. render_above_fold renders
+// only the part above it, which is all the first screen needs, and returns
+// the patch bringing that part up to the whole page, to be sent down the
+// patch channel once the client has connected and hydrated. Sessions keeping
+// a VirtualDom should start it from FoldSplit::above, which is what the
+// client holds until the patch arrives.
+//
+// The marker itself is never rendered. Markers inside Lazy nodes aren't
+// found, as looking for them would evaluate the nodes; only the first
+// marker counts.
+use std::io::{self, Write};
+
+use ssr::render_stream;
+use {Children, DiffTree, Element, Key};
+
+// The tag name of the fold marker.
+pub const FOLD_MARKER: &str = "rd-fold";
+
+#[derive(Clone, Debug)]
+pub struct FoldSplit {
+    // The tree with everything after the marker left out.
+    pub above: Element,
+    // The tree without the marker.
+    pub full: Element,
+}
+
+impl FoldSplit {
+    // The patch from above to full, None if nothing is below the fold. It
+    // carries the checksum of above, see DiffTree::with_base_checksum.
+    pub fn rest(&self) -> Option<DiffTree> {
+        self.above.diff(&self.full).map(|diff| diff.with_base_checksum(&self.above))
+    }
+}
+
+impl Element {
+    // A fold marker, to be placed among the children of any element.
+    pub fn fold_marker(key: Key) -> Element {
+        Element::void(FOLD_MARKER).key(key).build()
+    }
+
+    // The parts of this tree above the fold and as a whole. Without a
+    // marker, both are the tree.
+    pub fn split_at_fold(&self) -> FoldSplit {
+        match split(self) {
+            Some((above, full)) => {
+                FoldSplit {
+                    above: above,
+                    full: full,
+                }
+            }
+            None => {
+                FoldSplit {
+                    above: self.clone(),
+                    full: self.clone(),
+                }
+            }
+        }
+    }
+}
+
+// Renders the part of `tree` above the fold as render_stream does, and
+// returns the patch with the rest, see FoldSplit::rest.
+pub fn render_above_fold<W: Write>(tree: &Element, head: &str, out: &mut W) -> io::Result<Option<DiffTree>> {
+    let split = tree.split_at_fold();
+    render_stream(&split.above, head, out)?;
+    Ok(split.rest())
+}
+
+fn is_marker(element: &Element) -> bool {
+    match *element {
+        Element::Void { ref name, .. } => name == FOLD_MARKER,
+        _ => false,
+    }
+}
+
+// The parts of `element` above the fold and without the marker, None if
+// the marker isn't below it. An element the fold passes through keeps its
+// children before the fold, even if that leaves it none.
+fn split(element: &Element) -> Option<(Element, Element)> {
+    let children = match *element {
+        Element::Parent { ref children, .. } => children,
+        _ => return None,
+    };
+    for (index, child) in children.iter().enumerate() {
+        let (above, full) = if is_marker(child) {
+            (children[..index].to_vec(),
+             children[..index].iter().chain(&children[index + 1..]).cloned().collect())
+        } else if let Some((child_above, child_full)) = split(child) {
+            let mut above = children[..index].to_vec();
+            above.push(child_above);
+            let mut full = children.clone();
+            full[index] = child_full;
+            (above, full)
+        } else {
+            continue;
+        };
+        return Some((with_children(element, above), with_children(element, full)));
+    }
+    None
+}
+
+fn with_children(element: &Element, children: Vec<Element>) -> Element {
+    match *element {
+        Element::Parent { ref key, ref name, ref attributes, ref listeners, flags, .. } => {
+            let (children, keymap) = children.into_iter().collect::<Children>().into_parts();
+            Element::Parent {
+                key: key.clone(),
+                name: name.clone(),
+                keymap: keymap,
+                attributes: attributes.clone(),
+                listeners: listeners.clone(),
+                children: children,
+                flags: flags,
+            }
+        }
+        _ => unreachable!("only parents are split"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_above_fold;
+    use {Element, Key};
+
+    fn text(value: &str) -> Element {
+        Element::text(Key::Local(0), value)
+    }
+
+    #[test]
+    fn test_split_at_fold() {
+        let tree = el!(main[
+            key=0,
+            el!(header[key=1, text("top")]),
+            el!(section[key=2, el!(p[key=0, text("a")]), Element::fold_marker(Key::Local(1)), el!(p[key=2, text("b")])]),
+            el!(footer[key=3, text("bottom")])
+        ]);
+        let split = tree.split_at_fold();
+        assert_eq!(split.above.to_html(), "<main><header>top</header><section><p>a</p></section></main>");
+        assert_eq!(split.full.to_html(),
+                   "<main><header>top</header><section><p>a</p><p>b</p></section><footer>bottom</footer></main>");
+
+        let mut out = vec![];
+        let rest = render_above_fold(&tree, "", &mut out).unwrap().unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains(">a</p>") && !html.contains("bottom") && !html.contains("rd-fold"));
+        let mut client = split.above.clone();
+        client.apply(&rest).unwrap();
+        assert!(client.deep_eq(&split.full));
+
+        let plain = el!(main[key=0, text("all")]);
+        assert!(plain.split_at_fold().rest().is_none());
+    }
+}
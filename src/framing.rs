@@ -0,0 +1,263 @@
+// Framing for serialized patches sent over a websocket or similar transport.
+//
+// A frame is a 12 byte header followed by the payload:
+//
+//   2 bytes  magic, "rd"
+//   1 byte   version, currently 1
+//   1 byte   encoding of the payload, see Encoding
+//   4 bytes  checksum of the tree the patch applies to, big endian
+//   4 bytes  payload length in bytes, big endian
+//
+// The checksum lets the receiver notice that its tree has drifted from the
+// one the patch was computed against and ask for a full render instead of
+// applying the patch. Compression itself is left to a Codec, so that the
+// crate doesn't pick a compression library for its users; only the identity
+// encoding is built in.
+//
+// A DiffTree can carry the same checksum itself, for transports that don't
+// use these frames.
+use std::io;
+
+use rustc_serialize::json::ToJson;
+use {DiffTree, Element, FrameError, PatchError};
+
+pub const MAGIC: [u8; 2] = *b"rd";
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 12;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Zstd,
+}
+
+impl Encoding {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Encoding::Identity => 0,
+            Encoding::Gzip => 1,
+            Encoding::Deflate => 2,
+            Encoding::Zstd => 3,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Encoding> {
+        match byte {
+            0 => Some(Encoding::Identity),
+            1 => Some(Encoding::Gzip),
+            2 => Some(Encoding::Deflate),
+            3 => Some(Encoding::Zstd),
+            _ => None,
+        }
+    }
+}
+
+pub trait Codec {
+    fn encoding(&self) -> Encoding;
+
+    fn compress(&self, data: &[u8]) -> Vec<u8>;
+
+    fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+// The codec for uncompressed payloads.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Identity;
+
+impl Codec for Identity {
+    fn encoding(&self) -> Encoding {
+        Encoding::Identity
+    }
+
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+
+    fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+}
+
+// A decoded frame with its payload decompressed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub encoding: Encoding,
+    pub base_checksum: u32,
+    pub payload: Vec<u8>,
+}
+
+// The CRC-32 of the JSON form of `tree`, with Lazy nodes evaluated.
+pub fn checksum(tree: &Element) -> u32 {
+    crc32(tree.resolve().to_json().to_string().as_bytes())
+}
+
+impl DiffTree {
+    // Records the checksum of `base`, the tree this patch was computed from,
+    // so that applying the patch to any other tree can be caught.
+    pub fn with_base_checksum(mut self, base: &Element) -> DiffTree {
+        self.base_checksum = Some(checksum(base));
+        self
+    }
+
+    pub fn base_checksum(&self) -> Option<u32> {
+        self.base_checksum
+    }
+
+    // Checks that `base` is the tree this patch was computed from. Patches
+    // without a checksum are accepted.
+    pub fn check_base(&self, base: &Element) -> Result<(), PatchError> {
+        match self.base_checksum {
+            Some(expected) => {
+                let found = checksum(base);
+                if expected == found {
+                    Ok(())
+                } else {
+                    Err(PatchError::BaseMismatch {
+                        expected: expected,
+                        found: found,
+                    })
+                }
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+// Frames `patch`, a serialized patch computed against `base`.
+pub fn encode_frame(codec: &dyn Codec, base: &Element, patch: &[u8]) -> Vec<u8> {
+    let payload = codec.compress(patch);
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&MAGIC);
+    frame.push(VERSION);
+    frame.push(codec.encoding().to_byte());
+    frame.extend_from_slice(&checksum(base).to_be_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+// Decodes a frame with whichever of `codecs` handles its encoding. Bytes
+// after the payload are ignored, so frames can be read off a stream one at a
+// time; the length of a frame is HEADER_LEN plus the length in its header.
+pub fn decode_frame(codecs: &[&dyn Codec], frame: &[u8]) -> Result<Frame, FrameError> {
+    if frame.len() < HEADER_LEN {
+        return Err(FrameError::Truncated);
+    }
+    if frame[..2] != MAGIC {
+        return Err(FrameError::BadMagic);
+    }
+    if frame[2] != VERSION {
+        return Err(FrameError::UnsupportedVersion(frame[2]));
+    }
+    let encoding = Encoding::from_byte(frame[3]).ok_or(FrameError::UnknownEncoding(frame[3]))?;
+    let base_checksum = u32::from_be_bytes([frame[4], frame[5], frame[6], frame[7]]);
+    let len = u32::from_be_bytes([frame[8], frame[9], frame[10], frame[11]]) as usize;
+    let payload = frame[HEADER_LEN..].get(..len).ok_or(FrameError::Truncated)?;
+    let codec = codecs.iter()
+        .find(|codec| codec.encoding() == encoding)
+        .ok_or(FrameError::NoCodec(encoding))?;
+    Ok(Frame {
+        encoding: encoding,
+        base_checksum: base_checksum,
+        payload: codec.decompress(payload).map_err(FrameError::Decompress)?,
+    })
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use super::{checksum, crc32, decode_frame, encode_frame, Codec, Encoding, Identity};
+    use {Element, FrameError, Key, PatchError};
+
+    // Run-length encoding, standing in for a real compressor.
+    struct Rle;
+
+    impl Codec for Rle {
+        fn encoding(&self) -> Encoding {
+            Encoding::Deflate
+        }
+
+        fn compress(&self, data: &[u8]) -> Vec<u8> {
+            let mut out = vec![];
+            for &byte in data {
+                match out.len() {
+                    n if n >= 2 && out[n - 1] == byte && out[n - 2] < 255 => out[n - 2] += 1,
+                    _ => out.extend_from_slice(&[1, byte]),
+                }
+            }
+            out
+        }
+
+        fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+            if !data.len().is_multiple_of(2) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "odd length"));
+            }
+            Ok(data.chunks(2).flat_map(|x| vec![x[1]; x[0] as usize]).collect())
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let base = Element::Text {
+            key: Key::Local(0),
+            value: "a".to_string(),
+        };
+        let patch = b"[{\"op\":\"remove\",\"path\":\"/children/0\"}]";
+        let mut frame = encode_frame(&Rle, &base, patch);
+        frame.extend_from_slice(b"next frame");
+
+        let decoded = decode_frame(&[&Identity, &Rle], &frame).unwrap();
+        assert_eq!(decoded.encoding, Encoding::Deflate);
+        assert_eq!(decoded.base_checksum, checksum(&base));
+        assert_eq!(&decoded.payload[..], &patch[..]);
+
+        match decode_frame(&[&Identity], &frame) {
+            Err(FrameError::NoCodec(Encoding::Deflate)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        match decode_frame(&[&Rle], &frame[..14]) {
+            Err(FrameError::Truncated) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_check_base() {
+        let text = |value: &str| {
+            Element::Text {
+                key: Key::Local(0),
+                value: value.to_string(),
+            }
+        };
+        let diff = text("a").diff(&text("b")).unwrap().with_base_checksum(&text("a"));
+        assert_eq!(diff.base_checksum(), Some(checksum(&text("a"))));
+        assert_eq!(diff.check_base(&text("a")), Ok(()));
+        assert_eq!(diff.check_base(&text("b")),
+                   Err(PatchError::BaseMismatch {
+                       expected: checksum(&text("a")),
+                       found: checksum(&text("b")),
+                   }));
+    }
+}
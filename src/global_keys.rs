@@ -0,0 +1,179 @@
+// Opt-in tracking of Key::Global values across a whole tree.
+//
+// A global key is meant to identify one node anywhere in the tree so that it
+// can be followed when it moves between parents. Two nodes sharing a global
+// key make that ambiguous, so the registry records every such collision with
+// the paths of both nodes.
+//
+// Paths are the keys of the children from the root down, the root itself
+// excluded, as in KeyIndex and Element::get_path, so the root's own key maps
+// to the empty path. Paths in trees added with add_tree are from their own
+// roots.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::panic::Location;
+
+use {Element, Key};
+
+#[derive(Debug, Default)]
+pub struct GlobalKeyRegistry {
+    // The path of each key and where its node was created.
+    paths: BTreeMap<Key, (Vec<Key>, Option<&'static Location<'static>>)>,
+    collisions: Vec<KeyCollision>,
+}
+
+// A global key found at two locations, by the paths of the two nodes. In
+// debug builds the sources say
+// where each node was created, when known; see Flags::source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyCollision {
+    pub key: Key,
+    pub first: Vec<Key>,
+    pub second: Vec<Key>,
+    pub first_source: Option<&'static Location<'static>>,
+    pub second_source: Option<&'static Location<'static>>,
+}
+
+impl fmt::Display for KeyCollision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "duplicate key {:?} at {:?}", self.key, self.first)?;
+        if let Some(source) = self.first_source {
+            write!(f, " (created at {})", source)?;
+        }
+        write!(f, " and {:?}", self.second)?;
+        if let Some(source) = self.second_source {
+            write!(f, " (created at {})", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl GlobalKeyRegistry {
+    pub fn new() -> GlobalKeyRegistry {
+        GlobalKeyRegistry::default()
+    }
+
+    pub fn from_tree(root: &Element) -> GlobalKeyRegistry {
+        let mut registry = GlobalKeyRegistry::new();
+        registry.rebuild(root);
+        registry
+    }
+
+    // Forgets everything previously registered and walks `root` again.
+    pub fn rebuild(&mut self, root: &Element) {
+        self.paths.clear();
+        self.collisions.clear();
+        let mut path = vec![];
+        self.visit(root, &mut path);
+    }
+
+    // Registers the nodes of another tree, such as a second mount point,
+    // without forgetting those already registered, so that a key shared
+    // between the trees is recorded as a collision.
+    pub fn add_tree(&mut self, root: &Element) {
+        let mut path = vec![];
+        self.visit(root, &mut path);
+    }
+
+    // The path of the first node registered under `key`.
+    pub fn path(&self, key: &Key) -> Option<&[Key]> {
+        self.paths.get(key).map(|&(ref path, _)| &path[..])
+    }
+
+    pub fn collisions(&self) -> &[KeyCollision] {
+        &self.collisions
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    fn visit(&mut self, element: &Element, path: &mut Vec<Key>) {
+        if let Element::Lazy { .. } = *element {
+            return self.visit(&element.force(), path);
+        }
+        let key = element.to_key();
+        if let Key::Global(_) = key {
+            let source = element.view().source();
+            match self.paths.get(&key) {
+                Some(&(ref first, first_source)) => {
+                    self.collisions.push(KeyCollision {
+                        key: key,
+                        first: first.clone(),
+                        second: path.clone(),
+                        first_source: first_source,
+                        second_source: source,
+                    })
+                }
+                None => {
+                    self.paths.insert(key, (path.clone(), source));
+                }
+            }
+        }
+        for child in element.view().children() {
+            path.push(child.key());
+            self.visit(child.element(), path);
+            path.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GlobalKeyRegistry, KeyCollision};
+    use {Element, Flags, Key, Listeners};
+
+    #[test]
+    fn test_collision() {
+        let tree = el!(div[
+            key=0,
+            el!(div[
+                key=1,
+                Element::Text { key: Key::Global(7), value: "a".to_string() }
+            ]),
+            Element::Text { key: Key::Global(7), value: "b".to_string() }
+        ]);
+        let registry = GlobalKeyRegistry::from_tree(&tree);
+
+        assert_eq!(registry.len(), 1);
+        assert_eq!(registry.path(&Key::Global(7)), Some(&[Key::Local(1), Key::Global(7)][..]));
+        assert!(tree.get_path(registry.path(&Key::Global(7)).unwrap()).is_some());
+        assert_eq!(registry.collisions(),
+                   &[KeyCollision {
+                         key: Key::Global(7),
+                         first: vec![Key::Local(1), Key::Global(7)],
+                         second: vec![Key::Global(7)],
+                         first_source: None,
+                         second_source: None,
+                     }]);
+    }
+
+    #[test]
+    fn test_collision_source() {
+        let node = Flags::here();
+        let line = line!() - 1;
+        let void = |flags| {
+            Element::Void {
+                key: Key::Global(1),
+                name: "div".to_string(),
+                attributes: None,
+                listeners: Listeners::new(),
+                flags: flags,
+            }
+        };
+        let tree = el!(div[key=0, void(node), void(Flags::default())]);
+        let registry = GlobalKeyRegistry::from_tree(&tree);
+        let collision = &registry.collisions()[0];
+
+        if cfg!(debug_assertions) {
+            assert_eq!(collision.first_source.map(|x| x.line()), Some(line));
+            assert_eq!(collision.to_string(),
+                       format!("duplicate key Global(1) at [Global(1)] (created at {}) and [Global(1)]",
+                               collision.first_source.unwrap()));
+        }
+    }
+}
@@ -0,0 +1,401 @@
+// The application's event handlers, and the dispatch of events to them.
+//
+// Trees only record which HandlerId listens for which event on a node. A
+// HandlerRegistry holds the closures those ids stand for, and dispatch runs
+// the ones an event reaches as the DOM would: first the capture phase, with
+// the handlers listed under the event name with CAPTURE_SUFFIX on each
+// ancestor of the target from the root down; then the target's own, capture
+// and then bubble; then, if the event bubbles, the bubble phase, with the
+// plain handlers of each ancestor from the target's parent up.
+//
+// Each handler returns a Handled, which can stop the event from going on to
+// further nodes and prevent its default action. Handlers returning nothing
+// do neither.
+//
+// A view that makes its closures afresh on every render should register them
+// with register_slot, naming the component instance they belong to by key
+// and the handler by slot. The same owner and slot get the same id on every
+// render, the new closure taking the old one's place, so the trees name the
+// same handlers and their diff holds no RemoveListener/AddListener pairs for
+// them.
+use std::collections::BTreeMap;
+
+use {Element, Event, HandlerId, IntoKey, Key, VirtualDom, CAPTURE_SUFFIX};
+
+type Handler = Box<dyn FnMut(&Event) -> Handled>;
+
+pub struct HandlerRegistry {
+    handlers: BTreeMap<HandlerId, Handler>,
+    // The ids given out by register_slot, by owner and slot.
+    slots: BTreeMap<(Key, String), HandlerId>,
+    next_id: u64,
+}
+
+// What a handler asks of the rest of the dispatch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Handled {
+    stop_propagation: bool,
+    prevent_default: bool,
+}
+
+impl Handled {
+    // Lets the event go on.
+    pub fn new() -> Handled {
+        Handled::default()
+    }
+
+    // Runs no handlers on nodes after this one. Any other handler on this
+    // node still runs.
+    pub fn stop_propagation(mut self) -> Handled {
+        self.stop_propagation = true;
+        self
+    }
+
+    // Asks that the event's default action be skipped, if it's cancelable.
+    pub fn prevent_default(mut self) -> Handled {
+        self.prevent_default = true;
+        self
+    }
+
+    pub fn propagation_stopped(&self) -> bool {
+        self.stop_propagation
+    }
+
+    pub fn default_prevented(&self) -> bool {
+        self.prevent_default
+    }
+}
+
+impl From<()> for Handled {
+    fn from(_: ()) -> Handled {
+        Handled::new()
+    }
+}
+
+// The outcome of a dispatch, for the caller to carry out the default action
+// unless it was prevented.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Dispatched {
+    pub handlers_run: usize,
+    pub propagation_stopped: bool,
+    // Only ever set for cancelable events.
+    pub default_prevented: bool,
+}
+
+impl Default for HandlerRegistry {
+    fn default() -> HandlerRegistry {
+        HandlerRegistry::new()
+    }
+}
+
+impl HandlerRegistry {
+    pub fn new() -> HandlerRegistry {
+        HandlerRegistry {
+            handlers: BTreeMap::new(),
+            slots: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+
+    // Adds `handler` under a new id, to be given to ElementBuilder::on or
+    // on_capture. It returns a Handled, or nothing.
+    pub fn register<F, R>(&mut self, handler: F) -> HandlerId
+        where F: FnMut(&Event) -> R + 'static,
+              R: Into<Handled>
+    {
+        let id = HandlerId(self.next_id);
+        self.next_id += 1;
+        self.handlers.insert(id, boxed(handler));
+        id
+    }
+
+    // Adds `handler` as the one in `slot` of the component instance keyed
+    // `owner`, such as Key::Global(7) and "submit". The first call for an
+    // owner and slot takes a new id, and later ones return it again with
+    // `handler` replacing the closure it stood for.
+    pub fn register_slot<K, F, R>(&mut self, owner: K, slot: &str, handler: F) -> HandlerId
+        where K: IntoKey,
+              F: FnMut(&Event) -> R + 'static,
+              R: Into<Handled>
+    {
+        let next_id = &mut self.next_id;
+        let id = *self.slots.entry((owner.into_key(), slot.to_string())).or_insert_with(|| {
+            *next_id += 1;
+            HandlerId(*next_id - 1)
+        });
+        self.handlers.insert(id, boxed(handler));
+        id
+    }
+
+    // Drops the handler, returning whether there was one. Ids aren't reused,
+    // so a tree still naming it just has nothing run, and registering its
+    // slot again takes a new id.
+    pub fn unregister(&mut self, id: HandlerId) -> bool {
+        self.slots.retain(|_, x| *x != id);
+        self.handlers.remove(&id).is_some()
+    }
+
+    pub fn contains(&self, id: HandlerId) -> bool {
+        self.handlers.contains_key(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.handlers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    // Runs the handlers `event` reaches in the tree of `dom` holding its
+    // target, in the order above. The target is found by global key, see
+    // VirtualDom::lookup, so nothing runs for a target with a local key or
+    // no longer in any tree.
+    pub fn dispatch(&mut self, dom: &VirtualDom, event: Event) -> Dispatched {
+        let (tree, path) = match dom.locate(&event.target()) {
+            Some(found) => found,
+            None => return Dispatched::default(),
+        };
+        let name = event.event_type().name();
+        let capture = format!("{}{}", name, CAPTURE_SUFFIX);
+        let mut nodes = vec![];
+        if !collect(tree, path, name, &capture, &mut nodes) {
+            return Dispatched::default();
+        }
+        // The handlers to run, with the depth of their node.
+        let target = nodes.len() - 1;
+        let mut route = vec![];
+        for (depth, &(capture, _)) in nodes.iter().enumerate() {
+            route.extend(capture.map(|id| (depth, id)));
+        }
+        route.extend(nodes[target].1.map(|id| (target, id)));
+        if event.bubbles() {
+            for (depth, &(_, bubble)) in nodes[..target].iter().enumerate().rev() {
+                route.extend(bubble.map(|id| (depth, id)));
+            }
+        }
+
+        let mut dispatched = Dispatched::default();
+        let mut stopped_at = None;
+        for (depth, id) in route {
+            if stopped_at.is_some() && stopped_at != Some(depth) {
+                break;
+            }
+            let handled = match self.handlers.get_mut(&id) {
+                Some(handler) => handler(&event),
+                None => continue,
+            };
+            dispatched.handlers_run += 1;
+            if handled.propagation_stopped() {
+                dispatched.propagation_stopped = true;
+                stopped_at = Some(depth);
+            }
+            if handled.default_prevented() && event.cancelable() {
+                dispatched.default_prevented = true;
+            }
+        }
+        dispatched
+    }
+}
+
+fn boxed<F, R>(mut handler: F) -> Handler
+    where F: FnMut(&Event) -> R + 'static,
+          R: Into<Handled>
+{
+    Box::new(move |event: &Event| handler(event).into())
+}
+
+// Pushes the capture and bubble handlers of each node from `element` down
+// `path`, and returns whether the path led to a node.
+fn collect(element: &Element,
+           path: &[Key],
+           name: &str,
+           capture: &str,
+           nodes: &mut Vec<(Option<HandlerId>, Option<HandlerId>)>)
+           -> bool {
+    let element = element.force();
+    nodes.push(match *element {
+        Element::Void { ref listeners, .. } |
+        Element::Parent { ref listeners, .. } => {
+            (listeners.get(capture).cloned(), listeners.get(name).cloned())
+        }
+        _ => (None, None),
+    });
+    match path.split_first() {
+        Some((key, rest)) => {
+            match element.get_child(key) {
+                Some(child) => collect(child, rest, name, capture, nodes),
+                None => false,
+            }
+        }
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::{Dispatched, Handled, HandlerRegistry};
+    use {DataTransfer, Element, Event, EventKind, Key, MouseEventData, VirtualDom};
+
+    fn click(target: Key, bubbles: bool, cancelable: bool) -> Event {
+        Event {
+            target: target,
+            bubbles: bubbles,
+            cancelable: cancelable,
+            timestamp: 0.0,
+            kind: EventKind::Click(MouseEventData::default()),
+        }
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let log = Rc::new(RefCell::new(vec![]));
+        let mut registry = HandlerRegistry::new();
+        let mut handler = |name: &'static str, handled: Handled| {
+            let log = log.clone();
+            registry.register(move |_: &Event| {
+                log.borrow_mut().push(name);
+                handled
+            })
+        };
+        let outer = handler("outer", Handled::new());
+        let outer_capture = handler("outer capture", Handled::new());
+        let inner = handler("inner", Handled::new().prevent_default());
+        let inner_capture = handler("inner capture", Handled::new());
+        let unused = handler("unused", Handled::new());
+        let tree = Element::parent("div")
+            .key(Key::Global(1))
+            .on("click", outer)
+            .on_capture("click", outer_capture)
+            .child(Element::parent("p")
+                .key(Key::Global(2))
+                .child(Element::void("button")
+                    .key(Key::Global(3))
+                    .on("click", inner)
+                    .on_capture("click", inner_capture)
+                    .on("keydown", unused)
+                    .build())
+                .build())
+            .build();
+        let dom = VirtualDom::new(tree);
+        let mut dispatch = |target: u64, bubbles: bool, cancelable: bool| {
+            log.borrow_mut().clear();
+            let dispatched = registry.dispatch(&dom, click(Key::Global(target), bubbles, cancelable));
+            (log.borrow().clone(), dispatched)
+        };
+
+        let (log, dispatched) = dispatch(3, true, true);
+        assert_eq!(log, vec!["outer capture", "inner capture", "inner", "outer"]);
+        assert_eq!(dispatched,
+                   Dispatched { handlers_run: 4, propagation_stopped: false, default_prevented: true });
+
+        let (log, dispatched) = dispatch(3, false, false);
+        assert_eq!(log, vec!["outer capture", "inner capture", "inner"]);
+        assert!(!dispatched.default_prevented);
+
+        assert_eq!(dispatch(2, true, true).0, vec!["outer capture", "outer"]);
+        assert_eq!(dispatch(9, true, true).1, Dispatched::default());
+    }
+
+    #[test]
+    fn test_stop_propagation() {
+        let log = Rc::new(RefCell::new(vec![]));
+        let mut registry = HandlerRegistry::new();
+        let mut handler = |name: &'static str, handled: Handled| {
+            let log = log.clone();
+            registry.register(move |_: &Event| {
+                log.borrow_mut().push(name);
+                handled
+            })
+        };
+        let outer = handler("outer", Handled::new());
+        let stop = handler("stop", Handled::new().stop_propagation());
+        let inner = handler("inner", Handled::new());
+        let tree = Element::parent("div")
+            .key(Key::Global(1))
+            .on("click", outer)
+            .child(Element::void("button")
+                .key(Key::Global(2))
+                .on_capture("click", stop)
+                .on("click", inner)
+                .build())
+            .build();
+        let dom = VirtualDom::new(tree);
+
+        let dispatched = registry.dispatch(&dom, click(Key::Global(2), true, true));
+        assert_eq!(*log.borrow(), vec!["stop", "inner"]);
+        assert!(dispatched.propagation_stopped);
+
+        // Handlers returning nothing let the event go on.
+        let plain = registry.register(|_: &Event| {});
+        assert!(registry.unregister(stop));
+        assert!(!registry.unregister(stop));
+        assert!(registry.contains(plain));
+        log.borrow_mut().clear();
+        registry.dispatch(&dom, click(Key::Global(2), true, true));
+        assert_eq!(*log.borrow(), vec!["inner", "outer"]);
+    }
+
+    #[test]
+    fn test_register_slot() {
+        let log = Rc::new(RefCell::new(vec![]));
+        let mut registry = HandlerRegistry::new();
+        let render = |registry: &mut HandlerRegistry, count: usize| {
+            let log = log.clone();
+            let click = registry.register_slot(Key::Global(1), "click", move |_: &Event| {
+                log.borrow_mut().push(count);
+            });
+            Element::void("button").key(Key::Global(1)).on("click", click).build()
+        };
+        let first = render(&mut registry, 1);
+        let second = render(&mut registry, 2);
+        assert!(first.diff(&second).is_none());
+        assert_eq!(registry.len(), 1);
+
+        // The latest closure is the one run.
+        let dom = VirtualDom::new(second);
+        registry.dispatch(&dom, click(Key::Global(1), true, true));
+        assert_eq!(*log.borrow(), vec![2]);
+
+        // Other owners and slots get ids of their own.
+        let click = registry.register_slot(Key::Global(1), "click", |_: &Event| {});
+        assert_ne!(registry.register_slot(Key::Global(2), "click", |_: &Event| {}), click);
+        assert_ne!(registry.register_slot(Key::Global(1), "blur", |_: &Event| {}), click);
+        assert!(registry.unregister(click));
+        assert_ne!(registry.register_slot(Key::Global(1), "click", |_: &Event| {}), click);
+    }
+
+    #[test]
+    fn test_clipboard() {
+        let pasted = Rc::new(RefCell::new(None));
+        let mut registry = HandlerRegistry::new();
+        let log = pasted.clone();
+        let paste = registry.register(move |event: &Event| {
+            *log.borrow_mut() = event.clipboard().and_then(|x| x.text()).map(|x| x.to_string());
+            Handled::new().prevent_default()
+        });
+        let tree = Element::parent("table")
+            .key(Key::Global(1))
+            .on("paste", paste)
+            .child(Element::void("td").key(Key::Global(2)).build())
+            .build();
+        let dom = VirtualDom::new(tree);
+
+        let event = Event {
+            target: Key::Global(2),
+            bubbles: true,
+            cancelable: true,
+            timestamp: 0.0,
+            kind: EventKind::Paste {
+                clipboard_data: DataTransfer {
+                    items: vec![("text/plain".to_string(), "1\t2".to_string())],
+                    ..DataTransfer::default()
+                },
+            },
+        };
+        assert!(registry.dispatch(&dom, event).default_prevented);
+        assert_eq!(*pasted.borrow(), Some("1\t2".to_string()));
+    }
+}
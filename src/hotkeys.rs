@@ -0,0 +1,294 @@
+// Document-wide keyboard shortcuts.
+//
+// A shortcut is a sequence of key strokes separated by spaces, each stroke
+// being a key with optional modifiers joined by `+`: "Ctrl+K", "Shift+?",
+// "g g". Bindings may belong to a context such as "editor", and only fire
+// while that context is enabled; bindings without one are always active.
+//
+// Strokes are built from the key and modifiers of KeyDown events. Events
+// from older clients carry only a char code, so strokes built from them
+// only know about Shift, inferred from upper case characters.
+use std::cmp;
+use std::collections::BTreeSet;
+
+use {Event, EventKind};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyStroke {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub meta: bool,
+    // A lower case character, or a key name such as "Enter" or "ArrowUp".
+    pub key: String,
+}
+
+// Identifies a binding registered with Hotkeys::register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HotkeyId(usize);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HotkeyError {
+    Invalid(&'static str),
+    // The sequence equals or overlaps an active binding's, which would make
+    // one of them unreachable.
+    Conflict(HotkeyId),
+}
+
+struct Binding {
+    id: HotkeyId,
+    sequence: Vec<KeyStroke>,
+    context: Option<String>,
+    handler: Box<dyn FnMut()>,
+}
+
+#[derive(Default)]
+pub struct Hotkeys {
+    bindings: Vec<Binding>,
+    contexts: BTreeSet<String>,
+    // The strokes of a sequence typed so far.
+    pending: Vec<KeyStroke>,
+    next_id: usize,
+}
+
+impl KeyStroke {
+    pub fn parse(source: &str) -> Result<KeyStroke, HotkeyError> {
+        let mut stroke = KeyStroke::default();
+        // A trailing `+` is the plus key itself, as in "Ctrl++".
+        let (modifiers, key) = match source.rfind('+') {
+            Some(index) if index + 1 == source.len() => (&source[..index.saturating_sub(1)], "+"),
+            Some(index) => (&source[..index], &source[index + 1..]),
+            None => ("", source),
+        };
+        for modifier in modifiers.split('+').filter(|x| !x.is_empty()) {
+            match &modifier.to_lowercase()[..] {
+                "ctrl" | "control" => stroke.ctrl = true,
+                "alt" | "option" => stroke.alt = true,
+                "shift" => stroke.shift = true,
+                "meta" | "cmd" | "super" => stroke.meta = true,
+                _ => return Err(HotkeyError::Invalid("unknown modifier")),
+            }
+        }
+        if key.is_empty() {
+            return Err(HotkeyError::Invalid("missing key"));
+        }
+        stroke.key = normalize(key);
+        Ok(stroke)
+    }
+
+    // The stroke of a KeyDown event.
+    pub fn from_event(event: &Event) -> Option<KeyStroke> {
+        match event.kind {
+            EventKind::KeyDown(ref keyboard) if keyboard.key.is_empty() => {
+                ::std::char::from_u32(keyboard.char_code).map(|c| {
+                    KeyStroke {
+                        shift: c.is_uppercase(),
+                        key: normalize(&c.to_string()),
+                        ..KeyStroke::default()
+                    }
+                })
+            }
+            EventKind::KeyDown(ref keyboard) => {
+                Some(KeyStroke {
+                    ctrl: keyboard.modifiers.ctrl,
+                    alt: keyboard.modifiers.alt,
+                    shift: keyboard.modifiers.shift,
+                    meta: keyboard.modifiers.meta,
+                    key: normalize(&keyboard.key),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+// Single characters compare case-insensitively, Shift being a modifier.
+fn normalize(key: &str) -> String {
+    if key.chars().count() == 1 {
+        key.to_lowercase()
+    } else {
+        key.to_string()
+    }
+}
+
+fn parse_sequence(source: &str) -> Result<Vec<KeyStroke>, HotkeyError> {
+    let sequence = source.split_whitespace().map(KeyStroke::parse).collect::<Result<Vec<_>, _>>()?;
+    if sequence.is_empty() {
+        return Err(HotkeyError::Invalid("empty shortcut"));
+    }
+    Ok(sequence)
+}
+
+impl Hotkeys {
+    pub fn new() -> Hotkeys {
+        Hotkeys::default()
+    }
+
+    pub fn register<F>(&mut self,
+                       shortcut: &str,
+                       context: Option<&str>,
+                       handler: F)
+                       -> Result<HotkeyId, HotkeyError>
+        where F: FnMut() + 'static
+    {
+        let sequence = parse_sequence(shortcut)?;
+        let context = context.map(|x| x.to_string());
+        for binding in &self.bindings {
+            // Two contexts can be enabled at once, but conflicts are only
+            // reported within one, or against the always active bindings.
+            let overlapping_context = binding.context.is_none() || context.is_none() ||
+                                      binding.context == context;
+            let shorter = cmp::min(binding.sequence.len(), sequence.len());
+            if overlapping_context && binding.sequence[..shorter] == sequence[..shorter] {
+                return Err(HotkeyError::Conflict(binding.id));
+            }
+        }
+        let id = HotkeyId(self.next_id);
+        self.next_id += 1;
+        self.bindings.push(Binding {
+            id: id,
+            sequence: sequence,
+            context: context,
+            handler: Box::new(handler),
+        });
+        Ok(id)
+    }
+
+    pub fn unregister(&mut self, id: HotkeyId) -> bool {
+        let len = self.bindings.len();
+        self.bindings.retain(|binding| binding.id != id);
+        self.bindings.len() != len
+    }
+
+    pub fn enable(&mut self, context: &str) {
+        self.contexts.insert(context.to_string());
+    }
+
+    pub fn disable(&mut self, context: &str) {
+        self.contexts.remove(context);
+        self.pending.clear();
+    }
+
+    pub fn is_enabled(&self, context: &str) -> bool {
+        self.contexts.contains(context)
+    }
+
+    // Feeds a key stroke, running the handler of a completed shortcut.
+    // Returns whether the stroke was consumed, either by completing a
+    // shortcut or as a prefix of one.
+    pub fn press(&mut self, stroke: KeyStroke) -> bool {
+        self.pending.push(stroke);
+        if self.advance() {
+            return true;
+        }
+        // An abandoned sequence may still leave this stroke starting another.
+        if self.pending.len() > 1 {
+            let stroke = self.pending.pop().unwrap();
+            self.pending.clear();
+            self.pending.push(stroke);
+            if self.advance() {
+                return true;
+            }
+        }
+        self.pending.clear();
+        false
+    }
+
+    // Feeds a KeyDown event, see press.
+    pub fn handle(&mut self, event: &Event) -> bool {
+        match KeyStroke::from_event(event) {
+            Some(stroke) => self.press(stroke),
+            None => false,
+        }
+    }
+
+    // Runs the binding completed by the pending strokes, if any. Returns
+    // whether they complete or begin an active binding.
+    fn advance(&mut self) -> bool {
+        let mut prefix = false;
+        let mut complete = None;
+        for (index, binding) in self.bindings.iter().enumerate() {
+            let active = binding.context.as_ref().is_none_or(|x| self.contexts.contains(x));
+            if active && binding.sequence.starts_with(&self.pending) {
+                if binding.sequence.len() == self.pending.len() {
+                    complete = Some(index);
+                }
+                prefix = true;
+            }
+        }
+        if let Some(index) = complete {
+            self.pending.clear();
+            (self.bindings[index].handler)();
+        }
+        prefix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use super::{HotkeyError, Hotkeys, KeyStroke};
+    use {Event, EventKind, Key, KeyboardEventData, Modifiers};
+
+    fn counter() -> (Rc<Cell<u32>>, impl FnMut() + 'static) {
+        let count = Rc::new(Cell::new(0));
+        let handle = count.clone();
+        (count, move || handle.set(handle.get() + 1))
+    }
+
+    #[test]
+    fn test_parse() {
+        let stroke = KeyStroke::parse("Ctrl+Shift+K").unwrap();
+        assert!(stroke.ctrl && stroke.shift && !stroke.alt);
+        assert_eq!(stroke.key, "k");
+        assert_eq!(KeyStroke::parse("Ctrl++").unwrap().key, "+");
+        assert_eq!(KeyStroke::parse("Hyper+K"), Err(HotkeyError::Invalid("unknown modifier")));
+    }
+
+    #[test]
+    fn test_from_event() {
+        let key_down = |keyboard: KeyboardEventData| {
+            Event {
+                target: Key::Local(0),
+                bubbles: true,
+                cancelable: true,
+                timestamp: 0.0,
+                kind: EventKind::KeyDown(keyboard),
+            }
+        };
+        let ctrl_k = KeyboardEventData {
+            key: "K".to_string(),
+            code: "KeyK".to_string(),
+            modifiers: Modifiers { ctrl: true, shift: true, ..Modifiers::default() },
+            ..KeyboardEventData::default()
+        };
+        assert_eq!(KeyStroke::from_event(&key_down(ctrl_k)), KeyStroke::parse("Ctrl+Shift+K").ok());
+
+        // Older clients only send the char code.
+        let legacy = KeyboardEventData { char_code: 'G' as u32, ..KeyboardEventData::default() };
+        assert_eq!(KeyStroke::from_event(&key_down(legacy)), KeyStroke::parse("Shift+g").ok());
+    }
+
+    #[test]
+    fn test_sequences_and_contexts() {
+        let mut hotkeys = Hotkeys::new();
+        let (search, on_search) = counter();
+        let (top, on_top) = counter();
+        let (save, on_save) = counter();
+        hotkeys.register("Ctrl+K", None, on_search).unwrap();
+        let go = hotkeys.register("g g", None, on_top).unwrap();
+        hotkeys.register("Ctrl+S", Some("editor"), on_save).unwrap();
+
+        assert_eq!(hotkeys.register("g", None, || ()), Err(HotkeyError::Conflict(go)));
+
+        assert!(hotkeys.press(KeyStroke::parse("Ctrl+K").unwrap()));
+        assert!(hotkeys.press(KeyStroke::parse("g").unwrap()));
+        assert!(hotkeys.press(KeyStroke::parse("g").unwrap()));
+        assert!(!hotkeys.press(KeyStroke::parse("Ctrl+S").unwrap()));
+        hotkeys.enable("editor");
+        assert!(hotkeys.press(KeyStroke::parse("Ctrl+S").unwrap()));
+
+        assert_eq!((search.get(), top.get(), save.get()), (1, 1, 1));
+    }
+}
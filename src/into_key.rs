@@ -0,0 +1,280 @@
+// Keys from the application's own identifiers.
+//
+// IntoKey turns integers and strings into local keys, so callers needn't
+// hash ids into Key::Local by hand, an Arc<str> into Key::Str, and with the
+// `uuid` feature UUIDs into Key::Uuid. Integers are used as they are, and
+// 128-bit values folded to 64 bits. Strings are hashed with SipHash-2-4
+// under a fixed seed, so the same string gets the same key on every run and
+// platform, and crafted ids can't easily be made to collide with each other.
+// A KeyHasher hashes under a seed of the caller's, and in debug builds
+// remembers what it hashed to report collisions, which would make siblings
+// share a key. Key::from_hash hashes anything with a Hash impl, such as a
+// tuple of ids, the same way.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use Key;
+
+// The seed strings are hashed under by IntoKey.
+pub const DEFAULT_SEED: (u64, u64) = (0x7275_7374_795f_646f, 0x6d5f_6b65_7973_3031);
+
+// Takes self by reference so that unsized ids, str among them, can
+// implement it.
+#[allow(clippy::wrong_self_convention)]
+pub trait IntoKey {
+    fn into_key(&self) -> Key;
+}
+
+impl IntoKey for Key {
+    fn into_key(&self) -> Key {
+        self.clone()
+    }
+}
+
+impl<T: IntoKey + ?Sized> IntoKey for &T {
+    fn into_key(&self) -> Key {
+        (**self).into_key()
+    }
+}
+
+macro_rules! integer_into_key {
+    ($($t:ty),*) => (
+        $(
+            impl IntoKey for $t {
+                fn into_key(&self) -> Key {
+                    Key::Local(*self as u64)
+                }
+            }
+        )*
+    );
+}
+
+integer_into_key!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl IntoKey for u128 {
+    fn into_key(&self) -> Key {
+        Key::Local((*self >> 64) as u64 ^ *self as u64)
+    }
+}
+
+impl IntoKey for str {
+    fn into_key(&self) -> Key {
+        Key::Local(sip_hash(DEFAULT_SEED, self.as_bytes()))
+    }
+}
+
+impl IntoKey for String {
+    fn into_key(&self) -> Key {
+        self[..].into_key()
+    }
+}
+
+// The string itself, rather than its hash, for ids that must never collide.
+impl IntoKey for Arc<str> {
+    fn into_key(&self) -> Key {
+        Key::Str(self.clone())
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl IntoKey for ::uuid::Uuid {
+    fn into_key(&self) -> Key {
+        Key::Uuid(*self.as_bytes())
+    }
+}
+
+impl Key {
+    // The local key for `value`, hashed under the seed IntoKey uses. Hash
+    // impls may write different bytes on other platforms or Rust versions,
+    // so unlike those of IntoKey these keys only stay the same within one
+    // build.
+    pub fn from_hash<T: Hash + ?Sized>(value: &T) -> Key {
+        let mut hasher = SipHasher(vec![]);
+        value.hash(&mut hasher);
+        Key::Local(hasher.finish())
+    }
+}
+
+// Collects what a value writes to hash it all at once with sip_hash.
+struct SipHasher(Vec<u8>);
+
+impl Hasher for SipHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        sip_hash(DEFAULT_SEED, &self.0)
+    }
+}
+
+// Two strings a KeyHasher hashed to the same key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HashCollision {
+    pub key: Key,
+    pub first: String,
+    pub second: String,
+}
+
+impl fmt::Display for HashCollision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} and {:?} both hash to {:?}", self.first, self.second, self.key)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct KeyHasher {
+    seed: (u64, u64),
+    // The string each key came from, in debug builds only.
+    seen: BTreeMap<u64, String>,
+    collisions: Vec<HashCollision>,
+}
+
+impl Default for KeyHasher {
+    fn default() -> KeyHasher {
+        KeyHasher::new()
+    }
+}
+
+impl KeyHasher {
+    // Hashes as IntoKey does.
+    pub fn new() -> KeyHasher {
+        KeyHasher::with_seed(DEFAULT_SEED.0, DEFAULT_SEED.1)
+    }
+
+    // A secret seed keeps ids chosen by users from being crafted to collide,
+    // as long as every party hashing them shares it.
+    pub fn with_seed(k0: u64, k1: u64) -> KeyHasher {
+        KeyHasher {
+            seed: (k0, k1),
+            seen: BTreeMap::new(),
+            collisions: vec![],
+        }
+    }
+
+    // The local key for `value`. Debug builds record a collision with any
+    // other string hashed before, see collisions.
+    pub fn key(&mut self, value: &str) -> Key {
+        let hash = sip_hash(self.seed, value.as_bytes());
+        if cfg!(debug_assertions) {
+            match self.seen.get(&hash) {
+                Some(first) if first != value => {
+                    self.collisions.push(HashCollision {
+                        key: Key::Local(hash),
+                        first: first.clone(),
+                        second: value.to_string(),
+                    });
+                }
+                Some(_) => {}
+                None => {
+                    self.seen.insert(hash, value.to_string());
+                }
+            }
+        }
+        Key::Local(hash)
+    }
+
+    // The collisions found so far, always empty in release builds.
+    pub fn collisions(&self) -> &[HashCollision] {
+        &self.collisions
+    }
+}
+
+// SipHash-2-4 of `bytes` under the 128-bit key `seed`.
+fn sip_hash(seed: (u64, u64), bytes: &[u8]) -> u64 {
+    let mut v = [seed.0 ^ 0x736f_6d65_7073_6575,
+                 seed.1 ^ 0x646f_7261_6e64_6f6d,
+                 seed.0 ^ 0x6c79_6765_6e65_7261,
+                 seed.1 ^ 0x7465_6462_7974_6573];
+    let chunks = bytes.chunks_exact(8);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut word = [0; 8];
+        word.copy_from_slice(chunk);
+        compress(&mut v, u64::from_le_bytes(word), 2);
+    }
+    let mut last = [0; 8];
+    last[..tail.len()].copy_from_slice(tail);
+    last[7] = bytes.len() as u8;
+    compress(&mut v, u64::from_le_bytes(last), 2);
+    v[2] ^= 0xff;
+    sip_rounds(&mut v, 4);
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+fn compress(v: &mut [u64; 4], word: u64, rounds: usize) {
+    v[3] ^= word;
+    sip_rounds(v, rounds);
+    v[0] ^= word;
+}
+
+fn sip_rounds(v: &mut [u64; 4], rounds: usize) {
+    for _ in 0..rounds {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{sip_hash, IntoKey, KeyHasher};
+    use {Element, Key};
+
+    #[test]
+    fn test_into_key() {
+        // The reference vectors for the key 00 01 .. 0f.
+        let seed = (0x0706_0504_0302_0100, 0x0f0e_0d0c_0b0a_0908);
+        assert_eq!(sip_hash(seed, b""), 0x726f_db47_dd0e_0e31);
+        let bytes: Vec<u8> = (0..15).collect();
+        assert_eq!(sip_hash(seed, &bytes), 0xa129_ca61_49be_45e5);
+
+        assert_eq!(7u32.into_key(), Key::Local(7));
+        assert_eq!((-1i64).into_key(), Key::Local(u64::MAX));
+        assert_eq!("row-1".into_key(), "row-1".to_string().into_key());
+        assert!("row-1".into_key() != "row-2".into_key());
+        assert_eq!(KeyHasher::new().key("row-1"), "row-1".into_key());
+        assert!(KeyHasher::with_seed(1, 2).key("row-1") != "row-1".into_key());
+        assert_eq!(Key::from_hash(&(3, "row-1")), Key::from_hash(&(3, "row-1".to_string())));
+        assert!(Key::from_hash(&(3, "row-1")) != Key::from_hash(&(4, "row-1")));
+
+        let item = Element::void("li").key("row-1").build();
+        assert_eq!(item.to_key(), "row-1".into_key());
+
+        let id: Arc<str> = "row-1".into();
+        assert_eq!(id.into_key(), Key::Str(id.clone()));
+        assert!(Key::Uuid([0xff; 16]) < id.into_key());
+        assert!(id.into_key() < Key::Str("row-2".into()));
+    }
+
+    #[test]
+    fn test_collisions() {
+        let mut hasher = KeyHasher::new();
+        hasher.key("a");
+        hasher.key("a");
+        assert!(hasher.collisions().is_empty());
+        // Pretend "c" was hashed to the key of "b" before.
+        let key = match hasher.key("b") {
+            Key::Local(hash) => hash,
+            _ => unreachable!(),
+        };
+        hasher.seen.insert(key, "c".to_string());
+        hasher.key("b");
+        if cfg!(debug_assertions) {
+            assert_eq!(hasher.collisions().len(), 1);
+            assert_eq!(hasher.collisions()[0].to_string(),
+                       format!("\"c\" and \"b\" both hash to Local({})", key));
+        }
+    }
+}
@@ -0,0 +1,98 @@
+// Partial hydration: only subtrees whose root has Flags::island set are
+// interactive. Server-side rendering still produces the whole page, but the
+// client only hydrates, diffs and wires events inside islands, treating
+// everything else as static markup that never changes.
+use {DiffTree, Element, Key};
+
+impl Element {
+    // Diffs only the islands of this tree against those of `other`. Nodes
+    // outside islands are assumed static and are only walked to find the
+    // islands beneath them; an island missing from either tree is ignored.
+    pub fn diff_islands(&self, other: &Element) -> Option<DiffTree> {
+        let left = self.force();
+        let right = other.force();
+        if right.view().flags().island {
+            return left.diff(&right);
+        }
+        let mut children = vec![];
+        if let (&Element::Parent { children: ref left_children, keymap: ref left_keymap, .. },
+                &Element::Parent { children: ref right_children, keymap: ref right_keymap, .. }) =
+            (&*left, &*right) {
+            for (key, &index) in left_keymap {
+                if let Some(&index_) = right_keymap.get(key) {
+                    if let Some(diff) = left_children[index].diff_islands(&right_children[index_]) {
+                        children.push((key.clone(), diff));
+                    }
+                }
+            }
+        }
+        if children.is_empty() {
+            None
+        } else {
+            Some(DiffTree {
+                changes: None,
+                children: Some(children.into_boxed_slice()),
+                base_checksum: None,
+            })
+        }
+    }
+
+    // The key paths of the outermost islands, the root excluded, in document
+    // order. Events targeting nodes outside these need no handlers. Lazy
+    // nodes are not evaluated, so islands inside them aren't found.
+    pub fn island_paths(&self) -> Vec<Vec<Key>> {
+        let mut paths = vec![];
+        let mut path = vec![];
+        collect(self, &mut path, &mut paths);
+        paths
+    }
+}
+
+fn collect(element: &Element, path: &mut Vec<Key>, paths: &mut Vec<Vec<Key>>) {
+    let view = element.view();
+    if view.flags().island {
+        paths.push(path.clone());
+        return;
+    }
+    for child in view.children() {
+        path.push(child.key());
+        collect(child.element(), path, paths);
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Element, Key};
+
+    fn text(value: &str) -> Element {
+        Element::Text {
+            key: Key::Local(0),
+            value: value.to_string(),
+        }
+    }
+
+    fn page(article: &str, count: &str) -> Element {
+        let mut counter = el!(button[key=0, text(count)]);
+        if let Element::Parent { ref mut flags, .. } = counter {
+            flags.island = true;
+        }
+        el!(body[
+            key=0,
+            el!(article[key=1, text(article)]),
+            el!(aside[key=2, counter])
+        ])
+    }
+
+    #[test]
+    fn test_diff_islands() {
+        let before = page("static", "1");
+        assert_eq!(before.island_paths(), vec![vec![Key::Local(2), Key::Local(0)]]);
+
+        let diff = before.diff_islands(&page("changed", "2")).unwrap();
+        let (ref key, ref aside) = diff.children.as_ref().unwrap()[0];
+        assert_eq!(*key, Key::Local(2));
+        assert_eq!(aside.children.as_ref().unwrap()[0].0, Key::Local(0));
+        assert_eq!(diff.children.as_ref().unwrap().len(), 1);
+    }
+}
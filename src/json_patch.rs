@@ -0,0 +1,510 @@
+// Conversion of a DiffTree into RFC 6902 JSON Patch operations.
+//
+// The patch targets the JSON representation of the tree produced by the
+// ToJson impls below: every node is an object with a "type" and "key", text
+// nodes carry a "value", elements carry their "attributes", "listeners" and
+// "flags", and parents carry their children as an ordered "children" array.
+// Attributes are an object keyed by qualified name whose values are strings,
+// or objects with a "namespace" and "value" for namespaced attributes, and
+// listeners an object of handler ids keyed by event name. Because a
+// DiffTree addresses children by Key while JSON Pointer addresses them by
+// index, the conversion needs the base tree the diff was computed against.
+// A MoveNode is written as a removal and an add of the node as it ends up.
+use std::cmp;
+use std::collections::BTreeMap;
+
+use rustc_serialize::json::{Json, ToJson};
+use ssr::key_marker;
+use text_diff::splice;
+use {AttrName, Attributes, Change, DiffTree, Element, Flags, Key, Listeners, PatchError};
+
+#[derive(Debug, PartialEq)]
+pub enum Operation {
+    Add { path: String, value: Json },
+    Remove { path: String },
+    Replace { path: String, value: Json },
+    Move { from: String, path: String },
+}
+
+impl ToJson for Operation {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        match *self {
+            Operation::Add { ref path, ref value } => {
+                object.insert("op".to_string(), "add".to_json());
+                object.insert("path".to_string(), path.to_json());
+                object.insert("value".to_string(), value.clone());
+            }
+            Operation::Remove { ref path } => {
+                object.insert("op".to_string(), "remove".to_json());
+                object.insert("path".to_string(), path.to_json());
+            }
+            Operation::Replace { ref path, ref value } => {
+                object.insert("op".to_string(), "replace".to_json());
+                object.insert("path".to_string(), path.to_json());
+                object.insert("value".to_string(), value.clone());
+            }
+            Operation::Move { ref from, ref path } => {
+                object.insert("op".to_string(), "move".to_json());
+                object.insert("from".to_string(), from.to_json());
+                object.insert("path".to_string(), path.to_json());
+            }
+        }
+        Json::Object(object)
+    }
+}
+
+impl ToJson for Key {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        match *self {
+            Key::Local(value) => object.insert("local".to_string(), value.to_json()),
+            Key::Global(value) => object.insert("global".to_string(), value.to_json()),
+            Key::Uuid(_) => object.insert("uuid".to_string(), key_marker(self)[1..].to_json()),
+            Key::Str(ref value) => object.insert("str".to_string(), value.to_json()),
+        };
+        Json::Object(object)
+    }
+}
+
+impl ToJson for Flags {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert("observe_visibility".to_string(), self.observe_visibility.to_json());
+        object.insert("observe_resize".to_string(), self.observe_resize.to_json());
+        object.insert("keep_until_transition_end".to_string(),
+                      self.keep_until_transition_end.to_json());
+        object.insert("island".to_string(), self.island.to_json());
+        Json::Object(object)
+    }
+}
+
+// Lazy nodes are evaluated, so the JSON form is that of the resolved tree.
+impl ToJson for Element {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        match *self {
+            Element::Text { ref key, ref value } => {
+                object.insert("type".to_string(), "text".to_json());
+                object.insert("key".to_string(), key.to_json());
+                object.insert("value".to_string(), value.to_json());
+            }
+            Element::Void { ref key, ref name, ref attributes, ref listeners, ref flags } => {
+                object.insert("type".to_string(), "void".to_json());
+                object.insert("key".to_string(), key.to_json());
+                object.insert("name".to_string(), name.to_json());
+                object.insert("attributes".to_string(), attributes_to_json(attributes));
+                object.insert("listeners".to_string(), listeners_to_json(listeners));
+                object.insert("flags".to_string(), flags.to_json());
+            }
+            Element::Parent { ref key, ref name, ref attributes, ref listeners, ref children, ref flags,
+                              .. } => {
+                object.insert("type".to_string(), "parent".to_json());
+                object.insert("key".to_string(), key.to_json());
+                object.insert("name".to_string(), name.to_json());
+                object.insert("attributes".to_string(), attributes_to_json(attributes));
+                object.insert("listeners".to_string(), listeners_to_json(listeners));
+                object.insert("children".to_string(), children.to_json());
+                object.insert("flags".to_string(), flags.to_json());
+            }
+            Element::Lazy { .. } => return self.force().to_json(),
+        }
+        Json::Object(object)
+    }
+}
+
+fn attributes_to_json(attributes: &Option<Attributes>) -> Json {
+    let attributes = match *attributes {
+        Some(ref attributes) => attributes,
+        None => return Json::Null,
+    };
+    let mut object = BTreeMap::new();
+    for (name, value) in attributes {
+        object.insert(name.name.clone(), attribute_to_json(name, value));
+    }
+    Json::Object(object)
+}
+
+fn listeners_to_json(listeners: &Listeners) -> Json {
+    Json::Object(listeners.iter().map(|(name, handler)| (name.clone(), handler.0.to_json())).collect())
+}
+
+fn attribute_to_json(name: &AttrName, value: &str) -> Json {
+    match name.namespace {
+        Some(ref namespace) => {
+            let mut namespaced = BTreeMap::new();
+            namespaced.insert("namespace".to_string(), namespace.to_json());
+            namespaced.insert("value".to_string(), value.to_json());
+            Json::Object(namespaced)
+        }
+        None => value.to_json(),
+    }
+}
+
+impl DiffTree {
+    // Converts this diff into JSON Patch operations against the JSON form of
+    // `base`, the tree the diff was computed from. Fails as Element::apply
+    // would if the diff doesn't apply to `base`.
+    pub fn to_json_patch(&self, base: &Element) -> Result<Vec<Operation>, PatchError> {
+        let mut operations = vec![];
+        if self.moves().is_empty() {
+            self.validate_against(base)?;
+            convert(base, self, String::new(), &mut operations);
+        } else {
+            let mut next = base.clone();
+            next.apply(self)?;
+            convert(base, &self.clone().split_moves(&next), String::new(), &mut operations);
+        }
+        Ok(operations)
+    }
+}
+
+// Operations for a node are emitted before those of its children, and the
+// children are addressed by their index after the node's own changes have
+// been applied.
+fn convert(base: &Element, diff: &DiffTree, path: String, operations: &mut Vec<Operation>) {
+    let base = base.force();
+    let base = &*base;
+    let mut order: Vec<Key> = match *base {
+        Element::Parent { ref children, .. } => children.iter().map(|x| x.to_key()).collect(),
+        _ => vec![],
+    };
+    // The JSON form has `null` rather than an empty object for a node
+    // without attributes.
+    let mut has_attributes = base.view().attributes().is_some();
+    // JSON Patch can't splice strings, so SpliceText replaces the whole
+    // value, tracked here as text changes are applied in turn.
+    let mut text = base.view().text().map(|x| x.to_string());
+
+    if let Some(ref changes) = diff.changes {
+        for change in changes.iter() {
+            match *change {
+                // The JSON form has no transitions, so exiting nodes go at
+                // once and their later RemoveChild finds nothing to remove.
+                Change::RemoveChild(ref key) |
+                Change::MarkExiting(ref key) => {
+                    if let Some(index) = order.iter().position(|x| x == key) {
+                        order.remove(index);
+                        operations.push(Operation::Remove {
+                            path: format!("{}/children/{}", path, index),
+                        });
+                    }
+                }
+                Change::InsertChild(index, ref element) => {
+                    let index = cmp::min(index, order.len());
+                    order.insert(index, element.to_key());
+                    operations.push(Operation::Add {
+                        path: format!("{}/children/{}", path, index),
+                        value: element.to_json(),
+                    });
+                }
+                Change::MoveChild(ref key, index) => {
+                    if let Some(current) = order.iter().position(|x| x == key) {
+                        order.remove(current);
+                        let index = cmp::min(index, order.len());
+                        order.insert(index, key.clone());
+                        if current != index {
+                            operations.push(Operation::Move {
+                                from: format!("{}/children/{}", path, current),
+                                path: format!("{}/children/{}", path, index),
+                            });
+                        }
+                    }
+                }
+                Change::SortChildren(ref keys) => {
+                    for (index, key) in keys.iter().enumerate() {
+                        let current = match order.iter().position(|x| x == key) {
+                            Some(current) => current,
+                            None => continue,
+                        };
+                        if current != index {
+                            let key = order.remove(current);
+                            order.insert(index, key.clone());
+                            operations.push(Operation::Move {
+                                from: format!("{}/children/{}", path, current),
+                                path: format!("{}/children/{}", path, index),
+                            });
+                        }
+                    }
+                }
+                Change::UpdateText(ref value) => {
+                    text = Some(value.clone());
+                    operations.push(Operation::Replace {
+                        path: format!("{}/value", path),
+                        value: value.to_json(),
+                    });
+                }
+                Change::SpliceText { start, delete, ref insert } => {
+                    text = text.and_then(|x| splice(&x, start, delete, insert));
+                    if let Some(ref value) = text {
+                        operations.push(Operation::Replace {
+                            path: format!("{}/value", path),
+                            value: value.to_json(),
+                        });
+                    }
+                }
+                Change::ReplaceNode(ref element) => {
+                    operations.push(Operation::Replace {
+                        path: path,
+                        value: element.to_json(),
+                    });
+                    return;
+                }
+                Change::SetAttribute(ref name, ref value) => {
+                    set_attribute(&path, name, value, &mut has_attributes, operations);
+                }
+                Change::RemoveAttribute(ref name) => remove_attribute(&path, name, operations),
+                Change::UpdateAttributes { ref set, ref remove } => {
+                    for name in remove {
+                        remove_attribute(&path, name, operations);
+                    }
+                    for &(ref name, ref value) in set {
+                        set_attribute(&path, name, value, &mut has_attributes, operations);
+                    }
+                }
+                Change::ObserveVisibility(_) => {
+                    operations.push(Operation::Replace {
+                        path: format!("{}/flags/observe_visibility", path),
+                        value: true.to_json(),
+                    });
+                }
+                Change::UnobserveVisibility(_) => {
+                    operations.push(Operation::Replace {
+                        path: format!("{}/flags/observe_visibility", path),
+                        value: false.to_json(),
+                    });
+                }
+                Change::ObserveResize(_) => {
+                    operations.push(Operation::Replace {
+                        path: format!("{}/flags/observe_resize", path),
+                        value: true.to_json(),
+                    });
+                }
+                Change::UnobserveResize(_) => {
+                    operations.push(Operation::Replace {
+                        path: format!("{}/flags/observe_resize", path),
+                        value: false.to_json(),
+                    });
+                }
+                Change::AddListener(_, ref name, handler) => {
+                    operations.push(Operation::Add {
+                        path: format!("{}/listeners/{}", path, escape_pointer(name)),
+                        value: handler.0.to_json(),
+                    });
+                }
+                Change::RemoveListener(_, ref name) => {
+                    operations.push(Operation::Remove {
+                        path: format!("{}/listeners/{}", path, escape_pointer(name)),
+                    });
+                }
+                // Commands leave the JSON form of the tree unchanged.
+                Change::Focus(_) |
+                Change::ScrollIntoView { .. } |
+                Change::Measure { .. } |
+                Change::SetStyle { .. } => {}
+                // Split up by to_json_patch.
+                Change::MoveNode { .. } => {}
+            }
+        }
+    }
+
+    if let Some(ref children) = diff.children {
+        for &(ref key, ref child) in children.iter() {
+            let index = order.iter().position(|x| x == key);
+            if let (Some(index), Some(base_child)) = (index, base.get_child(key)) {
+                convert(base_child,
+                        child,
+                        format!("{}/children/{}", path, index),
+                        operations);
+            }
+        }
+    }
+}
+
+fn set_attribute(path: &str,
+                 name: &str,
+                 value: &str,
+                 has_attributes: &mut bool,
+                 operations: &mut Vec<Operation>) {
+    if !*has_attributes {
+        operations.push(Operation::Add {
+            path: format!("{}/attributes", path),
+            value: Json::Object(BTreeMap::new()),
+        });
+        *has_attributes = true;
+    }
+    operations.push(Operation::Add {
+        path: format!("{}/attributes/{}", path, escape_pointer(name)),
+        value: attribute_to_json(&AttrName::from(name), value),
+    });
+}
+
+fn remove_attribute(path: &str, name: &str, operations: &mut Vec<Operation>) {
+    operations.push(Operation::Remove {
+        path: format!("{}/attributes/{}", path, escape_pointer(name)),
+    });
+}
+
+// Escapes a member name for use in a JSON Pointer, as RFC 6901 requires.
+fn escape_pointer(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use rustc_serialize::json::{Json, ToJson};
+    use super::Operation;
+    use {AttrName, Attributes, Element, Flags, Key, Listeners, PatchError};
+
+    #[test]
+    fn test_namespaced_attributes() {
+        let mut attributes = Attributes::new();
+        attributes.insert(AttrName::from("xlink:href"), "#icon".to_string());
+        attributes.insert(AttrName::from("class"), "icon".to_string());
+        let element = Element::Void {
+            key: Key::Local(0),
+            name: "use".to_string(),
+            attributes: Some(attributes),
+            listeners: Listeners::new(),
+            flags: Flags::default(),
+        };
+
+        assert_eq!(element.to_json().find("attributes").unwrap().to_string(),
+                   "{\"class\":\"icon\",\"xlink:href\":{\"namespace\":\
+                    \"http://www.w3.org/1999/xlink\",\"value\":\"#icon\"}}");
+    }
+
+    #[test]
+    fn test_attribute_changes() {
+        let element = |attributes: &[(&str, &str)]| {
+            Element::Void {
+                key: Key::Local(0),
+                name: "a".to_string(),
+                attributes: Some(attributes.iter()
+                    .map(|&(name, value)| (AttrName::from(name), value.to_string()))
+                    .collect()),
+                listeners: Listeners::new(),
+                flags: Flags::default(),
+            }
+        };
+        let left = el!(a[key=0]);
+        let right = element(&[("xlink:href", "#top")]);
+        assert_eq!(left.diff(&right).unwrap().to_json_patch(&left).unwrap(),
+                   vec![Operation::Add {
+                            path: "/attributes".to_string(),
+                            value: Json::Object(BTreeMap::new()),
+                        },
+                        Operation::Add {
+                            path: "/attributes/xlink:href".to_string(),
+                            value: right.to_json().find("attributes").unwrap().find("xlink:href").unwrap().clone(),
+                        }]);
+
+        let left = element(&[("class", "x"), ("id", "y")]);
+        let right = element(&[("class", "z")]);
+        assert_eq!(left.diff(&right).unwrap().to_json_patch(&left).unwrap(),
+                   vec![Operation::Remove { path: "/attributes/id".to_string() },
+                        Operation::Add {
+                            path: "/attributes/class".to_string(),
+                            value: "z".to_json(),
+                        }]);
+    }
+
+    #[test]
+    fn test_insert_and_sort() {
+        let left = el!(div[
+            key=0,
+            el!(div[key=1]),
+            el!(div[key=2])
+        ]);
+        let right = el!(div[
+            key=0,
+            el!(div[key=0]),
+            el!(div[key=2]),
+            el!(div[key=1])
+        ]);
+        let patch = left.diff(&right).unwrap().to_json_patch(&left).unwrap();
+
+        assert_eq!(patch, vec![
+            Operation::Move {
+                from: "/children/0".to_string(),
+                path: "/children/1".to_string(),
+            },
+            Operation::Add {
+                path: "/children/0".to_string(),
+                value: el!(div[key=0]).to_json(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_nested_text_update() {
+        let left = el!(div[
+            key=0,
+            el!(div[key=1]),
+            Element::Text { key: Key::Local(2), value: "foo".to_string() }
+        ]);
+        let right = el!(div[
+            key=0,
+            Element::Text { key: Key::Local(2), value: "bar".to_string() }
+        ]);
+        let patch = left.diff(&right).unwrap().to_json_patch(&left).unwrap();
+
+        assert_eq!(patch, vec![
+            Operation::Remove { path: "/children/0".to_string() },
+            Operation::Replace {
+                path: "/children/0/value".to_string(),
+                value: "bar".to_json(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_stable_output() {
+        let element = |names: &[&str]| {
+            Element::Void {
+                key: Key::Local(3),
+                name: "input".to_string(),
+                attributes: Some(names.iter()
+                    .map(|name| (AttrName::new(name), name.to_string()))
+                    .collect()),
+                listeners: Listeners::new(),
+                flags: Flags::default(),
+            }
+        };
+        let left = el!(div[key=0, el!(div[key=4]), el!(div[key=1])]);
+        let right = el!(div[key=0, element(&["type", "name", "id"]), el!(div[key=2])]);
+        let patch = |right: &Element| {
+            let operations = left.diff(right).unwrap().to_json_patch(&left).unwrap();
+            operations.iter().map(|x| x.to_json().to_string()).collect::<Vec<_>>().join(",")
+        };
+
+        let expected = patch(&right);
+        assert_eq!(expected,
+                   patch(&el!(div[key=0, element(&["id", "type", "name"]), el!(div[key=2])])));
+        assert!(expected.starts_with("{\"op\":\"remove\",\"path\":\"/children/1\"}"));
+        assert!(expected.contains("\"attributes\":{\"id\":\"id\",\"name\":\"name\",\"type\":\"type\"}"));
+    }
+
+    #[test]
+    fn test_moves() {
+        let moved = || Element::text(Key::Global(5), "x");
+        let left = el!(div[key=0, el!(ul[key=1, el!(li[key=3]), moved()]), el!(ol[key=2, el!(li[key=4])])]);
+        let right = el!(div[key=0, el!(ul[key=1, el!(li[key=3])]), el!(ol[key=2, el!(li[key=4]), moved()])]);
+        let diff = left.diff(&right).unwrap();
+        assert_eq!(diff.moves().len(), 1);
+        assert_eq!(diff.to_json_patch(&left).unwrap(),
+                   vec![Operation::Remove { path: "/children/0/children/1".to_string() },
+                        Operation::Add {
+                            path: "/children/1/children/1".to_string(),
+                            value: moved().to_json(),
+                        }]);
+
+        // A diff that doesn't apply to the base is an error, with or without
+        // moves.
+        assert_eq!(diff.to_json_patch(&right),
+                   Err(PatchError::NoSuchNode(vec![Key::Local(1), Key::Global(5)])));
+        let text = Element::text(Key::Local(0), "a");
+        assert!(text.diff(&Element::text(Key::Local(0), "b")).unwrap().to_json_patch(&left).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+// Hands out Key::Local values so callers don't have to invent integer keys.
+//
+// Keys only need to be unique among siblings, so every scope counts from
+// zero: entering a scope for a component instance and allocating its children
+// in order produces the same keys on every render, which is what lets the
+// diff match them up across frames. Keys for items that come from data
+// are better derived from their ids, with IntoKey or Key::from_hash, so
+// they follow the items as the list changes.
+use Key;
+
+#[derive(Debug, Default)]
+pub struct KeyAllocator {
+    next: u64,
+    scopes: Vec<u64>,
+}
+
+// The allocator state captured by KeyAllocator::snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeySnapshot {
+    next: u64,
+    scopes: Vec<u64>,
+}
+
+impl KeyAllocator {
+    pub fn new() -> KeyAllocator {
+        KeyAllocator::default()
+    }
+
+    // Returns a key not yet handed out in the current scope.
+    pub fn allocate(&mut self) -> Key {
+        let key = Key::Local(self.next);
+        self.next += 1;
+        key
+    }
+
+    // Starts a nested scope, e.g. for the children of a component instance.
+    pub fn enter(&mut self) {
+        self.scopes.push(self.next);
+        self.next = 0;
+    }
+
+    // Leaves the current scope and resumes the enclosing one where it left
+    // off. Panics if there is no scope to leave.
+    pub fn exit(&mut self) {
+        self.next = self.scopes.pop().expect("KeyAllocator::exit called without a matching enter");
+    }
+
+    // Number of scopes currently entered.
+    pub fn depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    pub fn snapshot(&self) -> KeySnapshot {
+        KeySnapshot {
+            next: self.next,
+            scopes: self.scopes.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: KeySnapshot) {
+        self.next = snapshot.next;
+        self.scopes = snapshot.scopes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyAllocator;
+    use Key;
+
+    #[test]
+    fn test_scopes() {
+        let mut keys = KeyAllocator::new();
+        assert_eq!(keys.allocate(), Key::Local(0));
+        keys.enter();
+        assert_eq!(keys.allocate(), Key::Local(0));
+        assert_eq!(keys.allocate(), Key::Local(1));
+        keys.exit();
+        assert_eq!(keys.allocate(), Key::Local(1));
+        assert_eq!(keys.depth(), 0);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut keys = KeyAllocator::new();
+        keys.allocate();
+        keys.enter();
+        let snapshot = keys.snapshot();
+        keys.allocate();
+        keys.exit();
+        keys.restore(snapshot);
+
+        assert_eq!(keys.depth(), 1);
+        assert_eq!(keys.allocate(), Key::Local(0));
+        keys.exit();
+        assert_eq!(keys.allocate(), Key::Local(1));
+    }
+}
@@ -0,0 +1,113 @@
+// The paths of the globally keyed nodes of a tree, kept in step with the
+// patches applied to it so that finding an event's target doesn't walk the
+// tree on every event.
+//
+// Only global keys are indexed, since a local key only identifies a node
+// among its siblings. Paths exclude the root, as in MutationObserver, so the
+// root's own key maps to the empty path. An entry is dropped when the node at
+// its path is removed, but not when a node of the same key is removed from
+// elsewhere: a node moving between parents is reported as inserted at its
+// new path and removed from its old one, in either order.
+use std::collections::HashMap;
+
+use mutation::MutationObserver;
+use {DiffTree, Element, Key};
+
+#[derive(Debug, Default)]
+pub(crate) struct KeyIndex {
+    paths: HashMap<Key, Vec<Key>>,
+}
+
+impl KeyIndex {
+    pub(crate) fn new(root: &Element) -> KeyIndex {
+        let mut index = KeyIndex::default();
+        let mut path = vec![];
+        index.insert(root, &mut path);
+        index
+    }
+
+    pub(crate) fn get(&self, key: &Key) -> Option<&[Key]> {
+        self.paths.get(key).map(|path| &path[..])
+    }
+
+    // Updates the index for `diff` being applied to `base`.
+    pub(crate) fn update(&mut self, base: &Element, diff: &DiffTree) {
+        diff.observe(base, self);
+    }
+
+    // Drops the entries of the node at `path` and its descendants, for nodes
+    // leaving the document outside of a diff, such as exiting ones.
+    pub(crate) fn remove_path(&mut self, path: &[Key]) {
+        self.paths.retain(|_, x| !x.starts_with(path));
+    }
+
+    fn insert(&mut self, element: &Element, path: &mut Vec<Key>) {
+        if let Element::Lazy { .. } = *element {
+            return self.insert(&element.force(), path);
+        }
+        if let Key::Global(_) = element.to_key() {
+            self.paths.insert(element.to_key(), path.clone());
+        }
+        for child in element.view().children() {
+            path.push(child.key());
+            self.insert(child.element(), path);
+            path.pop();
+        }
+    }
+
+    fn remove(&mut self, element: &Element, path: &mut Vec<Key>) {
+        if let Element::Lazy { .. } = *element {
+            return self.remove(&element.force(), path);
+        }
+        if let Key::Global(_) = element.to_key() {
+            if self.get(&element.to_key()) == Some(&path[..]) {
+                self.paths.remove(&element.to_key());
+            }
+        }
+        for child in element.view().children() {
+            path.push(child.key());
+            self.remove(child.element(), path);
+            path.pop();
+        }
+    }
+}
+
+impl MutationObserver for KeyIndex {
+    fn on_node_inserted(&mut self, path: &[Key], node: &Element) {
+        self.insert(node, &mut path.to_vec());
+    }
+
+    fn on_node_removed(&mut self, path: &[Key], node: &Element) {
+        self.remove(node, &mut path.to_vec());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyIndex;
+    use {Element, Key};
+
+    fn text(key: u64, value: &str) -> Element {
+        Element::Text {
+            key: Key::Global(key),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_update() {
+        let before = el!(div[key=0, el!(ul[key=1, text(7, "a")]), el!(ol[key=2, text(8, "b")])]);
+        let after = el!(div[key=0, el!(ul[key=1]), el!(ol[key=2, text(8, "b"), text(7, "a")])]);
+        let mut index = KeyIndex::new(&before);
+        assert_eq!(index.get(&Key::Global(7)), Some(&[Key::Local(1), Key::Global(7)][..]));
+        assert_eq!(index.get(&Key::Local(1)), None);
+
+        index.update(&before, &before.diff(&after).unwrap());
+        assert_eq!(index.get(&Key::Global(7)), Some(&[Key::Local(2), Key::Global(7)][..]));
+
+        let empty = el!(div[key=0, el!(ul[key=1])]);
+        index.update(&after, &after.diff(&empty).unwrap());
+        assert_eq!(index.get(&Key::Global(7)), None);
+        assert_eq!(index.get(&Key::Global(8)), None);
+    }
+}
@@ -0,0 +1,190 @@
+// A virtual DOM: element trees, the patches between them, and the events
+// renderers report back.
+//
+// The core lives in four modules: `element` for the tree itself, `diff` for
+// computing patches, `patch` for their representation and `event` for
+// events. Their types, and those of the features built on them, are
+// re-exported here.
+extern crate rustc_serialize;
+#[cfg(feature = "bench")]
+extern crate criterion;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+#[cfg(feature = "unicode-normalization")]
+extern crate unicode_normalization;
+#[cfg(feature = "uuid")]
+extern crate uuid;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+#[cfg(feature = "wasm")]
+extern crate web_sys;
+
+pub use animate::{Animation, AnimationId, Easing, Timeline};
+pub use app::App;
+pub use attr::{AttrName, Attributes};
+pub use attr_policy::{AttributePolicy, Rejection, SafeAttributes, Verdict, GUARDED_ATTRIBUTES};
+pub use audit::{AuditRecord, AuditSink, JsonLines};
+pub use builder::{Child, ElementBuilder, IntoChild};
+pub use change_graph::ChangeGraph;
+pub use children::Children;
+pub use critical_css::{critical_css, critical_style};
+pub use element::{Element, Flags, HandlerId, Key, Listeners, Thunk, CAPTURE_SUFFIX};
+pub use event::{DataTransfer, DragEventData, Event, EventKind, EventType, KeyboardEventData, Modifiers,
+                MouseEventData, PointerEventData, TouchEventData, TouchPoint};
+pub use explain::{diff_explain, diff_explain_with, Explanation, Reason};
+pub use diff_config::DiffConfig;
+pub use diff_ref::{ChangeRef, DiffRef};
+pub use dictionary::Dictionary;
+#[cfg(feature = "wasm")]
+pub use dom_patcher::{Batching, DomPatcher, DomRenderer};
+pub use element_ref::{ChildIter, ElementRef};
+pub use error::{DiffError, DomError, FrameError, PatchError};
+pub use focus_trap::FocusTrap;
+pub use fold::{render_above_fold, FoldSplit, FOLD_MARKER};
+pub use framing::{checksum, decode_frame, encode_frame, Codec, Encoding, Frame, Identity};
+pub use global_keys::{GlobalKeyRegistry, KeyCollision};
+pub use handlers::{Dispatched, Handled, HandlerRegistry};
+pub use hotkeys::{HotkeyError, HotkeyId, Hotkeys, KeyStroke};
+pub use into_key::{HashCollision, IntoKey, KeyHasher};
+pub use json_patch::Operation;
+pub use key_allocator::{KeyAllocator, KeySnapshot};
+pub use lint::{lint, LintIssue, LintKind};
+pub use measure::{MeasureRequests, Rect};
+pub use mutation::MutationObserver;
+pub use no_script::{Fallback, Fallbacks, FormMethod};
+pub use node_ids::{NodeId, NodeIds};
+#[cfg(feature = "unicode-normalization")]
+pub use normalize::{nfc, nfc_eq};
+pub use parse::ParseError;
+pub use patch::{Change, DiffTree, ScrollBehavior, ScrollBlock};
+pub use patch_buffer::PatchBuffer;
+pub use protocol::{Capabilities, ChangeKind, Envelope, PROTOCOL_VERSION};
+pub use rate_limit::RateLimiter;
+pub use renderer::{RenderDriver, Renderer, StringRenderer, TestRenderer};
+pub use resource_hints::{link_header, HintKind, ResourceHint};
+pub use selector::{Selector, SelectorError};
+pub use session_store::{SessionLimits, SessionStore};
+pub use snapshot::FrozenElement;
+pub use ssr::{escape, render_stream};
+pub use stats::TreeStats;
+pub use style_dedup::DedupedStyles;
+pub use telemetry::{DiffStats, SubtreeStats, Telemetry};
+pub use text_diff::TextDiff;
+pub use text_keys::TextKeys;
+pub use text_patch::TextPatch;
+pub use vdom::{HistoryEntry, KeyPolicy, SubscriptionId, VirtualDom};
+
+#[cfg(test)]
+macro_rules! el {
+    ($name:ident[key=$value:expr]) => (
+        {
+            $crate::Element::Void{
+                key: $crate::Key::Local($value),
+                name: stringify!($name).to_string(),
+                attributes: None,
+                listeners: $crate::Listeners::new(),
+                flags: $crate::Flags::here(),
+            }
+        }
+    );
+    ($name:ident[]) => (
+        {
+            $crate::Element::Void{
+                key: $crate::Key::Local(0),
+                name: stringify!($name).to_string(),
+                attributes: None,
+                listeners: $crate::Listeners::new(),
+                flags: $crate::Flags::here(),
+            }
+        }
+    );
+    ($name:ident[key=$value:expr, $($child:expr),* ]) => (
+        {
+            let mut children = vec![];
+            let mut keymap = ::std::collections::BTreeMap::new();
+            let mut index = 0;
+            $(
+                let child = $child;
+                keymap.entry(child.to_key()).or_insert(index);
+                children.push(child);
+                index += 1;
+            )*
+
+            $crate::Element::Parent{
+                key: $crate::Key::Local($value),
+                name: stringify!($name).to_string(),
+                keymap: keymap,
+                attributes: None,
+                listeners: $crate::Listeners::new(),
+                children: children,
+                flags: $crate::Flags::here(),
+            }
+        }
+    );
+}
+
+mod animate;
+mod app;
+mod attr;
+mod attr_policy;
+mod audit;
+#[cfg(feature = "bench")]
+pub mod bench;
+mod builder;
+mod change_graph;
+mod children;
+mod content_hash;
+mod critical_css;
+pub mod diff;
+mod diff_config;
+mod diff_ref;
+mod dictionary;
+#[cfg(feature = "wasm")]
+mod dom_patcher;
+pub mod element;
+mod element_ref;
+mod error;
+pub mod event;
+mod explain;
+mod focus_trap;
+mod fold;
+mod framing;
+mod global_keys;
+mod handlers;
+mod hotkeys;
+mod islands;
+mod into_key;
+mod json_patch;
+mod key_allocator;
+mod key_index;
+mod lint;
+mod measure;
+mod mutation;
+mod no_script;
+mod node_ids;
+#[cfg(feature = "unicode-normalization")]
+mod normalize;
+pub mod parse;
+pub mod patch;
+mod patch_buffer;
+mod protocol;
+mod rate_limit;
+mod renderer;
+mod resource_hints;
+mod selector;
+#[cfg(feature = "serde")]
+mod serde_impls;
+mod session_store;
+mod snapshot;
+mod ssr;
+mod stats;
+mod style_dedup;
+mod telemetry;
+pub mod testkit;
+mod text_diff;
+mod text_keys;
+mod text_patch;
+mod vdom;
@@ -0,0 +1,258 @@
+// Checks for HTML structure mistakes that browsers silently repair. A `<div>`
+// inside a `<p>` closes the paragraph, a stray `<li>` or nested `<a>` gets
+// moved, and once the live DOM is restructured like this the patches
+// computed against the tree no longer address the nodes they were meant
+// for. Meant for tests and dev mode rather than every render.
+//
+// Lazy subtrees are evaluated so that they are checked like any other.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use {Element, ElementRef, Key};
+
+// Elements that close an open `<p>` when they start.
+const BLOCK_ELEMENTS: &[&str] = &["address", "article", "aside", "blockquote",
+                                  "details", "div", "dl", "fieldset",
+                                  "figcaption", "figure", "footer", "form", "h1",
+                                  "h2", "h3", "h4", "h5", "h6", "header",
+                                  "hgroup", "hr", "main", "menu", "nav", "ol",
+                                  "p", "pre", "section", "table", "ul"];
+
+const LIST_ELEMENTS: &[&str] = &["ol", "ul", "menu"];
+
+const INTERACTIVE_ELEMENTS: &[&str] = &["a", "button", "details", "embed",
+                                        "iframe", "input", "label", "select",
+                                        "textarea"];
+
+// Attributes without which an element is broken or inaccessible.
+const REQUIRED_ATTRIBUTES: &[(&str, &[&str])] =
+    &[("img", &["src", "alt"]),
+      ("area", &["alt"]),
+      ("link", &["rel", "href"]),
+      ("optgroup", &["label"])];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LintIssue {
+    // Key path of the offending node from the root, excluding the root.
+    pub path: Vec<Key>,
+    pub kind: LintKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LintKind {
+    // A block element, named here, inside a `<p>`.
+    BlockInParagraph(String),
+    // An `<li>` whose parent isn't a list.
+    ListItemOutsideList,
+    // An interactive element inside another one, such as a `<button>` in an
+    // `<a href>`.
+    NestedInteractive { outer: String, inner: String },
+    // An id already used by the node at `first`.
+    DuplicateId { id: String, first: Vec<Key> },
+    MissingAttribute { tag: String, attribute: &'static str },
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            LintKind::BlockInParagraph(ref tag) => write!(f, "<{}> inside <p>", tag)?,
+            LintKind::ListItemOutsideList => write!(f, "<li> outside a list")?,
+            LintKind::NestedInteractive { ref outer, ref inner } => {
+                write!(f, "<{}> inside <{}>", inner, outer)?
+            }
+            LintKind::DuplicateId { ref id, ref first } => {
+                write!(f, "id {:?} already used at {:?}", id, first)?
+            }
+            LintKind::MissingAttribute { ref tag, attribute } => {
+                write!(f, "<{}> without {}", tag, attribute)?
+            }
+        }
+        write!(f, " at {:?}", self.path)
+    }
+}
+
+pub fn lint(tree: &Element) -> Vec<LintIssue> {
+    let mut linter = Linter {
+        ancestors: vec![],
+        path: vec![],
+        ids: BTreeMap::new(),
+        issues: vec![],
+    };
+    linter.visit(tree, true);
+    linter.issues
+}
+
+struct Linter {
+    // Tag names from the root to the parent of the node being visited.
+    ancestors: Vec<String>,
+    path: Vec<Key>,
+    ids: BTreeMap<String, Vec<Key>>,
+    issues: Vec<LintIssue>,
+}
+
+impl Linter {
+    fn visit(&mut self, element: &Element, root: bool) {
+        if let Element::Lazy { .. } = *element {
+            return self.visit(&element.force(), root);
+        }
+        let view = element.view();
+        let tag = match view.tag() {
+            Some(tag) => tag,
+            None => return,
+        };
+        if !root {
+            self.path.push(view.key());
+        }
+        self.check(view, tag);
+        self.ancestors.push(tag.to_string());
+        for child in view.children() {
+            self.visit(child.element(), false);
+        }
+        self.ancestors.pop();
+        if !root {
+            self.path.pop();
+        }
+    }
+
+    fn check(&mut self, view: ElementRef, tag: &str) {
+        if BLOCK_ELEMENTS.contains(&tag) && self.ancestors.iter().any(|x| x == "p") {
+            self.report(LintKind::BlockInParagraph(tag.to_string()));
+        }
+        if tag == "li" &&
+           !self.ancestors.last().is_some_and(|x| LIST_ELEMENTS.contains(&&x[..])) {
+            self.report(LintKind::ListItemOutsideList);
+        }
+        if is_interactive(view, tag) {
+            // Ancestors are only known by tag, so an `<a>` without href or an
+            // `<input type=hidden>` counts as interactive here.
+            let outer = self.ancestors
+                .iter()
+                .find(|x| INTERACTIVE_ELEMENTS.contains(&&x[..]))
+                .cloned();
+            if let Some(outer) = outer {
+                self.report(LintKind::NestedInteractive {
+                    outer: outer,
+                    inner: tag.to_string(),
+                });
+            }
+        }
+        if let Some(id) = view.attribute("id") {
+            if let Some(first) = self.ids.get(id).cloned() {
+                self.report(LintKind::DuplicateId {
+                    id: id.to_string(),
+                    first: first,
+                });
+            } else {
+                self.ids.insert(id.to_string(), self.path.clone());
+            }
+        }
+        for &(name, attributes) in REQUIRED_ATTRIBUTES {
+            if name != tag {
+                continue;
+            }
+            for &attribute in attributes {
+                if view.attribute(attribute).is_none() {
+                    self.report(LintKind::MissingAttribute {
+                        tag: tag.to_string(),
+                        attribute: attribute,
+                    });
+                }
+            }
+        }
+    }
+
+    fn report(&mut self, kind: LintKind) {
+        self.issues.push(LintIssue {
+            path: self.path.clone(),
+            kind: kind,
+        });
+    }
+}
+
+fn is_interactive(view: ElementRef, tag: &str) -> bool {
+    match tag {
+        "a" => view.attribute("href").is_some(),
+        "input" => view.attribute("type") != Some("hidden"),
+        _ => INTERACTIVE_ELEMENTS.contains(&tag),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lint, LintIssue, LintKind};
+    use {AttrName, Attributes, Element, Flags, Key, Listeners};
+
+    fn void(key: u64, name: &str, attributes: &[(&str, &str)]) -> Element {
+        let attributes: Attributes = attributes.iter()
+            .map(|&(name, value)| (AttrName::from(name), value.to_string()))
+            .collect();
+        Element::Void {
+            key: Key::Local(key),
+            name: name.to_string(),
+            attributes: Some(attributes),
+            listeners: Listeners::new(),
+            flags: Flags::default(),
+        }
+    }
+
+    #[test]
+    fn test_lint() {
+        let tree = el!(body[
+            key=0,
+            el!(p[key=1, el!(span[key=2, void(3, "div", &[("id", "a")])])]),
+            el!(li[key=4, void(5, "img", &[("id", "a"), ("alt", "")])]),
+            el!(ul[key=6, el!(li[key=7, void(8, "img", &[("src", "x.png"), ("alt", "")])])])
+        ]);
+        let issues = lint(&tree);
+        let path = |keys: &[u64]| keys.iter().map(|&x| Key::Local(x)).collect::<Vec<_>>();
+        assert_eq!(issues,
+                   vec![LintIssue {
+                            path: path(&[1, 2, 3]),
+                            kind: LintKind::BlockInParagraph("div".to_string()),
+                        },
+                        LintIssue {
+                            path: path(&[4]),
+                            kind: LintKind::ListItemOutsideList,
+                        },
+                        LintIssue {
+                            path: path(&[4, 5]),
+                            kind: LintKind::DuplicateId {
+                                id: "a".to_string(),
+                                first: path(&[1, 2, 3]),
+                            },
+                        },
+                        LintIssue {
+                            path: path(&[4, 5]),
+                            kind: LintKind::MissingAttribute {
+                                tag: "img".to_string(),
+                                attribute: "src",
+                            },
+                        }]);
+        assert_eq!(issues[1].to_string(), "<li> outside a list at [Local(4)]");
+    }
+
+    #[test]
+    fn test_nested_interactive() {
+        let tree = el!(div[
+            key=0,
+            el!(a[key=1, void(2, "button", &[])]),
+            el!(label[key=3, void(4, "input", &[("type", "hidden")])]),
+            el!(button[key=5, el!(span[key=6, void(7, "input", &[])])])
+        ]);
+        assert_eq!(lint(&tree),
+                   vec![LintIssue {
+                            path: vec![Key::Local(1), Key::Local(2)],
+                            kind: LintKind::NestedInteractive {
+                                outer: "a".to_string(),
+                                inner: "button".to_string(),
+                            },
+                        },
+                        LintIssue {
+                            path: vec![Key::Local(5), Key::Local(6), Key::Local(7)],
+                            kind: LintKind::NestedInteractive {
+                                outer: "button".to_string(),
+                                inner: "input".to_string(),
+                            },
+                        }]);
+    }
+}
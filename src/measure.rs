@@ -0,0 +1,130 @@
+// Layout measurement through the patch and event pipeline. Components that
+// need real geometry, such as tooltips positioning themselves, add a
+// Change::Measure to the outgoing patch and get the answer back as an
+// Event::Measured.
+use std::collections::BTreeMap;
+
+use {Change, DiffTree, Event, EventKind, Key};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+// Allocates request ids and routes each reply to the callback that asked
+// for it.
+#[derive(Default)]
+pub struct MeasureRequests {
+    next_id: u64,
+    pending: BTreeMap<u64, Box<dyn FnOnce(Rect)>>,
+}
+
+impl MeasureRequests {
+    pub fn new() -> MeasureRequests {
+        MeasureRequests::default()
+    }
+
+    // Adds a measurement of the node at `path` to `diff`, calling `callback`
+    // with its rect once the reply arrives. Returns the request id, or None
+    // for an empty path, as the root has no key in the patch to address.
+    pub fn request<F>(&mut self, diff: &mut DiffTree, path: &[Key], callback: F) -> Option<u64>
+        where F: FnOnce(Rect) + 'static
+    {
+        let key = path.last()?.clone();
+        let id = self.next_id;
+        self.next_id += 1;
+        diff.push_at(path,
+                     Change::Measure {
+                         key: key,
+                         request_id: id,
+                     });
+        self.pending.insert(id, Box::new(callback));
+        Some(id)
+    }
+
+    // Runs the callback for a Measured event. Returns false for other
+    // events and for replies to unknown or cancelled requests.
+    pub fn handle(&mut self, event: &Event) -> bool {
+        match event.kind {
+            EventKind::Measured { request_id, rect } => {
+                match self.pending.remove(&request_id) {
+                    Some(callback) => {
+                        callback(rect);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    // Drops a request whose reply is no longer wanted, for example because
+    // the component asking for it went away.
+    pub fn cancel(&mut self, request_id: u64) -> bool {
+        self.pending.remove(&request_id).is_some()
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use super::{MeasureRequests, Rect};
+    use {Change, DiffTree, Event, EventKind, Key};
+
+    #[test]
+    fn test_request_and_reply() {
+        let mut requests = MeasureRequests::new();
+        let mut diff = DiffTree {
+            changes: None,
+            children: None,
+            base_checksum: None,
+        };
+        let measured = Rc::new(Cell::new(None));
+        let result = measured.clone();
+        let id = requests.request(&mut diff, &[Key::Local(4)], move |rect| result.set(Some(rect)))
+            .unwrap();
+
+        let mut expected = DiffTree {
+            changes: None,
+            children: None,
+            base_checksum: None,
+        };
+        expected.push_at(&[Key::Local(4)],
+                         Change::Measure {
+                             key: Key::Local(4),
+                             request_id: id,
+                         });
+        assert_eq!(diff, expected);
+
+        let rect = Rect {
+            x: 10.0,
+            y: 20.0,
+            width: 100.0,
+            height: 30.0,
+        };
+        let reply = Event {
+            target: Key::Local(4),
+            bubbles: false,
+            cancelable: false,
+            timestamp: 0.0,
+            kind: EventKind::Measured {
+                request_id: id,
+                rect: rect,
+            },
+        };
+        assert!(requests.handle(&reply));
+        assert_eq!(measured.get(), Some(rect));
+        assert!(!requests.handle(&reply));
+        assert_eq!(requests.pending(), 0);
+    }
+}
@@ -0,0 +1,181 @@
+// Callbacks describing what a patch does to a tree, so integrations can keep
+// side indexes (a search index, analytics) in step with the tree without
+// walking it again after every update.
+//
+// Paths are the keys of the children from the root down, the root itself
+// excluded, as in TextPatch.
+//
+// Element::apply_with calls the observer as each change lands. A side that
+// keeps indexes without holding the tree itself, such as NodeIds, can have
+// DiffTree::observe report the same calls from the patch and its base.
+use text_diff::splice;
+use {Change, DiffTree, Element, Key};
+
+pub trait MutationObserver {
+    fn on_node_inserted(&mut self, _path: &[Key], _node: &Element) {}
+
+    fn on_node_removed(&mut self, _path: &[Key], _node: &Element) {}
+
+    fn on_text_changed(&mut self, _path: &[Key], _old: &str, _new: &str) {}
+}
+
+impl DiffTree {
+    // Reports every node this patch inserts, removes or retexts when applied
+    // to `base`, the tree it was computed from. A replaced node is reported
+    // as removed and then inserted, and a moved one as removed from its old
+    // path and inserted at its new one, as it was before the patch.
+    pub fn observe(&self, base: &Element, observer: &mut dyn MutationObserver) {
+        let mut path = vec![];
+        self.observe_at(base, base, &mut path, observer);
+    }
+
+    fn observe_at(&self,
+                  root: &Element,
+                  base: &Element,
+                  path: &mut Vec<Key>,
+                  observer: &mut dyn MutationObserver) {
+        let base = base.force();
+        // Children moved here, found at their old paths.
+        let mut arrived = vec![];
+        if let Some(ref changes) = self.changes {
+            for change in changes.iter() {
+                match *change {
+                    Change::InsertChild(_, ref node) => {
+                        path.push(node.to_key());
+                        observer.on_node_inserted(path, node);
+                        path.pop();
+                    }
+                    Change::RemoveChild(ref key) => {
+                        if let Some(node) = base.get_child(key) {
+                            path.push(key.clone());
+                            observer.on_node_removed(path, node);
+                            path.pop();
+                        }
+                    }
+                    Change::UpdateText(ref value) => {
+                        if let Element::Text { value: ref old, .. } = *base {
+                            observer.on_text_changed(path, old, value);
+                        }
+                    }
+                    Change::SpliceText { start, delete, ref insert } => {
+                        if let Element::Text { value: ref old, .. } = *base {
+                            if let Some(value) = splice(old, start, delete, insert) {
+                                observer.on_text_changed(path, old, &value);
+                            }
+                        }
+                    }
+                    Change::ReplaceNode(ref node) => {
+                        observer.on_node_removed(path, &base);
+                        observer.on_node_inserted(path, node);
+                    }
+                    Change::MoveNode { ref key, ref from, .. } => {
+                        if let Some(node) = root.get_path(from).and_then(|x| x.get_child(key)) {
+                            let mut old = from.to_vec();
+                            old.push(key.clone());
+                            observer.on_node_removed(&old, node);
+                            path.push(key.clone());
+                            observer.on_node_inserted(path, node);
+                            path.pop();
+                            arrived.push((key, node));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if let Some(ref children) = self.children {
+            for &(ref key, ref diff) in children.iter() {
+                let child = match arrived.iter().find(|&&(x, _)| x == key) {
+                    Some(&(_, node)) => Some(node),
+                    None => base.get_child(key),
+                };
+                if let Some(child) = child {
+                    path.push(key.clone());
+                    diff.observe_at(root, child, path, observer);
+                    path.pop();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MutationObserver;
+    use {Element, Key};
+
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    impl MutationObserver for Log {
+        fn on_node_inserted(&mut self, path: &[Key], _node: &Element) {
+            self.0.push(format!("insert {:?}", path));
+        }
+
+        fn on_node_removed(&mut self, path: &[Key], _node: &Element) {
+            self.0.push(format!("remove {:?}", path));
+        }
+
+        fn on_text_changed(&mut self, path: &[Key], old: &str, new: &str) {
+            self.0.push(format!("text {:?} {} -> {}", path, old, new));
+        }
+    }
+
+    #[test]
+    fn test_observe() {
+        let before = el!(ul[
+            key=0,
+            el!(li[key=1]),
+            el!(p[key=2, Element::Text { key: Key::Local(0), value: "a".to_string() }])
+        ]);
+        let after = el!(ul[
+            key=0,
+            el!(p[key=2, Element::Text { key: Key::Local(0), value: "b".to_string() }]),
+            el!(li[key=3])
+        ]);
+        let mut log = Log::default();
+        before.diff(&after).unwrap().observe(&before, &mut log);
+        assert_eq!(log.0,
+                   vec!["remove [Local(1)]",
+                        "insert [Local(3)]",
+                        "text [Local(2), Local(0)] a -> b"]);
+    }
+
+    #[test]
+    fn test_apply_with() {
+        let global = |key: u64, value: &str| Element::text(Key::Global(key), value);
+        let nested = Element::parent("li").key(Key::Global(7)).child(global(8, "a")).build();
+        let before = el!(div[
+            key=0,
+            el!(ul[key=1, el!(li[key=2]), nested]),
+            el!(ol[key=3, el!(li[key=4])])
+        ]);
+        let after = el!(div[
+            key=0,
+            el!(ul[key=1, el!(p[key=2]), global(8, "b")]),
+            el!(ol[key=3, el!(li[key=4]), Element::parent("li").key(Key::Global(7)).build()])
+        ]);
+        let diff = before.diff(&after).unwrap();
+        let mut observed = Log::default();
+        diff.observe(&before, &mut observed);
+
+        let mut log = Log::default();
+        let mut tree = before.clone();
+        tree.apply_with(&diff, &mut log).unwrap();
+        assert_eq!(tree, after);
+        assert_eq!(log.0,
+                   vec!["remove [Local(1), Global(7), Global(8)]",
+                        "insert [Local(1), Global(8)]",
+                        "remove [Local(1), Local(2)]",
+                        "insert [Local(1), Local(2)]",
+                        "text [Local(1), Global(8)] a -> b",
+                        "remove [Local(1), Global(7)]",
+                        "insert [Local(3), Global(7)]"]);
+        assert_eq!(log.0, observed.0);
+
+        // A patch that fails reports nothing.
+        let mut log = Log::default();
+        assert!(tree.apply_with(&diff, &mut log).is_err());
+        assert!(log.0.is_empty());
+    }
+}
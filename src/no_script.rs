@@ -0,0 +1,192 @@
+// Rendering for clients that never run the script establishing the patch
+// channel, such as crawlers, text browsers or browsers with scripts off.
+//
+// Events only reach the app through the patch channel, so without it every
+// click and submit handled there would do nothing. The app instead gives a
+// Fallback for the keys whose events it handles, naming the URL the server
+// also answers that interaction at, and the tree is rendered with plain
+// HTML semantics doing the same:
+//
+// - A Link on an `a` sets its href. A `button` is wrapped in a GET form
+//   submitting to the URL, and any other element is rendered as an `a`.
+// - A Form on a `form` sets its action and method. Any other element, such
+//   as a lone submit button, is wrapped in a form with them.
+//
+// Fallbacks apply to every node with their key, so interactive nodes should
+// have global keys. Lazy nodes are evaluated in place and no markers or
+// scripts are written, as in Element::to_html.
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use ssr::{escape, write_attributes, VOID_ELEMENTS};
+use {AttrName, Attributes, Element, Key};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FormMethod {
+    Get,
+    Post,
+}
+
+impl FormMethod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FormMethod::Get => "get",
+            FormMethod::Post => "post",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fallback {
+    // Activating the node navigates to `href`.
+    Link(String),
+    // Activating the node submits the enclosed fields to `action`.
+    Form { action: String, method: FormMethod },
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Fallbacks {
+    fallbacks: HashMap<Key, Fallback>,
+}
+
+impl Fallbacks {
+    pub fn new() -> Fallbacks {
+        Fallbacks::default()
+    }
+
+    pub fn link(mut self, key: Key, href: &str) -> Fallbacks {
+        self.fallbacks.insert(key, Fallback::Link(href.to_string()));
+        self
+    }
+
+    pub fn form(mut self, key: Key, action: &str, method: FormMethod) -> Fallbacks {
+        self.fallbacks.insert(key,
+                              Fallback::Form {
+                                  action: action.to_string(),
+                                  method: method,
+                              });
+        self
+    }
+
+    pub fn get(&self, key: Key) -> Option<&Fallback> {
+        self.fallbacks.get(&key)
+    }
+}
+
+impl Element {
+    pub fn to_fallback_html(&self, fallbacks: &Fallbacks) -> String {
+        let mut out = vec![];
+        self.write_fallback_html(fallbacks, &mut out).expect("writing to a Vec can't fail");
+        String::from_utf8(out).expect("rendered HTML is UTF-8")
+    }
+
+    pub fn write_fallback_html<W: Write>(&self, fallbacks: &Fallbacks, out: &mut W) -> io::Result<()> {
+        let (key, name, attributes, children) = match *self {
+            Element::Text { ref value, .. } => return out.write_all(escape(value, false).as_bytes()),
+            Element::Lazy { .. } => return self.force().write_fallback_html(fallbacks, out),
+            Element::Void { ref key, ref name, ref attributes, .. } => (key, name, attributes, None),
+            Element::Parent { ref key, ref name, ref attributes, ref children, .. } => {
+                (key, name, attributes, Some(&children[..]))
+            }
+        };
+        let tag = Tag {
+            name: name,
+            attributes: match *attributes {
+                Some(ref attributes) => Cow::Borrowed(attributes),
+                None => Cow::Owned(Attributes::new()),
+            },
+            children: children,
+        };
+        match fallbacks.get(key.clone()) {
+            None => tag.write(out, fallbacks),
+            Some(&Fallback::Link(ref href)) if name == "button" => {
+                write_form(out, href, FormMethod::Get)?;
+                tag.write(out, fallbacks)?;
+                out.write_all(b"</form>")
+            }
+            Some(&Fallback::Link(ref href)) => tag.with("href", href).renamed("a").write(out, fallbacks),
+            Some(&Fallback::Form { ref action, method }) if name == "form" => {
+                tag.with("action", action).with("method", method.as_str()).write(out, fallbacks)
+            }
+            Some(&Fallback::Form { ref action, method }) => {
+                write_form(out, action, method)?;
+                tag.write(out, fallbacks)?;
+                out.write_all(b"</form>")
+            }
+        }
+    }
+}
+
+fn write_form<W: Write>(out: &mut W, action: &str, method: FormMethod) -> io::Result<()> {
+    write!(out, "<form action=\"{}\" method=\"{}\">", escape(action, true), method.as_str())
+}
+
+// An element as it is to be written, once its fallback is applied.
+struct Tag<'a> {
+    name: &'a str,
+    attributes: Cow<'a, Attributes>,
+    // None for a void element.
+    children: Option<&'a [Element]>,
+}
+
+impl<'a> Tag<'a> {
+    fn with(mut self, name: &str, value: &str) -> Tag<'a> {
+        self.attributes.to_mut().insert(AttrName::new(name), value.to_string());
+        self
+    }
+
+    fn renamed(self, name: &'a str) -> Tag<'a> {
+        Tag { name: name, ..self }
+    }
+
+    fn write<W: Write>(&self, out: &mut W, fallbacks: &Fallbacks) -> io::Result<()> {
+        write!(out, "<{}", self.name)?;
+        write_attributes(out, Some(&self.attributes))?;
+        out.write_all(b">")?;
+        match self.children {
+            Some(children) => {
+                for child in children {
+                    child.write_fallback_html(fallbacks, out)?;
+                }
+            }
+            None if VOID_ELEMENTS.contains(&self.name) => return Ok(()),
+            None => {}
+        }
+        write!(out, "</{}>", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fallbacks, FormMethod};
+    use {Element, Key};
+
+    #[test]
+    fn test_fallback_html() {
+        let page = Element::parent("main")
+            .child(Element::parent("a").key(Key::Global(1)).child(Element::text(Key::Local(0), "home")))
+            .child(Element::parent("span").key(Key::Global(2)).child(Element::text(Key::Local(0), "more")))
+            .child(Element::parent("button").key(Key::Global(3)).child(Element::text(Key::Local(0), "next")))
+            .child(Element::parent("form")
+                .key(Key::Global(4))
+                .child(Element::void("input").key(Key::Local(0)).attr("name", "q")))
+            .child(Element::void("input").key(Key::Global(5)).attr("type", "submit"))
+            .child(Element::void("img").key(Key::Global(6)).attr("src", "/a.png"))
+            .build();
+        let fallbacks = Fallbacks::new()
+            .link(Key::Global(1), "/")
+            .link(Key::Global(2), "/more?a=1&b=2")
+            .link(Key::Global(3), "/page/2")
+            .form(Key::Global(4), "/search", FormMethod::Get)
+            .form(Key::Global(5), "/like", FormMethod::Post)
+            .link(Key::Global(6), "/a");
+        assert_eq!(page.to_fallback_html(&fallbacks),
+                   "<main><a href=\"/\">home</a><a href=\"/more?a=1&amp;b=2\">more</a>\
+                    <form action=\"/page/2\" method=\"get\"><button>next</button></form>\
+                    <form action=\"/search\" method=\"get\"><input name=\"q\"></form>\
+                    <form action=\"/like\" method=\"post\"><input type=\"submit\"></form>\
+                    <a href=\"/a\" src=\"/a.png\"></a></main>");
+        assert_eq!(page.to_fallback_html(&Fallbacks::new()), page.to_html());
+    }
+}
@@ -0,0 +1,118 @@
+// Compact node identifiers. Paths of keys get long in deep trees, and global
+// keys can be arbitrary values, so the side applying patches can number
+// nodes as it creates them and report the numbers back; later patches and
+// events can then refer to a node by its id alone.
+//
+// Ids increase monotonically and are never reused, so a stale id can't
+// silently refer to a newer node. The table is kept in step with the tree by
+// feeding it every patch along with the tree the patch was computed from.
+use std::collections::BTreeMap;
+
+use mutation::MutationObserver;
+use {DiffTree, Element, Key};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(pub u64);
+
+#[derive(Clone, Debug, Default)]
+pub struct NodeIds {
+    next: u64,
+    ids: BTreeMap<Vec<Key>, NodeId>,
+    paths: BTreeMap<NodeId, Vec<Key>>,
+}
+
+impl NodeIds {
+    // Numbers every node of `tree` in document order, the root first.
+    pub fn new(tree: &Element) -> NodeIds {
+        let mut ids = NodeIds::default();
+        ids.assign(&mut vec![], tree, &mut vec![]);
+        ids
+    }
+
+    pub fn get(&self, path: &[Key]) -> Option<NodeId> {
+        self.ids.get(path).cloned()
+    }
+
+    pub fn path(&self, id: NodeId) -> Option<&[Key]> {
+        self.paths.get(&id).map(|x| &x[..])
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    // Updates the table for `diff` being applied to `base` and returns the
+    // ids given to the nodes it creates, in the order they are created: the
+    // acknowledgement to send back to the side that produced the patch.
+    pub fn update(&mut self, base: &Element, diff: &DiffTree) -> Vec<(Vec<Key>, NodeId)> {
+        let mut observer = Assigner {
+            ids: self,
+            assigned: vec![],
+        };
+        diff.observe(base, &mut observer);
+        observer.assigned
+    }
+
+    fn assign(&mut self, path: &mut Vec<Key>, node: &Element, assigned: &mut Vec<(Vec<Key>, NodeId)>) {
+        let id = NodeId(self.next);
+        self.next += 1;
+        self.ids.insert(path.clone(), id);
+        self.paths.insert(id, path.clone());
+        assigned.push((path.clone(), id));
+        for child in node.force().view().children() {
+            path.push(child.key());
+            self.assign(path, child.element(), assigned);
+            path.pop();
+        }
+    }
+
+    fn release(&mut self, path: &[Key]) {
+        let removed: Vec<Vec<Key>> = self.ids.keys().filter(|x| x.starts_with(path)).cloned().collect();
+        for path in removed {
+            if let Some(id) = self.ids.remove(&path) {
+                self.paths.remove(&id);
+            }
+        }
+    }
+}
+
+struct Assigner<'a> {
+    ids: &'a mut NodeIds,
+    assigned: Vec<(Vec<Key>, NodeId)>,
+}
+
+impl<'a> MutationObserver for Assigner<'a> {
+    fn on_node_inserted(&mut self, path: &[Key], node: &Element) {
+        self.ids.assign(&mut path.to_vec(), node, &mut self.assigned);
+    }
+
+    fn on_node_removed(&mut self, path: &[Key], _node: &Element) {
+        self.ids.release(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NodeId, NodeIds};
+    use Key;
+
+    #[test]
+    fn test_node_ids() {
+        let before = el!(ul[key=0, el!(li[key=1]), el!(li[key=2])]);
+        let mut ids = NodeIds::new(&before);
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids.get(&[]), Some(NodeId(0)));
+        assert_eq!(ids.get(&[Key::Local(2)]), Some(NodeId(2)));
+
+        let after = el!(ul[key=0, el!(li[key=2]), el!(li[key=3])]);
+        let assigned = ids.update(&before, &before.diff(&after).unwrap());
+        assert_eq!(assigned, vec![(vec![Key::Local(3)], NodeId(3))]);
+        assert_eq!(ids.get(&[Key::Local(1)]), None);
+        assert_eq!(ids.path(NodeId(1)), None);
+        assert_eq!(ids.path(NodeId(2)), Some(&[Key::Local(2)][..]));
+    }
+}
@@ -0,0 +1,71 @@
+// Unicode normalization of text, behind the `unicode-normalization` feature.
+//
+// Text from different sources, such as user input and a database, often
+// differs only in normalization form: "é" may be one code point or an "e"
+// followed by a combining accent. Both render the same, so diffing them
+// should produce no UpdateText. Set DiffConfig::normalize_text to compare
+// text nodes in NFC, or normalize trees as they are built.
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+use Element;
+
+pub fn nfc(value: &str) -> String {
+    value.nfc().collect()
+}
+
+// Whether the two strings are equal once normalized to NFC.
+pub fn nfc_eq(left: &str, right: &str) -> bool {
+    left == right || nfc(left) == nfc(right)
+}
+
+impl Element {
+    // Normalizes every text node of this tree to NFC. Lazy nodes are left
+    // alone.
+    pub fn normalize_text(self) -> Element {
+        match self {
+            Element::Text { key, value } => {
+                Element::Text {
+                    key: key,
+                    value: if is_nfc(&value) { value } else { nfc(&value) },
+                }
+            }
+            Element::Parent { key, name, keymap, attributes, listeners, children, flags } => {
+                Element::Parent {
+                    key: key,
+                    name: name,
+                    keymap: keymap,
+                    attributes: attributes,
+                    listeners: listeners,
+                    children: children.into_iter().map(|child| child.normalize_text()).collect(),
+                    flags: flags,
+                }
+            }
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diff_config::DiffConfig;
+    use {Element, Key};
+
+    fn text(value: &str) -> Element {
+        Element::Text {
+            key: Key::Local(0),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        let composed = el!(p[key=0, text("caf\u{e9}")]);
+        let decomposed = el!(p[key=0, text("cafe\u{301}")]);
+        assert!(composed.diff(&decomposed).is_some());
+
+        let config = DiffConfig { normalize_text: true, ..DiffConfig::default() };
+        assert_eq!(text("caf\u{e9}").diff_with(&text("cafe\u{301}"), &config), None);
+        assert_eq!(decomposed.normalize_text().view().children().next().unwrap().text(),
+                   Some("caf\u{e9}"));
+    }
+}
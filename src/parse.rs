@@ -0,0 +1,374 @@
+// Parsing HTML fragments into element trees, so that server-rendered markup
+// can be diffed against a new virtual tree.
+//
+// This is a small parser for the well-formed markup servers generate, not
+// the full HTML tree construction algorithm: end tags are only implied for
+// void elements and for elements still open when an enclosing element or the
+// input ends. Comments and doctypes are skipped, character references are
+// decoded, and the contents of `script` and `style` are kept as raw text.
+// Tag and attribute names are lowercased, and whitespace between tags is
+// kept as text nodes.
+//
+// Keys are synthesized from position: every node gets Key::Local with its
+// index among its siblings. As with el!, an element without children becomes
+// a Void and one with children a Parent.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use ssr::{RAW_TEXT_ELEMENTS, VOID_ELEMENTS};
+use {AttrName, Attributes, Element, Flags, Key, Listeners};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    // Byte offset into the source where parsing failed.
+    pub position: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.reason, self.position)
+    }
+}
+
+// Parses a fragment holding a single root element, which may be surrounded
+// by whitespace, comments and a doctype.
+pub fn parse(html: &str) -> Result<Element, ParseError> {
+    let mut nodes = parse_fragment(html)?;
+    nodes.retain(|node| match *node {
+        Element::Text { ref value, .. } => !value.trim().is_empty(),
+        _ => true,
+    });
+    match nodes.len() {
+        1 => {
+            let mut root = nodes.pop().unwrap();
+            rekey(&mut root, 0);
+            Ok(root)
+        }
+        0 => Err(ParseError { position: html.len(), reason: "no root element" }),
+        _ => Err(ParseError { position: 0, reason: "more than one root node" }),
+    }
+}
+
+// Parses a fragment into its top-level nodes, keyed by position.
+pub fn parse_fragment(html: &str) -> Result<Vec<Element>, ParseError> {
+    Parser {
+        source: html,
+        position: 0,
+    }
+        .parse()
+}
+
+// An element whose end tag hasn't been seen yet.
+struct Open {
+    name: String,
+    attributes: Option<Attributes>,
+    children: Vec<Element>,
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(mut self) -> Result<Vec<Element>, ParseError> {
+        let mut stack = vec![Open {
+                                 name: String::new(),
+                                 attributes: None,
+                                 children: vec![],
+                             }];
+        while self.position < self.source.len() {
+            let rest = self.rest();
+            if rest.starts_with("<!--") {
+                self.position += match rest.find("-->") {
+                    Some(end) => end + 3,
+                    None => return Err(self.error("unterminated comment")),
+                };
+            } else if rest.starts_with("<!") || rest.starts_with("<?") {
+                self.position += match rest.find('>') {
+                    Some(end) => end + 1,
+                    None => return Err(self.error("unterminated declaration")),
+                };
+            } else if rest.starts_with("</") {
+                self.position += 2;
+                let name = self.name()?;
+                self.skip_whitespace();
+                self.expect('>')?;
+                // A stray end tag is ignored; otherwise everything opened
+                // since the matching start tag is closed with it.
+                if let Some(index) = stack.iter().rposition(|x| x.name == name) {
+                    while stack.len() > index {
+                        close(&mut stack);
+                    }
+                }
+            } else if rest.starts_with('<') && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+                self.position += 1;
+                let name = self.name()?;
+                let attributes = self.attributes()?;
+                let self_closing = self.rest().starts_with("/>");
+                self.position += if self_closing { 2 } else { 1 };
+                let mut open = Open {
+                    name: name,
+                    attributes: attributes,
+                    children: vec![],
+                };
+                if RAW_TEXT_ELEMENTS.contains(&&open.name[..]) && !self_closing {
+                    let end = format!("</{}", open.name);
+                    let length = self.rest().to_ascii_lowercase().find(&end).unwrap_or(self.rest().len());
+                    if length > 0 {
+                        open.children.push(text(&self.rest()[..length]));
+                    }
+                    self.position += length;
+                }
+                stack.push(open);
+                if self_closing || VOID_ELEMENTS.contains(&&stack.last().unwrap().name[..]) {
+                    close(&mut stack);
+                }
+            } else {
+                // A `<` not starting a tag is text, so the search starts
+                // after the first character.
+                let first = rest.chars().next().map_or(0, |c| c.len_utf8());
+                let length = rest[first..].find('<').map_or(rest.len(), |x| x + first);
+                let value = decode(&rest[..length]);
+                let children = &mut stack.last_mut().unwrap().children;
+                // Text split by a stray `<` or a skipped comment is joined up.
+                if let Some(&mut Element::Text { value: ref mut previous, .. }) = children.last_mut() {
+                    previous.push_str(&value);
+                } else {
+                    children.push(text(&value));
+                }
+                self.position += length;
+            }
+        }
+        while stack.len() > 1 {
+            close(&mut stack);
+        }
+        let mut nodes = stack.pop().unwrap().children;
+        for (index, node) in nodes.iter_mut().enumerate() {
+            rekey(node, index as u64);
+        }
+        Ok(nodes)
+    }
+
+    // Attributes up to the end of a start tag, which is left unconsumed.
+    fn attributes(&mut self) -> Result<Option<Attributes>, ParseError> {
+        let mut attributes = Attributes::new();
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with('>') || rest.starts_with("/>") {
+                break;
+            }
+            if rest.is_empty() {
+                return Err(self.error("unterminated start tag"));
+            }
+            let name = self.name()?;
+            self.skip_whitespace();
+            let value = if self.rest().starts_with('=') {
+                self.position += 1;
+                self.skip_whitespace();
+                self.value()?
+            } else {
+                String::new()
+            };
+            // The first of repeated attributes wins, as in browsers.
+            attributes.entry(AttrName::from(&name[..])).or_insert(value);
+        }
+        Ok(if attributes.is_empty() {
+            None
+        } else {
+            Some(attributes)
+        })
+    }
+
+    fn value(&mut self) -> Result<String, ParseError> {
+        let rest = self.rest();
+        match rest.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                let length = match rest[1..].find(quote) {
+                    Some(length) => length,
+                    None => return Err(self.error("unterminated attribute value")),
+                };
+                self.position += length + 2;
+                Ok(decode(&rest[1..length + 1]))
+            }
+            _ => {
+                let length = rest.find(|c: char| c.is_whitespace() || c == '>').unwrap_or(rest.len());
+                if length == 0 {
+                    return Err(self.error("expected an attribute value"));
+                }
+                self.position += length;
+                Ok(decode(&rest[..length]))
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, ParseError> {
+        let rest = self.rest();
+        let length = rest.find(|c: char| c.is_whitespace() || c == '>' || c == '/' || c == '=')
+            .unwrap_or(rest.len());
+        if length == 0 {
+            return Err(self.error("expected a name"));
+        }
+        self.position += length;
+        Ok(rest[..length].to_ascii_lowercase())
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.rest().starts_with(c) {
+            self.position += c.len_utf8();
+            Ok(())
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    fn error(&self, reason: &'static str) -> ParseError {
+        ParseError {
+            position: self.position,
+            reason: reason,
+        }
+    }
+}
+
+// Pops the innermost open element into its parent's children.
+fn close(stack: &mut Vec<Open>) {
+    let open = stack.pop().unwrap();
+    let element = if open.children.is_empty() {
+        Element::Void {
+            key: Key::Local(0),
+            name: open.name,
+            attributes: open.attributes,
+            listeners: Listeners::new(),
+            flags: Flags::default(),
+        }
+    } else {
+        Element::Parent {
+            key: Key::Local(0),
+            name: open.name,
+            keymap: BTreeMap::new(),
+            attributes: open.attributes,
+            listeners: Listeners::new(),
+            children: open.children,
+            flags: Flags::default(),
+        }
+    };
+    stack.last_mut().unwrap().children.push(element);
+}
+
+// Gives `element` the key `index`, and its children keys by position.
+pub(crate) fn rekey(element: &mut Element, index: u64) {
+    match *element {
+        Element::Text { ref mut key, .. } |
+        Element::Void { ref mut key, .. } => *key = Key::Local(index),
+        Element::Parent { ref mut key, ref mut keymap, ref mut children, .. } => {
+            *key = Key::Local(index);
+            keymap.clear();
+            for (index, child) in children.iter_mut().enumerate() {
+                rekey(child, index as u64);
+                keymap.insert(Key::Local(index as u64), index);
+            }
+        }
+        Element::Lazy { .. } => {}
+    }
+}
+
+fn text(value: &str) -> Element {
+    Element::Text {
+        key: Key::Local(0),
+        value: value.to_string(),
+    }
+}
+
+// Decodes character references. Unknown or malformed ones are kept as
+// written.
+fn decode(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                name if name.starts_with("#x") || name.starts_with("#X") => {
+                    u32::from_str_radix(&name[2..], 16).ok().and_then(::std::char::from_u32)
+                }
+                name if name.starts_with('#') => name[1..].parse().ok().and_then(::std::char::from_u32),
+                _ => None,
+            };
+            c.map(|c| (c, end + 1))
+        });
+        match reference {
+            Some((c, length)) => {
+                decoded.push(c);
+                rest = &rest[length..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, parse_fragment, ParseError};
+    use {Element, Key};
+
+    #[test]
+    fn test_parse() {
+        let html = "<!DOCTYPE html>\n<UL class=\"list\" data-x='a &amp; b' hidden>\
+                    <li>one<br>two</li><!-- gap --><li><img src=x.png></li></ul>\n";
+        let tree = parse(html).unwrap();
+        assert_eq!(tree.to_html(),
+                   "<ul class=\"list\" data-x=\"a &amp; b\" hidden=\"\"><li>one<br>two</li>\
+                    <li><img src=\"x.png\"></li></ul>");
+        assert_eq!(tree.to_key(), Key::Local(0));
+        let item = tree.get_child(Key::Local(0)).unwrap();
+        assert_eq!(item.view().children().map(|x| x.key()).collect::<Vec<_>>(),
+                   vec![Key::Local(0), Key::Local(1), Key::Local(2)]);
+
+        let next = el!(ul[key=0, el!(li[key=0]), el!(li[key=1])]);
+        assert!(tree.diff(&next).is_some());
+    }
+
+    #[test]
+    fn test_parse_fragment() {
+        let nodes = parse_fragment("a &lt;&#x41;&#66;&bogus; < é<p><b>x</p>y<script>if (a<b) {}</script>")
+            .unwrap();
+        let html: Vec<String> = nodes[..3].iter().map(|x| x.to_html()).collect();
+        assert_eq!(html,
+                   vec!["a &lt;AB&amp;bogus; &lt; é".to_string(),
+                        "<p><b>x</b></p>".to_string(),
+                        "y".to_string()]);
+        match nodes[3].view().children().next().map(|x| x.element()) {
+            Some(&Element::Text { ref value, .. }) => assert_eq!(value, "if (a<b) {}"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(parse("<p></p><p></p>"),
+                   Err(ParseError {
+                       position: 0,
+                       reason: "more than one root node",
+                   }));
+        assert_eq!(parse("<p a=\"x>").unwrap_err().reason, "unterminated attribute value");
+    }
+}
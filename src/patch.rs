@@ -0,0 +1,828 @@
+// Patches: the changes turning one element tree into another, addressed by
+// key path from the root.
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+
+use element::rebuild_keymap;
+use mutation::MutationObserver;
+use text_diff::splice;
+use {AttrName, Attributes, Element, Flags, HandlerId, Key, Listeners, PatchError};
+
+// Output is deterministic: the same pair of trees always produces the same
+// patch, and every serialized form of it is byte for byte the same across
+// runs, so outputs can be cached and compared. Within a node, changes come
+// in this order:
+//
+//   1. RemoveAttribute, then SetAttribute, each by qualified name, or a
+//      single UpdateAttributes listing them in the same order
+//   2. flag changes, visibility before resize
+//   3. RemoveChild and MarkExiting, by ascending key
+//   4. MoveChild, only for children kept on both sides that change their
+//      relative order, each index being into the children as they are when
+//      it applies
+//   5. InsertChild and MoveNode, by ascending index
+//
+// Child diffs follow in ascending key order. Attributes are always written
+// in the order of Attributes, by qualified name and then namespace, in HTML
+// and JSON alike, and JSON objects have their members sorted by name.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DiffTree {
+    pub(crate) changes: Option<Box<[Change]>>,
+    pub(crate) children: Option<Box<[(Key, DiffTree)]>>,
+    // Checksum of the tree the patch was computed from; only ever set on the
+    // root. See DiffTree::with_base_checksum.
+    pub(crate) base_checksum: Option<u32>,
+}
+
+impl DiffTree {
+    // Appends `change` to the node reached by following `path`, the keys of
+    // the children from the root down, creating entries along the way.
+    pub fn push_at(&mut self, path: &[Key], change: Change) {
+        match path.split_first() {
+            None => {
+                let mut changes = self.changes.take().map_or(vec![], |x| x.into_vec());
+                changes.push(change);
+                self.changes = Some(changes.into_boxed_slice());
+            }
+            Some((key, rest)) => {
+                let mut children = self.children.take().map_or(vec![], |x| x.into_vec());
+                let index = match children.iter().position(|&(ref x, _)| x == key) {
+                    Some(index) => index,
+                    None => {
+                        children.push((key.clone(),
+                                       DiffTree {
+                            changes: None,
+                            children: None,
+                            base_checksum: None,
+                        }));
+                        children.len() - 1
+                    }
+                };
+                children[index].1.push_at(rest, change);
+                self.children = Some(children.into_boxed_slice());
+            }
+        }
+    }
+
+    // Whether applying this patch would change nothing, as when it was
+    // composed of diffs of unchanged subtrees.
+    pub fn is_empty(&self) -> bool {
+        self.changes.as_ref().is_none_or(|changes| changes.is_empty()) &&
+        self.children.as_ref().is_none_or(|children| children.iter().all(|&(_, ref x)| x.is_empty()))
+    }
+}
+
+impl DiffTree {
+    // Checks that applying this patch to `base` would succeed, without
+    // changing it: the base checksum, if any, matches, every node the patch
+    // addresses exists when its changes are reached, as does every child a
+    // change names, and every change fits its node. Element::apply runs
+    // this first. Only the nodes the patch touches are copied.
+    pub fn validate_against(&self, base: &Element) -> Result<(), PatchError> {
+        self.check_base(base)?;
+        let moved = self.moved_nodes(base)?;
+        self.validate_at(base, &moved, &mut vec![])
+    }
+
+    // The MoveNode changes anywhere in this patch, as the key of the node
+    // and the path of its old parent.
+    pub(crate) fn moves(&self) -> Vec<(Key, &[Key])> {
+        let mut moves = vec![];
+        self.collect_moves(&mut moves);
+        moves
+    }
+
+    fn collect_moves<'a>(&'a self, moves: &mut Vec<(Key, &'a [Key])>) {
+        for change in self.changes.iter().flat_map(|x| x.iter()) {
+            if let Change::MoveNode { ref key, ref from, .. } = *change {
+                moves.push((key.clone(), from));
+            }
+        }
+        for &(_, ref child) in self.children.iter().flat_map(|x| x.iter()) {
+            child.collect_moves(moves);
+        }
+    }
+
+    // The nodes of `base` the MoveNode changes of this patch move, by key,
+    // with the path of their old parent. A node that isn't there, or is
+    // moved twice, is reported missing at its old path.
+    fn moved_nodes<'a>(&'a self,
+                       base: &'a Element)
+                       -> Result<BTreeMap<Key, (&'a [Key], &'a Element)>, PatchError> {
+        let mut moved = BTreeMap::new();
+        for (key, from) in self.moves() {
+            match base.get_path(from).and_then(|parent| parent.get_child(&key)) {
+                Some(node) if !moved.contains_key(&key) => {
+                    moved.insert(key, (from, node));
+                }
+                _ => {
+                    let mut path = from.to_vec();
+                    path.push(key);
+                    return Err(PatchError::NoSuchNode(path));
+                }
+            }
+        }
+        Ok(moved)
+    }
+
+    fn validate_at(&self,
+                   base: &Element,
+                   moved: &BTreeMap<Key, (&[Key], &Element)>,
+                   path: &mut Vec<Key>)
+                   -> Result<(), PatchError> {
+        let base = base.force();
+        let mut node = base.skeleton();
+        // Children whose stand-ins in `node` are still for those of `base`,
+        // rather than nodes the patch put there.
+        let mut original: BTreeSet<Key> = base.view().children().map(|child| child.key()).collect();
+        // Children moved here from elsewhere, whose stand-ins are for the
+        // nodes in `moved`.
+        let mut arrived = BTreeSet::new();
+        // Children marked exiting, which a RemoveChild may follow.
+        let mut exiting = BTreeSet::new();
+        for (key, &(from, _)) in moved {
+            if from == &path[..] {
+                original.remove(key);
+                let _ = node.apply_change(&Change::RemoveChild(key.clone()));
+            }
+        }
+        if let Some(ref changes) = self.changes {
+            for (index, change) in changes.iter().enumerate() {
+                if let Some(key) = node_key(change) {
+                    if *key != node.to_key() {
+                        return Err(PatchError::Mismatch {
+                            path: path.clone(),
+                            index: index,
+                        });
+                    }
+                }
+                match *change {
+                    Change::ReplaceNode(_) => return Ok(()),
+                    Change::RemoveChild(ref key) |
+                    Change::MarkExiting(ref key) |
+                    Change::MoveChild(ref key, _) if lacks_child(&node, key) && !exiting.contains(key) => {
+                        let mut path = path.clone();
+                        path.push(key.clone());
+                        return Err(PatchError::NoSuchNode(path));
+                    }
+                    Change::MarkExiting(ref key) => {
+                        exiting.insert(key.clone());
+                    }
+                    Change::InsertChild(_, ref child) => {
+                        original.remove(&child.to_key());
+                        arrived.remove(&child.to_key());
+                    }
+                    Change::MoveNode { ref key, .. } => {
+                        original.remove(key);
+                        arrived.insert(key.clone());
+                    }
+                    _ => {}
+                }
+                node.apply_change(change).map_err(|()| {
+                        PatchError::Mismatch {
+                            path: path.clone(),
+                            index: index,
+                        }
+                    })?;
+            }
+        }
+        if let Some(ref children) = self.children {
+            for &(ref key, ref child) in children.iter() {
+                path.push(key.clone());
+                let target = match node.get_child(key) {
+                    Some(_) if original.contains(key) => base.get_child(key),
+                    Some(_) if arrived.contains(key) => moved.get(key).map(|&(_, node)| node),
+                    target => target,
+                };
+                match target {
+                    Some(target) => child.validate_at(target, moved, path)?,
+                    None => return Err(PatchError::NoSuchNode(path.clone())),
+                }
+                path.pop();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Change {
+    RemoveChild(Key),
+    // Inserts the child at this index among the node's children. Inserts
+    // come in ascending order of index, which is the child's position once
+    // the whole patch has been applied.
+    InsertChild(usize, Element),
+    // Moves the child to this index among the node's children, as they are
+    // when the move applies; see the order of changes above.
+    MoveChild(Key, usize),
+    // Moves the node with this global key from the children of the node at
+    // `from`, its old parent's path in the tree the patch applies to, to
+    // this index among the node's children, in place of an InsertChild of
+    // it. The node is kept rather than created anew, with what state it has
+    // in the renderer, such as focus and scroll position, and changes to it
+    // follow in the child diffs as for any other child. Nodes are taken
+    // from their old parents before any other change applies.
+    MoveNode {
+        key: Key,
+        from: Box<[Key]>,
+        index: usize,
+    },
+    // Puts the children in this order. Not produced by diff, which emits
+    // MoveChild instead; kept for clients without it, see
+    // DiffTree::downgrade.
+    SortChildren(Box<[Key]>),
+    UpdateText(String),
+    // Replaces `delete` UTF-16 code units of the text from `start` on with
+    // `insert`, as CharacterData.replaceData. See TextDiff.
+    SpliceText {
+        start: usize,
+        delete: usize,
+        insert: String,
+    },
+    ReplaceNode(Element),
+    // Sets or removes the attribute with this qualified name. Its namespace
+    // is the one the name's prefix implies, see AttrName's From impl; an
+    // attribute in any other namespace has to come with a ReplaceNode.
+    SetAttribute(String, String),
+    RemoveAttribute(String),
+    // Removes the attributes in `remove` and then sets those in `set`, the
+    // same as a RemoveAttribute or SetAttribute for each, in fewer bytes
+    // when many attributes change at once.
+    UpdateAttributes {
+        set: Vec<(String, String)>,
+        remove: Vec<String>,
+    },
+    ObserveVisibility(Key),
+    UnobserveVisibility(Key),
+    ObserveResize(Key),
+    UnobserveResize(Key),
+    // Moves keyboard focus to the node. Commands such as this one don't
+    // change the tree and are carried out after the rest of the patch.
+    Focus(Key),
+    // Scrolls the node's ancestors so that it is visible, as
+    // Element.scrollIntoView in the DOM.
+    ScrollIntoView {
+        key: Key,
+        behavior: ScrollBehavior,
+        block: ScrollBlock,
+    },
+    // Asks the renderer for the node's layout, answered with an
+    // Event::Measured carrying the same request id.
+    Measure { key: Key, request_id: u64 },
+    // Takes the child out of the tree while leaving it in the document,
+    // after the other children, until a RemoveChild for it follows. The
+    // renderer should start its exit transition.
+    MarkExiting(Key),
+    // Sets an inline style property on the node, as
+    // CSSStyleDeclaration.setProperty, without changing the tree. Used for
+    // animations, whose intermediate values aren't part of any tree.
+    SetStyle {
+        key: Key,
+        property: String,
+        value: String,
+    },
+    // Registers the handler for the event of this name on the node, in
+    // place of any handler it had for it.
+    AddListener(Key, String, HandlerId),
+    RemoveListener(Key, String),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ScrollBehavior {
+    #[default]
+    Auto,
+    Smooth,
+    Instant,
+}
+
+// Where the node ends up along the block axis of the scroll container.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ScrollBlock {
+    #[default]
+    Start,
+    Center,
+    End,
+    Nearest,
+}
+
+impl DiffTree {
+    // Requests that the node at `path` be scrolled into view once the rest
+    // of this patch has been applied, for example a newly inserted message.
+    pub fn scroll_into_view(&mut self, path: &[Key], behavior: ScrollBehavior, block: ScrollBlock) {
+        let key = match path.last() {
+            Some(key) => key.clone(),
+            None => return,
+        };
+        self.push_at(path,
+                     Change::ScrollIntoView {
+                         key: key,
+                         behavior: behavior,
+                         block: block,
+                     });
+    }
+}
+
+impl Element {
+    // Applies `diff`, computed from this tree, so that the tree becomes the
+    // one the diff was computed against, Lazy nodes along the way evaluated.
+    // Commands, which don't change the tree, are skipped, and an exiting
+    // node is removed at once. The patch is applied whole or not at all: it
+    // is checked as by DiffTree::validate_against before anything changes,
+    // so on error the tree is as it was.
+    pub fn apply(&mut self, diff: &DiffTree) -> Result<(), PatchError> {
+        self.apply_with(diff, &mut NoObserver)
+    }
+
+    // Applies `diff` as apply does, reporting each node inserted, removed or
+    // retexted to `observer` as the change lands, as DiffTree::observe
+    // would. An exiting node is reported removed along with the change that
+    // removes it. Nothing is reported for a patch that fails.
+    pub fn apply_with(&mut self,
+                      diff: &DiffTree,
+                      observer: &mut dyn MutationObserver)
+                      -> Result<(), PatchError> {
+        diff.validate_against(self)?;
+        let mut moved = self.take_moved(diff);
+        self.apply_at(diff, &mut moved, &mut vec![], observer);
+        Ok(())
+    }
+
+    // Takes the nodes the MoveNode changes of a validated patch move out of
+    // their old parents. Deeper ones go first, so that every path still
+    // leads to its node.
+    fn take_moved(&mut self, diff: &DiffTree) -> BTreeMap<Key, Element> {
+        let mut moves = diff.moves();
+        moves.sort_by_key(|&(_, from)| cmp::Reverse(from.len()));
+        let mut moved = BTreeMap::new();
+        for (key, from) in moves {
+            let mut parent = &mut *self;
+            for step in from {
+                parent = parent.get_child_mut(step).expect("validated move has no old parent");
+            }
+            let (keymap, children) = children_mut(parent).expect("validated move has no old parent");
+            let node = children.remove(keymap[&key]);
+            rebuild_keymap(keymap, children);
+            moved.insert(key, node);
+        }
+        moved
+    }
+
+    // Applies a validated patch, which can't fail, with the nodes it moves
+    // in `moved`, to the node at `path`.
+    fn apply_at(&mut self,
+                diff: &DiffTree,
+                moved: &mut BTreeMap<Key, Element>,
+                path: &mut Vec<Key>,
+                observer: &mut dyn MutationObserver) {
+        if let Element::Lazy { .. } = *self {
+            *self = self.force().into_owned();
+        }
+        if let Some(ref changes) = diff.changes {
+            for change in changes.iter() {
+                match *change {
+                    Change::ReplaceNode(ref node) => {
+                        let old = mem::replace(self, node.clone());
+                        observer.on_node_removed(path, &old);
+                        observer.on_node_inserted(path, self);
+                        return;
+                    }
+                    Change::RemoveChild(ref key) |
+                    Change::MarkExiting(ref key) => {
+                        let removed = remove_child(self, key).expect("validated change failed to apply");
+                        if let Some(node) = removed {
+                            path.push(key.clone());
+                            observer.on_node_removed(path, &node);
+                            path.pop();
+                        }
+                    }
+                    Change::UpdateText(_) |
+                    Change::SpliceText { .. } => {
+                        let old = retext(self, change).expect("validated change failed to apply");
+                        if let Element::Text { ref value, .. } = *self {
+                            observer.on_text_changed(path, &old, value);
+                        }
+                    }
+                    Change::InsertChild(_, ref node) => {
+                        self.apply_change(change).expect("validated change failed to apply");
+                        path.push(node.to_key());
+                        observer.on_node_inserted(path, node);
+                        path.pop();
+                    }
+                    // The change puts a stand-in where the node goes.
+                    Change::MoveNode { ref key, ref from, .. } => {
+                        self.apply_change(change).expect("validated change failed to apply");
+                        let node = moved.remove(key).expect("validated move has no node");
+                        let slot = self.get_child_mut(key).expect("validated move failed to apply");
+                        *slot = node;
+                        let mut old = from.to_vec();
+                        old.push(key.clone());
+                        observer.on_node_removed(&old, slot);
+                        path.push(key.clone());
+                        observer.on_node_inserted(path, slot);
+                        path.pop();
+                    }
+                    _ => self.apply_change(change).expect("validated change failed to apply"),
+                }
+            }
+        }
+        if let Some(ref children) = diff.children {
+            for &(ref key, ref child) in children.iter() {
+                path.push(key.clone());
+                self.get_child_mut(key)
+                    .expect("validated patch addresses a missing node")
+                    .apply_at(child, moved, path, observer);
+                path.pop();
+            }
+        }
+    }
+
+    // The node with its children, if any, swapped for empty text nodes of the
+    // same keys, to run changes against without copying whole subtrees.
+    fn skeleton(&self) -> Element {
+        match *self {
+            Element::Parent { ref key, ref name, ref keymap, ref attributes, ref listeners, ref children,
+                              flags } => {
+                Element::Parent {
+                    key: key.clone(),
+                    name: name.clone(),
+                    keymap: keymap.clone(),
+                    attributes: attributes.clone(),
+                    listeners: listeners.clone(),
+                    children: children.iter()
+                        .map(|child| {
+                            Element::Text {
+                                key: child.to_key(),
+                                value: String::new(),
+                            }
+                        })
+                        .collect(),
+                    flags: flags,
+                }
+            }
+            _ => self.clone(),
+        }
+    }
+
+    fn apply_change(&mut self, change: &Change) -> Result<(), ()> {
+        match *change {
+            // A RemoveChild following a MarkExiting finds its node gone.
+            Change::RemoveChild(ref key) |
+            Change::MarkExiting(ref key) => {
+                remove_child(self, key)?;
+            }
+            Change::InsertChild(index, ref node) => {
+                let (keymap, children) = children_mut(self)?;
+                children.insert(cmp::min(index, children.len()), node.clone());
+                rebuild_keymap(keymap, children);
+            }
+            // Element::apply puts the node itself in the stand-in's place.
+            Change::MoveNode { ref key, index, .. } => {
+                let (keymap, children) = children_mut(self)?;
+                let stand_in = Element::Text {
+                    key: key.clone(),
+                    value: String::new(),
+                };
+                children.insert(cmp::min(index, children.len()), stand_in);
+                rebuild_keymap(keymap, children);
+            }
+            Change::MoveChild(ref key, index) => {
+                let (keymap, children) = children_mut(self)?;
+                let from = keymap.get(key).cloned().ok_or(())?;
+                let child = children.remove(from);
+                children.insert(cmp::min(index, children.len()), child);
+                rebuild_keymap(keymap, children);
+            }
+            Change::SortChildren(ref keys) => {
+                // Children missing from `keys` keep their order, after the rest.
+                let position = |node: &Element| {
+                    keys.iter().position(|key| *key == node.to_key()).unwrap_or(keys.len())
+                };
+                let (keymap, children) = children_mut(self)?;
+                children.sort_by_key(position);
+                rebuild_keymap(keymap, children);
+            }
+            Change::UpdateText(_) |
+            Change::SpliceText { .. } => {
+                retext(self, change)?;
+            }
+            Change::ReplaceNode(ref node) => *self = node.clone(),
+            Change::SetAttribute(ref name, ref value) => {
+                set_attribute(attributes_mut(self)?, name, Some(value))
+            }
+            Change::RemoveAttribute(ref name) => set_attribute(attributes_mut(self)?, name, None),
+            Change::UpdateAttributes { ref set, ref remove } => {
+                let attributes = attributes_mut(self)?;
+                for name in remove {
+                    set_attribute(attributes, name, None);
+                }
+                for &(ref name, ref value) in set {
+                    set_attribute(attributes, name, Some(value));
+                }
+            }
+            Change::ObserveVisibility(_) => flags_mut(self)?.observe_visibility = true,
+            Change::UnobserveVisibility(_) => flags_mut(self)?.observe_visibility = false,
+            Change::ObserveResize(_) => flags_mut(self)?.observe_resize = true,
+            Change::UnobserveResize(_) => flags_mut(self)?.observe_resize = false,
+            Change::AddListener(_, ref name, handler) => {
+                listeners_mut(self)?.insert(name.clone(), handler);
+            }
+            Change::RemoveListener(_, ref name) => {
+                listeners_mut(self)?.remove(name);
+            }
+            Change::Focus(_) |
+            Change::ScrollIntoView { .. } |
+            Change::Measure { .. } |
+            Change::SetStyle { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+struct NoObserver;
+
+impl MutationObserver for NoObserver {}
+
+// Removes the child of this key, if there is one.
+fn remove_child(element: &mut Element, key: &Key) -> Result<Option<Element>, ()> {
+    let (keymap, children) = children_mut(element)?;
+    Ok(keymap.get(key).cloned().map(|index| {
+        let child = children.remove(index);
+        rebuild_keymap(keymap, children);
+        child
+    }))
+}
+
+// Applies an UpdateText or SpliceText change, returning the old text.
+fn retext(element: &mut Element, change: &Change) -> Result<String, ()> {
+    let value = match *element {
+        Element::Text { ref mut value, .. } => value,
+        _ => return Err(()),
+    };
+    let text = match *change {
+        Change::UpdateText(ref text) => text.clone(),
+        Change::SpliceText { start, delete, ref insert } => splice(value, start, delete, insert).ok_or(())?,
+        _ => return Err(()),
+    };
+    Ok(mem::replace(value, text))
+}
+
+// Whether the node is a parent without a child of this key. Changes to the
+// children of other nodes don't fit them at all.
+fn lacks_child(node: &Element, key: &Key) -> bool {
+    match *node {
+        Element::Parent { ref keymap, .. } => !keymap.contains_key(key),
+        _ => false,
+    }
+}
+
+// The key of the node a change to its own state names, which has to be that
+// of the node it is listed under.
+fn node_key(change: &Change) -> Option<&Key> {
+    match *change {
+        Change::ObserveVisibility(ref key) |
+        Change::UnobserveVisibility(ref key) |
+        Change::ObserveResize(ref key) |
+        Change::UnobserveResize(ref key) |
+        Change::Focus(ref key) |
+        Change::ScrollIntoView { ref key, .. } |
+        Change::Measure { ref key, .. } |
+        Change::SetStyle { ref key, .. } |
+        Change::AddListener(ref key, ..) |
+        Change::RemoveListener(ref key, ..) => Some(key),
+        _ => None,
+    }
+}
+
+fn children_mut(element: &mut Element) -> Result<(&mut BTreeMap<Key, usize>, &mut Vec<Element>), ()> {
+    match *element {
+        Element::Parent { ref mut keymap, ref mut children, .. } => Ok((keymap, children)),
+        _ => Err(()),
+    }
+}
+
+fn attributes_mut(element: &mut Element) -> Result<&mut Option<Attributes>, ()> {
+    match *element {
+        Element::Void { ref mut attributes, .. } |
+        Element::Parent { ref mut attributes, .. } => Ok(attributes),
+        _ => Err(()),
+    }
+}
+
+fn listeners_mut(element: &mut Element) -> Result<&mut Listeners, ()> {
+    match *element {
+        Element::Void { ref mut listeners, .. } |
+        Element::Parent { ref mut listeners, .. } => Ok(listeners),
+        _ => Err(()),
+    }
+}
+
+fn flags_mut(element: &mut Element) -> Result<&mut Flags, ()> {
+    match *element {
+        Element::Void { ref mut flags, .. } |
+        Element::Parent { ref mut flags, .. } => Ok(flags),
+        _ => Err(()),
+    }
+}
+
+// Sets or, given None, removes an attribute. A node left without attributes
+// has None rather than an empty map, as the diff treats them alike.
+fn set_attribute(attributes: &mut Option<Attributes>, name: &str, value: Option<&String>) {
+    let name = AttrName::from(name);
+    match value {
+        Some(value) => {
+            attributes.get_or_insert_with(Attributes::new).insert(name, value.clone());
+        }
+        None => {
+            let empty = match *attributes {
+                Some(ref mut map) => {
+                    map.remove(&name);
+                    map.is_empty()
+                }
+                None => false,
+            };
+            if empty {
+                *attributes = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ScrollBehavior, ScrollBlock};
+    use {AttrName, Change, DiffTree, Element, HandlerId, Key, PatchError};
+
+    #[test]
+    fn test_scroll_into_view() {
+        let left = el!(ul[key=0, el!(li[key=1])]);
+        let right = el!(ul[key=0, el!(li[key=1]), el!(li[key=2])]);
+
+        let mut diff = left.diff(&right).unwrap();
+        diff.scroll_into_view(&[Key::Local(2)], ScrollBehavior::Smooth, ScrollBlock::End);
+
+        assert_eq!(diff.children, Some(vec![
+            (Key::Local(2), DiffTree{
+                changes: Some(vec![
+                    Change::ScrollIntoView {
+                        key: Key::Local(2),
+                        behavior: ScrollBehavior::Smooth,
+                        block: ScrollBlock::End,
+                    },
+                ].into_boxed_slice()),
+                children: None,
+                base_checksum: None,
+            }),
+        ].into_boxed_slice()));
+    }
+
+    fn text(key: u64, value: &str) -> Element {
+        Element::Text {
+            key: Key::Local(key),
+            value: value.to_string(),
+        }
+    }
+
+    fn with_class(mut element: Element, class: &str) -> Element {
+        if let Element::Parent { ref mut attributes, ref mut flags, .. } = element {
+            *attributes = Some(vec![(AttrName::new("class"), class.to_string())].into_iter().collect());
+            flags.observe_resize = true;
+        }
+        element
+    }
+
+    #[test]
+    fn test_apply() {
+        let before = el!(ul[
+            key=0,
+            el!(li[key=1, text(0, "a")]),
+            el!(li[key=2, text(0, "b")]),
+            el!(li[key=3, text(0, "c")])
+        ]);
+        let after = with_class(el!(ul[
+            key=0,
+            el!(li[key=3, text(0, "c")]),
+            el!(li[key=4, text(0, "d")]),
+            with_class(el!(li[key=1, text(0, "e")]), "done")
+        ]), "list");
+
+        let mut tree = before.clone();
+        tree.apply(&before.diff(&after).unwrap()).unwrap();
+        assert!(tree.deep_eq(&after));
+        assert_eq!(tree.view().children().map(|x| x.key()).collect::<Vec<_>>(),
+                   vec![Key::Local(3), Key::Local(4), Key::Local(1)]);
+        assert_eq!(tree.get_child(Key::Local(1)).map(|x| x.to_key()), Some(Key::Local(1)));
+
+        let mut tree = before.clone();
+        tree.apply(&before.diff(&text(0, "x")).unwrap()).unwrap();
+        assert!(tree.deep_eq(&text(0, "x")));
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let before = el!(ul[key=0, el!(li[key=1, text(0, "a")])]);
+        let after = el!(ul[key=0, el!(li[key=1, text(0, "b")])]);
+        let diff = before.diff(&after).unwrap().with_base_checksum(&before);
+
+        let mut other = el!(ul[key=0, el!(li[key=2, text(0, "a")])]);
+        match other.apply(&diff) {
+            Err(PatchError::BaseMismatch { .. }) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+        assert_eq!(other.apply(&before.diff(&after).unwrap()),
+                   Err(PatchError::NoSuchNode(vec![Key::Local(1)])));
+
+        let mut diff = DiffTree {
+            changes: None,
+            children: None,
+            base_checksum: None,
+        };
+        diff.push_at(&[Key::Local(1), Key::Local(0)], Change::InsertChild(0, text(1, "x")));
+        assert_eq!(before.clone().apply(&diff),
+                   Err(PatchError::Mismatch {
+                       path: vec![Key::Local(1), Key::Local(0)],
+                       index: 0,
+                   }));
+    }
+
+    #[test]
+    fn test_validate_against() {
+        let before = el!(ul[key=0, el!(li[key=1, text(0, "a")]), el!(li[key=2, text(0, "b")])]);
+        let after = el!(ul[key=0, el!(li[key=3, text(0, "c")]), el!(li[key=1, text(0, "d")])]);
+        let mut diff = before.diff(&after).unwrap();
+        // Changes below an inserted node address the inserted one.
+        diff.push_at(&[Key::Local(3), Key::Local(0)], Change::UpdateText("e".to_string()));
+        assert_eq!(diff.validate_against(&before), Ok(()));
+
+        diff.push_at(&[Key::Local(2)], Change::UpdateText("f".to_string()));
+        assert_eq!(diff.validate_against(&before), Err(PatchError::NoSuchNode(vec![Key::Local(2)])));
+
+        let mut diff = before.diff(&after).unwrap();
+        diff.push_at(&[Key::Local(1)], Change::UpdateText("g".to_string()));
+        assert_eq!(diff.validate_against(&before),
+                   Err(PatchError::Mismatch {
+                       path: vec![Key::Local(1)],
+                       index: 0,
+                   }));
+
+        // Keys a change names have to be there too.
+        let mut diff = DiffTree {
+            changes: None,
+            children: None,
+            base_checksum: None,
+        };
+        diff.push_at(&[], Change::MarkExiting(Key::Local(2)));
+        diff.push_at(&[], Change::RemoveChild(Key::Local(2)));
+        assert_eq!(diff.validate_against(&before), Ok(()));
+        diff.push_at(&[], Change::RemoveChild(Key::Local(4)));
+        assert_eq!(diff.validate_against(&before),
+                   Err(PatchError::NoSuchNode(vec![Key::Local(4)])));
+        let mut diff = DiffTree {
+            changes: None,
+            children: None,
+            base_checksum: None,
+        };
+        diff.push_at(&[Key::Local(1)],
+                     Change::AddListener(Key::Local(1), "click".to_string(), HandlerId(1)));
+        assert_eq!(diff.validate_against(&before), Ok(()));
+        diff.push_at(&[Key::Local(1)], Change::Focus(Key::Local(2)));
+        assert_eq!(diff.validate_against(&before),
+                   Err(PatchError::Mismatch {
+                       path: vec![Key::Local(1)],
+                       index: 1,
+                   }));
+
+        let stale = before.diff(&after).unwrap().with_base_checksum(&after);
+        match stale.validate_against(&before) {
+            Err(PatchError::BaseMismatch { .. }) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_apply_is_atomic() {
+        let before = el!(ul[key=0, el!(li[key=1, text(0, "a")]), el!(li[key=2, text(0, "b")])]);
+        let after = el!(ul[key=0, el!(li[key=3, text(0, "c")]), el!(li[key=1, text(0, "d")])]);
+        let mut diff = before.diff(&after).unwrap();
+        diff.push_at(&[Key::Local(1), Key::Local(0)], Change::UpdateText("e".to_string()));
+        diff.push_at(&[Key::Local(1), Key::Local(0)], Change::RemoveChild(Key::Local(0)));
+
+        let mut tree = before.clone();
+        assert_eq!(tree.apply(&diff),
+                   Err(PatchError::Mismatch {
+                       path: vec![Key::Local(1), Key::Local(0)],
+                       index: 2,
+                   }));
+        assert!(tree.deep_eq(&before));
+        assert_eq!(PatchError::Mismatch {
+                           path: vec![Key::Local(1)],
+                           index: 2,
+                       }
+                       .to_string(),
+                   "patch change 2 doesn't fit the node at [Local(1)]");
+    }
+}
@@ -0,0 +1,478 @@
+// Coalescing of patches for clients that consume them slower than frames are
+// produced. Instead of queueing every DiffTree, a connection keeps a single
+// PatchBuffer whose pending patch is composed with each new one.
+//
+// Composing alone can't shorten everything, since how far an index or a
+// splice reaches depends on the tree. So each patch is pushed along with the
+// tree it leads to, and every node of the pending patch is then settled
+// against it: its text changes become one UpdateText, an insert and later
+// removal of the same child cancel, and repeated reorderings become one
+// SortChildren of the node's children as they end up. The pending patch is
+// then bounded by the size of the tree rather than by the number of frames,
+// apart from Measure changes, each of which awaits its own answer, and nodes
+// moving back and forth between parents.
+use {Change, DiffTree, Element, Key};
+
+#[derive(Debug, Default)]
+pub struct PatchBuffer {
+    pending: Option<DiffTree>,
+    // Patches coalesced into `pending` since it was last taken.
+    frames: usize,
+}
+
+impl PatchBuffer {
+    pub fn new() -> PatchBuffer {
+        PatchBuffer::default()
+    }
+
+    // Adds `diff`, which leads to `tree`, the tree it was computed against.
+    pub fn push(&mut self, diff: DiffTree, tree: &Element) {
+        let mut pending = match self.pending.take() {
+            Some(pending) => pending.compose(diff),
+            None => diff,
+        };
+        settle(&mut pending, tree);
+        self.pending = Some(pending);
+        self.frames += 1;
+    }
+
+    // The patch taking the client from the last tree it saw to the latest
+    // one, or None if nothing changed since, including when the changes
+    // pushed cancelled each other out.
+    pub fn take(&mut self) -> Option<DiffTree> {
+        self.frames = 0;
+        self.pending.take().filter(|pending| !pending.is_empty())
+    }
+
+    pub fn peek(&self) -> Option<&DiffTree> {
+        self.pending.as_ref()
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_none()
+    }
+}
+
+impl DiffTree {
+    // A single patch with the effect of applying this one and then `next`.
+    pub fn compose(self, next: DiffTree) -> DiffTree {
+        let next_changes = next.changes.map_or(vec![], |x| x.into_vec());
+        let next_children = next.children.map_or(vec![], |x| x.into_vec());
+
+        // A replacement discards everything that happened to the node before.
+        if next_changes.iter().any(|change| matches!(*change, Change::ReplaceNode(_))) {
+            return DiffTree {
+                changes: boxed(next_changes),
+                children: boxed(next_children),
+                base_checksum: self.base_checksum,
+            };
+        }
+
+        let mut changes = self.changes.map_or(vec![], |x| x.into_vec());
+        let mut children = self.children.map_or(vec![], |x| x.into_vec());
+
+        // Nothing after a replacement applies, so `next` is applied to the
+        // replacement instead. If it doesn't apply, it wasn't computed
+        // against the tree this patch leads to, and is appended as is.
+        let replaced = changes.iter().position(|change| matches!(*change, Change::ReplaceNode(_)));
+        if let Some(index) = replaced {
+            if let Change::ReplaceNode(ref node) = changes[index] {
+                let mut node = node.clone();
+                let next = DiffTree {
+                    changes: boxed(next_changes.clone()),
+                    children: boxed(next_children.clone()),
+                    base_checksum: None,
+                };
+                if node.apply(&next).is_ok() {
+                    return DiffTree {
+                        changes: Some(Box::new([Change::ReplaceNode(node)])),
+                        children: None,
+                        base_checksum: self.base_checksum,
+                    };
+                }
+            }
+        }
+        for change in next_changes {
+            coalesce(&mut changes, &mut children, change);
+        }
+        for (key, diff) in next_children {
+            match children.iter().position(|&(ref x, _)| *x == key) {
+                Some(index) => {
+                    let (_, previous) = children.remove(index);
+                    children.insert(index, (key, previous.compose(diff)));
+                }
+                None => children.push((key, diff)),
+            }
+        }
+        DiffTree {
+            changes: boxed(changes),
+            children: boxed(children),
+            base_checksum: self.base_checksum,
+        }
+    }
+}
+
+// None for an empty list, as DiffTree leaves out empty change lists.
+pub(crate) fn boxed<T>(items: Vec<T>) -> Option<Box<[T]>> {
+    if items.is_empty() {
+        None
+    } else {
+        Some(items.into_boxed_slice())
+    }
+}
+
+// Appends `change` to `changes`, dropping or cancelling earlier changes that
+// it makes redundant.
+fn coalesce(changes: &mut Vec<Change>, children: &mut Vec<(Key, DiffTree)>, change: Change) {
+    match change {
+        Change::UpdateText(_) => {
+            changes.retain(|x| !matches!(*x, Change::UpdateText(_) | Change::SpliceText { .. }));
+        }
+        Change::SortChildren(_) => {
+            changes.retain(|x| !matches!(*x, Change::SortChildren(_)));
+        }
+        Change::RemoveChild(ref key) => {
+            children.retain(|&(ref x, _)| x != key);
+            let inserted = changes.iter().position(|x| match *x {
+                Change::InsertChild(_, ref element) => element.to_key() == *key,
+                _ => false,
+            });
+            // Children removed or moved since the insert may have come
+            // before the child, shifting it by an amount only the tree
+            // knows, so then both changes are kept.
+            let shifted = |index: usize| {
+                changes[index..].iter().any(|x| {
+                    matches!(*x, Change::RemoveChild(_) |
+                                 Change::MarkExiting(_) |
+                                 Change::MoveChild(..) |
+                                 Change::MoveNode { .. } |
+                                 Change::SortChildren(_))
+                })
+            };
+            if let Some(index) = inserted.filter(|&index| !shifted(index)) {
+                // Inserted and removed again: the client never needs to see
+                // it, and children inserted after it, past where it then
+                // was, move up one place.
+                let mut position = match changes.remove(index) {
+                    Change::InsertChild(position, _) => position,
+                    _ => unreachable!(),
+                };
+                for x in changes[index..].iter_mut() {
+                    if let Change::InsertChild(ref mut x, _) = *x {
+                        if *x <= position {
+                            position += 1;
+                        } else {
+                            *x -= 1;
+                        }
+                    }
+                }
+                return;
+            }
+        }
+        Change::ObserveVisibility(ref key) |
+        Change::UnobserveVisibility(ref key) => {
+            if cancel(changes, |x| match *x {
+                Change::ObserveVisibility(ref x) |
+                Change::UnobserveVisibility(ref x) => x == key,
+                _ => false,
+            }) {
+                return;
+            }
+        }
+        Change::ObserveResize(ref key) |
+        Change::UnobserveResize(ref key) => {
+            if cancel(changes, |x| match *x {
+                Change::ObserveResize(ref x) |
+                Change::UnobserveResize(ref x) => x == key,
+                _ => false,
+            }) {
+                return;
+            }
+        }
+        Change::MarkExiting(ref key) => {
+            children.retain(|&(ref x, _)| x != key);
+        }
+        // Likewise for a listener.
+        Change::AddListener(_, ref name, _) |
+        Change::RemoveListener(_, ref name) => {
+            changes.retain(|x| match *x {
+                Change::AddListener(_, ref x, _) |
+                Change::RemoveListener(_, ref x) => x != name,
+                _ => true,
+            });
+        }
+        Change::SetStyle { ref key, ref property, .. } => {
+            // Only the latest value of a property matters.
+            changes.retain(|x| match *x {
+                Change::SetStyle { key: ref x, property: ref y, .. } => x != key || y != property,
+                _ => true,
+            });
+        }
+        // Only the latest change to an attribute matters. Earlier bulk
+        // updates are kept whole, and later changes simply override them.
+        Change::SetAttribute(ref name, _) |
+        Change::RemoveAttribute(ref name) => {
+            changes.retain(|x| !changes_attribute(x, name));
+        }
+        Change::UpdateAttributes { ref set, ref remove } => {
+            changes.retain(|x| {
+                !set.iter().any(|&(ref name, _)| changes_attribute(x, name)) &&
+                !remove.iter().any(|name| changes_attribute(x, name))
+            });
+        }
+        // Only the latest focus and scroll matter, but every measurement is
+        // answered, so only a repeated request is dropped.
+        Change::Focus(_) => changes.retain(|x| !matches!(*x, Change::Focus(_))),
+        Change::ScrollIntoView { .. } => changes.retain(|x| !matches!(*x, Change::ScrollIntoView { .. })),
+        Change::Measure { request_id, .. } => {
+            changes.retain(|x| !matches!(*x, Change::Measure { request_id: x, .. } if x == request_id));
+        }
+        // Splices apply on top of each other, and indices depend on the
+        // tree, so these are kept for settle.
+        Change::SpliceText { .. } |
+        Change::MoveChild(..) |
+        Change::InsertChild(..) |
+        Change::MoveNode { .. } |
+        Change::ReplaceNode(_) => {}
+    }
+    changes.push(change);
+}
+
+// Shortens the changes of each node of `diff`, which leads to `tree`, as
+// far as knowing the tree allows, see the header.
+fn settle(diff: &mut DiffTree, tree: &Element) {
+    let tree = tree.force();
+    if let Some(changes) = diff.changes.take() {
+        let mut changes = changes.into_vec();
+        settle_changes(&mut changes, &tree);
+        diff.changes = boxed(changes);
+    }
+    if let Some(ref mut children) = diff.children {
+        for &mut (ref key, ref mut child) in children.iter_mut() {
+            if let Some(node) = tree.get_child(key) {
+                settle(child, node);
+            }
+        }
+    }
+}
+
+fn settle_changes(changes: &mut Vec<Change>, node: &Element) {
+    if changes.iter().any(|x| matches!(*x, Change::ReplaceNode(_))) {
+        return;
+    }
+    let is_text = |x: &Change| matches!(*x, Change::UpdateText(_) | Change::SpliceText { .. });
+    if changes.iter().filter(|x| is_text(x)).count() > 1 {
+        if let Element::Text { ref value, .. } = *node {
+            changes.retain(|x| !is_text(x));
+            changes.push(Change::UpdateText(value.clone()));
+        }
+    }
+
+    // A child inserted and removed again goes, along with its exit, which
+    // can shift the indices of the changes after it; the SortChildren
+    // below puts them right.
+    let mut reorder = false;
+    let mut index = 0;
+    while index < changes.len() {
+        let inserted = match changes[index] {
+            Change::RemoveChild(ref key) => {
+                changes[..index]
+                    .iter()
+                    .position(|x| matches!(*x, Change::InsertChild(..)) && names_child(x, key))
+                    .map(|inserted| (inserted, key.clone()))
+            }
+            _ => None,
+        };
+        match inserted {
+            Some((inserted, key)) => {
+                let kept: Vec<Change> =
+                    changes.drain(inserted..index + 1).filter(|x| !names_child(x, &key)).collect();
+                index = inserted + kept.len();
+                changes.splice(inserted..inserted, kept);
+                reorder = true;
+            }
+            None => index += 1,
+        }
+    }
+
+    let is_order = |x: &Change| matches!(*x, Change::MoveChild(..) | Change::SortChildren(_));
+    if reorder || changes.iter().filter(|x| is_order(x)).count() > 1 {
+        if let Element::Parent { ref children, .. } = *node {
+            changes.retain(|x| !is_order(x));
+            changes.push(Change::SortChildren(children.iter().map(Element::to_key).collect()));
+        }
+    }
+}
+
+// Whether `change` inserts, removes or moves the child keyed `key`.
+fn names_child(change: &Change, key: &Key) -> bool {
+    match *change {
+        Change::InsertChild(_, ref element) => element.to_key() == *key,
+        Change::RemoveChild(ref x) |
+        Change::MarkExiting(ref x) |
+        Change::MoveChild(ref x, _) => x == key,
+        _ => false,
+    }
+}
+
+// Whether `change` is a SetAttribute or RemoveAttribute of `name`.
+fn changes_attribute(change: &Change, name: &str) -> bool {
+    match *change {
+        Change::SetAttribute(ref x, _) |
+        Change::RemoveAttribute(ref x) => x == name,
+        _ => false,
+    }
+}
+
+// Removes an earlier change matching `f`, returning whether there was one.
+// Flag changes only ever toggle, so a second one undoes the first.
+fn cancel<F>(changes: &mut Vec<Change>, f: F) -> bool
+    where F: Fn(&Change) -> bool
+{
+    match changes.iter().position(f) {
+        Some(index) => {
+            changes.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PatchBuffer;
+    use {DiffConfig, DiffTree, Element, Key, TextDiff};
+
+    fn label(value: &str) -> Element {
+        el!(p[key=0, Element::Text { key: Key::Local(0), value: value.to_string() }])
+    }
+
+    #[test]
+    fn test_coalesce_text_updates() {
+        let mut buffer = PatchBuffer::new();
+        buffer.push(label("a").diff(&label("b")).unwrap(), &label("b"));
+        buffer.push(label("b").diff(&label("c")).unwrap(), &label("c"));
+        assert_eq!(buffer.frames(), 2);
+
+        let pending = buffer.take().unwrap();
+        assert_eq!(pending, label("a").diff(&label("c")).unwrap());
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.frames(), 0);
+    }
+
+    #[test]
+    fn test_insert_then_remove_cancels() {
+        let empty = el!(ul[key=0, el!(li[key=1])]);
+        let full = el!(ul[key=0, el!(li[key=1]), el!(li[key=2])]);
+        let mut buffer = PatchBuffer::new();
+        buffer.push(empty.diff(&full).unwrap(), &full);
+        buffer.push(full.diff(&empty).unwrap(), &empty);
+
+        assert!(buffer.peek().unwrap().is_empty());
+        assert_eq!(buffer.take(), None);
+    }
+
+    fn size(diff: &DiffTree) -> usize {
+        diff.changes.as_ref().map_or(0, |x| x.len()) +
+        diff.children.iter().flat_map(|x| x.iter()).map(|&(_, ref x)| size(x)).sum::<usize>()
+    }
+
+    #[test]
+    fn test_pending_stays_bounded() {
+        let item = |key: u64, value: &str| el!(li[key=key, Element::text(Key::Local(0), value)]);
+        let frames = [
+            el!(ul[key=0, item(1, "a"), item(2, "b"), item(3, "c")]),
+            el!(ul[key=0, item(2, "ab"), item(1, "a"), item(4, "d"), item(3, "c")]),
+            el!(ul[key=0, item(3, "c"), item(1, "b"), item(2, "abc")]),
+        ];
+        let config = DiffConfig { text_diff: TextDiff::Chars, ..DiffConfig::default() };
+        let mut buffer = PatchBuffer::new();
+        for frame in 1..1000 {
+            let (previous, next) = (&frames[(frame - 1) % 3], &frames[frame % 3]);
+            buffer.push(previous.diff_with(next, &config).unwrap(), next);
+            let pending = buffer.peek().unwrap();
+            assert!(size(pending) <= 8, "{} changes after {} frames", size(pending), frame);
+            let mut patched = frames[0].clone();
+            patched.apply(pending).unwrap();
+            assert!(patched.deep_eq(next));
+        }
+        assert_eq!(buffer.frames(), 999);
+
+        // Swapping back and forth leaves a single ordering.
+        let swap = [el!(ul[key=0, el!(li[key=1]), el!(li[key=2])]),
+                    el!(ul[key=0, el!(li[key=2]), el!(li[key=1])])];
+        let mut buffer = PatchBuffer::new();
+        for frame in 1..1001 {
+            buffer.push(swap[(frame - 1) % 2].diff(&swap[frame % 2]).unwrap(), &swap[frame % 2]);
+        }
+        assert_eq!(size(buffer.peek().unwrap()), 1);
+    }
+
+    fn next(seed: &mut u64, n: u64) -> u64 {
+        *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (*seed >> 33) % n
+    }
+
+    // A small tree of a few tags, attributes and texts, so that random pairs
+    // share structure.
+    fn random_tree(seed: &mut u64, key: Key, depth: usize) -> Element {
+        if next(seed, 4) == 0 {
+            return Element::text(key, ["a", "b", "c"][next(seed, 3) as usize]);
+        }
+        let name = ["div", "p", "br"][next(seed, 3) as usize];
+        let parent = depth > 0 && next(seed, 3) != 0;
+        let mut builder = if parent {
+            Element::parent(name)
+        } else {
+            Element::void(name)
+        };
+        builder = builder.key(key);
+        if next(seed, 2) == 0 {
+            builder = builder.attr("class", ["x", "y"][next(seed, 2) as usize]);
+        }
+        if next(seed, 3) == 0 {
+            builder = builder.attr("id", "q");
+        }
+        if parent {
+            let mut keys: Vec<u64> = (0..5).filter(|_| next(seed, 2) == 0).collect();
+            for index in (1..keys.len()).rev() {
+                keys.swap(index, next(seed, index as u64 + 1) as usize);
+            }
+            for key in keys {
+                builder = builder.child(random_tree(seed, Key::Local(key), depth - 1));
+            }
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn test_compose_round_trip() {
+        let diff = |a: &Element, b: &Element| {
+            a.diff(b).unwrap_or(DiffTree { changes: None, children: None, base_checksum: None })
+        };
+        // Composing any run of patches gives a patch with their effect.
+        let mut seed = 11u64;
+        for _ in 0..20000 {
+            let trees: Vec<Element> = (0..4).map(|_| random_tree(&mut seed, Key::Local(0), 3)).collect();
+            let mut composed = diff(&trees[0], &trees[1]);
+            let mut buffer = PatchBuffer::new();
+            buffer.push(composed.clone(), &trees[1]);
+            for pair in trees[1..].windows(2) {
+                composed = composed.compose(diff(&pair[0], &pair[1]));
+                let mut patched = trees[0].clone();
+                patched.apply(&composed).unwrap();
+                assert!(patched.deep_eq(&pair[1]), "{:?}", trees);
+
+                // Settling against the tree keeps the effect.
+                buffer.push(diff(&pair[0], &pair[1]), &pair[1]);
+                let mut patched = trees[0].clone();
+                patched.apply(buffer.peek().unwrap()).unwrap();
+                assert!(patched.deep_eq(&pair[1]), "{:?}", trees);
+            }
+        }
+    }
+}
@@ -0,0 +1,509 @@
+// Versioning for the patch protocol. Serialized patches travel in an
+// envelope carrying the protocol version, and clients advertise the change
+// kinds they understand so that servers can keep sending patches old clients
+// can apply as the Change enum grows.
+//
+// Patches are downgraded for a client as follows. An unsupported
+// MarkExiting becomes RemoveChild, skipping the exit transition, unsupported
+// MoveChild changes become one SortChildren, an unsupported SpliceText
+// becomes UpdateText with the whole new value, and an unsupported
+// UpdateAttributes becomes one change per attribute. An unsupported
+// MoveNode becomes a RemoveChild at the node's old parent and an InsertChild
+// of it as it ends up, losing its renderer state. Unsupported
+// commands and flag changes are dropped, since they don't change the tree.
+// Any other unsupported change replaces the whole node it belongs to, which
+// every client supports.
+use std::collections::{BTreeMap, BTreeSet};
+
+use json_patch::Operation;
+use patch_buffer::boxed;
+use rustc_serialize::json::{Json, ToJson};
+use {Change, DiffTree, Element, Key, PatchError};
+
+pub const PROTOCOL_VERSION: u64 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChangeKind {
+    RemoveChild,
+    InsertChild,
+    MoveChild,
+    SortChildren,
+    UpdateText,
+    SpliceText,
+    ReplaceNode,
+    SetAttribute,
+    RemoveAttribute,
+    UpdateAttributes,
+    ObserveVisibility,
+    UnobserveVisibility,
+    ObserveResize,
+    UnobserveResize,
+    Focus,
+    ScrollIntoView,
+    Measure,
+    MarkExiting,
+    SetStyle,
+    AddListener,
+    RemoveListener,
+    MoveNode,
+}
+
+const ALL_KINDS: [ChangeKind; 22] = [ChangeKind::RemoveChild,
+                                     ChangeKind::InsertChild,
+                                     ChangeKind::MoveChild,
+                                     ChangeKind::SortChildren,
+                                     ChangeKind::UpdateText,
+                                     ChangeKind::SpliceText,
+                                     ChangeKind::ReplaceNode,
+                                     ChangeKind::SetAttribute,
+                                     ChangeKind::RemoveAttribute,
+                                     ChangeKind::UpdateAttributes,
+                                     ChangeKind::ObserveVisibility,
+                                     ChangeKind::UnobserveVisibility,
+                                     ChangeKind::ObserveResize,
+                                     ChangeKind::UnobserveResize,
+                                     ChangeKind::Focus,
+                                     ChangeKind::ScrollIntoView,
+                                     ChangeKind::Measure,
+                                     ChangeKind::MarkExiting,
+                                     ChangeKind::SetStyle,
+                                     ChangeKind::AddListener,
+                                     ChangeKind::RemoveListener,
+                                     ChangeKind::MoveNode];
+
+impl ChangeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChangeKind::RemoveChild => "RemoveChild",
+            ChangeKind::InsertChild => "InsertChild",
+            ChangeKind::MoveChild => "MoveChild",
+            ChangeKind::SortChildren => "SortChildren",
+            ChangeKind::UpdateText => "UpdateText",
+            ChangeKind::SpliceText => "SpliceText",
+            ChangeKind::ReplaceNode => "ReplaceNode",
+            ChangeKind::SetAttribute => "SetAttribute",
+            ChangeKind::RemoveAttribute => "RemoveAttribute",
+            ChangeKind::UpdateAttributes => "UpdateAttributes",
+            ChangeKind::ObserveVisibility => "ObserveVisibility",
+            ChangeKind::UnobserveVisibility => "UnobserveVisibility",
+            ChangeKind::ObserveResize => "ObserveResize",
+            ChangeKind::UnobserveResize => "UnobserveResize",
+            ChangeKind::Focus => "Focus",
+            ChangeKind::ScrollIntoView => "ScrollIntoView",
+            ChangeKind::Measure => "Measure",
+            ChangeKind::MarkExiting => "MarkExiting",
+            ChangeKind::SetStyle => "SetStyle",
+            ChangeKind::AddListener => "AddListener",
+            ChangeKind::RemoveListener => "RemoveListener",
+            ChangeKind::MoveNode => "MoveNode",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ChangeKind> {
+        ALL_KINDS.iter().cloned().find(|kind| kind.name() == name)
+    }
+
+    // Whether dropping a change of this kind leaves the tree as it would
+    // have been.
+    fn droppable(self) -> bool {
+        matches!(self,
+                 ChangeKind::ObserveVisibility |
+                 ChangeKind::UnobserveVisibility |
+                 ChangeKind::ObserveResize |
+                 ChangeKind::UnobserveResize |
+                 ChangeKind::Focus |
+                 ChangeKind::ScrollIntoView |
+                 ChangeKind::Measure |
+                 ChangeKind::SetStyle)
+    }
+}
+
+impl Change {
+    pub fn kind(&self) -> ChangeKind {
+        match *self {
+            Change::RemoveChild(_) => ChangeKind::RemoveChild,
+            Change::InsertChild(..) => ChangeKind::InsertChild,
+            Change::MoveChild(..) => ChangeKind::MoveChild,
+            Change::SortChildren(_) => ChangeKind::SortChildren,
+            Change::UpdateText(_) => ChangeKind::UpdateText,
+            Change::SpliceText { .. } => ChangeKind::SpliceText,
+            Change::ReplaceNode(_) => ChangeKind::ReplaceNode,
+            Change::SetAttribute(..) => ChangeKind::SetAttribute,
+            Change::RemoveAttribute(_) => ChangeKind::RemoveAttribute,
+            Change::UpdateAttributes { .. } => ChangeKind::UpdateAttributes,
+            Change::ObserveVisibility(_) => ChangeKind::ObserveVisibility,
+            Change::UnobserveVisibility(_) => ChangeKind::UnobserveVisibility,
+            Change::ObserveResize(_) => ChangeKind::ObserveResize,
+            Change::UnobserveResize(_) => ChangeKind::UnobserveResize,
+            Change::Focus(_) => ChangeKind::Focus,
+            Change::ScrollIntoView { .. } => ChangeKind::ScrollIntoView,
+            Change::Measure { .. } => ChangeKind::Measure,
+            Change::MarkExiting(_) => ChangeKind::MarkExiting,
+            Change::SetStyle { .. } => ChangeKind::SetStyle,
+            Change::AddListener(..) => ChangeKind::AddListener,
+            Change::RemoveListener(..) => ChangeKind::RemoveListener,
+            Change::MoveNode { .. } => ChangeKind::MoveNode,
+        }
+    }
+}
+
+// What one side of the connection understands.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    pub version: u64,
+    kinds: BTreeSet<ChangeKind>,
+}
+
+impl Capabilities {
+    // Everything this version of the crate produces.
+    pub fn current() -> Capabilities {
+        Capabilities::new(PROTOCOL_VERSION, &ALL_KINDS)
+    }
+
+    // ReplaceNode is always included, as the fallback for everything else.
+    pub fn new(version: u64, kinds: &[ChangeKind]) -> Capabilities {
+        let mut kinds: BTreeSet<ChangeKind> = kinds.iter().cloned().collect();
+        kinds.insert(ChangeKind::ReplaceNode);
+        Capabilities {
+            version: version,
+            kinds: kinds,
+        }
+    }
+
+    pub fn supports(&self, kind: ChangeKind) -> bool {
+        self.kinds.contains(&kind)
+    }
+
+    // What both sides understand: the older version and the shared kinds.
+    pub fn negotiate(&self, other: &Capabilities) -> Capabilities {
+        Capabilities {
+            version: self.version.min(other.version),
+            kinds: self.kinds.intersection(&other.kinds).cloned().collect(),
+        }
+    }
+
+    // Reads a client's advertisement, `{"version": 1, "changes": [...]}`,
+    // ignoring change kinds this version doesn't know.
+    pub fn from_json(json: &Json) -> Option<Capabilities> {
+        let version = json.find("version")?.as_u64()?;
+        let kinds: Vec<ChangeKind> = json.find("changes")?
+            .as_array()?
+            .iter()
+            .filter_map(|x| x.as_string().and_then(ChangeKind::from_name))
+            .collect();
+        Some(Capabilities::new(version, &kinds))
+    }
+}
+
+impl ToJson for Capabilities {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert("version".to_string(), self.version.to_json());
+        object.insert("changes".to_string(),
+                      Json::Array(self.kinds.iter().map(|x| x.name().to_json()).collect()));
+        Json::Object(object)
+    }
+}
+
+// A serialized patch along with the protocol version it was written for.
+#[derive(Debug, PartialEq)]
+pub struct Envelope {
+    pub version: u64,
+    pub operations: Vec<Operation>,
+}
+
+impl Envelope {
+    // Fails if `diff` doesn't apply to `base`, see DiffTree::to_json_patch.
+    pub fn new(diff: &DiffTree, base: &Element) -> Result<Envelope, PatchError> {
+        Ok(Envelope {
+            version: PROTOCOL_VERSION,
+            operations: diff.to_json_patch(base)?,
+        })
+    }
+}
+
+impl ToJson for Envelope {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert("version".to_string(), self.version.to_json());
+        object.insert("patch".to_string(),
+                      Json::Array(self.operations.iter().map(|x| x.to_json()).collect()));
+        Json::Object(object)
+    }
+}
+
+impl DiffTree {
+    // Rewrites this patch to use only change kinds in `capabilities`.
+    // `next` is the tree the patch leads to, the source of replacement
+    // nodes.
+    pub fn downgrade(self, capabilities: &Capabilities, next: &Element) -> DiffTree {
+        // Moves span two nodes, so they are split up front.
+        if capabilities.supports(ChangeKind::MoveNode) {
+            self.downgrade_at(capabilities, next)
+        } else {
+            self.split_moves(next).downgrade_at(capabilities, next)
+        }
+    }
+
+    fn downgrade_at(self, capabilities: &Capabilities, next: &Element) -> DiffTree {
+        let next = next.force();
+        let changes = self.changes.map_or(vec![], |x| x.into_vec());
+        let mut kept = vec![];
+        let mut sorted = false;
+        for change in changes {
+            let kind = change.kind();
+            if capabilities.supports(kind) {
+                kept.push(change);
+            } else if !kind.droppable() {
+                match change {
+                    Change::MarkExiting(ref key) if capabilities.supports(ChangeKind::RemoveChild) => {
+                        kept.push(Change::RemoveChild(key.clone()))
+                    }
+                    // Moves are applied before inserts, and sorting leaves the
+                    // children not yet inserted out of the way.
+                    Change::MoveChild(..) if capabilities.supports(ChangeKind::SortChildren) => {
+                        if !sorted {
+                            let keys: Vec<Key> = next.view().children().map(|x| x.key()).collect();
+                            kept.push(Change::SortChildren(keys.into_boxed_slice()));
+                            sorted = true;
+                        }
+                    }
+                    Change::SpliceText { .. } if capabilities.supports(ChangeKind::UpdateText) => {
+                        match *next {
+                            Element::Text { ref value, .. } => kept.push(Change::UpdateText(value.clone())),
+                            _ => return replace(&next, self.base_checksum),
+                        }
+                    }
+                    Change::UpdateAttributes { set, remove } if
+                        capabilities.supports(ChangeKind::SetAttribute) &&
+                        capabilities.supports(ChangeKind::RemoveAttribute) => {
+                        kept.extend(remove.into_iter().map(Change::RemoveAttribute));
+                        kept.extend(set.into_iter().map(|(name, value)| Change::SetAttribute(name, value)));
+                    }
+                    _ => return replace(&next, self.base_checksum),
+                }
+            }
+        }
+        let children = self.children.map(|children| {
+            children.into_vec()
+                .into_iter()
+                .filter_map(|(key, diff)| {
+                    next.get_child(&key).map(|child| (key, diff.downgrade_at(capabilities, child)))
+                })
+                .collect::<Vec<_>>()
+                .into_boxed_slice()
+        });
+        DiffTree {
+            changes: Some(kept.into_boxed_slice()),
+            children: children,
+            base_checksum: self.base_checksum,
+        }
+    }
+}
+
+impl DiffTree {
+    // This patch with every MoveNode replaced by a RemoveChild at the old
+    // parent and an InsertChild of the node from `next`, the tree the patch
+    // leads to. Nodes moved out of a node that is itself moved are removed
+    // along with it.
+    pub(crate) fn split_moves(mut self, next: &Element) -> DiffTree {
+        let moves: Vec<(Key, Vec<Key>)> =
+            self.moves().into_iter().map(|(key, from)| (key, from.to_vec())).collect();
+        self.insert_moved(next);
+        for &(ref key, ref from) in &moves {
+            let carried = moves.iter().any(|&(ref other, ref old)| {
+                from.len() > old.len() && from.starts_with(old) && from[old.len()] == *other
+            });
+            if !carried {
+                self.remove_at(from, key.clone());
+            }
+        }
+        self
+    }
+
+    // Replaces the MoveNode changes with InsertChild changes, dropping the
+    // changes to the moved nodes.
+    fn insert_moved(&mut self, next: &Element) {
+        let next = next.force();
+        let mut inserted = vec![];
+        if let Some(ref mut changes) = self.changes {
+            for change in changes.iter_mut() {
+                let (key, index) = match *change {
+                    Change::MoveNode { ref key, index, .. } => (key.clone(), index),
+                    _ => continue,
+                };
+                let node = next.get_child(&key).expect("moved node missing from the next tree");
+                *change = Change::InsertChild(index, node.resolve());
+                inserted.push(key);
+            }
+        }
+        if let Some(children) = self.children.take() {
+            let mut children = children.into_vec();
+            children.retain(|&(ref key, _)| !inserted.contains(key));
+            for &mut (ref key, ref mut child) in &mut children {
+                if let Some(next) = next.get_child(key) {
+                    child.insert_moved(next);
+                }
+            }
+            self.children = boxed(children);
+        }
+    }
+
+    // Adds a RemoveChild of `key` to the node at `path`, before its moves
+    // and inserts.
+    fn remove_at(&mut self, path: &[Key], key: Key) {
+        match path.split_first() {
+            None => {
+                let mut changes = self.changes.take().map_or(vec![], |x| x.into_vec());
+                let index = changes.iter()
+                    .position(|x| {
+                        matches!(*x, Change::MoveChild(..) |
+                                     Change::InsertChild(..) |
+                                     Change::MoveNode { .. })
+                    })
+                    .unwrap_or(changes.len());
+                changes.insert(index, Change::RemoveChild(key));
+                self.changes = Some(changes.into_boxed_slice());
+            }
+            Some((first, rest)) => {
+                let mut children = self.children.take().map_or(vec![], |x| x.into_vec());
+                let index = match children.binary_search_by_key(&first, |&(ref x, _)| x) {
+                    Ok(index) => index,
+                    Err(index) => {
+                        children.insert(index,
+                                        (first.clone(),
+                                         DiffTree {
+                                            changes: None,
+                                            children: None,
+                                            base_checksum: None,
+                                        }));
+                        index
+                    }
+                };
+                children[index].1.remove_at(rest, key);
+                self.children = Some(children.into_boxed_slice());
+            }
+        }
+    }
+}
+
+fn replace(next: &Element, base_checksum: Option<u32>) -> DiffTree {
+    DiffTree {
+        changes: Some(Box::new([Change::ReplaceNode(next.resolve())])),
+        children: None,
+        base_checksum: base_checksum,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use rustc_serialize::json::{Json, ToJson};
+    use json_patch::Operation;
+    use super::{Capabilities, ChangeKind, PROTOCOL_VERSION};
+    use {Change, DiffTree, Element, Key};
+
+    fn text(value: &str) -> Element {
+        Element::Text {
+            key: Key::Local(0),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let mut client = BTreeMap::new();
+        client.insert("version".to_string(), 0u64.to_json());
+        client.insert("changes".to_string(), vec!["UpdateText".to_string(), "SpliceText".to_string()].to_json());
+        let client = Capabilities::from_json(&Json::Object(client)).unwrap();
+        let agreed = Capabilities::current().negotiate(&client);
+        assert_eq!(agreed.version, 0);
+        assert!(agreed.supports(ChangeKind::UpdateText));
+        assert!(agreed.supports(ChangeKind::ReplaceNode));
+        assert!(agreed.supports(ChangeKind::SpliceText));
+        assert!(!agreed.supports(ChangeKind::SortChildren));
+        assert_eq!(Capabilities::from_json(&Capabilities::current().to_json()),
+                   Some(Capabilities::current()));
+        assert_eq!(Capabilities::current().version, PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn test_downgrade() {
+        let before = el!(ul[key=0, el!(li[key=1, text("a")]), el!(li[key=2])]);
+        let after = el!(ul[key=0, el!(li[key=1, text("b")])]);
+        let mut diff = before.diff(&after).unwrap();
+        diff.push_at(&[Key::Local(1)], Change::Focus(Key::Local(1)));
+
+        let capabilities = Capabilities::new(1, &[ChangeKind::UpdateText]);
+        let downgraded = diff.downgrade(&capabilities, &after);
+        assert_eq!(downgraded.changes,
+                   Some(Box::new([Change::ReplaceNode(after.clone())]) as Box<[_]>));
+
+        let mut diff = el!(ul[key=0, text("a")]).diff(&el!(ul[key=0, text("b")])).unwrap();
+        diff.push_at(&[], Change::Focus(Key::Local(0)));
+        let downgraded = diff.downgrade(&capabilities, &el!(ul[key=0, text("b")]));
+        assert_eq!(downgraded.changes.as_ref().map(|x| x.len()), Some(0));
+        assert_eq!(downgraded.children.unwrap()[0].1.changes,
+                   Some(Box::new([Change::UpdateText("b".to_string())]) as Box<[_]>));
+    }
+
+    #[test]
+    fn test_downgrade_bulk_attributes() {
+        let diff = DiffTree {
+            changes: Some(Box::new([Change::UpdateAttributes {
+                                        set: vec![("id".to_string(), "x".to_string())],
+                                        remove: vec!["class".to_string()],
+                                    }])),
+            children: None,
+            base_checksum: None,
+        };
+        let capabilities = Capabilities::new(1, &[ChangeKind::SetAttribute, ChangeKind::RemoveAttribute]);
+        assert_eq!(diff.downgrade(&capabilities, &text("b")).changes,
+                   Some(Box::new([Change::RemoveAttribute("class".to_string()),
+                                  Change::SetAttribute("id".to_string(), "x".to_string())]) as
+                        Box<[_]>));
+    }
+
+    #[test]
+    fn test_downgrade_move_node() {
+        let item = |value: &str| Element::text(Key::Global(7), value);
+        let before = el!(div[key=0, el!(ul[key=1, el!(li[key=3]), item("a")]), el!(ol[key=2, text("b")])]);
+        let after = el!(div[key=0, el!(ul[key=1, el!(li[key=3])]), el!(ol[key=2, text("b"), item("c")])]);
+        let diff = before.diff(&after).unwrap();
+        let capabilities = Capabilities::new(1, &[ChangeKind::RemoveChild, ChangeKind::InsertChild]);
+        let downgraded = diff.clone().downgrade(&capabilities, &after);
+
+        let children = downgraded.children.as_ref().unwrap();
+        assert_eq!(children[0].0, Key::Local(1));
+        assert_eq!(children[0].1.changes,
+                   Some(Box::new([Change::RemoveChild(Key::Global(7))]) as Box<[_]>));
+        assert_eq!(children[1].1.changes,
+                   Some(Box::new([Change::InsertChild(1, item("c"))]) as Box<[_]>));
+        assert_eq!(children[1].1.children, None);
+        let mut patched = before.clone();
+        patched.apply(&downgraded).unwrap();
+        assert!(patched.deep_eq(&after));
+
+        assert_eq!(diff.to_json_patch(&before).unwrap(),
+                   vec![Operation::Remove { path: "/children/0/children/1".to_string() },
+                        Operation::Add {
+                            path: "/children/1/children/1".to_string(),
+                            value: item("c").to_json(),
+                        }]);
+    }
+
+    #[test]
+    fn test_downgrade_moves() {
+        let before = el!(ul[key=0, el!(li[key=1]), el!(li[key=2]), el!(li[key=3])]);
+        let after = el!(ul[key=0, el!(li[key=3]), el!(li[key=4]), el!(li[key=2]), el!(li[key=1])]);
+        let capabilities = Capabilities::new(1, &[ChangeKind::SortChildren, ChangeKind::InsertChild]);
+        let downgraded = before.diff(&after).unwrap().downgrade(&capabilities, &after);
+        let keys = [3, 4, 2, 1].iter().map(|&x| Key::Local(x)).collect::<Vec<_>>();
+        assert_eq!(downgraded.changes,
+                   Some(Box::new([Change::SortChildren(keys.into_boxed_slice()),
+                                  Change::InsertChild(1, el!(li[key=4]))]) as Box<[_]>));
+        let mut patched = before.clone();
+        patched.apply(&downgraded).unwrap();
+        assert!(patched.deep_eq(&after));
+    }
+}
@@ -0,0 +1,197 @@
+// Throttling and debouncing of high-frequency events such as MouseMove, so
+// that a burst of events doesn't cause a re-render per event.
+//
+// Events are limited separately for each (event type, target) pair. The last
+// event held back is always delivered eventually, once poll is called at or
+// after its deadline:
+//
+//     let mut limiter = RateLimiter::new();
+//     limiter.throttle(EventType::MouseMove, Duration::from_millis(16));
+//     if let Some(event) = limiter.push(event, Instant::now()) {
+//         handle(event);
+//     }
+//     for event in limiter.poll(Instant::now()) {
+//         handle(event);
+//     }
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use {Event, EventType, Key};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Limit {
+    // At most one event per interval, delivered at the start of it.
+    Throttle(Duration),
+    // Only the last event of a burst, once no event arrived for the delay.
+    Debounce(Duration),
+}
+
+#[derive(Debug)]
+struct Slot {
+    // When the last event was delivered, for throttling.
+    delivered: Option<Instant>,
+    // The event held back and when it is due.
+    pending: Option<(Event, Instant)>,
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    limits: BTreeMap<EventType, Limit>,
+    slots: BTreeMap<(EventType, Key), Slot>,
+}
+
+impl RateLimiter {
+    pub fn new() -> RateLimiter {
+        RateLimiter::default()
+    }
+
+    pub fn throttle(&mut self, kind: EventType, interval: Duration) -> &mut RateLimiter {
+        self.limits.insert(kind, Limit::Throttle(interval));
+        self
+    }
+
+    pub fn debounce(&mut self, kind: EventType, delay: Duration) -> &mut RateLimiter {
+        self.limits.insert(kind, Limit::Debounce(delay));
+        self
+    }
+
+    // Delivers events of `kind` without delay again. Any held back event is
+    // dropped.
+    pub fn clear(&mut self, kind: EventType) {
+        self.limits.remove(&kind);
+        self.slots.retain(|&(x, _), _| x != kind);
+    }
+
+    // Returns the event if it should be handled now, or holds it back to be
+    // returned by a later poll.
+    pub fn push(&mut self, event: Event, now: Instant) -> Option<Event> {
+        let kind = event.event_type();
+        let limit = match self.limits.get(&kind) {
+            Some(&limit) => limit,
+            None => return Some(event),
+        };
+        let slot = self.slots.entry((kind, event.target())).or_insert(Slot {
+            delivered: None,
+            pending: None,
+        });
+        match limit {
+            Limit::Throttle(interval) => {
+                match slot.delivered {
+                    Some(delivered) if now < delivered + interval => {
+                        slot.pending = Some((event, delivered + interval));
+                        None
+                    }
+                    _ => {
+                        slot.delivered = Some(now);
+                        slot.pending = None;
+                        Some(event)
+                    }
+                }
+            }
+            Limit::Debounce(delay) => {
+                slot.pending = Some((event, now + delay));
+                None
+            }
+        }
+    }
+
+    // The held back events that are due by `now`, in the order of their
+    // deadlines.
+    pub fn poll(&mut self, now: Instant) -> Vec<Event> {
+        let mut due = vec![];
+        for slot in self.slots.values_mut() {
+            let ready = match slot.pending {
+                Some((_, deadline)) => deadline <= now,
+                None => false,
+            };
+            if ready {
+                let (event, deadline) = slot.pending.take().unwrap();
+                slot.delivered = Some(deadline);
+                due.push((deadline, event));
+            }
+        }
+        // Forget targets that have nothing pending and are no longer throttled.
+        let limits = &self.limits;
+        self.slots.retain(|&(kind, _), slot| {
+            slot.pending.is_some() ||
+            match (limits.get(&kind), slot.delivered) {
+                (Some(&Limit::Throttle(interval)), Some(delivered)) => now < delivered + interval,
+                _ => false,
+            }
+        });
+        due.sort_by_key(|&(deadline, _)| deadline);
+        due.into_iter().map(|(_, event)| event).collect()
+    }
+
+    // When poll should next be called, if any event is held back.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.slots.values().filter_map(|slot| slot.pending.as_ref().map(|x| x.1)).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::RateLimiter;
+    use {Event, EventKind, EventType, Key, MouseEventData};
+
+    fn mouse_move() -> Event {
+        Event {
+            target: Key::Local(0),
+            bubbles: true,
+            cancelable: true,
+            timestamp: 0.0,
+            kind: EventKind::MouseMove(MouseEventData::default()),
+        }
+    }
+
+    fn change(value: &str) -> Event {
+        Event {
+            target: Key::Local(1),
+            bubbles: true,
+            cancelable: false,
+            timestamp: 0.0,
+            kind: EventKind::Change { value: value.to_string() },
+        }
+    }
+
+    #[test]
+    fn test_throttle_delivers_trailing_event() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut limiter = RateLimiter::new();
+        limiter.throttle(EventType::MouseMove, ms(16));
+
+        assert!(limiter.push(mouse_move(), start).is_some());
+        assert!(limiter.push(mouse_move(), start + ms(5)).is_none());
+        assert!(limiter.push(mouse_move(), start + ms(10)).is_none());
+        assert_eq!(limiter.next_deadline(), Some(start + ms(16)));
+        assert!(limiter.poll(start + ms(12)).is_empty());
+        assert_eq!(limiter.poll(start + ms(16)).len(), 1);
+        assert!(limiter.push(mouse_move(), start + ms(20)).is_none());
+    }
+
+    #[test]
+    fn test_debounce_keeps_last_event() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut limiter = RateLimiter::new();
+        limiter.debounce(EventType::Change, ms(100));
+
+        assert!(limiter.push(change("a"), start).is_none());
+        assert!(limiter.push(change("ab"), start + ms(50)).is_none());
+        assert!(limiter.poll(start + ms(120)).is_empty());
+        match limiter.poll(start + ms(150)).pop() {
+            Some(Event { kind: EventKind::Change { ref value }, .. }) => assert_eq!(value, "ab"),
+            other => panic!("expected the last change, got {:?}", other),
+        }
+        let click = Event {
+            target: Key::Local(1),
+            bubbles: true,
+            cancelable: true,
+            timestamp: 0.0,
+            kind: EventKind::Click(MouseEventData::default()),
+        };
+        assert!(limiter.push(click, start).is_some());
+    }
+}
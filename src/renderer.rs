@@ -0,0 +1,703 @@
+// Applying patches to something other than an element tree: a browser DOM
+// through a wasm binding, a native widget toolkit, an HTML string.
+//
+// A Renderer creates nodes and edits them through handles of its own, and a
+// RenderDriver keeps the handle for every node of the tree it mounted, so
+// patches addressed by key reach the right node. Children are positioned
+// relative to a sibling, as with insertBefore, so that a renderer may keep
+// exiting nodes in the document without throwing off the indices of the
+// rest. Changes that leave the tree alone, such as Focus or SetStyle, are
+// passed to Renderer::effect. A node given a MoveNode keeps its handle and
+// is passed to Renderer::move_node.
+//
+// Unlike Element::apply, a patch that doesn't fit fails partway through, as
+// the calls already made can't be taken back; check it against the tree it
+// was computed from with DiffTree::validate_against first where that matters.
+use std::cmp;
+use std::collections::BTreeMap;
+
+use ssr::{escape, write_attributes, RAW_TEXT_ELEMENTS, VOID_ELEMENTS};
+use text_diff::splice;
+use {AttrName, Attributes, Change, DiffTree, Element, HandlerId, Key, PatchError};
+
+pub trait Renderer {
+    type Node: Clone;
+
+    fn create_element(&mut self, name: &str) -> Self::Node;
+
+    fn create_text(&mut self, value: &str) -> Self::Node;
+
+    fn set_text(&mut self, node: &Self::Node, value: &str);
+
+    fn set_attribute(&mut self, node: &Self::Node, name: &AttrName, value: &str);
+
+    fn remove_attribute(&mut self, node: &Self::Node, name: &AttrName);
+
+    // Has the node call `handler` for events named `event`, in place of any
+    // handler it had for them. Renderers that don't dispatch events can
+    // leave listeners alone.
+    fn add_listener(&mut self, _node: &Self::Node, _event: &str, _handler: HandlerId) {}
+
+    fn remove_listener(&mut self, _node: &Self::Node, _event: &str) {}
+
+    // Inserts `child` before `before`, one of the children of `parent`, or
+    // after all of them.
+    fn insert_child(&mut self, parent: &Self::Node, child: &Self::Node, before: Option<&Self::Node>);
+
+    fn remove_child(&mut self, parent: &Self::Node, child: &Self::Node);
+
+    // Moves `child`, one of the children of `parent`, as insert_child places
+    // a new one.
+    fn move_child(&mut self, parent: &Self::Node, child: &Self::Node, before: Option<&Self::Node>) {
+        self.remove_child(parent, child);
+        self.insert_child(parent, child, before);
+    }
+
+    // Moves `child`, one of the children of `old_parent`, into `parent` as
+    // insert_child places a new node. Renderers that can move a node without
+    // taking it out of the document first keep more of its state.
+    fn move_node(&mut self,
+                 old_parent: &Self::Node,
+                 parent: &Self::Node,
+                 child: &Self::Node,
+                 before: Option<&Self::Node>) {
+        self.remove_child(old_parent, child);
+        self.insert_child(parent, child, before);
+    }
+
+    // Puts `new` in the place of `old`, the child of `parent` or the root.
+    fn replace_node(&mut self, parent: Option<&Self::Node>, old: &Self::Node, new: &Self::Node);
+
+    // The child has left the tree and should play its exit transition; see
+    // Change::MarkExiting. Renderers without transitions remove it at once.
+    fn mark_exiting(&mut self, parent: &Self::Node, child: &Self::Node) {
+        self.remove_child(parent, child);
+    }
+
+    // A change to `node` that leaves the tree as it is: observing it, focus,
+    // scrolling, measuring or an inline style.
+    fn effect(&mut self, _node: &Self::Node, _change: &Change) {}
+
+    // Called once a mount or patch has made all its calls, for renderers
+    // that hold calls back to make them together.
+    fn flush(&mut self) {}
+}
+
+pub struct RenderDriver<R: Renderer> {
+    renderer: R,
+    root: Mounted<R::Node>,
+}
+
+// A node as mounted, with the handles of its children in order.
+struct Mounted<N> {
+    key: Key,
+    node: N,
+    // The value of a text node, for SpliceText.
+    text: Option<String>,
+    children: Vec<Mounted<N>>,
+    // Children marked exiting, still in the renderer's document until their
+    // RemoveChild arrives.
+    exiting: Vec<(Key, N)>,
+}
+
+// The nodes a patch moves, taken from their old parents before it applies,
+// with the handle of the old parent.
+type Moved<N> = BTreeMap<Key, (N, Mounted<N>)>;
+
+impl<R: Renderer> RenderDriver<R> {
+    // Creates the nodes of `tree`, with Lazy nodes evaluated. The root is
+    // left for the caller to attach, see root.
+    pub fn mount(mut renderer: R, tree: &Element) -> RenderDriver<R> {
+        let root = create(&mut renderer, tree);
+        renderer.flush();
+        RenderDriver {
+            renderer: renderer,
+            root: root,
+        }
+    }
+
+    pub fn root(&self) -> &R::Node {
+        &self.root.node
+    }
+
+    // The node at `path`, the keys of the children from the root down.
+    pub fn node(&self, path: &[Key]) -> Option<&R::Node> {
+        path.iter()
+            .try_fold(&self.root, |mounted, key| mounted.children.iter().find(|child| child.key == *key))
+            .map(|mounted| &mounted.node)
+    }
+
+    pub fn renderer(&self) -> &R {
+        &self.renderer
+    }
+
+    pub fn renderer_mut(&mut self) -> &mut R {
+        &mut self.renderer
+    }
+
+    pub fn into_renderer(self) -> R {
+        self.renderer
+    }
+
+    // Makes the calls carrying out `diff`, a patch of the mounted tree. The
+    // renderer is flushed even if the patch fails partway.
+    pub fn patch(&mut self, diff: &DiffTree) -> Result<(), PatchError> {
+        let result = take_moved(&mut self.root, diff).and_then(|mut moved| {
+            patch_at(&mut self.renderer, &mut self.root, None, diff, &mut moved, &mut vec![])
+        });
+        self.renderer.flush();
+        result
+    }
+}
+
+// Takes the nodes `diff` moves out of their old parents' bookkeeping, deeper
+// ones first, leaving the renderer's nodes where they are until their
+// MoveNode is reached.
+fn take_moved<N: Clone>(root: &mut Mounted<N>, diff: &DiffTree) -> Result<Moved<N>, PatchError> {
+    let mut moves = diff.moves();
+    moves.sort_by_key(|&(_, from)| cmp::Reverse(from.len()));
+    let mut moved = BTreeMap::new();
+    for (key, from) in moves {
+        let mut parent = Some(&mut *root);
+        for step in from {
+            parent = parent.and_then(|x| x.children.iter_mut().find(|child| child.key == *step));
+        }
+        let parent = parent.ok_or_else(|| PatchError::NoSuchNode(from.to_vec()))?;
+        match parent.children.iter().position(|child| child.key == key) {
+            Some(index) => {
+                moved.insert(key, (parent.node.clone(), parent.children.remove(index)));
+            }
+            None => {
+                let mut path = from.to_vec();
+                path.push(key);
+                return Err(PatchError::NoSuchNode(path));
+            }
+        }
+    }
+    Ok(moved)
+}
+
+fn create<R: Renderer>(renderer: &mut R, element: &Element) -> Mounted<R::Node> {
+    let (key, name, attributes, listeners, children) = match *element {
+        Element::Text { ref key, ref value } => {
+            return Mounted {
+                key: key.clone(),
+                node: renderer.create_text(value),
+                text: Some(value.clone()),
+                children: vec![],
+                exiting: vec![],
+            }
+        }
+        Element::Lazy { .. } => return create(renderer, &element.force()),
+        Element::Void { ref key, ref name, ref attributes, ref listeners, .. } => {
+            (key, name, attributes, listeners, &[][..])
+        }
+        Element::Parent { ref key, ref name, ref attributes, ref listeners, ref children, .. } => {
+            (key, name, attributes, listeners, &children[..])
+        }
+    };
+    let node = renderer.create_element(name);
+    for (name, value) in attributes.iter().flat_map(|x| x.iter()) {
+        renderer.set_attribute(&node, name, value);
+    }
+    for (event, &handler) in listeners {
+        renderer.add_listener(&node, event, handler);
+    }
+    let children = children.iter()
+        .map(|child| {
+            let child = create(renderer, child);
+            renderer.insert_child(&node, &child.node, None);
+            child
+        })
+        .collect();
+    Mounted {
+        key: key.clone(),
+        node: node,
+        text: None,
+        children: children,
+        exiting: vec![],
+    }
+}
+
+fn patch_at<R: Renderer>(renderer: &mut R,
+                         mounted: &mut Mounted<R::Node>,
+                         parent: Option<&R::Node>,
+                         diff: &DiffTree,
+                         moved: &mut Moved<R::Node>,
+                         path: &mut Vec<Key>)
+                         -> Result<(), PatchError> {
+    if let Some(ref changes) = diff.changes {
+        for (index, change) in changes.iter().enumerate() {
+            if apply_change(renderer, mounted, parent, change, moved).is_err() {
+                return Err(PatchError::Mismatch {
+                    path: path.clone(),
+                    index: index,
+                });
+            }
+        }
+    }
+    if let Some(ref children) = diff.children {
+        let Mounted { ref node, children: ref mut mounted_children, .. } = *mounted;
+        for &(ref key, ref child_diff) in children.iter() {
+            path.push(key.clone());
+            match mounted_children.iter_mut().find(|child| child.key == *key) {
+                Some(child) => patch_at(renderer, child, Some(node), child_diff, moved, path)?,
+                None => return Err(PatchError::NoSuchNode(path.clone())),
+            }
+            path.pop();
+        }
+    }
+    Ok(())
+}
+
+fn apply_change<R: Renderer>(renderer: &mut R,
+                             mounted: &mut Mounted<R::Node>,
+                             parent: Option<&R::Node>,
+                             change: &Change,
+                             moved: &mut Moved<R::Node>)
+                             -> Result<(), ()> {
+    let is_text = mounted.text.is_some();
+    match *change {
+        Change::RemoveChild(ref key) |
+        Change::MarkExiting(ref key) => {
+            if is_text {
+                return Err(());
+            }
+            let node = match mounted.children.iter().position(|child| child.key == *key) {
+                Some(index) => mounted.children.remove(index).node,
+                // A RemoveChild following a MarkExiting finds its node here.
+                None => {
+                    match mounted.exiting.iter().position(|&(ref x, _)| x == key) {
+                        Some(index) => mounted.exiting.remove(index).1,
+                        None => return Ok(()),
+                    }
+                }
+            };
+            match *change {
+                Change::MarkExiting(_) => {
+                    renderer.mark_exiting(&mounted.node, &node);
+                    mounted.exiting.push((key.clone(), node));
+                }
+                _ => renderer.remove_child(&mounted.node, &node),
+            }
+        }
+        Change::InsertChild(index, ref element) => {
+            if is_text {
+                return Err(());
+            }
+            let child = create(renderer, element);
+            let index = index.min(mounted.children.len());
+            renderer.insert_child(&mounted.node,
+                                  &child.node,
+                                  mounted.children.get(index).map(|x| &x.node));
+            mounted.children.insert(index, child);
+        }
+        Change::MoveChild(ref key, index) => {
+            let from = mounted.children.iter().position(|child| child.key == *key).ok_or(())?;
+            let child = mounted.children.remove(from);
+            let index = index.min(mounted.children.len());
+            renderer.move_child(&mounted.node,
+                                &child.node,
+                                mounted.children.get(index).map(|x| &x.node));
+            mounted.children.insert(index, child);
+        }
+        Change::MoveNode { ref key, index, .. } => {
+            if is_text {
+                return Err(());
+            }
+            let (old_parent, child) = moved.remove(key).ok_or(())?;
+            let index = index.min(mounted.children.len());
+            renderer.move_node(&old_parent,
+                               &mounted.node,
+                               &child.node,
+                               mounted.children.get(index).map(|x| &x.node));
+            mounted.children.insert(index, child);
+        }
+        Change::SortChildren(ref keys) => {
+            if is_text {
+                return Err(());
+            }
+            // Children missing from `keys` keep their order, after the rest,
+            // as in Element::apply.
+            let position = |child: &Mounted<R::Node>| {
+                keys.iter().position(|key| *key == child.key).unwrap_or(keys.len())
+            };
+            mounted.children.sort_by_key(position);
+            for child in &mounted.children {
+                renderer.move_child(&mounted.node, &child.node, None);
+            }
+        }
+        Change::UpdateText(ref value) => {
+            if !is_text {
+                return Err(());
+            }
+            renderer.set_text(&mounted.node, value);
+            mounted.text = Some(value.clone());
+        }
+        Change::SpliceText { start, delete, ref insert } => {
+            let value = mounted.text.as_ref().and_then(|text| splice(text, start, delete, insert)).ok_or(())?;
+            renderer.set_text(&mounted.node, &value);
+            mounted.text = Some(value);
+        }
+        Change::ReplaceNode(ref element) => {
+            let new = create(renderer, element);
+            renderer.replace_node(parent, &mounted.node, &new.node);
+            *mounted = new;
+        }
+        Change::SetAttribute(ref name, ref value) => {
+            if is_text {
+                return Err(());
+            }
+            renderer.set_attribute(&mounted.node, &AttrName::from(&name[..]), value);
+        }
+        Change::RemoveAttribute(ref name) => {
+            if is_text {
+                return Err(());
+            }
+            renderer.remove_attribute(&mounted.node, &AttrName::from(&name[..]));
+        }
+        Change::UpdateAttributes { ref set, ref remove } => {
+            if is_text {
+                return Err(());
+            }
+            for name in remove {
+                renderer.remove_attribute(&mounted.node, &AttrName::from(&name[..]));
+            }
+            for &(ref name, ref value) in set {
+                renderer.set_attribute(&mounted.node, &AttrName::from(&name[..]), value);
+            }
+        }
+        Change::AddListener(_, ref event, handler) => {
+            if is_text {
+                return Err(());
+            }
+            renderer.add_listener(&mounted.node, event, handler);
+        }
+        Change::RemoveListener(_, ref event) => {
+            if is_text {
+                return Err(());
+            }
+            renderer.remove_listener(&mounted.node, event);
+        }
+        Change::ObserveVisibility(_) |
+        Change::UnobserveVisibility(_) |
+        Change::ObserveResize(_) |
+        Change::UnobserveResize(_) |
+        Change::Focus(_) |
+        Change::ScrollIntoView { .. } |
+        Change::Measure { .. } |
+        Change::SetStyle { .. } => renderer.effect(&mounted.node, change),
+    }
+    Ok(())
+}
+
+// Renders into an arena of nodes that can be written out as HTML, the same
+// as Element::to_html would write the patched tree. Removed nodes are kept
+// in the arena until the renderer is dropped.
+#[derive(Clone, Debug, Default)]
+pub struct StringRenderer {
+    nodes: Vec<StringNode>,
+}
+
+#[derive(Clone, Debug)]
+enum StringNode {
+    Text(String),
+    Element {
+        name: String,
+        attributes: Attributes,
+        children: Vec<usize>,
+    },
+}
+
+impl StringRenderer {
+    pub fn new() -> StringRenderer {
+        StringRenderer::default()
+    }
+
+    pub fn to_html(&self, node: usize) -> String {
+        let mut out = String::new();
+        self.write_html(node, &mut out);
+        out
+    }
+
+    fn write_html(&self, node: usize, out: &mut String) {
+        match self.nodes[node] {
+            StringNode::Text(ref value) => out.push_str(&escape(value, false)),
+            StringNode::Element { ref name, ref attributes, ref children } => {
+                let mut tag = format!("<{}", name).into_bytes();
+                write_attributes(&mut tag, Some(attributes)).expect("writing to a Vec can't fail");
+                out.push_str(&String::from_utf8(tag).expect("rendered HTML is UTF-8"));
+                out.push('>');
+                let raw = RAW_TEXT_ELEMENTS.contains(&&name[..]);
+                for &child in children {
+                    match self.nodes[child] {
+                        StringNode::Text(ref value) if raw => out.push_str(value),
+                        _ => self.write_html(child, out),
+                    }
+                }
+                if !children.is_empty() || !VOID_ELEMENTS.contains(&&name[..]) {
+                    out.push_str(&format!("</{}>", name));
+                }
+            }
+        }
+    }
+
+    fn children_mut(&mut self, node: usize) -> &mut Vec<usize> {
+        match self.nodes[node] {
+            StringNode::Element { ref mut children, .. } => children,
+            StringNode::Text(_) => panic!("text node {} has no children", node),
+        }
+    }
+
+    fn attributes_mut(&mut self, node: usize) -> &mut Attributes {
+        match self.nodes[node] {
+            StringNode::Element { ref mut attributes, .. } => attributes,
+            StringNode::Text(_) => panic!("text node {} has no attributes", node),
+        }
+    }
+}
+
+impl Renderer for StringRenderer {
+    type Node = usize;
+
+    fn create_element(&mut self, name: &str) -> usize {
+        self.nodes.push(StringNode::Element {
+            name: name.to_string(),
+            attributes: Attributes::new(),
+            children: vec![],
+        });
+        self.nodes.len() - 1
+    }
+
+    fn create_text(&mut self, value: &str) -> usize {
+        self.nodes.push(StringNode::Text(value.to_string()));
+        self.nodes.len() - 1
+    }
+
+    fn set_text(&mut self, node: &usize, value: &str) {
+        self.nodes[*node] = StringNode::Text(value.to_string());
+    }
+
+    fn set_attribute(&mut self, node: &usize, name: &AttrName, value: &str) {
+        self.attributes_mut(*node).insert(name.clone(), value.to_string());
+    }
+
+    fn remove_attribute(&mut self, node: &usize, name: &AttrName) {
+        self.attributes_mut(*node).remove(name);
+    }
+
+    fn insert_child(&mut self, parent: &usize, child: &usize, before: Option<&usize>) {
+        let children = self.children_mut(*parent);
+        let index = before.and_then(|before| children.iter().position(|x| x == before))
+            .unwrap_or(children.len());
+        children.insert(index, *child);
+    }
+
+    fn remove_child(&mut self, parent: &usize, child: &usize) {
+        self.children_mut(*parent).retain(|x| x != child);
+    }
+
+    fn replace_node(&mut self, parent: Option<&usize>, old: &usize, new: &usize) {
+        if let Some(&parent) = parent {
+            for x in self.children_mut(parent).iter_mut().filter(|x| *x == old) {
+                *x = *new;
+            }
+        }
+    }
+}
+
+// Records the calls made to it, one line each, for tests of what a patch
+// does to a renderer. Nodes are numbered in order of creation.
+#[derive(Clone, Debug, Default)]
+pub struct TestRenderer {
+    calls: Vec<String>,
+    next: usize,
+}
+
+impl TestRenderer {
+    pub fn new() -> TestRenderer {
+        TestRenderer::default()
+    }
+
+    pub fn calls(&self) -> &[String] {
+        &self.calls
+    }
+
+    // The calls made so far, clearing the record.
+    pub fn take_calls(&mut self) -> Vec<String> {
+        ::std::mem::take(&mut self.calls)
+    }
+
+    fn create(&mut self, call: String) -> usize {
+        self.calls.push(format!("{} -> {}", call, self.next));
+        self.next += 1;
+        self.next - 1
+    }
+}
+
+impl Renderer for TestRenderer {
+    type Node = usize;
+
+    fn create_element(&mut self, name: &str) -> usize {
+        self.create(format!("create_element {}", name))
+    }
+
+    fn create_text(&mut self, value: &str) -> usize {
+        self.create(format!("create_text {:?}", value))
+    }
+
+    fn set_text(&mut self, node: &usize, value: &str) {
+        self.calls.push(format!("set_text {} {:?}", node, value));
+    }
+
+    fn set_attribute(&mut self, node: &usize, name: &AttrName, value: &str) {
+        self.calls.push(format!("set_attribute {} {}={:?}", node, name.name, value));
+    }
+
+    fn remove_attribute(&mut self, node: &usize, name: &AttrName) {
+        self.calls.push(format!("remove_attribute {} {}", node, name.name));
+    }
+
+    fn add_listener(&mut self, node: &usize, event: &str, handler: HandlerId) {
+        self.calls.push(format!("add_listener {} {} {}", node, event, handler.0));
+    }
+
+    fn remove_listener(&mut self, node: &usize, event: &str) {
+        self.calls.push(format!("remove_listener {} {}", node, event));
+    }
+
+    fn insert_child(&mut self, parent: &usize, child: &usize, before: Option<&usize>) {
+        self.calls.push(format!("insert_child {} {} before {:?}", parent, child, before));
+    }
+
+    fn remove_child(&mut self, parent: &usize, child: &usize) {
+        self.calls.push(format!("remove_child {} {}", parent, child));
+    }
+
+    fn move_child(&mut self, parent: &usize, child: &usize, before: Option<&usize>) {
+        self.calls.push(format!("move_child {} {} before {:?}", parent, child, before));
+    }
+
+    fn move_node(&mut self, old_parent: &usize, parent: &usize, child: &usize, before: Option<&usize>) {
+        self.calls.push(format!("move_node {} from {} to {} before {:?}", child, old_parent, parent, before));
+    }
+
+    fn replace_node(&mut self, parent: Option<&usize>, old: &usize, new: &usize) {
+        self.calls.push(format!("replace_node {:?} {} with {}", parent, old, new));
+    }
+
+    fn mark_exiting(&mut self, parent: &usize, child: &usize) {
+        self.calls.push(format!("mark_exiting {} {}", parent, child));
+    }
+
+    fn effect(&mut self, node: &usize, change: &Change) {
+        self.calls.push(format!("effect {} {:?}", node, change));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RenderDriver, StringRenderer, TestRenderer};
+    use {Change, DiffTree, Element, HandlerId, Key, PatchError};
+
+    fn text(key: u64, value: &str) -> Element {
+        Element::text(Key::Local(key), value)
+    }
+
+    #[test]
+    fn test_string_renderer() {
+        let before = Element::parent("ul")
+            .attr("class", "a")
+            .child(el!(li[key=1, text(0, "one")]))
+            .child(el!(li[key=2, text(0, "two")]))
+            .child(el!(li[key=3]))
+            .build();
+        let after = Element::parent("ul")
+            .attr("id", "list")
+            .child(el!(li[key=3]))
+            .child(el!(li[key=4, text(0, "four & more")]))
+            .child(el!(li[key=1, text(0, "uno")]))
+            .build();
+        let mut driver = RenderDriver::mount(StringRenderer::new(), &before);
+        assert_eq!(driver.renderer().to_html(*driver.root()), before.to_html());
+
+        driver.patch(&before.diff(&after).unwrap()).unwrap();
+        assert_eq!(driver.renderer().to_html(*driver.root()), after.to_html());
+        let four = *driver.node(&[Key::Local(4)]).unwrap();
+        assert_eq!(driver.renderer().to_html(four), "<li>four &amp; more</li>");
+        assert_eq!(driver.node(&[Key::Local(2)]), None);
+
+        let replaced = el!(ol[key=0, text(0, "x")]);
+        driver.patch(&after.diff(&replaced).unwrap()).unwrap();
+        assert_eq!(driver.renderer().to_html(*driver.root()), "<ol>x</ol>");
+    }
+
+    #[test]
+    fn test_test_renderer() {
+        let br = Element::void("br").key(Key::Local(2)).on("click", HandlerId(7)).build();
+        let before = el!(p[key=0, text(1, "a"), br.clone()]);
+        let after = el!(p[key=0, el!(br[key=2]), text(1, "b")]);
+        let mut driver = RenderDriver::mount(TestRenderer::new(), &before);
+        assert_eq!(driver.renderer_mut().take_calls(),
+                   vec!["create_element p -> 0",
+                        "create_text \"a\" -> 1",
+                        "insert_child 0 1 before None",
+                        "create_element br -> 2",
+                        "add_listener 2 click 7",
+                        "insert_child 0 2 before None"]);
+
+        let mut diff = before.diff(&after).unwrap();
+        diff.push_at(&[Key::Local(2)], Change::Focus(Key::Local(2)));
+        driver.patch(&diff).unwrap();
+        assert_eq!(driver.renderer().calls(),
+                   &["move_child 0 1 before None".to_string(),
+                     "set_text 1 \"b\"".to_string(),
+                     "remove_listener 2 click".to_string(),
+                     "effect 2 Focus(Local(2))".to_string()][..]);
+
+        let empty = DiffTree {
+            changes: None,
+            children: None,
+            base_checksum: None,
+        };
+        let mut exiting = empty.clone();
+        exiting.push_at(&[], Change::MarkExiting(Key::Local(1)));
+        exiting.push_at(&[], Change::RemoveChild(Key::Local(1)));
+        driver.renderer_mut().take_calls();
+        driver.patch(&exiting).unwrap();
+        assert_eq!(driver.renderer().calls(),
+                   &["mark_exiting 0 1".to_string(), "remove_child 0 1".to_string()][..]);
+
+        let mut missing = empty.clone();
+        missing.push_at(&[Key::Local(9)], Change::UpdateText("x".to_string()));
+        assert_eq!(driver.patch(&missing), Err(PatchError::NoSuchNode(vec![Key::Local(9)])));
+        let mut mismatch = empty.clone();
+        mismatch.push_at(&[Key::Local(2)], Change::UpdateText("x".to_string()));
+        assert_eq!(driver.patch(&mismatch),
+                   Err(PatchError::Mismatch {
+                       path: vec![Key::Local(2)],
+                       index: 0,
+                   }));
+    }
+
+    #[test]
+    fn test_move_node() {
+        let item = |value: &str| Element::text(Key::Global(7), value);
+        let before = el!(div[key=0, el!(ul[key=1, text(4, "x"), item("a")]), el!(ol[key=2, text(3, "b")])]);
+        let after = el!(div[key=0, el!(ul[key=1, text(4, "x")]), el!(ol[key=2, text(3, "b"), item("c")])]);
+        let diff = before.diff(&after).unwrap();
+
+        let mut driver = RenderDriver::mount(TestRenderer::new(), &before);
+        driver.renderer_mut().take_calls();
+        driver.patch(&diff).unwrap();
+        assert_eq!(driver.renderer().calls(),
+                   &["move_node 3 from 1 to 4 before None".to_string(),
+                     "set_text 3 \"c\"".to_string()][..]);
+        assert_eq!(driver.node(&[Key::Local(2), Key::Global(7)]), Some(&3));
+        assert_eq!(driver.node(&[Key::Local(1), Key::Global(7)]), None);
+
+        let mut driver = RenderDriver::mount(StringRenderer::new(), &before);
+        driver.patch(&diff).unwrap();
+        assert_eq!(driver.renderer().to_html(*driver.root()), after.to_html());
+    }
+}
@@ -0,0 +1,175 @@
+// Collects the resources a page will load, so server-side rendering can
+// announce them early, either as `<link rel=preload>` elements in the head or
+// as a Link header in an HTTP 103 Early Hints response.
+//
+// Stylesheets, scripts and images are preloaded. Images with
+// `loading="lazy"` and scripts with `async` or `defer` aren't needed for
+// first paint and are only prefetched.
+use std::collections::BTreeSet;
+
+use {AttrName, Attributes, Element, ElementRef, Flags, Key, Listeners};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HintKind {
+    Preload,
+    Prefetch,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResourceHint {
+    pub href: String,
+    pub kind: HintKind,
+    // The request destination, the `as` attribute: "image", "style" or
+    // "script".
+    pub destination: &'static str,
+    // The element's crossorigin attribute, which the hint must repeat for
+    // the browser to reuse the response.
+    pub crossorigin: Option<String>,
+}
+
+impl ResourceHint {
+    fn rel(&self) -> &'static str {
+        match self.kind {
+            HintKind::Preload => "preload",
+            HintKind::Prefetch => "prefetch",
+        }
+    }
+
+    // A `<link>` element for the document head.
+    pub fn to_element(&self, key: Key) -> Element {
+        let mut attributes = Attributes::new();
+        attributes.insert(AttrName::new("rel"), self.rel().to_string());
+        attributes.insert(AttrName::new("href"), self.href.clone());
+        attributes.insert(AttrName::new("as"), self.destination.to_string());
+        if let Some(ref crossorigin) = self.crossorigin {
+            attributes.insert(AttrName::new("crossorigin"), crossorigin.clone());
+        }
+        Element::Void {
+            key: key,
+            name: "link".to_string(),
+            attributes: Some(attributes),
+            listeners: Listeners::new(),
+            flags: Flags::default(),
+        }
+    }
+
+    // A single entry of a Link header.
+    pub fn to_link_header(&self) -> String {
+        let mut header = format!("<{}>; rel={}; as={}", self.href, self.rel(), self.destination);
+        if let Some(ref crossorigin) = self.crossorigin {
+            if crossorigin.is_empty() {
+                header.push_str("; crossorigin");
+            } else {
+                header.push_str(&format!("; crossorigin={}", crossorigin));
+            }
+        }
+        header
+    }
+}
+
+// The value of a Link header announcing all of `hints`.
+pub fn link_header(hints: &[ResourceHint]) -> String {
+    hints.iter().map(|hint| hint.to_link_header()).collect::<Vec<_>>().join(", ")
+}
+
+impl Element {
+    // The resources referenced by this tree in document order, each once.
+    // Lazy nodes are evaluated, as rendering would.
+    pub fn resource_hints(&self) -> Vec<ResourceHint> {
+        let mut hints = vec![];
+        let mut seen = BTreeSet::new();
+        collect(self, &mut hints, &mut seen);
+        hints
+    }
+}
+
+fn collect(element: &Element, hints: &mut Vec<ResourceHint>, seen: &mut BTreeSet<String>) {
+    let element = element.force();
+    let view = element.view();
+    if let Some(hint) = hint(view) {
+        if seen.insert(hint.href.clone()) {
+            hints.push(hint);
+        }
+    }
+    for child in view.children() {
+        collect(child.element(), hints, seen);
+    }
+}
+
+fn hint(view: ElementRef) -> Option<ResourceHint> {
+    let (href, kind, destination) = match view.tag() {
+        Some("img") => {
+            let kind = if view.attribute("loading") == Some("lazy") {
+                HintKind::Prefetch
+            } else {
+                HintKind::Preload
+            };
+            (view.attribute("src")?, kind, "image")
+        }
+        Some("script") => {
+            let kind = if view.attribute("async").is_some() || view.attribute("defer").is_some() {
+                HintKind::Prefetch
+            } else {
+                HintKind::Preload
+            };
+            (view.attribute("src")?, kind, "script")
+        }
+        Some("link") => {
+            let stylesheet = view.attribute("rel")
+                .is_some_and(|rel| rel.split_whitespace().any(|x| x.eq_ignore_ascii_case("stylesheet")));
+            if !stylesheet {
+                return None;
+            }
+            (view.attribute("href")?, HintKind::Preload, "style")
+        }
+        _ => return None,
+    };
+    Some(ResourceHint {
+        href: href.to_string(),
+        kind: kind,
+        destination: destination,
+        crossorigin: view.attribute("crossorigin").map(|x| x.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{link_header, HintKind};
+    use {AttrName, Element, Flags, Key, Listeners};
+
+    fn void(key: u64, name: &str, attributes: &[(&str, &str)]) -> Element {
+        Element::Void {
+            key: Key::Local(key),
+            name: name.to_string(),
+            attributes: Some(attributes.iter()
+                .map(|&(name, value)| (AttrName::new(name), value.to_string()))
+                .collect()),
+            listeners: Listeners::new(),
+            flags: Flags::default(),
+        }
+    }
+
+    #[test]
+    fn test_resource_hints() {
+        let tree = el!(html[
+            key=0,
+            void(0, "link", &[("rel", "stylesheet"), ("href", "/site.css")]),
+            void(1, "link", &[("rel", "icon"), ("href", "/favicon.ico")]),
+            void(2, "script", &[("src", "/app.js"), ("defer", "")]),
+            void(3, "img", &[("src", "/hero.jpg"), ("crossorigin", "anonymous")]),
+            void(4, "img", &[("src", "/hero.jpg")]),
+            void(5, "img", &[("src", "/footer.png"), ("loading", "lazy")])
+        ]);
+        let hints = tree.resource_hints();
+        let summary: Vec<_> = hints.iter().map(|x| (&x.href[..], x.kind, x.destination)).collect();
+        assert_eq!(summary,
+                   vec![("/site.css", HintKind::Preload, "style"),
+                        ("/app.js", HintKind::Prefetch, "script"),
+                        ("/hero.jpg", HintKind::Preload, "image"),
+                        ("/footer.png", HintKind::Prefetch, "image")]);
+        assert_eq!(link_header(&hints[..1]), "</site.css>; rel=preload; as=style");
+        assert_eq!(hints[2].to_link_header(),
+                   "</hero.jpg>; rel=preload; as=image; crossorigin=anonymous");
+        assert_eq!(hints[0].to_element(Key::Local(7)).view().attribute("rel"), Some("preload"));
+    }
+}
@@ -0,0 +1,450 @@
+// A small CSS selector engine for finding elements in a tree.
+//
+// Supported syntax is a comma separated list of complex selectors made of
+// type (`div`), universal (`*`), id (`#title`), class (`.row`) and attribute
+// (`[type]`, `[type=text]`, `[type="text"]`) selectors, joined by descendant
+// (whitespace) and child (`>`) combinators. Pseudo-classes are not supported.
+//
+// Lazy nodes never match and Element::select does not look inside them, since
+// it can only return references into the tree as it stands. Matching against
+// a patch evaluates them, as the patch itself already did.
+use {Change, DiffTree, Element};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selector {
+    alternatives: Vec<Complex>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectorError {
+    // Byte offset into the selector source where parsing failed.
+    pub position: usize,
+    pub reason: &'static str,
+}
+
+// compounds[i] and compounds[i + 1] are joined by combinators[i].
+#[derive(Clone, Debug, PartialEq)]
+struct Complex {
+    compounds: Vec<Compound>,
+    combinators: Vec<Combinator>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Compound {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    attributes: Vec<(String, Option<String>)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+impl Selector {
+    pub fn parse(source: &str) -> Result<Selector, SelectorError> {
+        Parser {
+            source: source,
+            position: 0,
+        }
+            .parse()
+    }
+
+    // Whether `element` matches, given its ancestors ordered from the root
+    // down to its parent.
+    pub fn matches(&self, element: &Element, ancestors: &[&Element]) -> bool {
+        self.alternatives
+            .iter()
+            .any(|complex| complex.matches(complex.compounds.len() - 1, element, ancestors))
+    }
+
+    // Whether `element` or any of its descendants matches.
+    pub fn matches_within(&self, element: &Element, ancestors: &[&Element]) -> bool {
+        let element = &*element.force();
+        if self.matches(element, ancestors) {
+            return true;
+        }
+        let mut ancestors = ancestors.to_vec();
+        ancestors.push(element);
+        element.view().children().any(|child| self.matches_within(child.element(), &ancestors))
+    }
+
+    // Narrows `diff`, the patch from `old` to `new`, down to the changes that
+    // touch a matching node: everything at or below a node matching in either
+    // tree, plus removals, insertions and replacements of subtrees that
+    // contain a match. Returns None if nothing in the patch is relevant.
+    pub fn filter_diff(&self, old: &Element, new: &Element, diff: &DiffTree) -> Option<DiffTree> {
+        self.filter(old, &[], new, &[], diff)
+    }
+
+    fn filter(&self,
+              old: &Element,
+              old_ancestors: &[&Element],
+              new: &Element,
+              new_ancestors: &[&Element],
+              diff: &DiffTree)
+              -> Option<DiffTree> {
+        let (old, new) = (&*old.force(), &*new.force());
+        if self.matches(new, new_ancestors) || self.matches(old, old_ancestors) {
+            return if has_changes(diff) {
+                Some(diff.clone())
+            } else {
+                None
+            };
+        }
+
+        let mut old_chain = old_ancestors.to_vec();
+        old_chain.push(old);
+        let mut new_chain = new_ancestors.to_vec();
+        new_chain.push(new);
+
+        let mut changes = vec![];
+        if let Some(ref all) = diff.changes {
+            for change in all.iter() {
+                let touches = match *change {
+                    Change::RemoveChild(ref key) => {
+                        old.get_child(key)
+                            .is_some_and(|child| self.matches_within(child, &old_chain))
+                    }
+                    Change::InsertChild(_, ref element) => self.matches_within(element, &new_chain),
+                    Change::MoveNode { ref key, .. } => {
+                        new.get_child(key)
+                            .is_some_and(|child| self.matches_within(child, &new_chain))
+                    }
+                    Change::ReplaceNode(ref element) => {
+                        self.matches_within(element, new_ancestors) ||
+                        self.matches_within(old, old_ancestors)
+                    }
+                    _ => false,
+                };
+                if touches {
+                    changes.push(change.clone());
+                }
+            }
+        }
+
+        let mut children = vec![];
+        if let Some(ref all) = diff.children {
+            for &(ref key, ref child) in all.iter() {
+                if let (Some(old_child), Some(new_child)) = (old.get_child(key),
+                                                             new.get_child(key)) {
+                    if let Some(filtered) = self.filter(old_child,
+                                                        &old_chain,
+                                                        new_child,
+                                                        &new_chain,
+                                                        child) {
+                        children.push((key.clone(), filtered));
+                    }
+                }
+            }
+        }
+
+        if changes.is_empty() && children.is_empty() {
+            None
+        } else {
+            Some(DiffTree {
+                changes: if changes.is_empty() {
+                    None
+                } else {
+                    Some(changes.into_boxed_slice())
+                },
+                children: if children.is_empty() {
+                    None
+                } else {
+                    Some(children.into_boxed_slice())
+                },
+                base_checksum: None,
+            })
+        }
+    }
+}
+
+fn has_changes(diff: &DiffTree) -> bool {
+    diff.changes.as_ref().is_some_and(|changes| !changes.is_empty()) ||
+    diff.children.as_ref().is_some_and(|children| {
+        children.iter().any(|&(_, ref child)| has_changes(child))
+    })
+}
+
+impl Element {
+    // All elements in this tree matching `selector`, in document order.
+    pub fn select(&self, selector: &Selector) -> Vec<&Element> {
+        let mut matches = vec![];
+        let mut ancestors = vec![];
+        select(self, selector, &mut ancestors, &mut matches);
+        matches
+    }
+}
+
+fn select<'a>(element: &'a Element,
+              selector: &Selector,
+              ancestors: &mut Vec<&'a Element>,
+              matches: &mut Vec<&'a Element>) {
+    if selector.matches(element, ancestors) {
+        matches.push(element);
+    }
+    ancestors.push(element);
+    for child in element.view().children() {
+        select(child.element(), selector, ancestors, matches);
+    }
+    ancestors.pop();
+}
+
+impl Complex {
+    fn matches(&self, index: usize, element: &Element, ancestors: &[&Element]) -> bool {
+        if !self.compounds[index].matches(element) {
+            return false;
+        }
+        if index == 0 {
+            return true;
+        }
+        match self.combinators[index - 1] {
+            Combinator::Child => {
+                match ancestors.split_last() {
+                    Some((parent, rest)) => self.matches(index - 1, parent, rest),
+                    None => false,
+                }
+            }
+            Combinator::Descendant => {
+                (0..ancestors.len())
+                    .rev()
+                    .any(|i| self.matches(index - 1, ancestors[i], &ancestors[..i]))
+            }
+        }
+    }
+}
+
+impl Compound {
+    fn matches(&self, element: &Element) -> bool {
+        let view = element.view();
+        let name = match view.tag() {
+            Some(name) => name,
+            None => return false,
+        };
+        if let Some(ref tag) = self.tag {
+            if !tag.eq_ignore_ascii_case(name) {
+                return false;
+            }
+        }
+        if let Some(ref id) = self.id {
+            if view.attribute("id") != Some(id) {
+                return false;
+            }
+        }
+        if !self.classes.is_empty() {
+            let class = match view.attribute("class") {
+                Some(class) => class,
+                None => return false,
+            };
+            if !self.classes.iter().all(|wanted| class.split_whitespace().any(|x| x == wanted)) {
+                return false;
+            }
+        }
+        self.attributes.iter().all(|&(ref name, ref value)| {
+            match (view.attribute(name), value) {
+                (Some(actual), &Some(ref value)) => actual == value,
+                (Some(_), &None) => true,
+                (None, _) => false,
+            }
+        })
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(mut self) -> Result<Selector, SelectorError> {
+        let mut alternatives = vec![self.complex()?];
+        while self.peek() == Some(',') {
+            self.position += 1;
+            alternatives.push(self.complex()?);
+        }
+        if self.peek().is_some() {
+            return Err(self.error("unexpected character"));
+        }
+        Ok(Selector { alternatives: alternatives })
+    }
+
+    fn complex(&mut self) -> Result<Complex, SelectorError> {
+        self.skip_whitespace();
+        let mut compounds = vec![self.compound()?];
+        let mut combinators = vec![];
+        loop {
+            let had_whitespace = self.skip_whitespace();
+            match self.peek() {
+                Some('>') => {
+                    self.position += 1;
+                    self.skip_whitespace();
+                    combinators.push(Combinator::Child);
+                }
+                Some(',') | None => break,
+                Some(_) if had_whitespace => combinators.push(Combinator::Descendant),
+                Some(_) => return Err(self.error("unexpected character")),
+            }
+            compounds.push(self.compound()?);
+        }
+        Ok(Complex {
+            compounds: compounds,
+            combinators: combinators,
+        })
+    }
+
+    fn compound(&mut self) -> Result<Compound, SelectorError> {
+        let start = self.position;
+        let mut compound = Compound::default();
+        if self.peek() == Some('*') {
+            self.position += 1;
+        } else if self.peek().is_some_and(is_name_char) {
+            compound.tag = Some(self.name()?);
+        }
+        loop {
+            match self.peek() {
+                Some('#') => {
+                    self.position += 1;
+                    compound.id = Some(self.name()?);
+                }
+                Some('.') => {
+                    self.position += 1;
+                    compound.classes.push(self.name()?);
+                }
+                Some('[') => {
+                    self.position += 1;
+                    compound.attributes.push(self.attribute()?);
+                }
+                _ => break,
+            }
+        }
+        if self.position == start {
+            return Err(self.error("expected a selector"));
+        }
+        Ok(compound)
+    }
+
+    fn attribute(&mut self) -> Result<(String, Option<String>), SelectorError> {
+        self.skip_whitespace();
+        let name = self.name()?;
+        self.skip_whitespace();
+        let value = match self.peek() {
+            Some('=') => {
+                self.position += 1;
+                self.skip_whitespace();
+                let value = match self.peek() {
+                    Some(quote) if quote == '"' || quote == '\'' => {
+                        self.position += 1;
+                        let rest = &self.source[self.position..];
+                        let end = match rest.find(quote) {
+                            Some(end) => end,
+                            None => return Err(self.error("unterminated string")),
+                        };
+                        self.position += end + 1;
+                        rest[..end].to_string()
+                    }
+                    _ => self.name()?,
+                };
+                self.skip_whitespace();
+                Some(value)
+            }
+            _ => None,
+        };
+        if self.peek() != Some(']') {
+            return Err(self.error("expected ']'"));
+        }
+        self.position += 1;
+        Ok((name, value))
+    }
+
+    fn name(&mut self) -> Result<String, SelectorError> {
+        let rest = &self.source[self.position..];
+        let end = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+        if end == 0 {
+            return Err(self.error("expected a name"));
+        }
+        self.position += end;
+        Ok(rest[..end].to_string())
+    }
+
+    // Whitespace is ASCII only, as in CSS, so it is always a byte long.
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+        self.position != start
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.position..].chars().next()
+    }
+
+    fn error(&self, reason: &'static str) -> SelectorError {
+        SelectorError {
+            position: self.position,
+            reason: reason,
+        }
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_' || c == ':'
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::Selector;
+    use {AttrName, Element, Flags, Key, Listeners};
+
+    fn with_attributes(name: &str, key: u64, attributes: &[(&str, &str)]) -> Element {
+        let mut map = BTreeMap::new();
+        for &(name, value) in attributes {
+            map.insert(AttrName::new(name), value.to_string());
+        }
+        Element::Void {
+            key: Key::Local(key),
+            name: name.to_string(),
+            attributes: Some(map),
+            listeners: Listeners::new(),
+            flags: Flags::default(),
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let tree = el!(div[
+            key=0,
+            el!(form[
+                key=1,
+                with_attributes("input", 0, &[("type", "text"), ("class", "a b")])
+            ]),
+            with_attributes("input", 2, &[("id", "go"), ("type", "submit")])
+        ]);
+        let select = |source: &str| -> Vec<Key> {
+            let selector = Selector::parse(source).unwrap();
+            tree.select(&selector).iter().map(|x| x.to_key()).collect()
+        };
+
+        assert_eq!(select("input"), vec![Key::Local(0), Key::Local(2)]);
+        assert_eq!(select("form input.b"), vec![Key::Local(0)]);
+        assert_eq!(select("div > input"), vec![Key::Local(2)]);
+        assert_eq!(select("[type=\"submit\"], .a"), vec![Key::Local(0), Key::Local(2)]);
+        assert_eq!(select("#go[type]"), vec![Key::Local(2)]);
+        assert_eq!(select("span"), vec![]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Selector::parse("div >").unwrap_err().position, 5);
+        assert_eq!(Selector::parse("div[type").unwrap_err().reason, "expected ']'");
+        assert!(Selector::parse("").is_err());
+        // Other whitespace is just an unexpected character.
+        let error = Selector::parse("div\u{a0}p").unwrap_err();
+        assert_eq!((error.position, error.reason), (3, "unexpected character"));
+        assert!(Selector::parse("\u{3000}div").is_err());
+        assert!(Selector::parse("div\t>\np").is_ok());
+    }
+}
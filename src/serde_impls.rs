@@ -0,0 +1,326 @@
+// Serde support, behind the `serde` feature, for shipping patches to a
+// JavaScript client and reading events back from browser JSON.
+//
+// Keys serialize as strings in the same compact form server-side rendering
+// writes: `"3"` for Key::Local(3), `"g3"` for Key::Global(3), `"u"` and 32
+// hex digits for a Key::Uuid and `"s"` and the percent-encoded string for a
+// Key::Str. Attribute names serialize as their qualified name, or in Clark
+// notation, `{namespace}name`, when their namespace isn't the one their
+// prefix implies.
+// Elements have the shape of their JSON form in json_patch, an object with a
+// "type" of "text", "void" or "parent"; Lazy nodes are evaluated, and
+// keymaps are rebuilt from the children when deserializing.
+use serde::de::{Error, Unexpected};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use ssr::key_marker;
+use {AttrName, Attributes, Children, Element, Flags, Key, Listeners};
+
+impl Serialize for Key {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(&key_marker(self))
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D>(deserializer: D) -> Result<Key, D::Error>
+        where D: Deserializer<'de>
+    {
+        let marker = String::deserialize(deserializer)?;
+        let key = if let Some(global) = marker.strip_prefix('g') {
+            global.parse().ok().map(Key::Global)
+        } else if let Some(hex) = marker.strip_prefix('u') {
+            parse_uuid(hex).map(Key::Uuid)
+        } else if let Some(encoded) = marker.strip_prefix('s') {
+            percent_decode(encoded).map(|x| Key::Str(x.into()))
+        } else {
+            marker.parse().ok().map(Key::Local)
+        };
+        key.ok_or_else(|| D::Error::invalid_value(Unexpected::Str(&marker), &"a key such as \"3\" or \"g3\""))
+    }
+}
+
+fn parse_uuid(hex: &str) -> Option<[u8; 16]> {
+    if hex.len() != 32 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; 16];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = ::std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+impl Serialize for AttrName {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        if AttrName::from(&self.name[..]) == *self {
+            serializer.serialize_str(&self.name)
+        } else {
+            let namespace = self.namespace.as_ref().map_or("", |x| &x[..]);
+            serializer.serialize_str(&format!("{{{}}}{}", namespace, self.name))
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for AttrName {
+    fn deserialize<D>(deserializer: D) -> Result<AttrName, D::Error>
+        where D: Deserializer<'de>
+    {
+        let name = String::deserialize(deserializer)?;
+        if !name.starts_with('{') {
+            return Ok(AttrName::from(&name[..]));
+        }
+        match name.find('}') {
+            Some(1) => {
+                Ok(AttrName {
+                    name: name[2..].to_string(),
+                    namespace: None,
+                })
+            }
+            Some(end) => Ok(AttrName::with_namespace(&name[1..end], &name[end + 1..])),
+            None => Err(D::Error::invalid_value(Unexpected::Str(&name), &"an attribute name")),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Borrowed<'a> {
+    Text { key: &'a Key, value: &'a str },
+    Void {
+        key: &'a Key,
+        name: &'a str,
+        attributes: &'a Option<Attributes>,
+        listeners: &'a Listeners,
+        flags: &'a Flags,
+    },
+    Parent {
+        key: &'a Key,
+        name: &'a str,
+        attributes: &'a Option<Attributes>,
+        listeners: &'a Listeners,
+        children: &'a [Element],
+        flags: &'a Flags,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Owned {
+    Text { key: Key, value: String },
+    Void {
+        key: Key,
+        name: String,
+        #[serde(default)]
+        attributes: Option<Attributes>,
+        #[serde(default)]
+        listeners: Listeners,
+        #[serde(default)]
+        flags: Flags,
+    },
+    Parent {
+        key: Key,
+        name: String,
+        #[serde(default)]
+        attributes: Option<Attributes>,
+        #[serde(default)]
+        listeners: Listeners,
+        children: Vec<Element>,
+        #[serde(default)]
+        flags: Flags,
+    },
+}
+
+impl Serialize for Element {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let borrowed = match *self {
+            Element::Text { ref key, ref value } => {
+                Borrowed::Text {
+                    key: key,
+                    value: value,
+                }
+            }
+            Element::Void { ref key, ref name, ref attributes, ref listeners, ref flags } => {
+                Borrowed::Void {
+                    key: key,
+                    name: name,
+                    attributes: attributes,
+                    listeners: listeners,
+                    flags: flags,
+                }
+            }
+            Element::Parent { ref key, ref name, ref attributes, ref listeners, ref children, ref flags,
+                              .. } => {
+                Borrowed::Parent {
+                    key: key,
+                    name: name,
+                    attributes: attributes,
+                    listeners: listeners,
+                    children: children,
+                    flags: flags,
+                }
+            }
+            Element::Lazy { .. } => return self.force().as_ref().serialize(serializer),
+        };
+        borrowed.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Element {
+    fn deserialize<D>(deserializer: D) -> Result<Element, D::Error>
+        where D: Deserializer<'de>
+    {
+        Ok(match Owned::deserialize(deserializer)? {
+            Owned::Text { key, value } => {
+                Element::Text {
+                    key: key,
+                    value: value,
+                }
+            }
+            Owned::Void { key, name, attributes, listeners, flags } => {
+                Element::Void {
+                    key: key,
+                    name: name,
+                    attributes: attributes,
+                    listeners: listeners,
+                    flags: flags,
+                }
+            }
+            Owned::Parent { key, name, attributes, listeners, children, flags } => {
+                let (children, keymap) = children.into_iter().collect::<Children>().into_parts();
+                Element::Parent {
+                    key: key,
+                    name: name,
+                    keymap: keymap,
+                    attributes: attributes,
+                    listeners: listeners,
+                    children: children,
+                    flags: flags,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use attr::XLINK_NAMESPACE;
+    use {AttrName, Change, Element, Event, EventKind, Key};
+
+    #[test]
+    fn test_keys_and_names() {
+        assert_eq!(serde_json::to_string(&Key::Global(3)).unwrap(), "\"g3\"");
+        assert_eq!(serde_json::from_str::<Key>("\"7\"").unwrap(), Key::Local(7));
+        assert!(serde_json::from_str::<Key>("\"x7\"").is_err());
+        let uuid = Key::Uuid([0xab; 16]);
+        let json = format!("\"u{}\"", "ab".repeat(16));
+        assert_eq!(serde_json::to_string(&uuid).unwrap(), json);
+        assert_eq!(serde_json::from_str::<Key>(&json).unwrap(), uuid);
+        assert!(serde_json::from_str::<Key>("\"uab\"").is_err());
+        let id = Key::Str("row 1/\u{e9}".into());
+        assert_eq!(serde_json::to_string(&id).unwrap(), "\"srow%201%2F%C3%A9\"");
+        assert_eq!(serde_json::from_str::<Key>("\"srow%201%2F%C3%A9\"").unwrap(), id);
+        assert!(serde_json::from_str::<Key>("\"s%2\"").is_err());
+
+        let names = [AttrName::from("xlink:href"),
+                     AttrName::with_namespace("urn:x", "x:y"),
+                     AttrName::new("xml:lang")];
+        let json = serde_json::to_string(&names).unwrap();
+        assert_eq!(json, "[\"xlink:href\",\"{urn:x}x:y\",\"{}xml:lang\"]");
+        assert_eq!(serde_json::from_str::<Vec<AttrName>>(&json).unwrap(), names.to_vec());
+        assert_eq!(names[0].namespace, Some(XLINK_NAMESPACE.to_string()));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let before = el!(ul[key=0, el!(li[key=1])]);
+        let after = el!(ul[key=0, el!(li[key=2, Element::Text { key: Key::Local(0), value: "x".to_string() }])]);
+        let diff = before.diff(&after).unwrap();
+        let json = serde_json::to_string(&diff).unwrap();
+        assert_eq!(serde_json::from_str::<::DiffTree>(&json).unwrap(), diff);
+
+        let inserted = serde_json::to_value(Change::InsertChild(0, after.clone())).unwrap();
+        assert_eq!(inserted["InsertChild"][1]["type"], "parent");
+        let element: Element = serde_json::from_value(inserted["InsertChild"][1].clone()).unwrap();
+        assert!(element.deep_eq(&after));
+        assert!(element.get_child(Key::Local(2)).is_some());
+
+        let event: Event = serde_json::from_str("{\"type\": \"mouse_down\", \"bubbles\": true, \
+                                                 \"cancelable\": true, \"target\": \"g4\", \
+                                                 \"client_x\": 3.0, \"ctrl\": true}")
+            .unwrap();
+        match event {
+            Event { target: Key::Global(4), kind: EventKind::MouseDown(ref mouse), .. } => {
+                assert_eq!((mouse.client_x, mouse.screen_x), (3.0, 0.0));
+                assert!(mouse.modifiers.ctrl && !mouse.modifiers.shift);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let event: Event = serde_json::from_str("{\"type\": \"wheel\", \"bubbles\": true, \
+                                                 \"cancelable\": true, \"target\": \"g4\", \
+                                                 \"delta_x\": 0.0, \"delta_y\": 120.0}")
+            .unwrap();
+        assert_eq!(event.event_type().name(), "wheel");
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!((&json["type"], &json["delta_y"], &json["timestamp"]),
+                   (&"wheel".into(), &120.0.into(), &0.0.into()));
+
+        let event: Event = serde_json::from_str("{\"type\": \"visibility_changed\", \"target\": \"5\", \
+                                                 \"ratio\": 0.5, \"timestamp\": 12.5}")
+            .unwrap();
+        assert!(!event.bubbles() && !event.cancelable());
+        assert_eq!(event.timestamp(), 12.5);
+
+        let event: Event = serde_json::from_str("{\"type\": \"touch_end\", \"bubbles\": true, \
+                                                 \"cancelable\": true, \"target\": \"3\", \
+                                                 \"changed_touches\": [{\"identifier\": 7, \
+                                                 \"client_x\": 10.0}]}")
+            .unwrap();
+        let touch = event.touch().unwrap();
+        assert!(touch.touches.is_empty());
+        assert_eq!((touch.changed_touches[0].identifier, touch.changed_touches[0].client_x), (7, 10.0));
+
+        let event: Event = serde_json::from_str("{\"type\": \"pointer_down\", \"bubbles\": true, \
+                                                 \"cancelable\": true, \"target\": \"3\", \
+                                                 \"pointer_id\": 2, \"pointer_type\": \"pen\", \
+                                                 \"pressure\": 0.25, \"client_y\": 4.0, \"alt\": true}")
+            .unwrap();
+        assert_eq!(event.event_type().name(), "pointerdown");
+        let pointer = event.pointer().unwrap();
+        assert_eq!((pointer.pointer_id, &pointer.pointer_type[..], pointer.pressure), (2, "pen", 0.25));
+        assert!(pointer.mouse.client_y == 4.0 && pointer.mouse.modifiers.alt);
+
+        let event: Event = serde_json::from_str("{\"type\": \"drop\", \"bubbles\": true, \
+                                                 \"cancelable\": true, \"target\": \"3\", \
+                                                 \"data_transfer\": {\"items\": [[\"text/plain\", \"hi\"]], \
+                                                 \"drop_effect\": \"move\"}, \"client_x\": 5.0}")
+            .unwrap();
+        let drag = event.drag().unwrap();
+        assert_eq!(drag.data_transfer.get("text/plain"), Some("hi"));
+        assert_eq!((&drag.data_transfer.drop_effect[..], drag.mouse.client_x), ("move", 5.0));
+    }
+}
@@ -0,0 +1,656 @@
+// Many VirtualDoms, one per client session, for servers driving the UI of
+// thousands of clients at once.
+//
+// Sessions are evicted from memory by least recent use, once more than
+// `max_resident` are held or their estimated trees take more than
+// `max_bytes`, and by idle time in evict_idle. With a spill directory an
+// evicted session is written there and read back on its next use; without
+// one it is dropped. Spilling keeps the main tree, the mounted roots and the
+// key policy. History, subscriptions, telemetry and speculative diffs don't
+// survive it, and Lazy nodes are written evaluated.
+//
+// A spilled session is one file per session, named by the hex of its id:
+//
+//   1 byte   version, currently 2
+//   1 byte   key policy, 0 for Independent and 1 for SharedGlobals
+//   element  the main tree
+//   4 bytes  number of mounted roots, followed by each root's name and tree
+//
+// An element is a type byte, 0 for text, 1 for void and 2 for parent, then
+// its key as a byte, 0 for local or 1 for global followed by a u64, 2 for a
+// UUID followed by its 16 bytes or 3 for a string followed by the string.
+// Text is followed by its value; others by the name, attributes, listeners
+// and flags, and a parent by its child count and children. Strings are a
+// u32 byte length and the UTF-8 bytes, and all integers are big endian.
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use {AttrName, Attributes, Element, Flags, HandlerId, Key, KeyPolicy, Listeners, TextDiff, VirtualDom};
+
+const VERSION: u8 = 2;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SessionLimits {
+    // Most sessions held in memory at once.
+    pub max_resident: Option<usize>,
+    // Most estimated bytes of trees held in memory at once. The session in
+    // use is never evicted, even if it alone is over the limit.
+    pub max_bytes: Option<usize>,
+    // How long a session stays in memory unused before evict_idle takes it.
+    pub idle_timeout: Option<Duration>,
+}
+
+pub struct SessionStore {
+    limits: SessionLimits,
+    spill_dir: Option<PathBuf>,
+    resident: HashMap<String, Session>,
+    // Ordering for least recent use, as several uses can share an Instant.
+    next_use: u64,
+}
+
+struct Session {
+    dom: VirtualDom,
+    bytes: usize,
+    last_used: Instant,
+    use_order: u64,
+}
+
+impl SessionStore {
+    // A store dropping the sessions it evicts.
+    pub fn new(limits: SessionLimits) -> SessionStore {
+        SessionStore {
+            limits: limits,
+            spill_dir: None,
+            resident: HashMap::new(),
+            next_use: 0,
+        }
+    }
+
+    // A store writing the sessions it evicts to `dir`, which must exist.
+    // Sessions already spilled there by an earlier store are picked up.
+    pub fn with_spill_dir(limits: SessionLimits, dir: PathBuf) -> SessionStore {
+        SessionStore { spill_dir: Some(dir), ..SessionStore::new(limits) }
+    }
+
+    // Adds a session, replacing any with the same id, and evicts others if
+    // the limits are now exceeded.
+    pub fn insert(&mut self, id: &str, dom: VirtualDom, now: Instant) -> io::Result<()> {
+        self.remove_spilled(id)?;
+        let session = Session {
+            bytes: estimate(&dom),
+            dom: dom,
+            last_used: now,
+            use_order: self.next_use,
+        };
+        self.next_use += 1;
+        self.resident.insert(id.to_string(), session);
+        self.enforce(id)
+    }
+
+    // Runs `f` on the session, reading it back first if it was spilled, and
+    // then accounts for the memory it uses now. None if there is no such
+    // session.
+    pub fn with_session<F, R>(&mut self, id: &str, now: Instant, f: F) -> io::Result<Option<R>>
+        where F: FnOnce(&mut VirtualDom) -> R
+    {
+        if !self.resident.contains_key(id) {
+            let dom = match self.read_spilled(id)? {
+                Some(dom) => dom,
+                None => return Ok(None),
+            };
+            self.insert(id, dom, now)?;
+        }
+        let result = {
+            let session = self.resident.get_mut(id).unwrap();
+            let result = f(&mut session.dom);
+            session.bytes = estimate(&session.dom);
+            session.last_used = now;
+            session.use_order = self.next_use;
+            result
+        };
+        self.next_use += 1;
+        self.enforce(id)?;
+        Ok(Some(result))
+    }
+
+    // Removes the session, from memory or the spill directory.
+    pub fn remove(&mut self, id: &str) -> io::Result<Option<VirtualDom>> {
+        match self.resident.remove(id) {
+            Some(session) => Ok(Some(session.dom)),
+            None => {
+                let dom = self.read_spilled(id)?;
+                self.remove_spilled(id)?;
+                Ok(dom)
+            }
+        }
+    }
+
+    // Evicts every session unused for `idle_timeout` as of `now`, returning
+    // how many were evicted.
+    pub fn evict_idle(&mut self, now: Instant) -> io::Result<usize> {
+        let timeout = match self.limits.idle_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(0),
+        };
+        let idle: Vec<String> = self.resident
+            .iter()
+            .filter(|&(_, session)| now.saturating_duration_since(session.last_used) >= timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &idle {
+            self.evict(id)?;
+        }
+        Ok(idle.len())
+    }
+
+    // Evicts every session, as when shutting down a server whose sessions
+    // should outlive it.
+    pub fn evict_all(&mut self) -> io::Result<()> {
+        let ids: Vec<String> = self.resident.keys().cloned().collect();
+        for id in &ids {
+            self.evict(id)?;
+        }
+        Ok(())
+    }
+
+    pub fn is_resident(&self, id: &str) -> bool {
+        self.resident.contains_key(id)
+    }
+
+    // Whether the session is held in memory or spilled.
+    pub fn contains(&self, id: &str) -> bool {
+        self.is_resident(id) || self.spill_path(id).is_some_and(|path| path.exists())
+    }
+
+    // The number of sessions held in memory.
+    pub fn resident_len(&self) -> usize {
+        self.resident.len()
+    }
+
+    // Estimated bytes taken by the trees of the session, if it is held in
+    // memory. This counts nodes, names, text and attributes, not allocator
+    // overhead or history.
+    pub fn memory_usage(&self, id: &str) -> Option<usize> {
+        self.resident.get(id).map(|session| session.bytes)
+    }
+
+    pub fn total_memory(&self) -> usize {
+        self.resident.values().map(|session| session.bytes).sum()
+    }
+
+    // Evicts least recently used sessions other than `keep` until the limits
+    // are met.
+    fn enforce(&mut self, keep: &str) -> io::Result<()> {
+        loop {
+            let over_count = self.limits.max_resident.is_some_and(|max| self.resident.len() > max);
+            let over_bytes = self.limits.max_bytes.is_some_and(|max| self.total_memory() > max);
+            if !over_count && !over_bytes {
+                return Ok(());
+            }
+            let oldest = self.resident
+                .iter()
+                .filter(|&(id, _)| id != keep)
+                .min_by_key(|&(_, session)| session.use_order)
+                .map(|(id, _)| id.clone());
+            match oldest {
+                Some(id) => self.evict(&id)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn evict(&mut self, id: &str) -> io::Result<()> {
+        let session = match self.resident.remove(id) {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        if let Some(path) = self.spill_path(id) {
+            if let Err(error) = fs::write(&path, encode_session(&session.dom)) {
+                self.resident.insert(id.to_string(), session);
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    fn spill_path(&self, id: &str) -> Option<PathBuf> {
+        self.spill_dir.as_ref().map(|dir| {
+            let name: String = id.bytes().map(|byte| format!("{:02x}", byte)).collect();
+            dir.join(name + ".session")
+        })
+    }
+
+    fn read_spilled(&self, id: &str) -> io::Result<Option<VirtualDom>> {
+        let path = match self.spill_path(id) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        decode_session(&data).map(Some)
+    }
+
+    fn remove_spilled(&self, id: &str) -> io::Result<()> {
+        if let Some(path) = self.spill_path(id) {
+            match fs::remove_file(path) {
+                Err(ref error) if error.kind() == io::ErrorKind::NotFound => {}
+                result => return result,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn estimate(dom: &VirtualDom) -> usize {
+    let trees = Some(dom.tree()).into_iter().chain(dom.root_names().filter_map(|name| dom.root(name)));
+    trees.map(|tree| {
+            let stats = tree.stats();
+            let names: usize = stats.per_tag_histogram.iter().map(|(tag, count)| tag.len() * count).sum();
+            stats.node_count * mem::size_of::<Element>() + names + stats.text_bytes + stats.attr_bytes
+        })
+        .sum()
+}
+
+fn encode_session(dom: &VirtualDom) -> Vec<u8> {
+    let mut out = vec![VERSION];
+    out.push(match dom.key_policy() {
+        KeyPolicy::Independent => 0,
+        KeyPolicy::SharedGlobals => 1,
+    });
+    write_element(dom.tree(), &mut out);
+    let names: Vec<&str> = dom.root_names().collect();
+    write_u32(names.len(), &mut out);
+    for name in names {
+        write_str(name, &mut out);
+        write_element(dom.root(name).unwrap(), &mut out);
+    }
+    out
+}
+
+fn decode_session(data: &[u8]) -> io::Result<VirtualDom> {
+    let mut reader = Reader { data: data, position: 0 };
+    if reader.byte()? != VERSION {
+        return Err(corrupt());
+    }
+    let policy = match reader.byte()? {
+        0 => KeyPolicy::Independent,
+        1 => KeyPolicy::SharedGlobals,
+        _ => return Err(corrupt()),
+    };
+    let mut dom = VirtualDom::new(reader.element()?);
+    dom.set_key_policy(policy);
+    let mut roots = vec![];
+    for _ in 0..reader.u32()? {
+        roots.push((reader.string()?, reader.element()?));
+    }
+    dom.update_roots(roots).map_err(|_| corrupt())?;
+    if reader.position != data.len() {
+        return Err(corrupt());
+    }
+    Ok(dom)
+}
+
+fn write_element(element: &Element, out: &mut Vec<u8>) {
+    match *element {
+        Element::Text { ref key, ref value } => {
+            out.push(0);
+            write_key(key, out);
+            write_str(value, out);
+        }
+        Element::Void { ref key, ref name, ref attributes, ref listeners, ref flags } => {
+            out.push(1);
+            write_key(key, out);
+            write_str(name, out);
+            write_attributes(attributes, out);
+            write_listeners(listeners, out);
+            write_flags(flags, out);
+        }
+        Element::Parent { ref key, ref name, ref attributes, ref listeners, ref children, ref flags, .. } => {
+            out.push(2);
+            write_key(key, out);
+            write_str(name, out);
+            write_attributes(attributes, out);
+            write_listeners(listeners, out);
+            write_flags(flags, out);
+            write_u32(children.len(), out);
+            for child in children {
+                write_element(child, out);
+            }
+        }
+        Element::Lazy { .. } => write_element(&element.force(), out),
+    }
+}
+
+fn write_key(key: &Key, out: &mut Vec<u8>) {
+    let (kind, value) = match *key {
+        Key::Local(value) => (0, value),
+        Key::Global(value) => (1, value),
+        Key::Uuid(ref bytes) => {
+            out.push(2);
+            return out.extend_from_slice(bytes);
+        }
+        Key::Str(ref value) => {
+            out.push(3);
+            return write_str(value, out);
+        }
+    };
+    out.push(kind);
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+// A presence byte, then the count and each attribute's namespace, also
+// behind a presence byte, qualified name and value.
+fn write_attributes(attributes: &Option<Attributes>, out: &mut Vec<u8>) {
+    let attributes = match *attributes {
+        Some(ref attributes) => attributes,
+        None => return out.push(0),
+    };
+    out.push(1);
+    write_u32(attributes.len(), out);
+    for (name, value) in attributes {
+        match name.namespace {
+            Some(ref namespace) => {
+                out.push(1);
+                write_str(namespace, out);
+            }
+            None => out.push(0),
+        }
+        write_str(&name.name, out);
+        write_str(value, out);
+    }
+}
+
+// The count, then each event name and handler id.
+fn write_listeners(listeners: &Listeners, out: &mut Vec<u8>) {
+    write_u32(listeners.len(), out);
+    for (name, handler) in listeners {
+        write_str(name, out);
+        out.extend_from_slice(&handler.0.to_be_bytes());
+    }
+}
+
+// A byte of the boolean flags, lowest bit first in declaration order, and a
+// byte for the text diff policy, 0 when inherited.
+fn write_flags(flags: &Flags, out: &mut Vec<u8>) {
+    let bits = [flags.observe_visibility, flags.observe_resize, flags.keep_until_transition_end, flags.island];
+    out.push(bits.iter().enumerate().fold(0, |byte, (bit, &set)| byte | (set as u8) << bit));
+    out.push(match flags.text_diff {
+        None => 0,
+        Some(TextDiff::Replace) => 1,
+        Some(TextDiff::Lines) => 2,
+        Some(TextDiff::Chars) => 3,
+    });
+}
+
+fn write_u32(value: usize, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(value as u32).to_be_bytes());
+}
+
+fn write_str(value: &str, out: &mut Vec<u8>) {
+    write_u32(value.len(), out);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupt session file")
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() - self.position < len {
+            return Err(corrupt());
+        }
+        let bytes = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<usize> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(bytes) as usize)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| corrupt())
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    fn key(&mut self) -> io::Result<Key> {
+        match self.byte()? {
+            0 => Ok(Key::Local(self.u64()?)),
+            1 => Ok(Key::Global(self.u64()?)),
+            2 => {
+                let mut bytes = [0; 16];
+                bytes.copy_from_slice(self.take(16)?);
+                Ok(Key::Uuid(bytes))
+            }
+            3 => Ok(Key::Str(self.string()?.into())),
+            _ => Err(corrupt()),
+        }
+    }
+
+    fn attributes(&mut self) -> io::Result<Option<Attributes>> {
+        if self.byte()? == 0 {
+            return Ok(None);
+        }
+        let mut attributes = Attributes::new();
+        for _ in 0..self.u32()? {
+            let namespace = match self.byte()? {
+                0 => None,
+                _ => Some(self.string()?),
+            };
+            let name = AttrName {
+                name: self.string()?,
+                namespace: namespace,
+            };
+            attributes.insert(name, self.string()?);
+        }
+        Ok(Some(attributes))
+    }
+
+    fn listeners(&mut self) -> io::Result<Listeners> {
+        let mut listeners = Listeners::new();
+        for _ in 0..self.u32()? {
+            let name = self.string()?;
+            listeners.insert(name, HandlerId(self.u64()?));
+        }
+        Ok(listeners)
+    }
+
+    fn flags(&mut self) -> io::Result<Flags> {
+        let bits = self.byte()?;
+        let text_diff = match self.byte()? {
+            0 => None,
+            1 => Some(TextDiff::Replace),
+            2 => Some(TextDiff::Lines),
+            3 => Some(TextDiff::Chars),
+            _ => return Err(corrupt()),
+        };
+        Ok(Flags {
+            observe_visibility: bits & 1 != 0,
+            observe_resize: bits & 2 != 0,
+            keep_until_transition_end: bits & 4 != 0,
+            island: bits & 8 != 0,
+            text_diff: text_diff,
+            source: None,
+        })
+    }
+
+    fn element(&mut self) -> io::Result<Element> {
+        let kind = self.byte()?;
+        let key = self.key()?;
+        if kind == 0 {
+            return Ok(Element::Text {
+                key: key,
+                value: self.string()?,
+            });
+        }
+        let name = self.string()?;
+        let attributes = self.attributes()?;
+        let listeners = self.listeners()?;
+        let flags = self.flags()?;
+        match kind {
+            1 => {
+                Ok(Element::Void {
+                    key: key,
+                    name: name,
+                    attributes: attributes,
+                    listeners: listeners,
+                    flags: flags,
+                })
+            }
+            2 => {
+                let mut children = vec![];
+                for _ in 0..self.u32()? {
+                    children.push(self.element()?);
+                }
+                let keymap = children.iter()
+                    .enumerate()
+                    .map(|(index, child)| (child.to_key(), index))
+                    .collect();
+                Ok(Element::Parent {
+                    key: key,
+                    name: name,
+                    keymap: keymap,
+                    attributes: attributes,
+                    listeners: listeners,
+                    children: children,
+                    flags: flags,
+                })
+            }
+            _ => Err(corrupt()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+    use std::time::{Duration, Instant};
+
+    use super::{decode_session, encode_session, SessionLimits, SessionStore};
+    use {AttrName, Element, Flags, Key, KeyPolicy, TextDiff, VirtualDom};
+
+    fn spill_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rusty_dom-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn page(text: &str) -> Element {
+        el!(body[key=0, el!(p[key=1, Element::text(Key::Local(0), text)])])
+    }
+
+    #[test]
+    fn test_encode_session() {
+        let mut dom = VirtualDom::new(Element::parent("svg")
+            .key(Key::Global(3))
+            .attr("xlink:href", "#a")
+            .attr("class", "icon")
+            .flags(Flags { island: true, text_diff: Some(TextDiff::Lines), ..Flags::default() })
+            .child(Element::void("use").key(Key::Local(1)))
+            .child(Element::text(Key::Local(2), "caption"))
+            .child(Element::void("use").key(Key::Uuid([7; 16])))
+            .child(Element::void("use").key(Key::Str("row-1".into())))
+            .build());
+        dom.set_key_policy(KeyPolicy::SharedGlobals);
+        dom.update_roots(vec![("toasts".to_string(), page("saved"))]).unwrap();
+
+        let data = encode_session(&dom);
+        let decoded = decode_session(&data).unwrap();
+        assert!(decoded.tree().deep_eq(dom.tree()));
+        assert_eq!(decoded.tree().view().attributes().unwrap()[&AttrName::from("xlink:href")], "#a");
+        assert_eq!(decoded.tree().view().flags().text_diff, Some(TextDiff::Lines));
+        assert_eq!(decoded.key_policy(), KeyPolicy::SharedGlobals);
+        assert!(decoded.root("toasts").unwrap().deep_eq(&page("saved")));
+        assert!(decoded.lookup(&Key::Global(3)).is_some());
+
+        assert!(decode_session(&data[..data.len() - 1]).is_err());
+        assert!(decode_session(&[2]).is_err());
+    }
+
+    #[test]
+    fn test_lru() {
+        let dir = spill_dir("lru");
+        let now = Instant::now();
+        let limits = SessionLimits { max_resident: Some(2), ..SessionLimits::default() };
+        let mut store = SessionStore::with_spill_dir(limits, dir.clone());
+        store.insert("a", VirtualDom::new(page("a")), now).unwrap();
+        store.insert("b", VirtualDom::new(page("b")), now).unwrap();
+        store.with_session("a", now, |_| ()).unwrap();
+        store.insert("c/..", VirtualDom::new(page("c")), now).unwrap();
+        assert!(store.is_resident("a") && !store.is_resident("b") && store.contains("b"));
+        assert_eq!(store.resident_len(), 2);
+
+        // Using b reads it back, evicting a, now the least recently used.
+        let diff = store.with_session("b", now, |dom| dom.update(page("b2"), None)).unwrap().unwrap();
+        assert!(diff.is_some());
+        assert!(!store.is_resident("a") && store.is_resident("b"));
+        assert_eq!(store.with_session("missing", now, |_| ()).unwrap(), None);
+
+        let a = store.remove("a").unwrap().unwrap();
+        assert!(a.tree().deep_eq(&page("a")));
+        assert!(!store.contains("a"));
+
+        // Another store over the same directory finds the spilled sessions.
+        store.evict_all().unwrap();
+        assert_eq!(store.resident_len(), 0);
+        let mut other = SessionStore::with_spill_dir(SessionLimits::default(), dir.clone());
+        assert!(other.contains("c/..") && !other.is_resident("c/.."));
+        let tree = other.with_session("c/..", now, |dom| dom.tree().clone()).unwrap().unwrap();
+        assert!(tree.deep_eq(&page("c")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_idle_and_memory() {
+        let now = Instant::now();
+        let limits = SessionLimits { idle_timeout: Some(Duration::from_secs(60)), ..SessionLimits::default() };
+        let mut store = SessionStore::new(limits);
+        store.insert("a", VirtualDom::new(page("a")), now).unwrap();
+        store.insert("b", VirtualDom::new(page("b")), now + Duration::from_secs(30)).unwrap();
+
+        let small = store.memory_usage("a").unwrap();
+        store.with_session("a", now, |dom| dom.update(page(&"x".repeat(1000)), None)).unwrap();
+        assert_eq!(store.memory_usage("a").unwrap(), small + 999);
+        assert_eq!(store.total_memory(), small * 2 + 999);
+
+        // Without a spill directory evicted sessions are gone.
+        assert_eq!(store.evict_idle(now + Duration::from_secs(60)).unwrap(), 1);
+        assert!(!store.contains("a") && store.contains("b"));
+
+        let limits = SessionLimits { max_bytes: Some(small * 2), ..SessionLimits::default() };
+        let mut store = SessionStore::new(limits);
+        store.insert("a", VirtualDom::new(page("a")), now).unwrap();
+        store.insert("b", VirtualDom::new(page("b")), now).unwrap();
+        store.insert("c", VirtualDom::new(page(&"x".repeat(1000))), now).unwrap();
+        assert!(!store.contains("a") && !store.contains("b") && store.is_resident("c"));
+    }
+}
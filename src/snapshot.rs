@@ -0,0 +1,268 @@
+// Read-only copies of a tree for other threads.
+//
+// An Element can't leave the thread it was built on, as Lazy nodes hold
+// their thunks in an Rc. A FrozenElement is the same tree with Lazy nodes
+// evaluated, Send and Sync, and behind an Arc it's cheap to hand to any
+// number of background readers: search indexing, accessibility audits,
+// analytics. Readers needing the rest of the Element API can thaw it back
+// into an Element on their own thread.
+//
+// VirtualDom::snapshot keeps its latest snapshot up to date as the tree
+// changes, sharing the subtrees each patch leaves alone with the snapshot
+// before, so updates only copy what they change.
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use {Attributes, Change, Children, DiffTree, Element, Flags, Key, Listeners};
+
+#[derive(Clone, Debug)]
+pub enum FrozenElement {
+    Text { key: Key, value: String },
+    Void {
+        key: Key,
+        name: String,
+        attributes: Option<Attributes>,
+        listeners: Listeners,
+        flags: Flags,
+    },
+    Parent {
+        key: Key,
+        name: String,
+        attributes: Option<Attributes>,
+        listeners: Listeners,
+        children: Vec<Arc<FrozenElement>>,
+        flags: Flags,
+    },
+}
+
+impl Element {
+    // A frozen copy of this tree, with Lazy nodes evaluated.
+    pub fn freeze(&self) -> FrozenElement {
+        match *self {
+            Element::Text { ref key, ref value } => {
+                FrozenElement::Text {
+                    key: key.clone(),
+                    value: value.clone(),
+                }
+            }
+            Element::Void { ref key, ref name, ref attributes, ref listeners, flags } => {
+                FrozenElement::Void {
+                    key: key.clone(),
+                    name: name.clone(),
+                    attributes: attributes.clone(),
+                    listeners: listeners.clone(),
+                    flags: flags,
+                }
+            }
+            Element::Parent { ref key, ref name, ref attributes, ref listeners, ref children, flags, .. } => {
+                FrozenElement::Parent {
+                    key: key.clone(),
+                    name: name.clone(),
+                    attributes: attributes.clone(),
+                    listeners: listeners.clone(),
+                    children: children.iter().map(|child| Arc::new(child.freeze())).collect(),
+                    flags: flags,
+                }
+            }
+            Element::Lazy { .. } => self.force().freeze(),
+        }
+    }
+}
+
+impl FrozenElement {
+    pub fn key(&self) -> Key {
+        match *self {
+            FrozenElement::Text { ref key, .. } |
+            FrozenElement::Void { ref key, .. } |
+            FrozenElement::Parent { ref key, .. } => key.clone(),
+        }
+    }
+
+    // The tag name, None for a text node.
+    pub fn tag(&self) -> Option<&str> {
+        match *self {
+            FrozenElement::Void { ref name, .. } |
+            FrozenElement::Parent { ref name, .. } => Some(name),
+            FrozenElement::Text { .. } => None,
+        }
+    }
+
+    // The content of a text node.
+    pub fn text(&self) -> Option<&str> {
+        match *self {
+            FrozenElement::Text { ref value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn attributes(&self) -> Option<&Attributes> {
+        match *self {
+            FrozenElement::Void { ref attributes, .. } |
+            FrozenElement::Parent { ref attributes, .. } => attributes.as_ref(),
+            FrozenElement::Text { .. } => None,
+        }
+    }
+
+    pub fn children(&self) -> &[Arc<FrozenElement>] {
+        match *self {
+            FrozenElement::Parent { ref children, .. } => children,
+            _ => &[],
+        }
+    }
+
+    // The first child with the given key.
+    pub fn get_child(&self, key: Key) -> Option<&FrozenElement> {
+        self.children().iter().find(|child| child.key() == key).map(|child| &**child)
+    }
+
+    // An Element with the same content, for the rest of the Element API.
+    pub fn thaw(&self) -> Element {
+        match *self {
+            FrozenElement::Text { ref key, ref value } => {
+                Element::Text {
+                    key: key.clone(),
+                    value: value.clone(),
+                }
+            }
+            FrozenElement::Void { ref key, ref name, ref attributes, ref listeners, flags } => {
+                Element::Void {
+                    key: key.clone(),
+                    name: name.clone(),
+                    attributes: attributes.clone(),
+                    listeners: listeners.clone(),
+                    flags: flags,
+                }
+            }
+            FrozenElement::Parent { ref key, ref name, ref attributes, ref listeners, ref children,
+                                    flags } => {
+                let (children, keymap) = children.iter()
+                    .map(|child| child.thaw())
+                    .collect::<Children>()
+                    .into_parts();
+                Element::Parent {
+                    key: key.clone(),
+                    name: name.clone(),
+                    keymap: keymap,
+                    attributes: attributes.clone(),
+                    listeners: listeners.clone(),
+                    children: children,
+                    flags: flags,
+                }
+            }
+        }
+    }
+}
+
+// The frozen copy of `next`, the tree `diff` turns the one `previous` was
+// frozen from into. Children the diff doesn't touch are shared with
+// `previous`, unless siblings share keys, which patches can't tell apart.
+pub(crate) fn refreeze(previous: &Arc<FrozenElement>, next: &Element, diff: &DiffTree) -> Arc<FrozenElement> {
+    let next = next.force();
+    let changes = diff.changes.as_ref().map_or(&[][..], |changes| &changes[..]);
+    let mut inserted = BTreeSet::new();
+    for change in changes {
+        match *change {
+            Change::ReplaceNode(_) => return Arc::new(next.freeze()),
+            Change::InsertChild(_, ref child) => {
+                inserted.insert(child.to_key());
+            }
+            // Moved nodes are frozen anew rather than found at their old
+            // parents.
+            Change::MoveNode { ref key, .. } => {
+                inserted.insert(key.clone());
+            }
+            _ => {}
+        }
+    }
+    let next_children = match *next {
+        Element::Parent { ref children, .. } => &children[..],
+        _ => &[],
+    };
+    let shared = unique_keys(previous.children().iter().map(|child| child.key())) &&
+                 unique_keys(next_children.iter().map(|child| child.to_key()));
+    let old: BTreeMap<Key, &Arc<FrozenElement>> = if shared {
+        previous.children()
+            .iter()
+            .filter(|child| !inserted.contains(&child.key()))
+            .map(|child| (child.key(), child))
+            .collect()
+    } else {
+        BTreeMap::new()
+    };
+    let child_diffs: BTreeMap<Key, &DiffTree> =
+        diff.children.iter().flat_map(|x| x.iter()).map(|&(ref key, ref diff)| (key.clone(), diff)).collect();
+    let children: Vec<Arc<FrozenElement>> = next_children.iter()
+        .map(|child| {
+            let key = child.to_key();
+            match (old.get(&key), child_diffs.get(&key)) {
+                (Some(old), Some(child_diff)) => refreeze(old, child, child_diff),
+                (Some(old), None) => (*old).clone(),
+                (None, _) => Arc::new(child.freeze()),
+            }
+        })
+        .collect();
+    let unchanged = changes.is_empty() && children.len() == previous.children().len() &&
+                    children.iter().zip(previous.children()).all(|(a, b)| Arc::ptr_eq(a, b));
+    if unchanged {
+        return previous.clone();
+    }
+    Arc::new(match *next {
+        Element::Parent { ref key, ref name, ref attributes, ref listeners, flags, .. } => {
+            FrozenElement::Parent {
+                key: key.clone(),
+                name: name.clone(),
+                attributes: attributes.clone(),
+                listeners: listeners.clone(),
+                children: children,
+                flags: flags,
+            }
+        }
+        _ => next.freeze(),
+    })
+}
+
+fn unique_keys<I: Iterator<Item = Key>>(keys: I) -> bool {
+    let mut seen = BTreeSet::new();
+    keys.into_iter().all(|key| seen.insert(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use super::FrozenElement;
+    use {Element, Key, VirtualDom};
+
+    fn item(key: u64, value: &str) -> Element {
+        el!(li[key=key, Element::text(Key::Local(0), value)])
+    }
+
+    #[test]
+    fn test_snapshot() {
+        fn send_sync<T: Send + Sync>() {}
+        send_sync::<Arc<FrozenElement>>();
+
+        let before = el!(ul[key=0, item(1, "a"), item(2, "b"), item(3, "c")]);
+        let mut dom = VirtualDom::new(before.clone());
+        let first = dom.snapshot();
+        assert!(first.thaw().deep_eq(&before));
+        assert!(Arc::ptr_eq(&first, &dom.snapshot()));
+
+        let after = el!(ul[key=0, item(3, "c"), item(1, "a"), item(2, "x"), item(4, "d")]);
+        dom.update(after.clone(), None);
+        let second = dom.snapshot();
+        assert!(second.thaw().deep_eq(&after));
+        assert!(Arc::ptr_eq(&first.children()[0], &second.children()[1]));
+        assert!(Arc::ptr_eq(&first.children()[2], &second.children()[0]));
+        assert!(!Arc::ptr_eq(&first.children()[1], &second.children()[2]));
+
+        let reader = thread::spawn(move || {
+            second.children()
+                .iter()
+                .filter_map(|x| x.children()[0].text().map(|x| x.to_string()))
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(reader.join().unwrap(), vec!["c", "a", "x", "d"]);
+        assert_eq!(first.get_child(Key::Local(2)).and_then(|x| x.children()[0].text()), Some("b"));
+    }
+}
@@ -0,0 +1,329 @@
+// Streaming server-side rendering.
+//
+// The document shell and everything outside Lazy nodes are written and
+// flushed first, with a placeholder in place of each Lazy node. The Lazy
+// nodes are then evaluated one at a time and each is flushed as soon as it
+// is done, followed by an inline script moving it into its placeholder, so
+// the browser can show the page before the slowest parts are computed.
+//
+// Every element carries its key in a `data-rdk` attribute and every text
+// node is preceded by a `<!--rdt:key-->` comment, so that hydration can match
+// the document back to the tree. Keys are written as `3` for Key::Local(3),
+// `g3` for Key::Global(3), `u` and 32 hex digits for a Key::Uuid, and `s`
+// and the percent-encoded string for a Key::Str, its bytes other than ASCII
+// letters, digits, `_`, `.` and `~` written as `%` and two hex digits. The
+// roots of islands also carry a `data-rdi` attribute, as only those need
+// hydrating.
+//
+// Element::to_html writes a tree without any of these markers, with Lazy
+// nodes evaluated in place, for static pages and snapshot tests.
+//
+// The text in `script` and `style` is written as is, and without a marker,
+// since browsers take their contents as raw text up to the end tag, as
+// parse does. It must not itself hold that end tag.
+use std::io::{self, Write};
+
+use {Attributes, Element, Flags, Key, Thunk};
+
+// HTML elements that have no end tag.
+pub(crate) const VOID_ELEMENTS: &[&str] = &["area", "base", "br", "col", "embed", "hr",
+                                            "img", "input", "link", "meta", "source",
+                                            "track", "wbr"];
+
+// Elements whose contents are text up to their end tag.
+pub(crate) const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+// Moves the children of the completed chunk into its placeholder.
+const SWAP_SCRIPT: &str = "<script>function rdSwap(n){var p=document.getElementById(\
+                           'rds-'+n),c=document.getElementById('rdc-'+n);while(c.\
+                           firstChild)p.parentNode.insertBefore(c.firstChild,p);p.\
+                           remove();c.remove();}</script>";
+
+// Renders `tree` as the body of a complete document, with `head` as the
+// contents of the head element, flushing `out` at every point where a
+// part of the page is complete.
+pub fn render_stream<W: Write>(tree: &Element, head: &str, out: &mut W) -> io::Result<()> {
+    write!(out, "<!DOCTYPE html><html><head>{}</head><body>", head)?;
+    out.flush()?;
+
+    let mut deferred = vec![];
+    write_element(tree, out, &mut deferred)?;
+    out.flush()?;
+
+    // Chunks may defer further Lazy nodes of their own, which are appended.
+    let mut index = 0;
+    while index < deferred.len() {
+        if index == 0 {
+            out.write_all(SWAP_SCRIPT.as_bytes())?;
+        }
+        let element = deferred[index].call();
+        write!(out, "<div hidden id=\"rdc-{}\">", index)?;
+        write_element(&element, out, &mut deferred)?;
+        write!(out, "</div><script>rdSwap({})</script>", index)?;
+        out.flush()?;
+        index += 1;
+    }
+
+    out.write_all(b"</body></html>")?;
+    out.flush()
+}
+
+fn write_element<W: Write>(element: &Element, out: &mut W, deferred: &mut Vec<Thunk>) -> io::Result<()> {
+    match *element {
+        Element::Text { ref key, ref value } => {
+            write!(out, "<!--rdt:{}-->{}", key_marker(key), escape(value, false))
+        }
+        Element::Void { ref key, ref name, ref attributes, flags, .. } => {
+            write_start_tag(out, key, name, attributes.as_ref(), flags)?;
+            if !VOID_ELEMENTS.contains(&&name[..]) {
+                write!(out, "</{}>", name)?;
+            }
+            Ok(())
+        }
+        Element::Parent { ref key, ref name, ref attributes, ref children, flags, .. } => {
+            write_start_tag(out, key, name, attributes.as_ref(), flags)?;
+            let raw = RAW_TEXT_ELEMENTS.contains(&&name[..]);
+            for child in children {
+                match *child {
+                    Element::Text { ref value, .. } if raw => out.write_all(value.as_bytes())?,
+                    _ => write_element(child, out, deferred)?,
+                }
+            }
+            write!(out, "</{}>", name)
+        }
+        Element::Lazy { ref thunk, .. } => {
+            write!(out, "<template id=\"rds-{}\"></template>", deferred.len())?;
+            deferred.push(thunk.clone());
+            Ok(())
+        }
+    }
+}
+
+fn write_start_tag<W: Write>(out: &mut W,
+                             key: &Key,
+                             name: &str,
+                             attributes: Option<&Attributes>,
+                             flags: Flags)
+                             -> io::Result<()> {
+    write!(out, "<{} data-rdk=\"{}\"", name, key_marker(key))?;
+    if flags.island {
+        out.write_all(b" data-rdi")?;
+    }
+    write_attributes(out, attributes)?;
+    out.write_all(b">")
+}
+
+// Attributes are written in the order of Attributes, by qualified name.
+pub(crate) fn write_attributes<W: Write>(out: &mut W, attributes: Option<&Attributes>) -> io::Result<()> {
+    if let Some(attributes) = attributes {
+        for (name, value) in attributes {
+            write!(out, " {}=\"{}\"", name.name, escape(value, true))?;
+        }
+    }
+    Ok(())
+}
+
+impl Element {
+    pub fn to_html(&self) -> String {
+        let mut out = vec![];
+        self.write_html(&mut out).expect("writing to a Vec can't fail");
+        String::from_utf8(out).expect("rendered HTML is UTF-8")
+    }
+
+    pub fn write_html<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        match *self {
+            Element::Text { ref value, .. } => out.write_all(escape(value, false).as_bytes()),
+            Element::Void { ref name, ref attributes, .. } => {
+                write!(out, "<{}", name)?;
+                write_attributes(out, attributes.as_ref())?;
+                out.write_all(b">")?;
+                if !VOID_ELEMENTS.contains(&&name[..]) {
+                    write!(out, "</{}>", name)?;
+                }
+                Ok(())
+            }
+            Element::Parent { ref name, ref attributes, ref children, .. } => {
+                write!(out, "<{}", name)?;
+                write_attributes(out, attributes.as_ref())?;
+                out.write_all(b">")?;
+                let raw = RAW_TEXT_ELEMENTS.contains(&&name[..]);
+                for child in children {
+                    match *child {
+                        Element::Text { ref value, .. } if raw => out.write_all(value.as_bytes())?,
+                        _ => child.write_html(out)?,
+                    }
+                }
+                write!(out, "</{}>", name)
+            }
+            Element::Lazy { .. } => self.force().write_html(out),
+        }
+    }
+}
+
+// Str keys are percent-encoded, so that markers are safe in attributes and
+// comments alike.
+pub(crate) fn key_marker(key: &Key) -> String {
+    match *key {
+        Key::Local(value) => value.to_string(),
+        Key::Global(value) => format!("g{}", value),
+        Key::Uuid(ref bytes) => {
+            let mut marker = "u".to_string();
+            for byte in bytes {
+                marker.push_str(&format!("{:02x}", byte));
+            }
+            marker
+        }
+        Key::Str(ref value) => {
+            let mut marker = "s".to_string();
+            for &byte in value.as_bytes() {
+                if byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'.' || byte == b'~' {
+                    marker.push(byte as char);
+                } else {
+                    marker.push_str(&format!("%{:02X}", byte));
+                }
+            }
+            marker
+        }
+    }
+}
+
+// Escapes text content, or attribute values when `attribute` is set.
+pub fn escape(value: &str, attribute: bool) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use super::{key_marker, render_stream};
+    use {AttrName, Attributes, Element, Flags, Key, Listeners, Thunk};
+
+    // Records the output as the chunks seen at each flush.
+    #[derive(Default)]
+    struct Chunks {
+        buffer: Vec<u8>,
+        chunks: Vec<String>,
+    }
+
+    impl Write for Chunks {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.buffer.extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            let chunk = String::from_utf8(self.buffer.split_off(0)).unwrap();
+            self.chunks.push(chunk);
+            Ok(())
+        }
+    }
+
+    fn text(value: &str) -> Element {
+        Element::Text {
+            key: Key::Local(0),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_stream() {
+        let tree = el!(main[
+            key=0,
+            el!(h1[key=1, text("Tom & Jerry")]),
+            Element::Lazy {
+                key: Key::Local(2),
+                dirty: false,
+                thunk: Thunk::new(|| el!(p[key=2, text("slow")])),
+            }
+        ]);
+        let mut out = Chunks::default();
+        render_stream(&tree, "<title>t</title>", &mut out).unwrap();
+
+        assert_eq!(out.chunks.len(), 4);
+        assert_eq!(out.chunks[0], "<!DOCTYPE html><html><head><title>t</title></head><body>");
+        assert_eq!(out.chunks[1],
+                   "<main data-rdk=\"0\"><h1 data-rdk=\"1\"><!--rdt:0-->Tom &amp; Jerry</h1>\
+                    <template id=\"rds-0\"></template></main>");
+        assert!(out.chunks[2].ends_with("<div hidden id=\"rdc-0\"><p data-rdk=\"2\"><!--rdt:0-->slow</p>\
+                                         </div><script>rdSwap(0)</script>"));
+        assert_eq!(out.chunks[3], "</body></html>");
+    }
+
+    #[test]
+    fn test_key_marker() {
+        assert_eq!(key_marker(&Key::Global(3)), "g3");
+        assert_eq!(key_marker(&Key::Str("a b-->\"".into())), "sa%20b%2D%2D%3E%22");
+        let tree = Element::void("hr").key(Key::Str("rule.1".into())).build();
+        assert_eq!(tree.to_html(), "<hr>");
+        let mut out = Chunks::default();
+        render_stream(&tree, "", &mut out).unwrap();
+        assert!(out.chunks[1].starts_with("<hr data-rdk=\"srule.1\">"));
+    }
+
+    #[test]
+    fn test_attribute_order() {
+        let render = |names: &[&str]| {
+            let mut attributes = Attributes::new();
+            for name in names {
+                attributes.insert(AttrName::from(*name), "x".to_string());
+            }
+            let tree = Element::Void {
+                key: Key::Local(0),
+                name: "img".to_string(),
+                attributes: Some(attributes),
+                listeners: Listeners::new(),
+                flags: Flags::default(),
+            };
+            let mut out = Chunks::default();
+            render_stream(&tree, "", &mut out).unwrap();
+            out.chunks[1].clone()
+        };
+        let html = render(&["src", "alt", "xml:lang", "class"]);
+        assert_eq!(html,
+                   "<img data-rdk=\"0\" alt=\"x\" class=\"x\" src=\"x\" xml:lang=\"x\">");
+        assert_eq!(render(&["class", "xml:lang", "alt", "src"]), html);
+    }
+
+    #[test]
+    fn test_to_html() {
+        let mut attributes = Attributes::new();
+        attributes.insert(AttrName::new("title"), "\"quoted\" <b>".to_string());
+        attributes.insert(AttrName::new("class"), "a&b".to_string());
+        let tree = el!(p[
+            key=0,
+            text("1 < 2"),
+            Element::Void {
+                key: Key::Local(1),
+                name: "br".to_string(),
+                attributes: None,
+                listeners: Listeners::new(),
+                flags: Flags::default(),
+            },
+            Element::Void {
+                key: Key::Local(2),
+                name: "span".to_string(),
+                attributes: Some(attributes),
+                listeners: Listeners::new(),
+                flags: Flags::default(),
+            },
+            Element::Lazy {
+                key: Key::Local(3),
+                dirty: false,
+                thunk: Thunk::new(|| el!(em[key=3, text("lazy")])),
+            }
+        ]);
+        assert_eq!(tree.to_html(),
+                   "<p>1 &lt; 2<br><span class=\"a&amp;b\" title=\"&quot;quoted&quot; &lt;b&gt;\">\
+                    </span><em>lazy</em></p>");
+    }
+}
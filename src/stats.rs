@@ -0,0 +1,89 @@
+// Size and shape metrics for an element tree, used to monitor page
+// complexity budgets.
+use std::collections::BTreeMap;
+
+use Element;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct TreeStats {
+    // Total number of nodes, including text nodes.
+    pub node_count: usize,
+    // Number of nodes on the longest path from the root, so a lone root has
+    // a depth of 1.
+    pub max_depth: usize,
+    // Number of elements per tag name. Text nodes have no tag and are not
+    // counted here.
+    pub per_tag_histogram: BTreeMap<String, usize>,
+    // Bytes of text content across all text nodes.
+    pub text_bytes: usize,
+    // Bytes of qualified attribute names and values across all elements.
+    // Namespace URIs are not counted.
+    pub attr_bytes: usize,
+}
+
+impl Element {
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats::default();
+        collect(self, 1, &mut stats);
+        stats
+    }
+}
+
+// Lazy subtrees are evaluated so that they are counted like any other.
+fn collect(element: &Element, depth: usize, stats: &mut TreeStats) {
+    if let Element::Lazy { .. } = *element {
+        return collect(&element.force(), depth, stats);
+    }
+    let view = element.view();
+    stats.node_count += 1;
+    if depth > stats.max_depth {
+        stats.max_depth = depth;
+    }
+    if let Some(text) = view.text() {
+        stats.text_bytes += text.len();
+    }
+    if let Some(tag) = view.tag() {
+        *stats.per_tag_histogram.entry(tag.to_string()).or_insert(0) += 1;
+    }
+    if let Some(attributes) = view.attributes() {
+        stats.attr_bytes += attributes.iter().map(|(k, v)| k.name.len() + v.len()).sum::<usize>();
+    }
+    for child in view.children() {
+        collect(child.element(), depth + 1, stats);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use {AttrName, Element, Flags, Key, Listeners};
+
+    #[test]
+    fn test_stats() {
+        let mut attributes = BTreeMap::new();
+        attributes.insert(AttrName::new("type"), "text".to_string());
+        let tree = el!(div[
+            key=0,
+            el!(span[
+                key=1,
+                Element::Text { key: Key::Local(0), value: "hello".to_string() }
+            ]),
+            Element::Void {
+                key: Key::Local(2),
+                name: "input".to_string(),
+                attributes: Some(attributes),
+                listeners: Listeners::new(),
+                flags: Flags::default(),
+            }
+        ]);
+        let stats = tree.stats();
+
+        assert_eq!(stats.node_count, 4);
+        assert_eq!(stats.max_depth, 3);
+        assert_eq!(stats.text_bytes, 5);
+        assert_eq!(stats.attr_bytes, 8);
+        assert_eq!(stats.per_tag_histogram.get("div"), Some(&1));
+        assert_eq!(stats.per_tag_histogram.get("span"), Some(&1));
+        assert_eq!(stats.per_tag_histogram.get("input"), Some(&1));
+    }
+}
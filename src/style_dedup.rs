@@ -0,0 +1,183 @@
+// Deduplication of inline styles. Generated lists often repeat the same
+// `style` attribute on every row; moving each repeated declaration block into
+// one generated class shrinks both the serialized HTML and the patches that
+// insert such rows.
+use std::collections::BTreeMap;
+
+use {AttrName, Attributes, Children, Element, Key};
+
+// The prefix of generated class names, followed by a number.
+pub const CLASS_PREFIX: &str = "rd-s";
+
+#[derive(Clone, Debug)]
+pub struct DedupedStyles {
+    pub tree: Element,
+    // The generated rules, one per shared declaration block.
+    pub stylesheet: String,
+}
+
+impl DedupedStyles {
+    // A `<style>` element holding the generated rules.
+    pub fn style_element(&self, key: Key) -> Element {
+        let text = Element::Text {
+            key: Key::Local(0),
+            value: self.stylesheet.clone(),
+        };
+        Some(text).into_iter().collect::<Children>().into_parent(key, "style")
+    }
+}
+
+impl Element {
+    // Replaces every inline style occurring on at least `min_count` nodes
+    // with a shared generated class. Styles are compared after normalizing
+    // whitespace and semicolons, so `color:red;` and `color: red` are the
+    // same. Lazy nodes are left alone.
+    pub fn dedupe_styles(self, min_count: usize) -> DedupedStyles {
+        let mut counts = BTreeMap::new();
+        count(&self, &mut counts);
+
+        let mut classes = BTreeMap::new();
+        let mut order = vec![];
+        let mut stylesheet = String::new();
+        collect_order(&self, &counts, min_count, &mut order);
+        for style in order {
+            if !classes.contains_key(&style) {
+                let class = format!("{}{}", CLASS_PREFIX, classes.len());
+                stylesheet.push_str(&format!(".{}{{{}}}", class, style));
+                classes.insert(style, class);
+            }
+        }
+        DedupedStyles {
+            tree: rewrite(self, &classes),
+            stylesheet: stylesheet,
+        }
+    }
+}
+
+fn normalize(style: &str) -> String {
+    style.split(';')
+        .map(|declaration| {
+            match declaration.find(':') {
+                Some(index) => {
+                    format!("{}: {}", declaration[..index].trim(), declaration[index + 1..].trim())
+                }
+                None => declaration.trim().to_string(),
+            }
+        })
+        .filter(|declaration| !declaration.is_empty())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn style_of(element: &Element) -> Option<String> {
+    element.view().attribute("style").map(normalize).filter(|x| !x.is_empty())
+}
+
+fn count(element: &Element, counts: &mut BTreeMap<String, usize>) {
+    if let Some(style) = style_of(element) {
+        *counts.entry(style).or_insert(0) += 1;
+    }
+    for child in element.view().children() {
+        count(child.element(), counts);
+    }
+}
+
+// The shared styles in document order of first use, so generated names are
+// stable for a given tree.
+fn collect_order(element: &Element,
+                 counts: &BTreeMap<String, usize>,
+                 min_count: usize,
+                 order: &mut Vec<String>) {
+    if let Some(style) = style_of(element) {
+        if counts[&style] >= min_count {
+            order.push(style);
+        }
+    }
+    for child in element.view().children() {
+        collect_order(child.element(), counts, min_count, order);
+    }
+}
+
+fn rewrite(element: Element, classes: &BTreeMap<String, String>) -> Element {
+    let class = style_of(&element).and_then(|style| classes.get(&style).cloned());
+    match element {
+        Element::Void { key, name, mut attributes, listeners, flags } => {
+            if let Some(class) = class {
+                replace_style(&mut attributes, &class);
+            }
+            Element::Void {
+                key: key,
+                name: name,
+                attributes: attributes,
+                listeners: listeners,
+                flags: flags,
+            }
+        }
+        Element::Parent { key, name, keymap, mut attributes, listeners, children, flags } => {
+            if let Some(class) = class {
+                replace_style(&mut attributes, &class);
+            }
+            Element::Parent {
+                key: key,
+                name: name,
+                keymap: keymap,
+                attributes: attributes,
+                listeners: listeners,
+                children: children.into_iter().map(|child| rewrite(child, classes)).collect(),
+                flags: flags,
+            }
+        }
+        other => other,
+    }
+}
+
+fn replace_style(attributes: &mut Option<Attributes>, class: &str) {
+    if let Some(ref mut attributes) = *attributes {
+        attributes.remove(&AttrName::new("style"));
+        let classes = match attributes.get(&AttrName::new("class")) {
+            Some(existing) if !existing.trim().is_empty() => format!("{} {}", existing.trim(), class),
+            _ => class.to_string(),
+        };
+        attributes.insert(AttrName::new("class"), classes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {AttrName, Attributes, Element, Flags, Key, Listeners};
+
+    fn styled(key: u64, class: Option<&str>, style: &str) -> Element {
+        let mut attributes = Attributes::new();
+        attributes.insert(AttrName::new("style"), style.to_string());
+        if let Some(class) = class {
+            attributes.insert(AttrName::new("class"), class.to_string());
+        }
+        Element::Void {
+            key: Key::Local(key),
+            name: "li".to_string(),
+            attributes: Some(attributes),
+            listeners: Listeners::new(),
+            flags: Flags::default(),
+        }
+    }
+
+    #[test]
+    fn test_dedupe_styles() {
+        let tree = el!(ul[
+            key=0,
+            styled(0, None, "color:red;padding: 2px;"),
+            styled(1, Some("odd"), "color: red; padding:2px"),
+            styled(2, None, "color: blue")
+        ]);
+        let deduped = tree.dedupe_styles(2);
+        assert_eq!(deduped.stylesheet, ".rd-s0{color: red; padding: 2px}");
+
+        let view = deduped.tree.view();
+        let row = |key| view.child(Key::Local(key)).unwrap();
+        assert_eq!(row(0).attribute("class"), Some("rd-s0"));
+        assert_eq!(row(0).attribute("style"), None);
+        assert_eq!(row(1).attribute("class"), Some("odd rd-s0"));
+        assert_eq!(row(2).attribute("style"), Some("color: blue"));
+        assert_eq!(row(2).attribute("class"), None);
+    }
+}
@@ -0,0 +1,150 @@
+// Hooks for production monitoring. A VirtualDom given a Telemetry reports
+// every diff it computes, and the transport and event loop report patch
+// sizes and event latencies through it, so operators can export them to
+// their metrics system.
+use std::time::Duration;
+
+use {DiffTree, DomError, EventType, Key, Rejection};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiffStats {
+    // Time spent computing the patch.
+    pub duration: Duration,
+    // Number of changes in the patch, across all nodes.
+    pub change_count: usize,
+    // Number of nodes the patch has changes for.
+    pub touched_nodes: usize,
+    // The share of each child of the root kept by the diff, in ascending key
+    // order, to find the widget behind a slow frame. Changes to the root
+    // itself, including inserting and removing its children, aren't in any
+    // of them. Empty unless the diff was timed, as VirtualDom does.
+    pub subtrees: Vec<SubtreeStats>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubtreeStats {
+    // Key of the child of the root.
+    pub key: Key,
+    pub duration: Duration,
+    pub change_count: usize,
+    pub touched_nodes: usize,
+}
+
+impl DiffStats {
+    pub fn new(diff: Option<&DiffTree>, duration: Duration) -> DiffStats {
+        DiffStats::with_subtrees(diff, duration, &[])
+    }
+
+    // Stats with a breakdown for the children of the root diffed in
+    // `timings`, each with the time its diff took.
+    pub fn with_subtrees(diff: Option<&DiffTree>,
+                         duration: Duration,
+                         timings: &[(Key, Duration)])
+                         -> DiffStats {
+        let (change_count, touched_nodes) = diff.map_or((0, 0), count);
+        let child = |key| {
+            diff.and_then(|diff| diff.children.as_ref())
+                .and_then(|children| children.iter().find(|&&(ref x, _)| x == key))
+                .map(|&(_, ref child)| child)
+        };
+        DiffStats {
+            duration: duration,
+            change_count: change_count,
+            touched_nodes: touched_nodes,
+            subtrees: timings.iter()
+                .map(|&(ref key, duration)| {
+                    let (change_count, touched_nodes) = child(key).map_or((0, 0), count);
+                    SubtreeStats {
+                        key: key.clone(),
+                        duration: duration,
+                        change_count: change_count,
+                        touched_nodes: touched_nodes,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+// The number of changes in `diff` and of nodes they are for.
+fn count(diff: &DiffTree) -> (usize, usize) {
+    let (mut changes, mut nodes) = match diff.changes {
+        Some(ref changes) if !changes.is_empty() => (changes.len(), 1),
+        _ => (0, 0),
+    };
+    for &(_, ref child) in diff.children.iter().flat_map(|x| x.iter()) {
+        let (child_changes, child_nodes) = count(child);
+        changes += child_changes;
+        nodes += child_nodes;
+    }
+    (changes, nodes)
+}
+
+pub trait Telemetry {
+    // Called after every diff, including those finding no changes.
+    fn on_diff(&mut self, _stats: &DiffStats) {}
+
+    // Called with the size of each serialized patch sent to a renderer.
+    fn on_patch_sent(&mut self, _bytes: usize) {}
+
+    // Called with the time from an event arriving to its resulting patch
+    // being ready.
+    fn on_event(&mut self, _kind: EventType, _latency: Duration) {}
+
+    // Called for each attribute value the VirtualDom's AttributePolicy
+    // rejected from a patch.
+    fn on_attribute_rejected(&mut self, _rejection: &Rejection) {}
+
+    // Called in debug builds when a tree the VirtualDom diffs has siblings
+    // sharing a key, with the first such pair. The diff matches them by
+    // position, which usually means replacing their parent.
+    fn on_duplicate_key(&mut self, _error: &DomError) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{DiffStats, SubtreeStats};
+    use {Element, Key};
+
+    #[test]
+    fn test_diff_stats() {
+        let text = |value: &str| Element::Text { key: Key::Local(0), value: value.to_string() };
+        let before = el!(ul[key=0, el!(li[key=1, text("a")]), el!(li[key=2, text("b")])]);
+        let after = el!(ul[key=0, el!(li[key=1, text("c")]), el!(li[key=2, text("d")])]);
+        let diff = before.diff(&after);
+        let stats = DiffStats::new(diff.as_ref(), Duration::from_millis(1));
+        assert_eq!(stats.change_count, 2);
+        assert_eq!(stats.touched_nodes, 2);
+        assert_eq!(DiffStats::new(None, Duration::from_millis(1)).change_count, 0);
+        assert!(stats.subtrees.is_empty());
+    }
+
+    #[test]
+    fn test_subtrees() {
+        let text = |value: &str| Element::Text { key: Key::Local(0), value: value.to_string() };
+        let before = el!(ul[key=0, el!(li[key=1, text("a")]), el!(li[key=2, text("b")]), el!(li[key=3])]);
+        let after = el!(ul[key=0, el!(li[key=1, text("c")]), el!(li[key=2, text("b")]), el!(li[key=4])]);
+        let mut timings = vec![];
+        let diff = before.diff_timed(&after, &mut timings);
+        assert_eq!(diff, before.diff(&after));
+        let keys: Vec<Key> = timings.iter().map(|&(ref key, _)| key.clone()).collect();
+        assert_eq!(keys, vec![Key::Local(1), Key::Local(2)]);
+
+        let stats = DiffStats::with_subtrees(diff.as_ref(), Duration::from_millis(3), &timings);
+        assert_eq!(stats.change_count, 3);
+        assert_eq!(stats.subtrees,
+                   vec![SubtreeStats {
+                            key: Key::Local(1),
+                            duration: timings[0].1,
+                            change_count: 1,
+                            touched_nodes: 1,
+                        },
+                        SubtreeStats {
+                            key: Key::Local(2),
+                            duration: timings[1].1,
+                            change_count: 0,
+                            touched_nodes: 0,
+                        }]);
+    }
+}
@@ -0,0 +1,267 @@
+// Selector-based assertions for tests of code producing element trees:
+//
+//     assert_tree!(tree,
+//                  has("button.primary"),
+//                  text_eq("#title", "Hello"),
+//                  count(".row", 10));
+//
+// A failing check panics with a message showing the HTML of the elements it
+// looked at, or of the whole tree when nothing matched, rather than a Debug
+// dump of the Element. Selectors are those of Selector, and panic when they
+// don't parse. Lazy nodes aren't searched, as in Element::select, but their
+// text counts towards the text of a matching ancestor.
+//
+// Expected trees can be written as markup with tree!, rather than nested
+// builders:
+//
+//     assert!(tree.deep_eq(&tree!("<ul><li class=done>a</li></ul>")));
+use parse::{parse, rekey};
+use {Children, Element, Selector};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Check {
+    Has(String),
+    TextEq(String, String),
+    Count(String, usize),
+}
+
+// Passes if some element matches `selector`.
+pub fn has(selector: &str) -> Check {
+    Check::Has(selector.to_string())
+}
+
+// Passes if the first element matching `selector` has the text content
+// `text`, all the text nodes below it joined.
+pub fn text_eq(selector: &str, text: &str) -> Check {
+    Check::TextEq(selector.to_string(), text.to_string())
+}
+
+// Passes if exactly `n` elements match `selector`.
+pub fn count(selector: &str, n: usize) -> Check {
+    Check::Count(selector.to_string(), n)
+}
+
+impl Check {
+    // Runs the check against `tree`, returning the failure message if it
+    // fails.
+    pub fn check(&self, tree: &Element) -> Result<(), String> {
+        match *self {
+            Check::Has(ref selector) => {
+                if select(tree, selector).is_empty() {
+                    Err(format!("no element matches `{}` in:\n{}", selector, tree.to_html()))
+                } else {
+                    Ok(())
+                }
+            }
+            Check::TextEq(ref selector, ref expected) => {
+                match select(tree, selector).first() {
+                    None => Err(format!("no element matches `{}` in:\n{}", selector, tree.to_html())),
+                    Some(element) => {
+                        let found = text_content(element);
+                        if found == *expected {
+                            Ok(())
+                        } else {
+                            Err(format!("expected text {:?} for `{}`, found {:?} in:\n{}",
+                                        expected,
+                                        selector,
+                                        found,
+                                        element.to_html()))
+                        }
+                    }
+                }
+            }
+            Check::Count(ref selector, expected) => {
+                let matches = select(tree, selector);
+                if matches.len() == expected {
+                    return Ok(());
+                }
+                let mut message = format!("expected {} elements matching `{}`, found {}",
+                                          expected,
+                                          selector,
+                                          matches.len());
+                if matches.is_empty() {
+                    message.push_str(&format!(" in:\n{}", tree.to_html()));
+                } else {
+                    message.push(':');
+                    for element in matches {
+                        message.push('\n');
+                        message.push_str(&element.to_html());
+                    }
+                }
+                Err(message)
+            }
+        }
+    }
+}
+
+fn select<'a>(tree: &'a Element, selector: &str) -> Vec<&'a Element> {
+    match Selector::parse(selector) {
+        Ok(parsed) => tree.select(&parsed),
+        Err(error) => {
+            panic!("invalid selector `{}` at {}: {}", selector, error.position, error.reason)
+        }
+    }
+}
+
+fn text_content(element: &Element) -> String {
+    let element = element.force();
+    match element.view().text() {
+        Some(text) => text.to_string(),
+        None => element.view().children().map(|child| text_content(child.element())).collect(),
+    }
+}
+
+// The tree `html` describes, as parsed by parse::parse. Text of nothing but
+// whitespace spanning a line break, the indentation of a fixture written
+// over several lines, is dropped, and every node is keyed Key::Local with
+// its index among the siblings left, so the same markup always gives the
+// same keys. Panics if `html` isn't a single root element.
+pub fn fixture(html: &str) -> Element {
+    let mut tree = match parse(html) {
+        Ok(tree) => trim(tree),
+        Err(error) => panic!("invalid fixture `{}`: {}", html, error),
+    };
+    rekey(&mut tree, 0);
+    tree
+}
+
+fn trim(element: Element) -> Element {
+    match element {
+        Element::Parent { key, name, attributes, listeners, children, flags, .. } => {
+            let children: Vec<Element> = children.into_iter()
+                .filter(|child| match *child {
+                    Element::Text { ref value, .. } => !(value.trim().is_empty() && value.contains('\n')),
+                    _ => true,
+                })
+                .map(trim)
+                .collect();
+            if children.is_empty() {
+                return Element::Void {
+                    key: key,
+                    name: name,
+                    attributes: attributes,
+                    listeners: listeners,
+                    flags: flags,
+                };
+            }
+            let (children, keymap) = children.into_iter().collect::<Children>().into_parts();
+            Element::Parent {
+                key: key,
+                name: name,
+                keymap: keymap,
+                attributes: attributes,
+                listeners: listeners,
+                children: children,
+                flags: flags,
+            }
+        }
+        element => element,
+    }
+}
+
+// The Element for a fixture written as markup, see testkit::fixture.
+#[macro_export]
+macro_rules! tree {
+    ($html:expr) => ($crate::testkit::fixture($html));
+}
+
+// Asserts each check of testkit against a tree, panicking at the first that
+// fails. The checks are written as calls to has, text_eq and count, which
+// need not be imported.
+#[macro_export]
+macro_rules! assert_tree {
+    ($tree:expr, $($check:ident($($arg:expr),*)),+ $(,)*) => (
+        {
+            let tree: &$crate::Element = &$tree;
+            $(
+                if let Err(message) = $crate::testkit::$check($($arg),*).check(tree) {
+                    panic!("{}", message);
+                }
+            )+
+        }
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count, has, text_eq};
+    use {AttrName, Element, Flags, Key, Listeners};
+
+    fn text(value: &str) -> Element {
+        Element::Text {
+            key: Key::Local(0),
+            value: value.to_string(),
+        }
+    }
+
+    fn with_id(mut element: Element, id: &str) -> Element {
+        if let Element::Parent { ref mut attributes, .. } = element {
+            *attributes = Some(vec![(AttrName::new("id"), id.to_string())].into_iter().collect());
+        }
+        element
+    }
+
+    fn tree() -> Element {
+        el!(main[
+            key=0,
+            with_id(el!(h1[key=1, text("Hello"), el!(em[key=1, text(", you")])]), "title"),
+            Element::Void {
+                key: Key::Local(2),
+                name: "button".to_string(),
+                attributes: Some(vec![(AttrName::new("class"), "primary".to_string())]
+                    .into_iter()
+                    .collect()),
+                listeners: Listeners::new(),
+                flags: Flags::default(),
+            }
+        ])
+    }
+
+    #[test]
+    fn test_assert_tree() {
+        assert_tree!(tree(),
+                     has("button.primary"),
+                     text_eq("#title", "Hello, you"),
+                     count("h1 em", 1));
+    }
+
+    #[test]
+    fn test_failure_messages() {
+        assert_eq!(has("button.secondary").check(&tree()),
+                   Err(format!("no element matches `button.secondary` in:\n{}", tree().to_html())));
+        assert_eq!(text_eq("#title", "Hi").check(&tree()),
+                   Err("expected text \"Hi\" for `#title`, found \"Hello, you\" in:\n\
+                        <h1 id=\"title\">Hello<em>, you</em></h1>"
+                       .to_string()));
+        assert_eq!(count("em", 2).check(&tree()),
+                   Err("expected 2 elements matching `em`, found 1:\n<em>, you</em>".to_string()));
+    }
+
+    #[test]
+    fn test_fixture() {
+        let span = el!(span[key=0, Element::text(Key::Local(0), "hi")]);
+        let expected = Element::parent("div").key(Key::Local(0)).attr("id", "a").child(span).build();
+        assert!(tree!("<div id=a><span>hi</span></div>").deep_eq(&expected));
+        assert!(tree!("
+            <div id=a>
+                <span>hi</span>
+            </div>
+        ").deep_eq(&expected));
+
+        let inline = tree!("<p><b>a</b> <i>b</i><br>\n</p>");
+        assert_eq!(inline.to_html(), "<p><b>a</b> <i>b</i><br></p>");
+        assert_eq!(inline.get_child(Key::Local(2)).map(|x| x.to_key()), Some(Key::Local(2)));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid fixture `<p></p><p></p>`: more than one root node")]
+    fn test_fixture_panics() {
+        tree!("<p></p><p></p>");
+    }
+
+    #[test]
+    #[should_panic(expected = "found 1")]
+    fn test_assert_tree_panics() {
+        assert_tree!(tree(), has("h1"), count("button", 3));
+    }
+}
@@ -0,0 +1,143 @@
+// How changed text is sent, chosen per element to suit its content. A label
+// is best replaced whole, while a `<pre>` holding a log or a code editor's
+// buffer usually changes in one place, and resending all of it on every
+// keystroke or appended line costs far more than a splice.
+//
+// Splices are computed by trimming the longest common prefix and suffix, in
+// whole lines or in characters, leaving one changed range. Offsets are in
+// UTF-16 code units, as CharacterData.replaceData takes them. When nothing
+// is left in common the text is replaced with UpdateText all the same.
+use Change;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TextDiff {
+    // UpdateText with the whole new value.
+    #[default]
+    Replace,
+    // SpliceText of the changed lines.
+    Lines,
+    // SpliceText of the changed characters.
+    Chars,
+}
+
+// The change turning text node `left` into `right`, which differ.
+pub(crate) fn text_change(left: &str, right: &str, policy: TextDiff) -> Change {
+    let (prefix, suffix) = match policy {
+        TextDiff::Replace => (0, 0),
+        TextDiff::Lines => common_lines(left, right),
+        TextDiff::Chars => common_chars(left, right),
+    };
+    if prefix == 0 && suffix == 0 {
+        return Change::UpdateText(right.to_string());
+    }
+    Change::SpliceText {
+        start: utf16_len(&left[..prefix]),
+        delete: utf16_len(&left[prefix..left.len() - suffix]),
+        insert: right[prefix..right.len() - suffix].to_string(),
+    }
+}
+
+// Applies a SpliceText to `value`, or returns None if the range isn't
+// within it or splits a surrogate pair.
+pub(crate) fn splice(value: &str, start: usize, delete: usize, insert: &str) -> Option<String> {
+    let from = byte_offset(value, start)?;
+    let to = from + byte_offset(&value[from..], delete)?;
+    let mut spliced = String::with_capacity(value.len() - (to - from) + insert.len());
+    spliced.push_str(&value[..from]);
+    spliced.push_str(insert);
+    spliced.push_str(&value[to..]);
+    Some(spliced)
+}
+
+// The byte lengths of the common prefix and suffix, in whole lines, not
+// overlapping in either string.
+fn common_lines(left: &str, right: &str) -> (usize, usize) {
+    let prefix = left.split_inclusive('\n')
+        .zip(right.split_inclusive('\n'))
+        .take_while(|&(x, y)| x == y)
+        .map(|(x, _)| x.len())
+        .sum::<usize>();
+    let suffix = left[prefix..]
+        .split_inclusive('\n')
+        .rev()
+        .zip(right[prefix..].split_inclusive('\n').rev())
+        .take_while(|&(x, y)| x == y)
+        .map(|(x, _)| x.len())
+        .sum();
+    (prefix, suffix)
+}
+
+fn common_chars(left: &str, right: &str) -> (usize, usize) {
+    let prefix = left.chars()
+        .zip(right.chars())
+        .take_while(|&(x, y)| x == y)
+        .map(|(x, _)| x.len_utf8())
+        .sum::<usize>();
+    let suffix = left[prefix..]
+        .chars()
+        .rev()
+        .zip(right[prefix..].chars().rev())
+        .take_while(|&(x, y)| x == y)
+        .map(|(x, _)| x.len_utf8())
+        .sum();
+    (prefix, suffix)
+}
+
+fn utf16_len(value: &str) -> usize {
+    value.chars().map(char::len_utf16).sum()
+}
+
+// The byte offset `units` UTF-16 code units into `value`.
+fn byte_offset(value: &str, units: usize) -> Option<usize> {
+    let mut counted = 0;
+    for (index, c) in value.char_indices() {
+        if counted == units {
+            return Some(index);
+        }
+        if counted > units {
+            return None;
+        }
+        counted += c.len_utf16();
+    }
+    if counted == units {
+        Some(value.len())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{splice, text_change, TextDiff};
+    use Change;
+
+    fn spliced(start: usize, delete: usize, insert: &str) -> Change {
+        Change::SpliceText {
+            start: start,
+            delete: delete,
+            insert: insert.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_text_change() {
+        let left = "one\ntwo\nthree";
+        let right = "one\n2\nthree";
+        assert_eq!(text_change(left, right, TextDiff::Replace), Change::UpdateText(right.to_string()));
+        assert_eq!(text_change(left, right, TextDiff::Lines), spliced(4, 4, "2\n"));
+        assert_eq!(text_change(left, right, TextDiff::Chars), spliced(4, 3, "2"));
+        assert_eq!(text_change("log\n", "log\nmore\n", TextDiff::Lines), spliced(4, 0, "more\n"));
+        assert_eq!(text_change("ab", "cd", TextDiff::Chars), Change::UpdateText("cd".to_string()));
+        // An astral character counts as two code units.
+        assert_eq!(text_change("\u{1f600}a", "\u{1f600}b", TextDiff::Chars), spliced(2, 1, "b"));
+    }
+
+    #[test]
+    fn test_splice() {
+        assert_eq!(splice("one\ntwo\nthree", 4, 4, "2\n"), Some("one\n2\nthree".to_string()));
+        assert_eq!(splice("\u{1f600}a", 2, 1, "b"), Some("\u{1f600}b".to_string()));
+        assert_eq!(splice("\u{1f600}a", 1, 0, "b"), None);
+        assert_eq!(splice("abc", 2, 2, ""), None);
+    }
+}
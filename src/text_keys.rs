@@ -0,0 +1,169 @@
+// Keys for text nodes taken from the previous tree, so templates that
+// intersperse text with elements don't have to invent a key for every string
+// fragment.
+//
+// Patches address children by key, so rather than changing how the diff
+// compares keys, the next tree's text nodes are given keys before diffing: a
+// text node matched with one of the previous tree's text nodes among its
+// siblings takes that node's key, unless an element among its new siblings
+// has it, and any other gets a fresh local key used by none of its siblings
+// in either tree. The keys a template gave its text
+// nodes are ignored, duplicates included, and keymaps are rebuilt to match.
+// Children of the same key in both trees are matched up recursively; Lazy
+// nodes are left alone.
+use std::collections::BTreeSet;
+
+use element::rebuild_keymap;
+use {Element, Key};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextKeys {
+    // The n-th text child matches the previous n-th text child.
+    Positional,
+    // A text child matches the first previous text child not yet matched
+    // whose first `n` characters are the same, so fragments keep their keys
+    // when others are inserted before them.
+    Prefix(usize),
+}
+
+impl Element {
+    // Rekeys the text nodes of this tree, the next one to be diffed against
+    // `previous`, as `mode` says.
+    pub fn match_text_keys(&mut self, previous: &Element, mode: TextKeys) {
+        let (keymap, children) = match *self {
+            Element::Parent { ref mut keymap, ref mut children, .. } => (keymap, children),
+            _ => return,
+        };
+        let empty = vec![];
+        let old = match *previous {
+            Element::Parent { ref children, .. } => children,
+            _ => &empty,
+        };
+        let old_texts: Vec<(Key, &str)> = old.iter()
+            .filter_map(|child| match *child {
+                Element::Text { ref key, ref value } => Some((key.clone(), &value[..])),
+                _ => None,
+            })
+            .collect();
+        // An old text node's key is only reused if no new element has it.
+        let elements: BTreeSet<Key> = children.iter()
+            .filter(|child| !is_text(child))
+            .map(|child| child.to_key())
+            .collect();
+        let mut taken: BTreeSet<Key> = old.iter()
+            .map(|child| child.to_key())
+            .chain(elements.iter().cloned())
+            .collect();
+
+        let mut matched = vec![false; old_texts.len()];
+        let mut position = 0;
+        let mut fresh = 0;
+        for child in children.iter_mut() {
+            let value = match *child {
+                Element::Text { ref value, .. } => value,
+                ref mut child => {
+                    if let Some(previous) = previous.get_child(child.to_key()) {
+                        child.match_text_keys(previous, mode);
+                    }
+                    continue;
+                }
+            };
+            let free = |x: usize| !elements.contains(&old_texts[x].0);
+            let index = match mode {
+                TextKeys::Positional => Some(position).filter(|&x| x < old_texts.len() && free(x)),
+                TextKeys::Prefix(n) => {
+                    let prefix = |value: &str| value.chars().take(n).collect::<String>();
+                    (0..old_texts.len())
+                        .find(|&x| !matched[x] && free(x) && prefix(old_texts[x].1) == prefix(value))
+                }
+            };
+            position += 1;
+            let key = match index {
+                Some(index) => {
+                    matched[index] = true;
+                    old_texts[index].0.clone()
+                }
+                None => {
+                    while taken.contains(&Key::Local(fresh)) {
+                        fresh += 1;
+                    }
+                    taken.insert(Key::Local(fresh));
+                    Key::Local(fresh)
+                }
+            };
+            if let Element::Text { key: ref mut x, .. } = *child {
+                *x = key;
+            }
+        }
+        rebuild_keymap(keymap, children);
+    }
+}
+
+fn is_text(element: &Element) -> bool {
+    matches!(*element, Element::Text { .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TextKeys;
+    use {Change, Children, Element, Key};
+
+    fn text(value: &str) -> Element {
+        Element::Text {
+            key: Key::Local(0),
+            value: value.to_string(),
+        }
+    }
+
+    fn keys(element: &Element) -> Vec<Key> {
+        element.view().children().map(|child| child.key()).collect()
+    }
+
+    #[test]
+    fn test_positional() {
+        let previous = el!(p[key=0, text("Hello, "), el!(b[key=1]), text("!")]);
+        let mut previous_keyed = previous.clone();
+        previous_keyed.match_text_keys(&el!(p[key=0]), TextKeys::Positional);
+        assert_eq!(keys(&previous_keyed), vec![Key::Local(0), Key::Local(1), Key::Local(2)]);
+
+        let mut next = vec![text("Hi, "), el!(b[key=1]), text("?")]
+            .into_iter()
+            .collect::<Children>()
+            .into_parent(Key::Local(0), "p");
+        next.match_text_keys(&previous_keyed, TextKeys::Positional);
+        assert_eq!(keys(&next), keys(&previous_keyed));
+        let diff = previous_keyed.diff(&next).unwrap();
+        assert_eq!(diff.children.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_prefix() {
+        let mut previous = el!(p[key=0, text("one"), text("two")]);
+        previous.match_text_keys(&el!(p[key=0]), TextKeys::Prefix(2));
+        let mut next = el!(p[key=0, text("zero"), text("one"), text("twice")]);
+        next.match_text_keys(&previous, TextKeys::Prefix(2));
+        assert_eq!(keys(&next), vec![Key::Local(2), Key::Local(0), Key::Local(1)]);
+
+        let diff = previous.diff(&next).unwrap();
+        match diff.changes.unwrap()[0] {
+            Change::InsertChild(0, ref element) => assert_eq!(element.to_key(), Key::Local(2)),
+            ref change => panic!("unexpected change {:?}", change),
+        }
+    }
+
+    #[test]
+    fn test_element_takes_text_key() {
+        // The text before <b> had key 1, which <b> now has.
+        let mut previous = el!(p[key=0, text("a"), el!(b[key=0])]);
+        previous.match_text_keys(&el!(p[key=0]), TextKeys::Positional);
+        assert_eq!(keys(&previous), vec![Key::Local(1), Key::Local(0)]);
+
+        for &mode in &[TextKeys::Positional, TextKeys::Prefix(1)] {
+            let mut next = el!(p[key=0, text("a"), el!(b[key=1])]);
+            next.match_text_keys(&previous, mode);
+            assert_eq!(keys(&next), vec![Key::Local(2), Key::Local(1)]);
+            assert_eq!(next.validate(), Ok(()));
+            assert!(previous.try_diff(&next).is_ok());
+        }
+    }
+}
@@ -0,0 +1,238 @@
+// A fast path for frames where only text content changed.
+//
+// Rebuilding a tree whose structure is stable (a ticking clock, a counter)
+// normally still pays for a full DiffTree. Element::diff_text instead records
+// just the paths of the changed text nodes into a TextPatch, whose buffers
+// are reused from frame to frame, so once they have grown to the size of a
+// typical patch the fast path performs no allocations at all.
+use {Change, DiffTree, Element, Key};
+
+#[derive(Debug, Default)]
+pub struct TextPatch {
+    // The paths of all updates, stored back to back.
+    keys: Vec<Key>,
+    // The range of `keys` holding the path of each update.
+    updates: Vec<(usize, usize)>,
+    // The path of the node currently being compared.
+    stack: Vec<Key>,
+}
+
+impl TextPatch {
+    pub fn new() -> TextPatch {
+        TextPatch::default()
+    }
+
+    // Forgets all updates, keeping the allocated buffers.
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.updates.clear();
+        self.stack.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.updates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.updates.is_empty()
+    }
+
+    // The paths of the updated text nodes: the keys of the children from the
+    // root down, as used to address a DiffTree.
+    pub fn paths<'a>(&'a self) -> impl Iterator<Item = &'a [Key]> + 'a {
+        self.updates.iter().map(move |&(start, end)| &self.keys[start..end])
+    }
+
+    // The equivalent DiffTree, with the new text values taken from `next`,
+    // the tree this patch was computed against.
+    pub fn to_diff(&self, next: &Element) -> Option<DiffTree> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut diff = DiffTree {
+            changes: None,
+            children: None,
+            base_checksum: None,
+        };
+        for path in self.paths() {
+            let node = next.get_path(path);
+            if let Some(&Element::Text { ref value, .. }) = node {
+                diff.push_at(path, Change::UpdateText(value.clone()));
+            }
+        }
+        Some(diff)
+    }
+
+    fn walk(&mut self, left: &Element, right: &Element) -> bool {
+        match (left, right) {
+            (&Element::Text { key: ref left_key, value: ref left },
+             &Element::Text { key: ref right_key, value: ref right }) => {
+                if left_key != right_key {
+                    return false;
+                }
+                if left != right {
+                    let start = self.keys.len();
+                    self.keys.extend_from_slice(&self.stack);
+                    self.updates.push((start, self.keys.len()));
+                }
+                true
+            }
+            (&Element::Void { key: ref left_key,
+                              name: ref left_name,
+                              attributes: ref left_attributes,
+                              listeners: ref left_listeners,
+                              flags: left_flags },
+             &Element::Void { key: ref right_key,
+                              name: ref right_name,
+                              attributes: ref right_attributes,
+                              listeners: ref right_listeners,
+                              flags: right_flags }) => {
+                left_key == right_key && left_name == right_name && left_attributes == right_attributes &&
+                left_listeners == right_listeners && left_flags == right_flags
+            }
+            (&Element::Parent { key: ref left_key,
+                                name: ref left_name,
+                                attributes: ref left_attributes,
+                                listeners: ref left_listeners,
+                                flags: left_flags,
+                                keymap: ref left_keymap,
+                                children: ref left_children },
+             &Element::Parent { key: ref right_key,
+                                name: ref right_name,
+                                attributes: ref right_attributes,
+                                listeners: ref right_listeners,
+                                flags: right_flags,
+                                keymap: ref right_keymap,
+                                children: ref right_children }) => {
+                if left_key != right_key || left_name != right_name || left_attributes != right_attributes ||
+                   left_listeners != right_listeners || left_flags != right_flags ||
+                   left_keymap != right_keymap {
+                    return false;
+                }
+                // Equal keymaps put every key at the same index on both sides.
+                for (key, &index) in left_keymap {
+                    self.stack.push(key.clone());
+                    let unchanged = self.walk(&left_children[index], &right_children[index]);
+                    self.stack.pop();
+                    if !unchanged {
+                        return false;
+                    }
+                }
+                true
+            }
+            (&Element::Lazy { key: ref left, .. }, &Element::Lazy { key: ref right, dirty, .. }) => {
+                left == right && !dirty
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Element {
+    // Collects the text updates from this tree to `other` into `patch`, which
+    // is cleared first. Returns false if the trees differ in anything other
+    // than text content, in which case `patch` is incomplete and the caller
+    // should fall back to `diff`.
+    pub fn diff_text(&self, other: &Element, patch: &mut TextPatch) -> bool {
+        patch.clear();
+        patch.walk(self, other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use super::TextPatch;
+    use {Change, DiffTree, Element, HandlerId, Key};
+
+    // Counts the allocations made by the current thread, so that tests
+    // running in parallel don't interfere with each other.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(|count| count.get())
+    }
+
+    fn clock(time: &str) -> Element {
+        el!(div[
+            key=0,
+            el!(span[key=1, Element::Text { key: Key::Local(0), value: "Time:".to_string() }]),
+            el!(span[key=2, Element::Text { key: Key::Local(0), value: time.to_string() }])
+        ])
+    }
+
+    #[test]
+    fn test_text_only() {
+        let mut patch = TextPatch::new();
+        assert!(clock("12:00").diff_text(&clock("12:01"), &mut patch));
+        assert_eq!(patch.paths().collect::<Vec<_>>(), vec![&[Key::Local(2), Key::Local(0)][..]]);
+
+        let mut expected = DiffTree {
+            changes: None,
+            children: None,
+            base_checksum: None,
+        };
+        expected.push_at(&[Key::Local(2), Key::Local(0)], Change::UpdateText("12:01".to_string()));
+        assert_eq!(patch.to_diff(&clock("12:01")), Some(expected));
+    }
+
+    #[test]
+    fn test_structural_change_falls_back() {
+        let mut patch = TextPatch::new();
+        let left = clock("12:00");
+        let right = el!(div[key=0, el!(span[key=1])]);
+        assert!(!left.diff_text(&right, &mut patch));
+    }
+
+    #[test]
+    fn test_listener_change_falls_back() {
+        let mut patch = TextPatch::new();
+        let button = |listen: bool| {
+            let button = Element::void("button").key(Key::Local(1));
+            let button = if listen { button.on("click", HandlerId(1)) } else { button };
+            Element::parent("div").key(Key::Local(0)).child(button).build()
+        };
+        assert!(button(false).diff_text(&button(false), &mut patch));
+        assert!(!button(false).diff_text(&button(true), &mut patch));
+        let mut parent = button(false);
+        if let Element::Parent { ref mut listeners, .. } = parent {
+            listeners.insert("click".to_string(), HandlerId(1));
+        }
+        assert!(!button(false).diff_text(&parent, &mut patch));
+
+        // Nor are nodes of another key patched in place.
+        let text = |key: u64| Element::text(Key::Local(key), "a");
+        assert!(!text(0).diff_text(&text(1), &mut patch));
+    }
+
+    #[test]
+    fn test_steady_state_is_allocation_free() {
+        let frames = [clock("12:00"), clock("12:01"), clock("12:02")];
+        let mut patch = TextPatch::new();
+        assert!(frames[0].diff_text(&frames[1], &mut patch));
+
+        let before = allocations();
+        assert!(frames[1].diff_text(&frames[2], &mut patch));
+        assert_eq!(allocations(), before);
+        assert_eq!(patch.len(), 1);
+    }
+}
@@ -0,0 +1,804 @@
+// Owns the current tree and turns new trees into patches against it.
+//
+// Besides the main tree, a page often renders into other mount points, such
+// as a modal layer or a toast container outside the app's root element.
+// These are mounted as named roots: each has its own tree and patches, but
+// they are updated together and share one key space policy, so a single
+// event dispatcher can serve them all.
+use std::collections::{BTreeMap, VecDeque};
+use std::mem;
+use std::sync::Arc;
+use std::time::Instant;
+
+use key_index::KeyIndex;
+use selector::Selector;
+use snapshot::{refreeze, FrozenElement};
+use telemetry::{DiffStats, Telemetry};
+use {AttributePolicy, Change, DiffTree, DomError, Element, Event, EventKind, GlobalKeyRegistry, Key,
+     KeyCollision};
+
+pub struct VirtualDom {
+    tree: Element,
+    history: Option<History>,
+    subscriptions: Vec<Subscription>,
+    next_subscription: usize,
+    // Key paths of nodes marked exiting that are still in the document.
+    exiting: Vec<Vec<Key>>,
+    roots: BTreeMap<String, Element>,
+    // Paths of the globally keyed nodes of the main tree and of each root.
+    index: KeyIndex,
+    root_indexes: BTreeMap<String, KeyIndex>,
+    key_policy: KeyPolicy,
+    telemetry: Option<Box<dyn Telemetry>>,
+    // Patches from the current tree to predicted next trees, by content
+    // hash of the prediction, oldest first.
+    speculations: VecDeque<(u128, Option<DiffTree>)>,
+    // The frozen copy of the tree, once one was asked for, kept up to date.
+    snapshot: Option<Arc<FrozenElement>>,
+    attribute_policy: Option<Box<dyn AttributePolicy>>,
+}
+
+// How many predicted trees are kept diffed at once.
+const MAX_SPECULATIONS: usize = 8;
+
+// How keys relate across the main tree and the named roots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum KeyPolicy {
+    // Each tree is a key space of its own.
+    #[default]
+    Independent,
+    // A global key identifies one node across all trees, so a node can be
+    // followed when it moves from one root to another. Updates that would
+    // put a global key in two places are rejected.
+    SharedGlobals,
+}
+
+// Identifies a callback registered with VirtualDom::subscribe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(usize);
+
+struct Subscription {
+    id: SubscriptionId,
+    selector: Selector,
+    callback: Box<dyn FnMut(&DiffTree)>,
+}
+
+// A state the tree has been in, along with the event that caused the update
+// to it, if any.
+#[derive(Debug)]
+pub struct HistoryEntry {
+    pub tree: Element,
+    pub cause: Option<Event>,
+}
+
+struct History {
+    capacity: usize,
+    entries: VecDeque<HistoryEntry>,
+    cursor: usize,
+}
+
+impl VirtualDom {
+    pub fn new(tree: Element) -> VirtualDom {
+        VirtualDom {
+            index: KeyIndex::new(&tree),
+            tree: tree,
+            history: None,
+            subscriptions: vec![],
+            next_subscription: 0,
+            exiting: vec![],
+            roots: BTreeMap::new(),
+            root_indexes: BTreeMap::new(),
+            key_policy: KeyPolicy::Independent,
+            telemetry: None,
+            speculations: VecDeque::new(),
+            snapshot: None,
+            attribute_policy: None,
+        }
+    }
+
+    // Creates a VirtualDom that retains up to `capacity` states, including
+    // the initial one, for time-travel debugging. Older states are dropped
+    // once the limit is reached.
+    pub fn with_history(tree: Element, capacity: usize) -> VirtualDom {
+        assert!(capacity > 0, "history capacity must be at least 1");
+        let mut entries = VecDeque::with_capacity(capacity);
+        entries.push_back(HistoryEntry {
+            tree: tree.clone(),
+            cause: None,
+        });
+        VirtualDom {
+            history: Some(History {
+                capacity: capacity,
+                entries: entries,
+                cursor: 0,
+            }),
+            ..VirtualDom::new(tree)
+        }
+    }
+
+    pub fn tree(&self) -> &Element {
+        &self.tree
+    }
+
+    // A read-only copy of the current tree for other threads, see
+    // FrozenElement. The first call copies the whole tree; after that the
+    // copy follows each update, sharing unchanged subtrees with the last.
+    pub fn snapshot(&mut self) -> Arc<FrozenElement> {
+        let tree = &self.tree;
+        self.snapshot.get_or_insert_with(|| Arc::new(tree.freeze())).clone()
+    }
+
+    // Replaces the current tree with `next` and returns the patch between
+    // them. Any states ahead of the current one after a rewind are discarded.
+    pub fn update(&mut self, next: Element, cause: Option<Event>) -> Option<DiffTree> {
+        let diff = match self.take_speculation(&next) {
+            Some(diff) => diff,
+            None => diff(&self.tree, &next, &mut self.telemetry),
+        };
+        if let Some(ref mut history) = self.history {
+            history.entries.truncate(history.cursor + 1);
+            history.entries.push_back(HistoryEntry {
+                tree: next.clone(),
+                cause: cause,
+            });
+            if history.entries.len() > history.capacity {
+                history.entries.pop_front();
+            }
+            history.cursor = history.entries.len() - 1;
+        }
+        self.swap(next, diff)
+    }
+
+    // Diffs the current tree against `predicted`, a tree the next update is
+    // likely to bring, such as the page a hovered link leads to, so that an
+    // update to an identical tree takes the patch from the cache rather than
+    // diffing. Trees match by content, which Lazy nodes are clean and text
+    // diff policies. Meant for idle time. Predictions are forgotten once the
+    // tree changes, and only the latest few are kept.
+    pub fn speculate(&mut self, predicted: &Element) {
+        let hash = predicted.diff_hash();
+        if self.speculations.iter().any(|&(x, _)| x == hash) {
+            return;
+        }
+        let diff = diff(&self.tree, predicted, &mut self.telemetry);
+        if self.speculations.len() == MAX_SPECULATIONS {
+            self.speculations.pop_front();
+        }
+        self.speculations.push_back((hash, diff));
+    }
+
+    // Reports every diff computed from now on to `telemetry`, replacing any
+    // previously set.
+    pub fn set_telemetry<T>(&mut self, telemetry: T)
+        where T: Telemetry + 'static
+    {
+        self.telemetry = Some(Box::new(telemetry));
+    }
+
+    // Enforces `policy` on every patch from now on, the main tree's and the
+    // roots', replacing any previously set; rejections are reported to the
+    // telemetry. The trees keep the values they were given, so a rejected
+    // value that stays the same isn't checked again. App enforces it on the
+    // tree it mounts.
+    pub fn set_attribute_policy<P>(&mut self, policy: P)
+        where P: AttributePolicy + 'static
+    {
+        self.attribute_policy = Some(Box::new(policy));
+    }
+
+    pub fn attribute_policy(&self) -> Option<&dyn AttributePolicy> {
+        self.attribute_policy.as_deref()
+    }
+
+    // For the transport to report the size of a serialized patch once sent.
+    pub fn patch_sent(&mut self, bytes: usize) {
+        if let Some(ref mut telemetry) = self.telemetry {
+            telemetry.on_patch_sent(bytes);
+        }
+    }
+
+    // For the event loop to report that the patch for `event`, which arrived
+    // at `arrived`, is ready.
+    pub fn event_handled(&mut self, event: &Event, arrived: Instant) {
+        if let Some(ref mut telemetry) = self.telemetry {
+            telemetry.on_event(event.event_type(), arrived.elapsed());
+        }
+    }
+
+    pub fn key_policy(&self) -> KeyPolicy {
+        self.key_policy
+    }
+
+    pub fn set_key_policy(&mut self, policy: KeyPolicy) {
+        self.key_policy = policy;
+    }
+
+    // The tree mounted under `name`, if any.
+    pub fn root(&self, name: &str) -> Option<&Element> {
+        self.roots.get(name)
+    }
+
+    // The names of the mounted roots in ascending order.
+    pub fn root_names<'a>(&'a self) -> impl Iterator<Item = &'a str> + 'a {
+        self.roots.keys().map(|name| &name[..])
+    }
+
+    // Removes the root mounted under `name`, returning its tree.
+    pub fn unmount(&mut self, name: &str) -> Option<Element> {
+        self.root_indexes.remove(name);
+        self.roots.remove(name)
+    }
+
+    // Replaces the trees of the named roots in one update, returning the
+    // patch for each root that changed in the order given. Names not yet
+    // mounted are mounted and produce no patch, since there is nothing to
+    // patch; roots not named keep their trees. Under
+    // KeyPolicy::SharedGlobals nothing is changed if the new trees would
+    // share a global key with each other or with the main tree.
+    pub fn update_roots(&mut self,
+                        next: Vec<(String, Element)>)
+                        -> Result<Vec<(String, DiffTree)>, KeyCollision> {
+        if self.key_policy == KeyPolicy::SharedGlobals {
+            let mut registry = GlobalKeyRegistry::from_tree(&self.tree);
+            for (name, tree) in &self.roots {
+                if !next.iter().any(|&(ref x, _)| x == name) {
+                    registry.add_tree(tree);
+                }
+            }
+            for &(_, ref tree) in &next {
+                registry.add_tree(tree);
+            }
+            if let Some(collision) = registry.collisions().first() {
+                return Err(collision.clone());
+            }
+        }
+        let mut patches = vec![];
+        for (name, tree) in next {
+            let diff = match self.roots.get(&name) {
+                Some(previous) => {
+                    let diff = diff(previous, &tree, &mut self.telemetry);
+                    if let Some(ref diff) = diff {
+                        self.root_indexes.get_mut(&name).unwrap().update(previous, diff);
+                    }
+                    diff
+                }
+                None => {
+                    self.root_indexes.insert(name.clone(), KeyIndex::new(&tree));
+                    None
+                }
+            };
+            if let Some(mut diff) = diff {
+                self.enforce_policy(&mut diff);
+                patches.push((name.clone(), diff));
+            }
+            self.roots.insert(name, tree);
+        }
+        Ok(patches)
+    }
+
+    // The name of the root holding the node with global key `key`, for
+    // routing an event to the tree it came from. None if no mounted root
+    // holds it, as when it is in the main tree.
+    pub fn root_of(&self, key: &Key) -> Option<&str> {
+        self.root_indexes
+            .iter()
+            .find(|&(_, index)| index.get(key).is_some())
+            .map(|(name, _)| &name[..])
+    }
+
+    // The path from the root of the main tree to the node with global key
+    // `key`, the root itself excluded, as for finding the target of an
+    // event. Nodes marked exiting are found until their removal. Local keys
+    // aren't unique across the tree and are never found.
+    pub fn lookup(&self, key: &Key) -> Option<&[Key]> {
+        self.index.get(key)
+    }
+
+    // The node of the main tree with global key `key`, found through the
+    // index rather than by walking the tree. None for nodes under Lazy ones,
+    // which are evaluated afresh on every call to Element::force.
+    pub fn find(&self, key: &Key) -> Option<&Element> {
+        self.tree.get_path(self.lookup(key)?)
+    }
+
+    // The tree holding the node with global key `key`, the main tree or a
+    // root, and the node's path in it.
+    pub(crate) fn locate(&self, key: &Key) -> Option<(&Element, &[Key])> {
+        if let Some(path) = self.index.get(key) {
+            return Some((&self.tree, path));
+        }
+        self.root_indexes
+            .iter()
+            .filter_map(|(name, index)| index.get(key).map(|path| (&self.roots[name], path)))
+            .next()
+    }
+
+    // Calls `callback` with the part of every patch produced from now on that
+    // touches nodes matching `selector`, as narrowed by Selector::filter_diff.
+    // Patches that don't touch any matching node are not reported.
+    pub fn subscribe<F>(&mut self, selector: Selector, callback: F) -> SubscriptionId
+        where F: FnMut(&DiffTree) + 'static
+    {
+        let id = SubscriptionId(self.next_subscription);
+        self.next_subscription += 1;
+        self.subscriptions.push(Subscription {
+            id: id,
+            selector: selector,
+            callback: Box::new(callback),
+        });
+        id
+    }
+
+    // Removes a subscription, returning false if it was already removed.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let count = self.subscriptions.len();
+        self.subscriptions.retain(|subscription| subscription.id != id);
+        self.subscriptions.len() != count
+    }
+
+    // Completes the removal of a node marked exiting once its exit
+    // transition has ended, returning the patch removing it. Any other event
+    // returns None.
+    pub fn transition_end(&mut self, event: &Event) -> Option<DiffTree> {
+        let target = match *event {
+            Event { ref target, kind: EventKind::TransitionEnd { .. }, .. } => target.clone(),
+            _ => return None,
+        };
+        let index = self.exiting.iter().position(|path| path.last() == Some(&target))?;
+        let path = self.exiting.remove(index);
+        self.index.remove_path(&path);
+        let mut diff = DiffTree {
+            changes: None,
+            children: None,
+            base_checksum: None,
+        };
+        diff.push_at(&path[..path.len() - 1], Change::RemoveChild(target));
+        Some(diff)
+    }
+
+    // The key paths of the nodes currently marked exiting.
+    pub fn exiting(&self) -> &[Vec<Key>] {
+        &self.exiting
+    }
+
+    // The retained states from oldest to newest. Empty if history is not
+    // enabled.
+    pub fn history<'a>(&'a self) -> impl Iterator<Item = &'a HistoryEntry> + 'a {
+        self.history.iter().flat_map(|history| history.entries.iter())
+    }
+
+    // Moves back `n` states, or as far as the history goes, and returns the
+    // patch that takes the current tree to that state.
+    pub fn rewind(&mut self, n: usize) -> Option<DiffTree> {
+        let cursor = match self.history {
+            Some(ref history) => history.cursor.saturating_sub(n),
+            None => return None,
+        };
+        self.travel(cursor)
+    }
+
+    // Moves forward `n` states after a rewind, or as far as the history goes,
+    // and returns the patch that takes the current tree to that state.
+    pub fn forward(&mut self, n: usize) -> Option<DiffTree> {
+        let cursor = match self.history {
+            Some(ref history) => {
+                let last = history.entries.len() - 1;
+                if history.cursor + n > last {
+                    last
+                } else {
+                    history.cursor + n
+                }
+            }
+            None => return None,
+        };
+        self.travel(cursor)
+    }
+
+    fn travel(&mut self, cursor: usize) -> Option<DiffTree> {
+        let history = match self.history {
+            Some(ref mut history) => history,
+            None => return None,
+        };
+        if cursor == history.cursor {
+            return None;
+        }
+        history.cursor = cursor;
+        let target = history.entries[cursor].tree.clone();
+        let diff = diff(&self.tree, &target, &mut self.telemetry);
+        self.swap(target, diff)
+    }
+
+    // The cached patch to `next`, if it was predicted. Hashing is skipped
+    // when nothing was.
+    fn take_speculation(&mut self, next: &Element) -> Option<Option<DiffTree>> {
+        if self.speculations.is_empty() {
+            return None;
+        }
+        let hash = next.diff_hash();
+        let index = self.speculations.iter().position(|&(x, _)| x == hash)?;
+        self.speculations.remove(index).map(|(_, diff)| diff)
+    }
+
+    fn swap(&mut self, next: Element, mut diff: Option<DiffTree>) -> Option<DiffTree> {
+        self.speculations.clear();
+        if let Some(ref mut diff) = diff {
+            self.enforce_policy(diff);
+            self.track_exiting(&next, diff);
+        }
+        let previous = mem::replace(&mut self.tree, next);
+        if let Some(ref diff) = diff {
+            if let Some(ref mut snapshot) = self.snapshot {
+                *snapshot = refreeze(snapshot, &self.tree, diff);
+            }
+            self.index.update(&previous, diff);
+            for subscription in &mut self.subscriptions {
+                if let Some(filtered) = subscription.selector
+                    .filter_diff(&previous, &self.tree, diff) {
+                    (subscription.callback)(&filtered);
+                }
+            }
+        }
+        diff
+    }
+
+    fn enforce_policy(&mut self, diff: &mut DiffTree) {
+        if let Some(ref policy) = self.attribute_policy {
+            for rejection in diff.enforce(&**policy) {
+                if let Some(ref mut telemetry) = self.telemetry {
+                    telemetry.on_attribute_rejected(&rejection);
+                }
+            }
+        }
+    }
+
+    fn track_exiting(&mut self, next: &Element, diff: &mut DiffTree) {
+        let mut path = vec![];
+        collect_exiting(diff, &mut path, &mut self.exiting);
+        let mut stale = vec![];
+        let mut gone = vec![];
+        self.exiting.retain(|path| {
+            let (key, parent) = path.split_last().unwrap();
+            match next.get_path(parent) {
+                // Back in the tree before its transition ended: the exiting
+                // copy has to go before the new one is inserted.
+                Some(parent) if parent.get_child(key).is_some() => {
+                    stale.push(path.clone());
+                    false
+                }
+                Some(_) => true,
+                // The parent went away, taking the exiting node with it.
+                None => {
+                    gone.push(path.clone());
+                    false
+                }
+            }
+        });
+        for path in gone {
+            self.index.remove_path(&path);
+        }
+        for path in stale {
+            let (key, parent) = path.split_last().unwrap();
+            prepend_at(diff, parent, Change::RemoveChild(key.clone()));
+        }
+    }
+}
+
+// Diffs the trees, reporting to `telemetry` if set.
+fn diff(previous: &Element, next: &Element, telemetry: &mut Option<Box<dyn Telemetry>>) -> Option<DiffTree> {
+    let telemetry = match *telemetry {
+        Some(ref mut telemetry) => telemetry,
+        None => return previous.diff(next),
+    };
+    if cfg!(debug_assertions) {
+        if let Err(error @ DomError::DuplicateKey { .. }) = next.validate() {
+            telemetry.on_duplicate_key(&error);
+        }
+    }
+    let start = Instant::now();
+    let mut timings = vec![];
+    let diff = previous.diff_timed(next, &mut timings);
+    telemetry.on_diff(&DiffStats::with_subtrees(diff.as_ref(), start.elapsed(), &timings));
+    diff
+}
+
+fn collect_exiting(diff: &DiffTree, path: &mut Vec<Key>, exiting: &mut Vec<Vec<Key>>) {
+    if let Some(ref changes) = diff.changes {
+        for change in changes.iter() {
+            if let Change::MarkExiting(ref key) = *change {
+                let mut exiting_path = path.clone();
+                exiting_path.push(key.clone());
+                exiting.push(exiting_path);
+            }
+        }
+    }
+    if let Some(ref children) = diff.children {
+        for &(ref key, ref child) in children.iter() {
+            path.push(key.clone());
+            collect_exiting(child, path, exiting);
+            path.pop();
+        }
+    }
+}
+
+// Like DiffTree::push_at, but places the change before the node's others.
+fn prepend_at(diff: &mut DiffTree, path: &[Key], change: Change) {
+    let empty = DiffTree {
+        changes: None,
+        children: None,
+        base_checksum: None,
+    };
+    let mut first = empty.clone();
+    first.push_at(path, change);
+    let rest = mem::replace(diff, empty);
+    *diff = first.compose(rest);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::{KeyPolicy, VirtualDom};
+    use selector::Selector;
+    use telemetry::{DiffStats, Telemetry};
+    use {Change, DiffTree, DomError, Element, Event, EventKind, Flags, Key, TextDiff, Thunk};
+
+    fn text(value: &str) -> Element {
+        Element::Text {
+            key: Key::Local(0),
+            value: value.to_string(),
+        }
+    }
+
+    fn update_text(value: &str) -> Option<DiffTree> {
+        Some(DiffTree {
+            changes: Some(Box::new([Change::UpdateText(value.to_string())])),
+            children: None,
+            base_checksum: None,
+        })
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let page = |label: &str, heading: &str| {
+            el!(div[
+                key=0,
+                el!(button[key=1, text(label)]),
+                el!(h1[key=2, text(heading)])
+            ])
+        };
+        let received = Rc::new(RefCell::new(vec![]));
+        let mut dom = VirtualDom::new(page("Buy", "Shop"));
+        let sink = received.clone();
+        let id = dom.subscribe(Selector::parse("button").unwrap(),
+                               move |diff| sink.borrow_mut().push(diff.clone()));
+
+        dom.update(page("Buy now", "Shop!"), None);
+        dom.update(page("Buy now", "Shop"), None);
+        assert!(dom.unsubscribe(id));
+        dom.update(page("Buy", "Shop"), None);
+
+        let diff = &received.borrow()[..];
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].children.as_ref().unwrap()[0].0, Key::Local(1));
+        assert_eq!(diff[0].children.as_ref().unwrap()[0].1.children,
+                   Some(Box::new([(Key::Local(0), update_text("Buy now").unwrap())]) as Box<[_]>));
+    }
+
+    #[test]
+    fn test_transition_end() {
+        let item = |key| {
+            let mut item = el!(li[key=key]);
+            if let Element::Void { ref mut flags, .. } = item {
+                flags.keep_until_transition_end = true;
+            }
+            item
+        };
+        let mut dom = VirtualDom::new(el!(ul[key=0, item(1), item(2)]));
+
+        let diff = dom.update(el!(ul[key=0, item(2)]), None).unwrap();
+        assert_eq!(diff.changes.unwrap()[0], Change::MarkExiting(Key::Local(1)));
+        assert_eq!(dom.exiting(), &[vec![Key::Local(1)]][..]);
+
+        let end = |key| {
+            Event {
+                target: Key::Local(key),
+                bubbles: true,
+                cancelable: false,
+                timestamp: 0.0,
+                kind: EventKind::TransitionEnd { property_name: "opacity".to_string() },
+            }
+        };
+        assert_eq!(dom.transition_end(&end(2)), None);
+        let diff = dom.transition_end(&end(1)).unwrap();
+        assert_eq!(diff.changes, Some(Box::new([Change::RemoveChild(Key::Local(1))]) as Box<[_]>));
+        assert!(dom.exiting().is_empty());
+    }
+
+    #[test]
+    fn test_lookup() {
+        let item = |key| {
+            let mut item = el!(li[key=0]);
+            if let Element::Void { key: ref mut x, ref mut flags, .. } = item {
+                *x = Key::Global(key);
+                flags.keep_until_transition_end = true;
+            }
+            item
+        };
+        let mut dom = VirtualDom::new(el!(div[key=0, el!(ul[key=1, item(7)]), el!(ol[key=2, item(8)])]));
+        assert_eq!(dom.lookup(&Key::Global(7)), Some(&[Key::Local(1), Key::Global(7)][..]));
+        assert_eq!(dom.lookup(&Key::Local(1)), None);
+        assert!(dom.find(&Key::Global(7)).unwrap().deep_eq(&item(7)));
+        assert_eq!(dom.find(&Key::Global(9)), None);
+
+        dom.update(el!(div[key=0, el!(ul[key=1, item(7)]), el!(ol[key=2, text("none")])]), None);
+        assert_eq!(dom.lookup(&Key::Global(8)), Some(&[Key::Local(2), Key::Global(8)][..]));
+        let end = Event {
+            target: Key::Global(8),
+            bubbles: true,
+            cancelable: false,
+            timestamp: 0.0,
+            kind: EventKind::TransitionEnd { property_name: "opacity".to_string() },
+        };
+        assert!(dom.transition_end(&end).is_some());
+        assert_eq!(dom.lookup(&Key::Global(8)), None);
+
+        dom.update(el!(div[key=0, el!(ol[key=2, item(7)])]), None);
+        assert_eq!(dom.lookup(&Key::Global(7)), Some(&[Key::Local(2), Key::Global(7)][..]));
+    }
+
+    #[test]
+    fn test_rewind_forward() {
+        let mut dom = VirtualDom::with_history(text("a"), 10);
+        dom.update(text("b"), None);
+        dom.update(text("c"), None);
+
+        assert_eq!(dom.rewind(2), update_text("a"));
+        assert_eq!(dom.rewind(1), None);
+        assert_eq!(dom.forward(1), update_text("b"));
+        assert_eq!(dom.forward(5), update_text("c"));
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut dom = VirtualDom::with_history(text("a"), 2);
+        dom.update(text("b"), None);
+        dom.update(text("c"), None);
+
+        assert_eq!(dom.history().count(), 2);
+        assert_eq!(dom.rewind(5), update_text("b"));
+    }
+
+    #[test]
+    fn test_update_after_rewind_discards_future() {
+        let mut dom = VirtualDom::with_history(text("a"), 10);
+        dom.update(text("b"), None);
+        dom.rewind(1);
+        dom.update(text("c"), None);
+
+        assert_eq!(dom.history().count(), 2);
+        assert_eq!(dom.forward(1), None);
+    }
+
+    #[test]
+    fn test_roots() {
+        let mut dom = VirtualDom::new(el!(main[key=0, text("app")]));
+        let toast = |value: &str| {
+            el!(div[key=0, Element::Text { key: Key::Global(1), value: value.to_string() }])
+        };
+        let mounted = dom.update_roots(vec![("modal-layer".to_string(), el!(div[key=0])),
+                                            ("toasts".to_string(), toast("saved"))]);
+        assert_eq!(mounted, Ok(vec![]));
+        assert_eq!(dom.root_names().collect::<Vec<_>>(), vec!["modal-layer", "toasts"]);
+
+        let patches = dom.update_roots(vec![("toasts".to_string(), toast("deleted"))]).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].0, "toasts");
+        assert_eq!(dom.root_of(&Key::Global(1)), Some("toasts"));
+
+        dom.set_key_policy(KeyPolicy::SharedGlobals);
+        let collision = dom.update_roots(vec![("modal-layer".to_string(), toast("copy"))]);
+        assert_eq!(collision.unwrap_err().key, Key::Global(1));
+        assert!(dom.root("modal-layer").unwrap().deep_eq(&el!(div[key=0])));
+        assert_eq!(dom.unmount("toasts"), Some(toast("deleted")));
+    }
+
+    #[test]
+    fn test_telemetry() {
+        struct Recorder(Rc<RefCell<Vec<usize>>>);
+
+        impl Telemetry for Recorder {
+            fn on_diff(&mut self, stats: &DiffStats) {
+                self.0.borrow_mut().push(stats.change_count);
+            }
+
+            fn on_patch_sent(&mut self, bytes: usize) {
+                self.0.borrow_mut().push(bytes);
+            }
+        }
+
+        let log = Rc::new(RefCell::new(vec![]));
+        let mut dom = VirtualDom::new(text("a"));
+        dom.set_telemetry(Recorder(log.clone()));
+        dom.update(text("b"), None);
+        dom.update(text("b"), None);
+        dom.patch_sent(42);
+        assert_eq!(*log.borrow(), vec![1, 0, 42]);
+    }
+
+    #[test]
+    fn test_duplicate_key_telemetry() {
+        struct Recorder(Rc<RefCell<Vec<Key>>>);
+
+        impl Telemetry for Recorder {
+            fn on_duplicate_key(&mut self, error: &DomError) {
+                if let DomError::DuplicateKey { ref key, .. } = *error {
+                    self.0.borrow_mut().push(key.clone());
+                }
+            }
+        }
+
+        let log = Rc::new(RefCell::new(vec![]));
+        let mut dom = VirtualDom::new(el!(ul[key=0]));
+        dom.set_telemetry(Recorder(log.clone()));
+        dom.update(el!(ul[key=0, el!(li[key=1]), el!(li[key=2])]), None);
+        dom.update(el!(ul[key=0, el!(li[key=1]), el!(li[key=1])]), None);
+        if cfg!(debug_assertions) {
+            assert_eq!(*log.borrow(), vec![Key::Local(1)]);
+        }
+    }
+
+    #[test]
+    fn test_speculate() {
+        struct Counter(Rc<RefCell<usize>>);
+
+        impl Telemetry for Counter {
+            fn on_diff(&mut self, _stats: &DiffStats) {
+                *self.0.borrow_mut() += 1;
+            }
+        }
+
+        let diffs = Rc::new(RefCell::new(0));
+        let mut dom = VirtualDom::new(text("a"));
+        dom.set_telemetry(Counter(diffs.clone()));
+        dom.speculate(&text("b"));
+        dom.speculate(&text("b"));
+        dom.speculate(&text("c"));
+        assert_eq!(*diffs.borrow(), 2);
+
+        assert_eq!(dom.update(text("b"), None), update_text("b"));
+        assert_eq!(*diffs.borrow(), 2);
+        // The prediction for "c" was against "a", so it is gone.
+        assert_eq!(dom.update(text("c"), None), update_text("c"));
+        assert_eq!(*diffs.borrow(), 3);
+
+        // A clean Lazy node isn't evaluated against one of its key, so the
+        // same content behind a dirty one diffs differently.
+        let lazy = |dirty: bool, value: &'static str| {
+            el!(div[key=0, Element::Lazy {
+                key: Key::Local(1),
+                dirty: dirty,
+                thunk: Thunk::new(move || el!(p[key=1, text(value)])),
+            }])
+        };
+        dom.update(lazy(false, "a"), None);
+        dom.speculate(&lazy(false, "b"));
+        assert_eq!(dom.update(lazy(true, "b"), None).unwrap().children.unwrap()[0].0, Key::Local(1));
+        assert_eq!(*diffs.borrow(), 6);
+
+        // Nor does the same text under another text diff policy.
+        let policy = |text_diff: Option<TextDiff>, value: &str| {
+            Element::parent("p")
+                .flags(Flags { text_diff: text_diff, ..Flags::default() })
+                .child(text(value))
+                .build()
+        };
+        dom.update(policy(None, "one two"), None);
+        dom.speculate(&policy(None, "one three"));
+        let diff = dom.update(policy(Some(TextDiff::Chars), "one three"), None).unwrap();
+        match diff.children.unwrap()[0].1.changes.as_ref().unwrap()[0] {
+            Change::SpliceText { .. } => {}
+            ref other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(*diffs.borrow(), 9);
+    }
+}